@@ -24,7 +24,8 @@ use tracing::{instrument, warn};
 
 use git_branchless_opts::{write_man_pages, InitArgs, InstallManPagesArgs};
 use lib::core::config::{
-    get_default_branch_name, get_default_hooks_dir, get_main_worktree_hooks_dir,
+    get_configured_hooks_path, get_default_branch_name, get_default_hooks_dir,
+    get_main_worktree_hooks_dir,
 };
 use lib::core::dag::Dag;
 use lib::core::effects::Effects;
@@ -258,6 +259,9 @@ fn install_hooks(effects: &Effects, git_run_info: &GitRunInfo, repo: &Repo) -> e
 
     let default_hooks_dir = get_default_hooks_dir(repo)?;
     if hooks_dir != default_hooks_dir {
+        // Report the value as the user configured it, rather than as resolved.
+        let configured_hooks_path =
+            get_configured_hooks_path(git_run_info, repo, None)?.unwrap_or(hooks_dir);
         writeln!(
             effects.get_output_stream(),
             "\
@@ -265,7 +269,7 @@ fn install_hooks(effects: &Effects, git_run_info: &GitRunInfo, repo: &Repo) -> e
 which is not the expected default value of: {}
 The Git hooks above may have been installed to an unexpected global location.",
             style("Warning").yellow().bold(),
-            hooks_dir.to_string_lossy(),
+            configured_hooks_path.to_string_lossy(),
             default_hooks_dir.to_string_lossy()
         )?;
     }
@@ -387,22 +391,13 @@ fn install_aliases(
         install_alias(effects, repo, config, default_config, from, to)?;
     }
 
-    let version_str = git_run_info
-        .run_silent(repo, None, &["version"], Default::default())
-        .wrap_err("Determining Git version")?
-        .stdout;
-    let version_str =
-        String::from_utf8(version_str).wrap_err("Decoding stdout from Git subprocess")?;
-    let version_str = version_str.trim();
-    let version: GitVersion = version_str
-        .parse()
-        .wrap_err_with(|| format!("Parsing Git version string: {version_str}"))?;
+    let version = git_run_info.version(repo)?;
     if version < GitVersion(2, 29, 0) {
         write!(
             effects.get_output_stream(),
             "\
 {warning_str}: the branchless workflow's `git undo` command requires Git
-v2.29 or later, but your Git version is: {version_str}
+v2.29 or later, but your Git version is: {version}
 
 Some operations, such as branch updates, won't be correctly undone. Other
 operations may be undoable. Attempt at your own risk.
@@ -414,7 +409,6 @@ This only applies to the `git undo` command. Other commands which are part of
 the branchless workflow will work properly.
 ",
             warning_str = style("Warning").yellow().bold(),
-            version_str = version_str,
         )?;
     }
 
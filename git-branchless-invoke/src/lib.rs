@@ -20,7 +20,7 @@ use std::fmt::Write;
 use std::path::PathBuf;
 use std::time::SystemTime;
 
-use clap::{CommandFactory, FromArgMatches, Parser};
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser};
 use cursive_core::theme::BaseColor;
 use cursive_core::utils::markup::StyledString;
 use eyre::Context;
@@ -28,7 +28,7 @@ use git_branchless_opts::{ColorSetting, GlobalArgs};
 use lib::core::config::env_vars::{get_git_exec_path, get_path_to_git};
 use lib::core::effects::Effects;
 use lib::core::formatting::Glyphs;
-use lib::git::GitRunInfo;
+use lib::git::{GitRunInfo, BRANCHLESS_COMMAND_ENV_VAR};
 use lib::git::{Repo, RepoError};
 use lib::util::{ExitCode, EyreExitOr};
 use tracing::level_filters::LevelFilter;
@@ -51,7 +51,7 @@ pub struct CommandContext {
 
 #[must_use = "This function returns a guard object to flush traces. Dropping it immediately is probably incorrect. Make sure that the returned value lives until tracing has finished."]
 #[instrument]
-fn install_tracing(effects: Effects) -> eyre::Result<impl Drop> {
+fn install_tracing(effects: Effects, profile_path: Option<PathBuf>) -> eyre::Result<impl Drop> {
     let env_filter = EnvFilter::builder()
         .with_default_directive(LevelFilter::WARN.into())
         .parse(std::env::var(EnvFilter::DEFAULT_ENV).unwrap_or_else(|_|
@@ -76,8 +76,11 @@ fn install_tracing(effects: Effects) -> eyre::Result<impl Drop> {
             Ok(_) | Err(_) => false,
         };
 
-        let filename = match std::env::var("RUST_PROFILE") {
-            Ok(value) if value == "1" || value == "true" => {
+        let filename = match (profile_path, std::env::var("RUST_PROFILE")) {
+            // An explicitly-requested profile only applies to this process, so
+            // it's written to exactly the provided path.
+            (Some(profile_path), _) => Some(profile_path),
+            (None, Ok(value)) if value == "1" || value == "true" => {
                 let filename = format!(
                     "trace-{}.json-{}",
                     SystemTime::now()
@@ -85,10 +88,12 @@ fn install_tracing(effects: Effects) -> eyre::Result<impl Drop> {
                         .as_secs(),
                     nesting_level,
                 );
-                Some(filename)
+                Some(PathBuf::from(filename))
             }
-            Ok(value) if !value.is_empty() => Some(format!("{value}-{nesting_level}")),
-            Ok(_) | Err(_) => None,
+            (None, Ok(value)) if !value.is_empty() => {
+                Some(PathBuf::from(format!("{value}-{nesting_level}")))
+            }
+            (None, Ok(_) | Err(_)) => None,
         };
 
         match filename {
@@ -161,6 +166,28 @@ support repositories with this configuration option enabled.",
     Ok(None)
 }
 
+/// Find the value of the global `--color` option in the provided arguments or
+/// in those of any of their subcommands.
+fn find_color_setting(matches: &ArgMatches) -> Option<ColorSetting> {
+    match matches.try_get_one::<ColorSetting>("color") {
+        Ok(Some(color)) => Some(color.clone()),
+        Ok(None) | Err(_) => matches
+            .subcommand()
+            .and_then(|(_, subcommand_matches)| find_color_setting(subcommand_matches)),
+    }
+}
+
+/// Find the value of the `--profile` option in the provided arguments or in
+/// those of any of their subcommands.
+fn find_profile_setting(matches: &ArgMatches) -> Option<PathBuf> {
+    match matches.try_get_one::<PathBuf>("profile") {
+        Ok(Some(profile_path)) => Some(profile_path.clone()),
+        Ok(None) | Err(_) => matches
+            .subcommand()
+            .and_then(|(_, subcommand_matches)| find_profile_setting(subcommand_matches)),
+    }
+}
+
 /// Wrapper function for `main` to ensure that `Drop` is called for local
 /// variables, since `std::process::exit` will skip them. You probably want to
 /// call `invoke_subcommand_main` instead.
@@ -171,12 +198,20 @@ pub fn do_main_and_drop_locals<T: Parser>(
 ) -> eyre::Result<i32> {
     let command = GlobalArgs::command();
     let command_args = T::parse_from(&args);
+    let command_matches = T::command().ignore_errors(true).get_matches_from(&args);
+    let command_name = command_matches
+        .subcommand_name()
+        .map(|command_name| command_name.to_owned());
     let matches = command.ignore_errors(true).get_matches_from(&args);
     let GlobalArgs {
         working_directory,
         color,
     } = GlobalArgs::from_arg_matches(&matches)
         .map_err(|err| eyre::eyre!("Could not parse global arguments: {err}"))?;
+    // Parsing only the global arguments stops at the subcommand name, so
+    // `--color` passed after it (as in `git branchless sync --color never`)
+    // has to be found in the subcommand's arguments.
+    let color = color.or_else(|| find_color_setting(&command_matches));
 
     if let Some(working_directory) = working_directory {
         std::env::set_current_dir(&working_directory).wrap_err_with(|| {
@@ -189,27 +224,39 @@ pub fn do_main_and_drop_locals<T: Parser>(
 
     let path_to_git = get_path_to_git().unwrap_or_else(|_| PathBuf::from("git"));
     let path_to_git = PathBuf::from(&path_to_git);
-    let git_run_info = GitRunInfo {
-        path_to_git,
-        working_directory: std::env::current_dir()?,
-        env: {
-            let mut env: HashMap<OsString, OsString> = std::env::vars_os().collect();
-            if let Ok(git_exec_path) = get_git_exec_path() {
-                env.entry("GIT_EXEC_PATH".into())
-                    .or_insert(git_exec_path.into());
+    let git_run_info = GitRunInfo::new(path_to_git, std::env::current_dir()?, {
+        let mut env: HashMap<OsString, OsString> = std::env::vars_os().collect();
+        if let Ok(git_exec_path) = get_git_exec_path() {
+            env.entry("GIT_EXEC_PATH".into())
+                .or_insert(git_exec_path.into());
+        }
+        match command_name.as_deref() {
+            // The `hook` subcommand is run by Git on behalf of whichever
+            // command triggered the hook, so keep that command's name.
+            Some("hook") | None => {}
+            Some(command_name) => {
+                env.insert(BRANCHLESS_COMMAND_ENV_VAR.into(), command_name.into());
             }
-            env
-        },
-    };
+        }
+        env
+    });
 
-    let color = match color {
+    let glyphs = match color {
         Some(ColorSetting::Always) => Glyphs::pretty(),
         Some(ColorSetting::Never) => Glyphs::text(),
         Some(ColorSetting::Auto) | None => Glyphs::detect(),
     };
-    let effects = Effects::new(color);
+    // Progress meters are styled separately, so they have to be told about an
+    // explicit color setting too. `NO_COLOR` counts as one, since the
+    // detection used for them doesn't check it.
+    let is_no_color_set = std::env::var_os("NO_COLOR").map_or(false, |value| !value.is_empty());
+    if matches!(color, Some(ColorSetting::Always | ColorSetting::Never)) || is_no_color_set {
+        glyphs.apply_to_console();
+    }
+    let effects = Effects::new(glyphs);
 
-    let _tracing_guard = install_tracing(effects.clone());
+    let profile_path = find_profile_setting(&command_matches);
+    let _tracing_guard = install_tracing(effects.clone(), profile_path);
     install_libgit2_tracing();
 
     if let Some(ExitCode(exit_code)) = check_unsupported_config_options(&effects)? {
@@ -1,5 +1,6 @@
 use std::collections::HashSet;
 use std::path::PathBuf;
+use std::process::Command;
 
 use branchless::core::dag::{CommitSet, Dag};
 use branchless::core::effects::Effects;
@@ -7,10 +8,11 @@ use branchless::core::eventlog::{EventLogDb, EventReplayer};
 use branchless::core::formatting::Glyphs;
 use branchless::core::repo_ext::RepoExt;
 use branchless::core::rewrite::{
-    BuildRebasePlanOptions, RebasePlanBuilder, RebasePlanPermissions, RepoResource,
+    BuildRebasePlanOptions, MergeBaseStrategy, RebasePlanBuilder, RebasePlanPermissions,
+    RepoResource,
 };
-use branchless::git::{CherryPickFastOptions, Commit, Diff, Repo};
-use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use branchless::git::{CherryPickFastOptions, Commit, Diff, GitRunInfo, Repo};
+use criterion::{criterion_group, criterion_main, BatchSize, Bencher, Criterion};
 use rayon::ThreadPoolBuilder;
 
 fn get_repo() -> Repo {
@@ -32,61 +34,83 @@ fn nth_parent(commit: Commit, n: usize) -> Commit {
 
 fn bench_rebase_plan(c: &mut Criterion) {
     c.bench_function("RebasePlanBuilder::build", |b| {
-        let repo = get_repo();
-        let references_snapshot = repo.get_references_snapshot().unwrap();
-        let head_oid = repo.get_head_info().unwrap().oid.unwrap();
-        let later_commit = nth_parent(repo.find_commit_or_fail(head_oid).unwrap(), 20);
-        let earlier_commit = nth_parent(later_commit.clone(), 1000);
-        println!("Comparing {:?} with {:?}", &earlier_commit, &later_commit);
+        build_rebase_plan(b, MergeBaseStrategy::CommitGraph)
+    });
+}
 
-        let effects = Effects::new_suppress_for_test(Glyphs::text());
-        let conn = repo.get_db_conn().unwrap();
-        let event_log_db = EventLogDb::new(&conn).unwrap();
-        let event_replayer =
-            EventReplayer::from_event_log_db(&effects, &repo, &event_log_db).unwrap();
-        let event_cursor = event_replayer.make_default_cursor();
-        let dag = Dag::open_and_sync(
-            &effects,
-            &repo,
-            &event_replayer,
-            event_cursor,
-            &references_snapshot,
-        )
-        .unwrap();
-        let pool = ThreadPoolBuilder::new().build().unwrap();
-        let repo_pool = RepoResource::new_pool(&repo).unwrap();
-
-        let build_options = BuildRebasePlanOptions {
-            force_rewrite_public_commits: true,
-            dump_rebase_constraints: false,
-            dump_rebase_plan: false,
-            detect_duplicate_commits_via_patch_id: true,
+fn bench_merge_base_strategies(c: &mut Criterion) {
+    let mut group = c.benchmark_group("merge-base-strategy");
+    group.bench_function("commit-graph", |b| {
+        build_rebase_plan(b, MergeBaseStrategy::CommitGraph)
+    });
+    group.bench_function("git", |b| {
+        let repo = get_repo();
+        let git_run_info = GitRunInfo {
+            path_to_git: PathBuf::from("git"),
+            working_directory: repo.get_working_copy_path().unwrap(),
+            env: std::env::vars_os().collect(),
         };
-        let permissions = RebasePlanPermissions::verify_rewrite_set(
-            &dag,
-            build_options,
-            &CommitSet::from(later_commit.get_oid()),
-        )
-        .unwrap()
-        .unwrap();
-        let mut builder = RebasePlanBuilder::new(&dag, permissions);
-        builder
-            .move_subtree(later_commit.get_oid(), vec![earlier_commit.get_oid()])
-            .unwrap();
-        b.iter_batched(
-            || builder.clone(),
-            |builder| {
-                builder
-                    .build(&effects, &pool, &repo_pool)
-                    .unwrap()
-                    .unwrap()
-                    .unwrap()
-            },
-            BatchSize::PerIteration,
-        )
+        build_rebase_plan(b, MergeBaseStrategy::Git(git_run_info))
     });
 }
 
+/// Benchmark building a plan which moves a commit back by 1000 commits, so
+/// that there are many upstream commits to check for duplicates.
+fn build_rebase_plan(b: &mut Bencher, merge_base_strategy: MergeBaseStrategy) {
+    let repo = get_repo();
+    let references_snapshot = repo.get_references_snapshot().unwrap();
+    let head_oid = repo.get_head_info().unwrap().oid.unwrap();
+    let later_commit = nth_parent(repo.find_commit_or_fail(head_oid).unwrap(), 20);
+    let earlier_commit = nth_parent(later_commit.clone(), 1000);
+    println!("Comparing {:?} with {:?}", &earlier_commit, &later_commit);
+
+    let effects = Effects::new_suppress_for_test(Glyphs::text());
+    let conn = repo.get_db_conn().unwrap();
+    let event_log_db = EventLogDb::new(&conn).unwrap();
+    let event_replayer = EventReplayer::from_event_log_db(&effects, &repo, &event_log_db).unwrap();
+    let event_cursor = event_replayer.make_default_cursor();
+    let dag = Dag::open_and_sync(
+        &effects,
+        &repo,
+        &event_replayer,
+        event_cursor,
+        &references_snapshot,
+    )
+    .unwrap();
+    let pool = ThreadPoolBuilder::new().build().unwrap();
+    let repo_pool = RepoResource::new_pool(&repo).unwrap();
+
+    let build_options = BuildRebasePlanOptions {
+        force_rewrite_public_commits: true,
+        dump_rebase_constraints: false,
+        dump_rebase_plan: false,
+        detect_duplicate_commits_via_patch_id: true,
+        merge_base_strategy,
+    };
+    let permissions = RebasePlanPermissions::verify_rewrite_set(
+        &dag,
+        build_options,
+        &CommitSet::from(later_commit.get_oid()),
+    )
+    .unwrap()
+    .unwrap();
+    let mut builder = RebasePlanBuilder::new(&dag, permissions);
+    builder
+        .move_subtree(later_commit.get_oid(), vec![earlier_commit.get_oid()])
+        .unwrap();
+    b.iter_batched(
+        || builder.clone(),
+        |builder| {
+            builder
+                .build(&effects, &pool, &repo_pool)
+                .unwrap()
+                .unwrap()
+                .unwrap()
+        },
+        BatchSize::PerIteration,
+    )
+}
+
 fn bench_cherry_pick_fast(c: &mut Criterion) {
     let mut group = c.benchmark_group("cherry-pick");
     group.sample_size(10);
@@ -161,13 +185,42 @@ fn bench_get_paths_touched_by_commits(c: &mut Criterion) {
     });
 }
 
+fn bench_fetch(c: &mut Criterion) {
+    // After the first iteration, there's nothing left to download, so this
+    // measures the fixed overhead of a small fetch from `origin`.
+    let mut group = c.benchmark_group("fetch");
+
+    group.bench_function("Repo::fetch_remote", |b| {
+        let repo = get_repo();
+        b.iter(|| {
+            repo.fetch_remote("origin", true, |_current, _total| {})
+                .unwrap()
+        });
+    });
+
+    group.bench_function("git fetch", |b| {
+        let repo_dir =
+            std::env::var("PATH_TO_REPO").expect("`PATH_TO_REPO` environment variable not set");
+        b.iter(|| {
+            let status = Command::new("git")
+                .args(["fetch", "origin"])
+                .current_dir(&repo_dir)
+                .status()
+                .unwrap();
+            assert!(status.success());
+        });
+    });
+}
+
 criterion_group!(
     name = benches;
     config = Criterion::default().sample_size(10);
     targets =
         bench_cherry_pick_fast,
         bench_diff_fast,
+        bench_fetch,
         bench_get_paths_touched_by_commits,
+        bench_merge_base_strategies,
         bench_rebase_plan,
 );
 criterion_main!(benches);
@@ -2,7 +2,7 @@
 
 use std::ffi::OsString;
 use std::fmt::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use cursive::theme::{BaseColor, Effect, Style};
 use cursive::utils::markup::StyledString;
@@ -10,7 +10,7 @@ use eyre::Context;
 use tracing::{instrument, warn};
 
 use crate::core::formatting::StyledStringBuilder;
-use crate::git::{ConfigRead, GitRunInfo, GitRunOpts, Repo};
+use crate::git::{BranchType, Config, ConfigRead, GitRunInfo, GitRunOpts, Repo};
 
 use super::effects::Effects;
 use super::eventlog::EventTransactionId;
@@ -40,6 +40,29 @@ pub fn get_main_worktree_hooks_dir(
     repo: &Repo,
     event_tx_id: Option<EventTransactionId>,
 ) -> eyre::Result<PathBuf> {
+    let hooks_path = match get_configured_hooks_path(git_run_info, repo, event_tx_id)? {
+        // Git interprets a relative `core.hooksPath` relative to the directory
+        // where hooks are run, which is the root of the working copy, or
+        // `$GIT_DIR` for bare repositories.
+        Some(path) if path.is_relative() => match repo.get_working_copy_path() {
+            Some(working_copy_path) => working_copy_path.join(path),
+            None => repo.get_path().join(path),
+        },
+        Some(path) => path,
+        None => get_default_hooks_dir(repo)?,
+    };
+    Ok(hooks_path)
+}
+
+/// Get the value of `core.hooksPath` as it was configured, if any. A relative
+/// path is returned as-is; use [`get_main_worktree_hooks_dir`] to find the
+/// directory which hooks are actually run from.
+#[instrument]
+pub fn get_configured_hooks_path(
+    git_run_info: &GitRunInfo,
+    repo: &Repo,
+    event_tx_id: Option<EventTransactionId>,
+) -> eyre::Result<Option<PathBuf>> {
     let result = git_run_info
         .run_silent(
             repo,
@@ -51,25 +74,52 @@ pub fn get_main_worktree_hooks_dir(
             },
         )
         .context("Reading core.hooksPath")?;
-    let hooks_path = if result.exit_code.is_success() {
-        let path = String::from_utf8(result.stdout)
-            .context("Decoding git config output for hooks path")?;
-        PathBuf::from(path.strip_suffix('\n').unwrap_or(&path))
-    } else {
-        get_default_hooks_dir(repo)?
-    };
-    Ok(hooks_path)
+    if !result.exit_code.is_success() {
+        return Ok(None);
+    }
+    let path =
+        String::from_utf8(result.stdout).context("Decoding git config output for hooks path")?;
+    Ok(Some(PathBuf::from(
+        path.strip_suffix('\n').unwrap_or(&path),
+    )))
+}
+
+/// Determine whether `git branchless init` has been run in this repository,
+/// based on whether the main branch has been configured.
+///
+/// Commands also work without `git branchless init` if the main branch exists
+/// under its default name (such as in a fresh clone), so such repositories are
+/// considered to be initialized as well.
+#[instrument]
+pub fn is_branchless_initialized(repo: &Repo) -> eyre::Result<bool> {
+    let config = repo.get_readonly_config()?;
+    if config
+        .get::<String, _>("branchless.core.mainBranch")?
+        .or(config.get("branchless.mainBranch")?)
+        .is_some()
+    {
+        return Ok(true);
+    }
+    let main_branch_name = get_main_branch_name(repo)?;
+    Ok(repo
+        .find_branch(&main_branch_name, BranchType::Local)?
+        .is_some())
 }
 
 /// Get the configured name of the main branch.
 ///
 /// The following config values are resolved, in order. The first valid value is returned.
+/// - the override set with `Repo::set_main_branch_override`
 /// - branchless.core.mainBranch
 /// - (deprecated) branchless.mainBranch
 /// - init.defaultBranch
 /// - finally, default to "master"
 #[instrument]
 pub fn get_main_branch_name(repo: &Repo) -> eyre::Result<String> {
+    if let Some(branch_name) = repo.get_main_branch_override() {
+        return Ok(branch_name.to_owned());
+    }
+
     let config = repo.get_readonly_config()?;
 
     if let Some(branch_name) = config.get("branchless.core.mainBranch")? {
@@ -120,6 +170,20 @@ pub fn get_comment_char(repo: &Repo) -> eyre::Result<char> {
     Ok(comment_char)
 }
 
+/// Whether commits should be signed by default (`commit.gpgSign`).
+#[instrument]
+pub fn get_commit_gpg_sign(repo: &Repo) -> eyre::Result<bool> {
+    repo.get_readonly_config()?.get_or("commit.gpgSign", false)
+}
+
+/// Whether uncommitted changes should be stashed before rebasing and restored
+/// afterwards (`rebase.autoStash`).
+#[instrument]
+pub fn get_rebase_autostash(repo: &Repo) -> eyre::Result<bool> {
+    repo.get_readonly_config()?
+        .get_or("rebase.autoStash", false)
+}
+
 /// Get the commit template message, if any.
 #[instrument]
 pub fn get_commit_template(repo: &Repo) -> eyre::Result<Option<String>> {
@@ -212,6 +276,149 @@ pub fn get_restack_preserve_timestamps(repo: &Repo) -> eyre::Result<bool> {
         .get_or("branchless.restack.preserveTimestamps", false)
 }
 
+/// If `true`, pack the loose objects after `git sync` when there are many of
+/// them, as if `--gc` had been passed.
+#[instrument]
+pub fn get_sync_auto_gc(repo: &Repo) -> eyre::Result<bool> {
+    repo.get_readonly_config()?
+        .get_or("branchless.sync.autoGc", false)
+}
+
+/// If `true`, refresh Git's commit-graph file after `git sync` rewrites many
+/// commits, as if `--commit-graph-refresh` had been passed.
+#[instrument]
+pub fn get_sync_write_commit_graph(repo: &Repo) -> eyre::Result<bool> {
+    repo.get_readonly_config()?
+        .get_or("branchless.sync.writeCommitGraph", false)
+}
+
+/// The minimum number of commits that `git sync` must rewrite for the
+/// commit-graph file to be refreshed.
+#[instrument]
+pub fn get_sync_write_commit_graph_min_commits(repo: &Repo) -> eyre::Result<i32> {
+    repo.get_readonly_config()?
+        .get_or("branchless.sync.writeCommitGraphMinCommits", 100)
+}
+
+/// If `true`, send a desktop notification when `git sync` finishes, as if
+/// `--notify` had been passed.
+#[instrument]
+pub fn get_sync_notify(repo: &Repo) -> eyre::Result<bool> {
+    repo.get_readonly_config()?
+        .get_or("branchless.sync.notify", false)
+}
+
+/// If `true`, `git sync --pull` fetches the remote in-process via libgit2
+/// rather than by running `git fetch`, when the remote supports it.
+#[instrument]
+pub fn get_sync_native_fetch(repo: &Repo) -> eyre::Result<bool> {
+    repo.get_readonly_config()?
+        .get_or("branchless.sync.nativeFetch", false)
+}
+
+/// If `false`, `git sync --pull` doesn't fetch tags from the remote, which
+/// can be slow in repositories with many tags. Overridden by
+/// `git sync --no-fetch-tags`.
+#[instrument]
+pub fn get_sync_fetch_tags(repo: &Repo) -> eyre::Result<bool> {
+    repo.get_readonly_config()?
+        .get_or("branchless.sync.fetchTags", true)
+}
+
+/// Branches other than the main branch which `git sync --base-auto` may move
+/// stacks onto, such as `develop` or release branches. Specified as a
+/// whitespace-separated list of branch names.
+#[instrument]
+pub fn get_sync_trunks(repo: &Repo) -> eyre::Result<Vec<String>> {
+    let trunks: String = repo
+        .get_readonly_config()?
+        .get_or("branchless.sync.trunks", String::new())?;
+    Ok(trunks
+        .split_ascii_whitespace()
+        .map(|trunk| trunk.to_owned())
+        .collect())
+}
+
+/// The revsets which `git sync` syncs when none are provided on the command
+/// line, rather than every draft stack. Specified by setting
+/// `branchless.sync.defaultRevsets` once for each revset.
+#[instrument]
+pub fn get_sync_default_revsets(repo: &Repo) -> eyre::Result<Vec<String>> {
+    // Config entry names are normalized to lowercase.
+    let entries = repo
+        .get_readonly_config()?
+        .list(r"^branchless\.sync\.defaultrevsets$")?;
+    Ok(entries.into_iter().map(|(_name, value)| value).collect())
+}
+
+/// The template for the remote reference that `git sync --publish` pushes each
+/// synced branch to. `{user}` is replaced with the local part of `user.email`,
+/// and `{branch}` with the name of the local branch.
+#[instrument]
+pub fn get_sync_publish_ref_template(repo: &Repo) -> eyre::Result<String> {
+    repo.get_readonly_config()?
+        .get_or_else("branchless.sync.publishRefTemplate", || {
+            "refs/heads/users/{user}/{branch}".to_string()
+        })
+}
+
+/// The remote which `git sync --pull` fetches, and whose copy of the main
+/// branch the local main branch is updated to. Overridden by
+/// `git sync --fetch-remote`. If neither is set, the remote tracked by the
+/// main branch is fetched, or every remote if it doesn't track one.
+#[instrument]
+pub fn get_sync_remote(repo: &Repo) -> eyre::Result<Option<String>> {
+    repo.get_readonly_config()?.get("branchless.sync.remote")
+}
+
+/// The number of days after which the backups recorded by
+/// `git sync --record-backup-refs` are deleted by `git sync --list-backups`.
+#[instrument]
+pub fn get_sync_backup_expiry_days(repo: &Repo) -> eyre::Result<i32> {
+    repo.get_readonly_config()?
+        .get_or("branchless.sync.backupExpiryDays", 30)
+}
+
+/// The name of the file which marks a directory (such as a project in a
+/// monorepo) as having its own `git sync` settings. It's read as a Git
+/// configuration file.
+pub const SYNC_DIRECTORY_CONFIG_FILE_NAME: &str = ".branchless";
+
+/// The main branch to use when running `git sync` from the provided
+/// directory, as set by `branchless.sync.mainBranch` in the nearest
+/// `.branchless` file in that directory or one of its parents within the
+/// working copy. If there is no such file, or it doesn't set the main branch,
+/// the repository's main branch is used.
+#[instrument]
+pub fn get_sync_main_branch_override(repo: &Repo, dir: &Path) -> eyre::Result<Option<String>> {
+    let working_copy_path = match repo.get_working_copy_path() {
+        Some(working_copy_path) => working_copy_path,
+        None => return Ok(None),
+    };
+    // Resolve symlinks on both sides, since the working copy path may have
+    // been resolved when the repository was opened.
+    let working_copy_path = std::fs::canonicalize(&working_copy_path)
+        .wrap_err_with(|| format!("Resolving working copy path {working_copy_path:?}"))?;
+    let dir = std::fs::canonicalize(dir).wrap_err_with(|| format!("Resolving path {dir:?}"))?;
+    for dir in dir
+        .ancestors()
+        .take_while(|dir| dir.starts_with(&working_copy_path))
+    {
+        let path = dir.join(SYNC_DIRECTORY_CONFIG_FILE_NAME);
+        if path.is_file() {
+            return Config::open(&path)?.get("branchless.sync.mainBranch");
+        }
+    }
+    Ok(None)
+}
+
+/// A command to run for each commit rewritten by `git sync`, with `{old}` and
+/// `{new}` replaced by the old and new commit hashes, if any.
+#[instrument]
+pub fn get_sync_on_rewrite(repo: &Repo) -> eyre::Result<Option<String>> {
+    repo.get_readonly_config()?.get("branchless.sync.onRewrite")
+}
+
 /// If `true`, when advancing to a "next" commit, prompt interactively to
 /// if there is ambiguity in which commit to advance to.
 #[instrument]
@@ -349,6 +556,26 @@ pub mod env_vars {
     /// manually.
     pub const TEST_SEPARATE_COMMAND_BINARIES: &str = "TEST_SEPARATE_COMMAND_BINARIES";
 
+    /// A testing aid: if set to `1` or `true`, `git sync` runs in a fully
+    /// deterministic order, so that snapshots of its output are stable. Its
+    /// rebase plans are built on a single thread, and maps of rewritten
+    /// commits and branches are processed in sorted order (which affects the
+    /// order of the input to hooks, and so of their output). This is slower,
+    /// so it has no effect unless set, and isn't intended for normal use.
+    pub const BRANCHLESS_DETERMINISTIC: &str = "BRANCHLESS_DETERMINISTIC";
+
+    /// The path to the repository's `.git` directory. If set, Git uses this
+    /// repository rather than discovering one from the working directory.
+    ///
+    /// See <https://git-scm.com/docs/git#Documentation/git.txt-codeGITDIRcode>.
+    pub const GIT_DIR: &str = "GIT_DIR";
+
+    /// The path to the root of the working tree for the repository given by
+    /// [`GIT_DIR`]. This is commonly used for "bare repository" dotfiles setups.
+    ///
+    /// See <https://git-scm.com/docs/git#Documentation/git.txt-codeGITWORKTREEcode>.
+    pub const GIT_WORK_TREE: &str = "GIT_WORK_TREE";
+
     /// Get the path to the Git executable for testing.
     #[instrument]
     pub fn get_path_to_git() -> eyre::Result<PathBuf> {
@@ -381,6 +608,15 @@ or set `env.{0}` in your `config.toml` \
         Ok(git_exec_path)
     }
 
+    /// Determine whether to run in a deterministic order. See
+    /// [`BRANCHLESS_DETERMINISTIC`] for more details.
+    pub fn should_be_deterministic() -> bool {
+        match std::env::var(BRANCHLESS_DETERMINISTIC) {
+            Ok(value) => value == "1" || value == "true",
+            Err(_) => false,
+        }
+    }
+
     /// Determine whether the specified binary should be run separately. See
     /// [`TEST_SEPARATE_COMMAND_BINARIES`] for more details.
     #[instrument]
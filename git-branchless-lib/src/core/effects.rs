@@ -25,6 +25,7 @@ pub enum OperationType {
     ConstrainCommits,
     DetectDuplicateCommits,
     EvaluateRevset(Arc<String>),
+    FetchRemote(Arc<String>),
     FilterByTouchedPaths,
     FilterCommits,
     FindPathToMergeBase,
@@ -44,6 +45,7 @@ pub enum OperationType {
     RunTestOnCommit(Arc<String>),
     RunTests(Arc<String>),
     SortCommits,
+    Sync,
     SyncCommits,
     UpdateCommitGraph,
     UpdateCommits,
@@ -62,6 +64,9 @@ impl Display for OperationType {
             OperationType::EvaluateRevset(revset) => {
                 write!(f, "Evaluating revset: {revset}")
             }
+            OperationType::FetchRemote(remote_name) => {
+                write!(f, "Fetching from remote: {remote_name}")
+            }
             OperationType::FilterByTouchedPaths => {
                 write!(f, "Filtering upstream commits by touched paths")
             }
@@ -85,6 +90,7 @@ impl Display for OperationType {
             OperationType::RunTests(command) => write!(f, "Running command: {command}"),
             OperationType::RunTestOnCommit(commit) => write!(f, "Waiting to run on {commit}"),
             OperationType::SortCommits => write!(f, "Sorting commits"),
+            OperationType::Sync => write!(f, "Syncing"),
             OperationType::SyncCommits => write!(f, "Syncing commit stacks"),
             OperationType::UpdateCommits => write!(f, "Updating commits"),
             OperationType::UpdateCommitGraph => write!(f, "Updating commit graph"),
@@ -744,11 +750,20 @@ impl Effects {
             root_operation: Arc::clone(&self.root_operation),
         }
     }
+
+    /// Create a stream for informational messages and warnings which aren't
+    /// part of the command's results, such as notes about things which were
+    /// left unchanged. These are written alongside error output, so that
+    /// scripts parsing the regular output don't have to filter them out.
+    pub fn get_warning_stream(&self) -> ErrorStream {
+        self.get_error_stream()
+    }
 }
 
 trait WriteProgress {
     type Stream: WriteIo;
     fn get_stream() -> Self::Stream;
+    fn is_stream_attended() -> bool;
     fn get_buffer(&mut self) -> &mut String;
     fn get_root_operation(&self) -> Arc<Mutex<RootOperation>>;
     fn get_updater_thread_handle(&self) -> Arc<RwLock<UpdaterThreadHandle>>;
@@ -772,9 +787,16 @@ trait WriteProgress {
                 Self::get_stream().flush().unwrap();
             }
 
-            Some(_operation_state) if !console::user_attended_stderr() => {
-                // The progress meters will be hidden, and any `println`
-                // calls on them will be ignored.
+            Some(_operation_state)
+                if !console::user_attended_stderr() || !Self::is_stream_attended() =>
+            {
+                // Either the progress meters will be hidden, and any `println`
+                // calls on them will be ignored, or this stream has been
+                // redirected (e.g. piped into `head` or a pager), in which case
+                // printing above the progress meters would send the output to
+                // the terminal instead, out of order with respect to any
+                // subprocess output. Write directly to the stream and flush it
+                // immediately.
                 write!(Self::get_stream(), "{}", take(self.get_buffer())).unwrap();
                 Self::get_stream().flush().unwrap();
             }
@@ -853,6 +875,10 @@ impl WriteProgress for OutputStream {
         stdout()
     }
 
+    fn is_stream_attended() -> bool {
+        console::user_attended()
+    }
+
     fn get_buffer(&mut self) -> &mut String {
         &mut self.buffer
     }
@@ -913,6 +939,10 @@ impl WriteProgress for ErrorStream {
         stderr()
     }
 
+    fn is_stream_attended() -> bool {
+        console::user_attended_stderr()
+    }
+
     fn get_buffer(&mut self) -> &mut String {
         &mut self.buffer
     }
@@ -1131,6 +1161,37 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_effects_progress_sync_nesting() -> eyre::Result<()> {
+        let effects = Effects::new(Glyphs::text());
+        let (sync_effects, sync_progress) = effects.start_operation(OperationType::Sync);
+        let remote_name = Arc::new("origin".to_string());
+        let (_fetch_effects, fetch_progress) =
+            sync_effects.start_operation(OperationType::FetchRemote(Arc::clone(&remote_name)));
+        drop(fetch_progress);
+        let (_sync_commits_effects, sync_commits_progress) =
+            sync_effects.start_operation(OperationType::SyncCommits);
+        {
+            let mut root_operation = effects.root_operation.lock().unwrap();
+            assert_eq!(root_operation.children.len(), 1);
+            assert!(root_operation
+                .get_child(&[OperationType::Sync, OperationType::FetchRemote(remote_name)])
+                .is_some());
+            assert!(root_operation
+                .get_child(&[OperationType::Sync, OperationType::SyncCommits])
+                .is_some());
+        }
+
+        drop(sync_commits_progress);
+        drop(sync_progress);
+        {
+            let root_operation = effects.root_operation.lock().unwrap();
+            assert!(root_operation.children.is_empty());
+        }
+
+        Ok(())
+    }
+
     /// Test for the issue fixed by <https://github.com/console-rs/indicatif/pull/403>.
     #[test]
     fn test_effects_progress_rewind_panic() -> eyre::Result<()> {
@@ -62,6 +62,12 @@ pub enum EventTransactionId {
     Suppressed,
 }
 
+/// The message of the event transaction for a batch of the sync which started
+/// with `sync_event_tx_id`.
+fn make_sync_batch_message(sync_event_tx_id: EventTransactionId) -> String {
+    format!("sync batch {sync_event_tx_id}")
+}
+
 impl Display for EventTransactionId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -576,6 +582,23 @@ INSERT INTO event_log VALUES (
         Ok(())
     }
 
+    /// Get the row ID of the most recently-added event, or `None` if there are
+    /// no events. This is much cheaper than calling
+    /// [`EventLogDb::get_events`] when the caller only needs to determine
+    /// whether any events have been added.
+    #[instrument]
+    pub fn get_latest_event_id(&self) -> eyre::Result<Option<isize>> {
+        let result = self.conn.query_row(
+            "
+SELECT MAX(rowid)
+FROM event_log
+",
+            rusqlite::params![],
+            |row| row.get(0),
+        )?;
+        Ok(result)
+    }
+
     /// Get all the events in the database.
     ///
     /// Returns: All the events in the database, ordered from oldest to newest.
@@ -665,6 +688,67 @@ ORDER BY rowid ASC
         self.make_transaction_id_inner(now, message.as_ref())
     }
 
+    /// Get the ID of the most recent transaction with exactly the given
+    /// message, or `None` if there is no such transaction.
+    #[instrument]
+    pub fn get_latest_transaction_id_with_message(
+        &self,
+        message: &str,
+    ) -> eyre::Result<Option<EventTransactionId>> {
+        let result: Option<isize> = self.conn.query_row(
+            "
+SELECT MAX(event_tx_id)
+FROM event_transactions
+WHERE message = :message
+",
+            rusqlite::named_params![":message": message],
+            |row| row.get(0),
+        )?;
+        Ok(result.map(EventTransactionId::Id))
+    }
+
+    /// Create the event transaction for a batch of stacks after the first in
+    /// the sync which started with `sync_event_tx_id` (`git sync
+    /// --batch-size`). The batch is recorded as belonging to that sync, so
+    /// that [`EventLogDb::get_sync_transaction_ids`] can find it.
+    pub fn make_sync_batch_transaction_id(
+        &self,
+        now: SystemTime,
+        sync_event_tx_id: EventTransactionId,
+    ) -> eyre::Result<EventTransactionId> {
+        self.make_transaction_id_inner(now, &make_sync_batch_message(sync_event_tx_id))
+    }
+
+    /// Get the event transactions of the sync which started with
+    /// `sync_event_tx_id`: that transaction itself, followed by those of its
+    /// later batches, if any. Transactions started by other commands while
+    /// the sync was running aren't included.
+    #[instrument]
+    pub fn get_sync_transaction_ids(
+        &self,
+        sync_event_tx_id: EventTransactionId,
+    ) -> eyre::Result<Vec<EventTransactionId>> {
+        let mut stmt = self.conn.prepare(
+            "
+SELECT event_tx_id
+FROM event_transactions
+WHERE message = :message
+ORDER BY event_tx_id ASC
+",
+        )?;
+        let batch_event_tx_ids: rusqlite::Result<Vec<isize>> = stmt
+            .query_map(
+                rusqlite::named_params![
+                    ":message": make_sync_batch_message(sync_event_tx_id),
+                ],
+                |row| row.get("event_tx_id"),
+            )?
+            .collect();
+        Ok(std::iter::once(sync_event_tx_id)
+            .chain(batch_event_tx_ids?.into_iter().map(EventTransactionId::Id))
+            .collect())
+    }
+
     /// Get the message associated with the given transaction.
     pub fn get_transaction_message(&self, event_tx_id: EventTransactionId) -> eyre::Result<String> {
         let event_tx_id = match event_tx_id {
@@ -1101,6 +1185,18 @@ impl EventReplayer {
         EventCursor { event_id }
     }
 
+    /// Create an event cursor pointing to immediately after the provided event
+    /// ID. Unlike [`EventReplayer::make_cursor`], returns `None` if there is
+    /// no event with the provided ID, rather than clamping it.
+    pub fn make_cursor_checked(&self, event_id: isize) -> Option<EventCursor> {
+        let num_events: isize = self.events.len().try_into().unwrap();
+        if 0 < event_id && event_id <= num_events {
+            Some(EventCursor { event_id })
+        } else {
+            None
+        }
+    }
+
     /// Advance the event cursor by the specified number of events.
     ///
     /// Args:
@@ -218,6 +218,15 @@ impl Glyphs {
         }
     }
 
+    /// Make the styling done with the `console` crate (such as for progress
+    /// meters and the output printed above them) agree with these glyphs.
+    /// Otherwise, `console` detects color support on its own, which ignores
+    /// `--color` and `NO_COLOR`.
+    pub fn apply_to_console(&self) {
+        console::set_colors_enabled(self.should_write_ansi_escape_codes);
+        console::set_colors_enabled_stderr(self.should_write_ansi_escape_codes);
+    }
+
     /// Return a `Glyphs` object suitable for rendering graphs in the reverse of
     /// their usual order.
     pub fn reverse_order(mut self, reverse: bool) -> Self {
@@ -33,6 +33,15 @@ pub fn find_dangling_references<'repo>(
         if !is_gc_ref(&reference_name) {
             continue;
         }
+        // Backups recorded by `git sync --record-backup-refs` point to
+        // commits which were rewritten, so they would always be collected
+        // here. They're pruned by `git sync --list-backups` instead.
+        if reference_name
+            .as_str()
+            .starts_with("refs/branchless/backup/")
+        {
+            continue;
+        }
 
         // The graph only contains commits, so we don't need to handle the
         // case of the reference not peeling to a valid commit. (It might be
@@ -39,6 +39,10 @@ pub trait RepoExt {
     /// be stripped if desired.
     fn get_branch_oid_to_names(&self) -> eyre::Result<HashMap<NonZeroOid, HashSet<ReferenceName>>>;
 
+    /// Get a mapping from commit OID to the names of the tags which point to
+    /// that commit. Tags which don't point to a commit are not included.
+    fn get_tag_oid_to_names(&self) -> eyre::Result<HashMap<NonZeroOid, HashSet<ReferenceName>>>;
+
     /// Get the positions of references in the repository.
     fn get_references_snapshot(&self) -> eyre::Result<RepoReferencesSnapshot>;
 
@@ -49,7 +53,12 @@ pub trait RepoExt {
 impl RepoExt for Repo {
     fn get_main_branch(&self) -> eyre::Result<Branch> {
         let main_branch_name = get_main_branch_name(self)?;
-        match self.find_branch(&main_branch_name, BranchType::Local)? {
+        if let Some(branch) = self.find_branch(&main_branch_name, BranchType::Local)? {
+            return Ok(branch);
+        }
+        // Some users don't keep a local main branch at all, and configure a
+        // remote-tracking branch (such as `origin/main`) as the main branch.
+        match self.find_branch(&main_branch_name, BranchType::Remote)? {
             Some(branch) => Ok(branch),
             None => {
                 let suggestion = format!(
@@ -113,6 +122,24 @@ https://github.com/arxanas/git-branchless/discussions/595 for more details.",
         Ok(result)
     }
 
+    fn get_tag_oid_to_names(&self) -> eyre::Result<HashMap<NonZeroOid, HashSet<ReferenceName>>> {
+        let mut result: HashMap<NonZeroOid, HashSet<ReferenceName>> = HashMap::new();
+        for reference in self.get_all_references()? {
+            let reference_name = reference.get_name()?;
+            if !reference_name.as_str().starts_with("refs/tags/") {
+                continue;
+            }
+            if let Some(commit) = reference.peel_to_commit()? {
+                result
+                    .entry(commit.get_oid())
+                    .or_default()
+                    .insert(reference_name);
+            }
+        }
+
+        Ok(result)
+    }
+
     fn get_references_snapshot(&self) -> eyre::Result<RepoReferencesSnapshot> {
         let head_oid = self.get_head_info()?.oid;
         let main_branch_oid = self.get_main_branch_oid()?;
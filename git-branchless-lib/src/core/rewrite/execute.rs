@@ -6,9 +6,11 @@ use std::time::SystemTime;
 
 use bstr::BString;
 use eyre::Context;
+use itertools::Itertools;
 use tracing::warn;
 
 use crate::core::check_out::{check_out_commit, CheckOutCommitOptions, CheckoutTarget};
+use crate::core::config::env_vars::should_be_deterministic;
 use crate::core::effects::Effects;
 use crate::core::eventlog::{EventLogDb, EventTransactionId};
 use crate::core::formatting::Pluralize;
@@ -42,7 +44,11 @@ pub fn move_branches<'a>(
     // ones succeeded. See https://github.com/libgit2/libgit2/issues/5918
     let mut branch_moves: Vec<(NonZeroOid, MaybeZeroOid, &ReferenceName)> = Vec::new();
     let mut branch_move_err: Option<eyre::Error> = None;
-    'outer: for (old_oid, names) in branch_oid_to_names.iter() {
+    let mut branch_oid_to_names = branch_oid_to_names.iter().collect_vec();
+    if should_be_deterministic() {
+        branch_oid_to_names.sort_unstable_by_key(|(old_oid, _names)| **old_oid);
+    }
+    'outer: for (old_oid, names) in branch_oid_to_names {
         let new_oid = match rewritten_oids_map.get(old_oid) {
             Some(new_oid) => new_oid,
             None => continue,
@@ -151,7 +157,9 @@ pub fn move_branches<'a>(
         })
         .collect();
     let branch_moves_stdin = BString::from(branch_moves_stdin);
-    git_run_info.run_hook(
+    // Like Git, ignore the exit code of the hook, since the references have
+    // already been updated.
+    let _exit_code: ExitCode = git_run_info.run_hook(
         effects,
         repo,
         "reference-transaction",
@@ -337,6 +345,9 @@ pub enum FailedMergeInfo {
         /// The OID of the commit that, when moved, caused a conflict.
         commit_oid: NonZeroOid,
 
+        /// The OID of the commit which `commit_oid` was being applied onto.
+        onto_oid: NonZeroOid,
+
         /// The paths which were in conflict.
         conflicting_paths: HashSet<PathBuf>,
     },
@@ -360,6 +371,7 @@ impl FailedMergeInfo {
         match self {
             FailedMergeInfo::Conflict {
                 commit_oid,
+                onto_oid: _,
                 conflicting_paths,
             } => {
                 writeln!(
@@ -430,16 +442,16 @@ mod in_memory {
     use tracing::{instrument, warn};
 
     use crate::core::effects::{Effects, OperationIcon, OperationType};
-    use crate::core::eventlog::EventLogDb;
+    use crate::core::eventlog::{EventLogDb, EventTransactionId};
     use crate::core::gc::mark_commit_reachable;
     use crate::core::rewrite::execute::check_out_updated_head;
     use crate::core::rewrite::move_branches;
     use crate::core::rewrite::plan::{OidOrLabel, RebaseCommand, RebasePlan};
     use crate::git::{
-        AmendFastOptions, CherryPickFastOptions, CreateCommitFastError, GitRunInfo, MaybeZeroOid,
-        NonZeroOid, Repo,
+        AmendFastOptions, CherryPickFastOptions, CreateCommitFastError, GitRunInfo, GitRunOpts,
+        MaybeZeroOid, NonZeroOid, Repo, Signature,
     };
-    use crate::util::EyreExitOr;
+    use crate::util::ExitCode;
 
     use super::{ExecuteRebasePlanOptions, FailedMergeInfo};
 
@@ -457,9 +469,149 @@ mod in_memory {
         MergeFailed(FailedMergeInfo),
     }
 
+    /// Re-create the provided commit with a signature. `git2` can't produce
+    /// signatures itself, so this calls `git commit-tree`, which invokes the
+    /// signing program configured for the repository. Returns the OID of the
+    /// signed commit.
+    #[instrument]
+    fn sign_commit(
+        git_run_info: &GitRunInfo,
+        repo: &Repo,
+        event_tx_id: EventTransactionId,
+        gpg_sign_arg: &str,
+        commit_oid: NonZeroOid,
+    ) -> eyre::Result<NonZeroOid> {
+        let commit = repo.find_commit_or_fail(commit_oid)?;
+        let author = commit.get_author();
+        let committer = commit.get_committer();
+
+        // Keep the author and committer exactly as they were on the unsigned
+        // commit.
+        let mut git_run_info = git_run_info.clone();
+        for (key, value) in [
+            ("GIT_AUTHOR_NAME", author.get_name().unwrap_or_default()),
+            ("GIT_AUTHOR_EMAIL", author.get_email().unwrap_or_default()),
+            ("GIT_AUTHOR_DATE", &author.get_time().to_git_date()),
+            (
+                "GIT_COMMITTER_NAME",
+                committer.get_name().unwrap_or_default(),
+            ),
+            (
+                "GIT_COMMITTER_EMAIL",
+                committer.get_email().unwrap_or_default(),
+            ),
+            ("GIT_COMMITTER_DATE", &committer.get_time().to_git_date()),
+        ] {
+            git_run_info.env.insert(key.into(), value.into());
+        }
+
+        let tree_oid = commit.get_tree_oid().to_string();
+        let mut args = vec![
+            "commit-tree".to_string(),
+            gpg_sign_arg.to_string(),
+            tree_oid,
+        ];
+        for parent_oid in commit.get_parent_oids() {
+            args.push("-p".to_string());
+            args.push(parent_oid.to_string());
+        }
+        let result = git_run_info
+            .run_silent(
+                repo,
+                Some(event_tx_id),
+                &args,
+                GitRunOpts {
+                    treat_git_failure_as_error: true,
+                    stdin: Some(commit.get_message_raw().into()),
+                    working_directory: None,
+                },
+            )
+            .wrap_err("Signing rewritten commit")?;
+        let signed_commit_oid = result
+            .stdout
+            .to_str()
+            .wrap_err("Decoding output of `git commit-tree`")?
+            .trim()
+            .parse()?;
+        Ok(signed_commit_oid)
+    }
+
+    /// Set the author of a rewritten commit to the current user, updating the
+    /// author timestamp unless timestamps are being preserved.
+    fn reset_commit_author<'repo>(
+        repo: &Repo,
+        author: Signature<'repo>,
+        options: &ExecuteRebasePlanOptions,
+    ) -> eyre::Result<Signature<'repo>> {
+        let author = author.update_identity(&repo.get_user_signature()?)?;
+        if options.preserve_timestamps {
+            Ok(author)
+        } else {
+            Ok(author.update_timestamp(options.now)?)
+        }
+    }
+
+    /// Append the provided trailers to the commit message, replacing `{onto}`
+    /// in their values with `onto_oid`. Like `git interpret-trailers`, they're
+    /// added to the paragraph of existing trailers if there is one, or else to
+    /// a new paragraph, and trailers which are already present aren't added
+    /// again.
+    ///
+    /// Existing trailers are detected more strictly than by Git: the last
+    /// paragraph only counts as trailers if every line is a `Key: value` line
+    /// (with a key of ASCII letters, digits and `-`) or an indented
+    /// continuation line. Separators other than `:` (see `trailer.separators`)
+    /// aren't recognized, and neither is a paragraph which mixes trailers with
+    /// other text, so the trailers are added in a new paragraph instead.
+    fn add_commit_trailers(
+        message: &str,
+        trailers: &[(String, String)],
+        onto_oid: NonZeroOid,
+    ) -> String {
+        if trailers.is_empty() {
+            return message.to_string();
+        }
+
+        let message = message.trim_end();
+        let is_trailer_line = |line: &str| match line.split_once(": ") {
+            Some((key, _)) => {
+                !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            }
+            None => false,
+        };
+        // The subject line is never treated as a trailer, even if it looks
+        // like one.
+        let existing_trailers: Vec<&str> = match message.rsplit_once("\n\n") {
+            Some((_, last_paragraph))
+                if last_paragraph
+                    .lines()
+                    .all(|line| line.starts_with(char::is_whitespace) || is_trailer_line(line)) =>
+            {
+                last_paragraph.lines().collect()
+            }
+            Some(_) | None => Vec::new(),
+        };
+
+        let mut result = message.to_string();
+        let mut has_trailers = !existing_trailers.is_empty();
+        for (key, value) in trailers {
+            let value = value.replace("{onto}", &onto_oid.to_string());
+            let trailer = format!("{key}: {value}");
+            if existing_trailers.contains(&trailer.as_str()) {
+                continue;
+            }
+            result.push_str(if has_trailers { "\n" } else { "\n\n" });
+            result.push_str(&trailer);
+            has_trailers = true;
+        }
+        result.push('\n');
+        result
+    }
+
     #[instrument]
     pub fn rebase_in_memory(
         effects: &Effects,
+        git_run_info: &GitRunInfo,
         repo: &Repo,
         rebase_plan: &RebasePlan,
         options: &ExecuteRebasePlanOptions,
@@ -492,17 +644,24 @@ mod in_memory {
 
         let ExecuteRebasePlanOptions {
             now,
-            // Transaction ID will be passed to the `post-rewrite` hook via
-            // environment variable.
-            event_tx_id: _,
+            event_tx_id,
             preserve_timestamps,
             force_in_memory: _,
             force_on_disk: _,
             resolve_merge_conflicts: _, // May be needed once we can resolve merge conflicts in memory.
             check_out_commit_options: _, // Caller is responsible for checking out to new HEAD.
+            reapply_empty_commits,
+            preserve_committer,
+            reset_author,
+            sign_option,
+            no_verify: _,
+            commit_trailers,
         } = options;
 
         let mut current_oid = rebase_plan.first_dest_oid;
+        // The commit which the commits currently being applied are moved onto,
+        // for the `{onto}` placeholder in `commit_trailers`.
+        let mut onto_oid = rebase_plan.first_dest_oid;
         let mut labels: HashMap<String, NonZeroOid> = HashMap::new();
         let mut rewritten_oids: HashMap<NonZeroOid, MaybeZeroOid> = HashMap::new();
 
@@ -567,6 +726,12 @@ mod in_memory {
                             *commit_oid
                         }
                     };
+                    // A commit which isn't part of the plan is the destination
+                    // of the commits applied after it, such as when a plan
+                    // moves several stacks onto different bases.
+                    if !rewritten_oids.contains_key(commit_oid) {
+                        onto_oid = *commit_oid;
+                    }
                 }
 
                 RebaseCommand::Pick {
@@ -593,8 +758,15 @@ mod in_memory {
                         )
                     })?;
 
-                    let commit_author = original_commit.get_author();
-                    let committer_signature = if *preserve_timestamps {
+                    let commit_message =
+                        add_commit_trailers(commit_message, commit_trailers, onto_oid);
+
+                    let commit_author = if *reset_author {
+                        reset_commit_author(repo, original_commit.get_author(), options)?
+                    } else {
+                        original_commit.get_author()
+                    };
+                    let committer_signature = if *preserve_timestamps || *preserve_committer {
                         original_commit.get_committer()
                     } else {
                         original_commit.get_committer().update_timestamp(*now)?
@@ -654,6 +826,7 @@ mod in_memory {
                                 return Ok(RebaseInMemoryResult::MergeFailed(
                                     FailedMergeInfo::Conflict {
                                         commit_oid: *commit_oid,
+                                        onto_oid: rebased_commit_oid.unwrap_or(current_oid),
                                         conflicting_paths,
                                     },
                                 ))
@@ -673,7 +846,7 @@ mod in_memory {
                                 None,
                                 &commit_author,
                                 &committer_signature,
-                                commit_message,
+                                &commit_message,
                                 &commit_tree,
                                 vec![&current_commit],
                             )
@@ -685,6 +858,20 @@ mod in_memory {
 
                     let rebased_commit_oid =
                         rebased_commit_oid.expect("rebased oid should not be None");
+                    let is_skipped = !reapply_empty_commits
+                        && rebased_commit
+                            .expect("rebased commit should not be None")
+                            .is_empty();
+                    let rebased_commit_oid = match sign_option.as_git_arg() {
+                        Some(gpg_sign_arg) if !is_skipped => sign_commit(
+                            git_run_info,
+                            repo,
+                            *event_tx_id,
+                            &gpg_sign_arg,
+                            rebased_commit_oid,
+                        )?,
+                        Some(_) | None => rebased_commit_oid,
+                    };
                     let commit_description =
                         effects
                             .get_glyphs()
@@ -693,10 +880,7 @@ mod in_memory {
                                 rebased_commit_oid,
                             )?)?;
 
-                    if rebased_commit
-                        .expect("rebased commit should not be None")
-                        .is_empty()
-                    {
+                    if is_skipped {
                         rewritten_oids.insert(*original_commit_oid, MaybeZeroOid::Zero);
                         maybe_set_skipped_head_new_oid(*original_commit_oid, current_oid);
 
@@ -769,6 +953,9 @@ mod in_memory {
                             )
                         })?;
 
+                    let replacement_commit_message =
+                        add_commit_trailers(replacement_commit_message, commit_trailers, onto_oid);
+
                     let replacement_commit_description = effects
                         .get_glyphs()
                         .render(replacement_commit.friendly_describe(effects.get_glyphs())?)?;
@@ -776,7 +963,12 @@ mod in_memory {
                         OperationIcon::InProgress,
                         format!("Committing to repository: {replacement_commit_description}"),
                     );
-                    let committer_signature = if *preserve_timestamps {
+                    let commit_author = if *reset_author {
+                        reset_commit_author(repo, replacement_commit.get_author(), options)?
+                    } else {
+                        replacement_commit.get_author()
+                    };
+                    let committer_signature = if *preserve_timestamps || *preserve_committer {
                         replacement_commit.get_committer()
                     } else {
                         replacement_commit.get_committer().update_timestamp(*now)?
@@ -803,13 +995,23 @@ mod in_memory {
                     let rebased_commit_oid = repo
                         .create_commit(
                             None,
-                            &replacement_commit.get_author(),
+                            &commit_author,
                             &committer_signature,
-                            replacement_commit_message,
+                            &replacement_commit_message,
                             &replacement_tree,
                             parents.iter().collect(),
                         )
                         .wrap_err("Applying rebased commit")?;
+                    let rebased_commit_oid = match sign_option.as_git_arg() {
+                        Some(gpg_sign_arg) => sign_commit(
+                            git_run_info,
+                            repo,
+                            *event_tx_id,
+                            &gpg_sign_arg,
+                            rebased_commit_oid,
+                        )?,
+                        None => rebased_commit_oid,
+                    };
 
                     let commit_description =
                         effects
@@ -894,6 +1096,16 @@ mod in_memory {
         })
     }
 
+    /// The result of finishing an in-memory rebase.
+    pub struct PostRebaseInMemoryResult {
+        /// The exit code of the `post-rewrite` hook, or success if there's no
+        /// such hook.
+        pub post_rewrite_hook_exit_code: ExitCode,
+
+        /// The result of checking out the new `HEAD`.
+        pub check_out_result: Result<(), ExitCode>,
+    }
+
     pub fn post_rebase_in_memory(
         effects: &Effects,
         git_run_info: &GitRunInfo,
@@ -902,7 +1114,7 @@ mod in_memory {
         rewritten_oids: &HashMap<NonZeroOid, MaybeZeroOid>,
         skipped_head_updated_oid: Option<NonZeroOid>,
         options: &ExecuteRebasePlanOptions,
-    ) -> EyreExitOr<()> {
+    ) -> eyre::Result<PostRebaseInMemoryResult> {
         let ExecuteRebasePlanOptions {
             now: _,
             event_tx_id,
@@ -911,6 +1123,12 @@ mod in_memory {
             force_on_disk: _,
             resolve_merge_conflicts: _,
             check_out_commit_options,
+            reapply_empty_commits: _,
+            preserve_committer: _,
+            reset_author: _,
+            sign_option: _,
+            no_verify: _,
+            commit_trailers: _,
         } = options;
 
         for new_oid in rewritten_oids.values() {
@@ -929,14 +1147,20 @@ mod in_memory {
         move_branches(effects, git_run_info, repo, *event_tx_id, rewritten_oids)?;
 
         // Call the `post-rewrite` hook only after moving branches so that we don't
-        // produce a spurious abandoned-branch warning.
+        // produce a spurious abandoned-branch warning. Like the
+        // `reference-transaction` hook, it's run even if `no_verify` is set,
+        // since it keeps the event log up to date.
+        let mut rewritten_oids_lines = rewritten_oids.iter().collect_vec();
+        if should_be_deterministic() {
+            rewritten_oids_lines.sort_unstable_by_key(|(old_oid, _new_oid)| **old_oid);
+        }
         #[allow(clippy::format_collect)]
-        let post_rewrite_stdin: String = rewritten_oids
-            .iter()
+        let post_rewrite_stdin: String = rewritten_oids_lines
+            .into_iter()
             .map(|(old_oid, new_oid)| format!("{old_oid} {new_oid}\n"))
             .collect();
         let post_rewrite_stdin = BString::from(post_rewrite_stdin);
-        git_run_info.run_hook(
+        let post_rewrite_hook_exit_code = git_run_info.run_hook(
             effects,
             repo,
             "post-rewrite",
@@ -945,7 +1169,7 @@ mod in_memory {
             Some(post_rewrite_stdin),
         )?;
 
-        let exit_code = check_out_updated_head(
+        let check_out_result = check_out_updated_head(
             effects,
             git_run_info,
             repo,
@@ -956,7 +1180,10 @@ mod in_memory {
             skipped_head_updated_oid,
             check_out_commit_options,
         )?;
-        Ok(exit_code)
+        Ok(PostRebaseInMemoryResult {
+            post_rewrite_hook_exit_code,
+            check_out_result,
+        })
     }
 }
 
@@ -964,6 +1191,7 @@ mod on_disk {
     use std::fmt::Write;
 
     use eyre::Context;
+    use itertools::Itertools;
     use tracing::instrument;
 
     use crate::core::effects::{Effects, OperationType};
@@ -996,6 +1224,12 @@ mod on_disk {
             force_on_disk: _,
             resolve_merge_conflicts: _,
             check_out_commit_options: _, // Checkout happens after rebase has concluded.
+            reapply_empty_commits,
+            preserve_committer: _,
+            reset_author: _,
+            sign_option,
+            no_verify: _,
+            commit_trailers: _,
         } = options;
 
         let (effects, _progress) = effects.start_operation(OperationType::InitializeRebase);
@@ -1096,12 +1330,21 @@ mod on_disk {
             eyre::bail!("Not implemented: replacing commits in an on disk rebase");
         }
 
+        // Empty commits are dropped by the `DetectEmptyCommit` commands, so
+        // leave them out to keep the empty commits.
+        let commands = rebase_plan
+            .commands
+            .iter()
+            .filter(|command| {
+                !(*reapply_empty_commits
+                    && matches!(command, RebaseCommand::DetectEmptyCommit { .. }))
+            })
+            .collect_vec();
         let todo_file_path = rebase_state_dir.join("git-rebase-todo");
         #[allow(clippy::format_collect)]
         std::fs::write(
             &todo_file_path,
-            rebase_plan
-                .commands
+            commands
                 .iter()
                 .map(|command| format!("{}\n", command.to_rebase_command()))
                 .collect::<String>(),
@@ -1114,11 +1357,8 @@ mod on_disk {
         })?;
 
         let end_file_path = rebase_state_dir.join("end");
-        std::fs::write(
-            end_file_path.as_path(),
-            format!("{}\n", rebase_plan.commands.len()),
-        )
-        .wrap_err_with(|| format!("Writing `end` to: {:?}", end_file_path.as_path()))?;
+        std::fs::write(end_file_path.as_path(), format!("{}\n", commands.len()))
+            .wrap_err_with(|| format!("Writing `end` to: {:?}", end_file_path.as_path()))?;
 
         // Corresponds to the `--empty=keep` flag. We'll drop the commits later once
         // we find out that they're empty.
@@ -1140,6 +1380,17 @@ mod on_disk {
             })?;
         }
 
+        // Corresponds to the `--gpg-sign` flag.
+        if let Some(gpg_sign_arg) = sign_option.as_git_arg() {
+            let gpg_sign_opt_file_path = rebase_state_dir.join("gpg_sign_opt");
+            std::fs::write(&gpg_sign_opt_file_path, gpg_sign_arg).wrap_err_with(|| {
+                format!(
+                    "Writing `gpg_sign_opt` option file to: {:?}",
+                    &gpg_sign_opt_file_path
+                )
+            })?;
+        }
+
         // Make sure we don't move around the current branch unintentionally. If it
         // actually needs to be moved, then it will be moved as part of the
         // post-rebase operations.
@@ -1172,6 +1423,13 @@ mod on_disk {
             force_on_disk: _,
             resolve_merge_conflicts: _,
             check_out_commit_options: _, // Checkout happens after rebase has concluded.
+            reapply_empty_commits: _,
+            preserve_committer: _,
+            reset_author: _,
+            sign_option: _,
+            // On-disk rebases aren't attempted if either of these is set.
+            no_verify: _,
+            commit_trailers: _,
         } = options;
 
         match write_rebase_state_to_disk(effects, git_run_info, repo, rebase_plan, options)? {
@@ -1216,6 +1474,113 @@ pub struct ExecuteRebasePlanOptions {
 
     /// If `HEAD` was moved, the options for checking out the new `HEAD` commit.
     pub check_out_commit_options: CheckOutCommitOptions,
+
+    /// If `true`, commits which are empty after being rebased are kept (like
+    /// `git rebase --empty=keep`). If `false`, they're dropped.
+    pub reapply_empty_commits: bool,
+
+    /// If `true`, rewritten commits keep the original committer name, email,
+    /// and timestamp, regardless of `preserve_timestamps`. Since `git rebase`
+    /// always sets the committer itself, an on-disk rebase won't be attempted.
+    pub preserve_committer: bool,
+
+    /// If `true`, rewritten commits have their author set to the current user
+    /// (`user.name` and `user.email`), like `git commit --amend
+    /// --reset-author`. The author timestamp is updated like the committed
+    /// timestamp (see `preserve_timestamps`). The committer isn't affected, so
+    /// this can be combined with `preserve_committer`. Since `git rebase`
+    /// can't reset the author, an on-disk rebase won't be attempted.
+    pub reset_author: bool,
+
+    /// Whether to sign the rewritten commits. If disabled, any signatures on
+    /// the original commits are dropped, since they're invalidated by
+    /// rewriting.
+    pub sign_option: SignOption,
+
+    /// If `true`, skip the user-facing hooks which `git rebase` would run for
+    /// each rewritten commit (`prepare-commit-msg` and `post-commit`). An
+    /// in-memory rebase doesn't run them, but `git rebase --continue` can't be
+    /// told to skip them, so an on-disk rebase won't be attempted. The
+    /// `post-rewrite`, `reference-transaction`, and `post-checkout` hooks
+    /// still run, since they keep the event log up to date.
+    pub no_verify: bool,
+
+    /// Trailers (such as `Rebased-onto: {onto}`) to append to the message of
+    /// each rewritten commit, as key-value pairs. In each value, `{onto}` is
+    /// replaced with the OID of the commit which the rewritten commit's stack
+    /// is moved onto (which may differ between the stacks in one plan).
+    ///
+    /// Since `git rebase` can't add trailers, an on-disk rebase won't be
+    /// attempted.
+    pub commit_trailers: Vec<(String, String)>,
+}
+
+/// How rewritten commits should be signed.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum SignOption {
+    /// Don't sign rewritten commits.
+    #[default]
+    Disable,
+
+    /// Sign rewritten commits with the default key (`user.signingKey`).
+    UseConfig,
+
+    /// Sign rewritten commits with the provided key.
+    KeyOverride(String),
+}
+
+impl SignOption {
+    /// The `-S` argument to pass to Git commands to produce signed commits,
+    /// or `None` if signing is disabled.
+    pub fn as_git_arg(&self) -> Option<String> {
+        match self {
+            SignOption::Disable => None,
+            SignOption::UseConfig => Some("-S".to_string()),
+            SignOption::KeyOverride(key) => Some(format!("-S{key}")),
+        }
+    }
+}
+
+impl ExecuteRebasePlanOptions {
+    /// Whether executing a rebase plan with these options might rebase
+    /// on-disk (and so modify the working copy), either directly or after an
+    /// in-memory rebase fails with merge conflicts.
+    pub fn may_rebase_on_disk(&self) -> bool {
+        self.get_in_memory_only_reason().is_none()
+            && (self.force_on_disk || (self.resolve_merge_conflicts && !self.force_in_memory))
+    }
+
+    /// If any of the options can only be honored by an in-memory rebase,
+    /// describe the first such option, for reporting why an on-disk rebase
+    /// won't be attempted.
+    fn get_in_memory_only_reason(&self) -> Option<&'static str> {
+        let Self {
+            now: _,
+            event_tx_id: _,
+            preserve_timestamps: _,
+            force_in_memory: _,
+            force_on_disk: _,
+            resolve_merge_conflicts: _,
+            check_out_commit_options: _,
+            reapply_empty_commits: _,
+            preserve_committer,
+            reset_author,
+            sign_option: _,
+            no_verify,
+            commit_trailers,
+        } = self;
+        if *preserve_committer {
+            Some("preserving the committer")
+        } else if *reset_author {
+            Some("resetting the author")
+        } else if *no_verify {
+            Some("skipping hooks")
+        } else if !commit_trailers.is_empty() {
+            Some("adding commit trailers")
+        } else {
+            None
+        }
+    }
 }
 
 /// The result of executing a rebase plan.
@@ -1226,6 +1591,11 @@ pub enum ExecuteRebasePlanResult {
     Succeeded {
         /// Mapping from old OID to new/rewritten OID. Will always be empty for on disk rebases.
         rewritten_oids: Option<HashMap<NonZeroOid, MaybeZeroOid>>,
+
+        /// The exit code of the `post-rewrite` hook, or success if there's no
+        /// such hook. Will always be `None` for on disk rebases, since `git
+        /// rebase` runs the hook itself and ignores its exit code.
+        post_rewrite_hook_exit_code: Option<ExitCode>,
     },
 
     /// The rebase operation encounter a failure to merge, and it was not
@@ -1243,6 +1613,36 @@ pub enum ExecuteRebasePlanResult {
     },
 }
 
+/// Check whether the provided rebase plan can be carried out in-memory without
+/// merge conflicts. The rewritten commits are created, but no references are
+/// updated and nothing is checked out. Returns the first merge failure, if any.
+pub fn check_rebase_plan_conflicts(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    repo: &Repo,
+    rebase_plan: &RebasePlan,
+    options: &ExecuteRebasePlanOptions,
+) -> eyre::Result<Option<FailedMergeInfo>> {
+    // Signing is deliberately disabled: the commits created while probing for
+    // conflicts are discarded, so there's no point in invoking GPG for them.
+    let options = ExecuteRebasePlanOptions {
+        sign_option: SignOption::Disable,
+        ..options.clone()
+    };
+    match in_memory::rebase_in_memory(
+        &effects.suppress(),
+        git_run_info,
+        repo,
+        rebase_plan,
+        &options,
+    )? {
+        in_memory::RebaseInMemoryResult::Succeeded { .. } => Ok(None),
+        in_memory::RebaseInMemoryResult::MergeFailed(failed_merge_info) => {
+            Ok(Some(failed_merge_info))
+        }
+    }
+}
+
 /// Execute the provided rebase plan. Returns the exit status (zero indicates
 /// success).
 pub fn execute_rebase_plan(
@@ -1261,7 +1661,17 @@ pub fn execute_rebase_plan(
         force_on_disk,
         resolve_merge_conflicts,
         check_out_commit_options: _,
+        reapply_empty_commits: _,
+        preserve_committer: _,
+        reset_author: _,
+        sign_option: _,
+        no_verify: _,
+        commit_trailers: _,
     } = options;
+    let in_memory_only_reason = options.get_in_memory_only_reason();
+    let can_rebase_on_disk = in_memory_only_reason.is_none();
+    let force_in_memory = *force_in_memory || !can_rebase_on_disk;
+    let force_on_disk = *force_on_disk && can_rebase_on_disk;
 
     if !force_on_disk {
         use in_memory::*;
@@ -1270,39 +1680,45 @@ pub fn execute_rebase_plan(
             "Attempting rebase in-memory..."
         )?;
 
-        let failed_merge_info = match rebase_in_memory(effects, repo, rebase_plan, options)? {
-            RebaseInMemoryResult::MergeFailed(failed_merge_info) => failed_merge_info,
+        let failed_merge_info =
+            match rebase_in_memory(effects, git_run_info, repo, rebase_plan, options)? {
+                RebaseInMemoryResult::MergeFailed(failed_merge_info) => failed_merge_info,
 
-            RebaseInMemoryResult::Succeeded {
-                rewritten_oids,
-                new_head_oid,
-            } => {
-                // Ignore the return code, as it probably indicates that the
-                // checkout failed (which might happen if the user has changes
-                // which don't merge cleanly). The user can resolve that
-                // themselves.
-                match post_rebase_in_memory(
-                    effects,
-                    git_run_info,
-                    repo,
-                    event_log_db,
-                    &rewritten_oids,
+                RebaseInMemoryResult::Succeeded {
+                    rewritten_oids,
                     new_head_oid,
-                    options,
-                )? {
-                    Ok(()) => {}
-                    Err(_exit_code) => {
-                        // FIXME: we may still want to propagate the exit code to the
-                        // caller.
+                } => {
+                    let PostRebaseInMemoryResult {
+                        post_rewrite_hook_exit_code,
+                        check_out_result,
+                    } = post_rebase_in_memory(
+                        effects,
+                        git_run_info,
+                        repo,
+                        event_log_db,
+                        &rewritten_oids,
+                        new_head_oid,
+                        options,
+                    )?;
+                    // Ignore the return code, as it probably indicates that the
+                    // checkout failed (which might happen if the user has changes
+                    // which don't merge cleanly). The user can resolve that
+                    // themselves.
+                    match check_out_result {
+                        Ok(()) => {}
+                        Err(_exit_code) => {
+                            // FIXME: we may still want to propagate the exit code to the
+                            // caller.
+                        }
                     }
-                }
 
-                writeln!(effects.get_output_stream(), "In-memory rebase succeeded.")?;
-                return Ok(ExecuteRebasePlanResult::Succeeded {
-                    rewritten_oids: Some(rewritten_oids),
-                });
-            }
-        };
+                    writeln!(effects.get_output_stream(), "In-memory rebase succeeded.")?;
+                    return Ok(ExecuteRebasePlanResult::Succeeded {
+                        rewritten_oids: Some(rewritten_oids),
+                        post_rewrite_hook_exit_code: Some(post_rewrite_hook_exit_code),
+                    });
+                }
+            };
 
         if !resolve_merge_conflicts {
             return Ok(ExecuteRebasePlanResult::DeclinedToMerge { failed_merge_info });
@@ -1310,11 +1726,17 @@ pub fn execute_rebase_plan(
 
         // The rebase has failed at this point, decide whether or not to try
         // again with an on-disk rebase.
-        if *force_in_memory {
-            writeln!(
-                effects.get_output_stream(),
-                "Aborting since an in-memory rebase was requested."
-            )?;
+        if force_in_memory {
+            match in_memory_only_reason {
+                Some(reason) => writeln!(
+                    effects.get_output_stream(),
+                    "Aborting since {reason} requires an in-memory rebase."
+                )?,
+                None => writeln!(
+                    effects.get_output_stream(),
+                    "Aborting since an in-memory rebase was requested."
+                )?,
+            }
             return Ok(ExecuteRebasePlanResult::DeclinedToMerge { failed_merge_info });
         } else {
             writeln!(
@@ -1330,6 +1752,7 @@ pub fn execute_rebase_plan(
             Ok(exit_code) if exit_code.is_success() => {
                 return Ok(ExecuteRebasePlanResult::Succeeded {
                     rewritten_oids: None,
+                    post_rewrite_hook_exit_code: None,
                 });
             }
             Ok(exit_code) => return Ok(ExecuteRebasePlanResult::Failed { exit_code }),
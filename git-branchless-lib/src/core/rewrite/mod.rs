@@ -4,18 +4,20 @@ mod evolve;
 mod execute;
 mod plan;
 pub mod rewrite_hooks;
+mod sync;
 
 use std::sync::Mutex;
 
 pub use evolve::{find_abandoned_children, find_rewrite_target};
 pub use execute::{
-    execute_rebase_plan, move_branches, ExecuteRebasePlanOptions, ExecuteRebasePlanResult,
-    FailedMergeInfo, MergeConflictRemediation,
+    check_rebase_plan_conflicts, execute_rebase_plan, move_branches, ExecuteRebasePlanOptions,
+    ExecuteRebasePlanResult, FailedMergeInfo, MergeConflictRemediation, SignOption,
 };
 pub use plan::{
-    BuildRebasePlanError, BuildRebasePlanOptions, OidOrLabel, RebaseCommand, RebasePlan,
-    RebasePlanBuilder, RebasePlanPermissions,
+    BuildRebasePlanError, BuildRebasePlanOptions, MergeBaseStrategy, OidOrLabel, RebaseCommand,
+    RebasePlan, RebasePlanBuilder, RebasePlanPermissions,
 };
+pub use sync::{find_stack_roots_needing_sync, get_stack_roots, stack_needs_sync};
 use tracing::instrument;
 
 use crate::core::task::{Resource, ResourcePool};
@@ -8,6 +8,7 @@ use chashmap::CHashMap;
 use eyre::Context;
 use itertools::Itertools;
 use rayon::{prelude::*, ThreadPool};
+use serde::{Deserialize, Serialize};
 use tracing::{instrument, warn};
 
 use crate::core::dag::{sorted_commit_set, union_all, CommitSet, Dag};
@@ -15,13 +16,51 @@ use crate::core::effects::{Effects, OperationType, WithProgress};
 use crate::core::formatting::Pluralize;
 use crate::core::rewrite::{RepoPool, RepoResource};
 use crate::core::task::ResourcePool;
-use crate::git::{Commit, NonZeroOid, PatchId, Repo};
+use crate::git::{Commit, GitRunInfo, GitRunOpts, GitRunResult, NonZeroOid, PatchId, Repo};
+
+/// Serialize the OIDs in a rebase plan via
+/// [`crate::git::SerializedNonZeroOid`], so that plans can be saved and loaded.
+mod serde_oid {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use crate::git::{NonZeroOid, SerializedNonZeroOid};
+
+    pub fn serialize<S: Serializer>(oid: &NonZeroOid, serializer: S) -> Result<S::Ok, S::Error> {
+        SerializedNonZeroOid(*oid).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NonZeroOid, D::Error> {
+        let SerializedNonZeroOid(oid) = SerializedNonZeroOid::deserialize(deserializer)?;
+        Ok(oid)
+    }
+}
+
+/// Like [`serde_oid`], but for lists of OIDs.
+mod serde_oids {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use crate::git::{NonZeroOid, SerializedNonZeroOid};
+
+    pub fn serialize<S: Serializer>(oids: &[NonZeroOid], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(oids.iter().map(|oid| SerializedNonZeroOid(*oid)))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<NonZeroOid>, D::Error> {
+        let oids = Vec::<SerializedNonZeroOid>::deserialize(deserializer)?;
+        Ok(oids
+            .into_iter()
+            .map(|SerializedNonZeroOid(oid)| oid)
+            .collect())
+    }
+}
 
 /// Represents the target for certain [`RebaseCommand`]s.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum OidOrLabel {
     /// A commit hash to check out directly.
-    Oid(NonZeroOid),
+    Oid(#[serde(with = "serde_oid")] NonZeroOid),
 
     /// A label created previously with [`RebaseCommand::CreateLabel`].
     Label(String),
@@ -37,7 +76,7 @@ impl Display for OidOrLabel {
 }
 
 /// A command that can be applied for either in-memory or on-disk rebases.
-#[derive(Debug)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum RebaseCommand {
     /// Create a label (a reference stored in `refs/rewritten/`) pointing to the
     /// current rebase head for later use.
@@ -60,6 +99,7 @@ pub enum RebaseCommand {
     Pick {
         /// The original commit, which contains the relevant metadata such as
         /// the commit message.
+        #[serde(with = "serde_oid")]
         original_commit_oid: NonZeroOid,
 
         /// The commits whose patches should be applied to the rebase head.
@@ -70,12 +110,14 @@ pub enum RebaseCommand {
         ///  - If this is multiple commits, they will all be squashed into a
         ///    single commit, reusing the metadata (message, author, timestamps,
         ///    etc) from `original_commit_oid`.
+        #[serde(with = "serde_oids")]
         commits_to_apply_oids: Vec<NonZeroOid>,
     },
 
     /// Merge two or more parent commits.
     Merge {
         /// The original merge commit to copy the commit contents from.
+        #[serde(with = "serde_oid")]
         commit_oid: NonZeroOid,
 
         /// The other commits to merge into this one. This may be a list of
@@ -92,9 +134,11 @@ pub enum RebaseCommand {
     Replace {
         /// The commit to be replaced. It should be part of another rebase command, or else we will
         /// never encounter it for replacement.
+        #[serde(with = "serde_oid")]
         commit_oid: NonZeroOid,
 
         /// The replacement commit whose metadata and tree we'll use.
+        #[serde(with = "serde_oid")]
         replacement_commit_oid: NonZeroOid,
 
         /// The new parents for the replaced commit.
@@ -114,6 +158,7 @@ pub enum RebaseCommand {
     DetectEmptyCommit {
         /// The original commit. If the new commit is empty, then the original
         /// commit will be recorded as skipped.
+        #[serde(with = "serde_oid")]
         commit_oid: NonZeroOid,
     },
 
@@ -121,6 +166,7 @@ pub enum RebaseCommand {
     /// applied upstream. Skip it and record it in the `rewritten-list`.
     SkipUpstreamAppliedCommit {
         /// The original commit, which will be recorded as skipped.
+        #[serde(with = "serde_oid")]
         commit_oid: NonZeroOid,
     },
 }
@@ -187,16 +233,53 @@ impl RebaseCommand {
 
 /// Represents a sequence of commands that can be executed to carry out a rebase
 /// operation.
-#[derive(Debug)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct RebasePlan {
     /// The first commit OID that will be checked out. This is necessary to
     /// support on-disk rebases.
+    #[serde(with = "serde_oid")]
     pub first_dest_oid: NonZeroOid,
 
     /// The commands to run.
     pub commands: Vec<RebaseCommand>,
 }
 
+impl RebasePlan {
+    /// Get the commits which this plan applies before at least one of their
+    /// original ancestors that it also applies. That is, the commits whose
+    /// order in the plan doesn't match their original topological order.
+    #[instrument]
+    pub fn find_reordered_commits(&self, dag: &Dag) -> eyre::Result<Vec<NonZeroOid>> {
+        let applied_commit_oids = self
+            .commands
+            .iter()
+            .filter_map(|command| match command {
+                RebaseCommand::Pick {
+                    original_commit_oid,
+                    commits_to_apply_oids: _,
+                } => Some(*original_commit_oid),
+                RebaseCommand::Merge {
+                    commit_oid,
+                    commits_to_merge: _,
+                } => Some(*commit_oid),
+                _ => None,
+            })
+            .collect_vec();
+
+        let mut reordered_commit_oids = Vec::new();
+        for (i, commit_oid) in applied_commit_oids.iter().enumerate() {
+            let applied_later: CommitSet = applied_commit_oids[i + 1..].iter().copied().collect();
+            let ancestors_applied_later = dag
+                .query_ancestors(CommitSet::from(*commit_oid))?
+                .intersection(&applied_later);
+            if !dag.set_is_empty(&ancestors_applied_later)? {
+                reordered_commit_oids.push(*commit_oid);
+            }
+        }
+        Ok(reordered_commit_oids)
+    }
+}
+
 /// A token representing that the rebase plan has been checked for validity.
 #[derive(Clone, Debug)]
 pub struct RebasePlanPermissions {
@@ -210,6 +293,24 @@ impl RebasePlanPermissions {
         dag: &Dag,
         build_options: BuildRebasePlanOptions,
         commits: &CommitSet,
+    ) -> eyre::Result<Result<Self, BuildRebasePlanError>> {
+        Self::verify_rewrite_set_permitting_public_commits(
+            dag,
+            build_options,
+            commits,
+            &CommitSet::empty(),
+        )
+    }
+
+    /// Construct a new `RebasePlanPermissions`. The public commits in
+    /// `permitted_public_commits` may be rewritten even if
+    /// `force_rewrite_public_commits` is not set, but rewriting any other
+    /// public commit is still an error.
+    pub fn verify_rewrite_set_permitting_public_commits(
+        dag: &Dag,
+        build_options: BuildRebasePlanOptions,
+        commits: &CommitSet,
+        permitted_public_commits: &CommitSet,
     ) -> eyre::Result<Result<Self, BuildRebasePlanError>> {
         // This isn't necessary for correctness, but helps to produce a better
         // error message which indicates the magnitude of the issue.
@@ -217,7 +318,9 @@ impl RebasePlanPermissions {
 
         let public_commits = dag.query_public_commits_slow()?;
         if !build_options.force_rewrite_public_commits {
-            let public_commits_to_move = public_commits.intersection(&commits);
+            let public_commits_to_move = public_commits
+                .intersection(&commits)
+                .difference(permitted_public_commits);
             if !dag.set_is_empty(&public_commits_to_move)? {
                 return Ok(Err(BuildRebasePlanError::MovePublicCommits {
                     public_commits_to_move,
@@ -360,8 +463,13 @@ impl<'a> ConstraintGraph<'a> {
     /// Add additional edges to the constraint graph for each descendant commit
     /// of a referred-to commit. This adds enough information to the constraint
     /// graph that it now represents the actual end-state commit graph that we
-    /// want to create, not just a list of constraints.
-    fn add_descendant_constraints(&mut self, effects: &Effects) -> eyre::Result<()> {
+    /// want to create, not just a list of constraints. The subtrees rooted at
+    /// `left_subtree_oids` are excluded.
+    fn add_descendant_constraints(
+        &mut self,
+        effects: &Effects,
+        left_subtree_oids: &[NonZeroOid],
+    ) -> eyre::Result<()> {
         let (effects, _progress) = effects.start_operation(OperationType::ConstrainCommits);
         let _effects = effects;
 
@@ -375,6 +483,10 @@ impl<'a> ConstraintGraph<'a> {
                 .collect();
             let descendants = self.dag.query_descendants(commits_to_move.clone())?;
             let descendants = descendants.difference(&commits_to_move);
+            let left_commits = self
+                .dag
+                .query_descendants(left_subtree_oids.iter().copied().collect())?;
+            let descendants = descendants.difference(&left_commits);
             let descendants = self.dag.filter_visible_commits(descendants)?;
             let descendant_oids = self.dag.commit_set_to_vec(&descendants)?;
             for descendant_oid in descendant_oids {
@@ -563,6 +675,10 @@ pub struct RebasePlanBuilder<'a> {
     /// The constraints specified by the caller.
     initial_constraints: Vec<Constraint>,
 
+    /// The roots of subtrees which shouldn't be moved along with their
+    /// ancestors.
+    left_subtree_oids: Vec<NonZeroOid>,
+
     /// Mapping of commits that should be replaced to the commits that they should be replaced
     /// with.
     replacement_commits: HashMap<NonZeroOid, NonZeroOid>,
@@ -614,6 +730,23 @@ pub struct BuildRebasePlanOptions {
     /// patch IDs in the to-be-rebased commits. Commits which have patch IDs
     /// which are already upstream are skipped.
     pub detect_duplicate_commits_via_patch_id: bool,
+
+    /// How to compute the merge-bases used to find the upstream commits when
+    /// detecting duplicate commits.
+    pub merge_base_strategy: MergeBaseStrategy,
+}
+
+/// How to compute merge-bases when building a rebase plan.
+#[derive(Clone, Debug, Default)]
+pub enum MergeBaseStrategy {
+    /// Query the commit graph maintained by `git-branchless`. This is fast
+    /// even for large histories.
+    #[default]
+    CommitGraph,
+
+    /// Run `git merge-base --all` for each merge-base. This is slower, but
+    /// can be used to cross-check the results of the commit graph.
+    Git(GitRunInfo),
 }
 
 /// An error caused when attempting to build a rebase plan.
@@ -729,6 +862,7 @@ impl<'a> RebasePlanBuilder<'a> {
             dag,
             permissions,
             initial_constraints: Default::default(),
+            left_subtree_oids: Default::default(),
             replacement_commits: Default::default(),
             touched_paths_cache: Default::default(),
         }
@@ -1062,6 +1196,13 @@ impl<'a> RebasePlanBuilder<'a> {
         Ok(())
     }
 
+    /// Leave the subtree at `source_oid` on top of the original version of
+    /// its parent, rather than moving it along with its moved ancestors.
+    pub fn leave_subtree(&mut self, source_oid: NonZeroOid) -> eyre::Result<()> {
+        self.left_subtree_oids.push(source_oid);
+        Ok(())
+    }
+
     /// Generate a sequence of rebase steps that cause the commit at
     /// `source_oid` to be squashed into `dest_oid`, and for the descendants
     /// of `source_oid` to be rebased on top of its parent.
@@ -1117,6 +1258,7 @@ impl<'a> RebasePlanBuilder<'a> {
             dump_rebase_constraints,
             dump_rebase_plan,
             detect_duplicate_commits_via_patch_id,
+            merge_base_strategy: _,
         } = &self.permissions.build_options;
         if *dump_rebase_constraints {
             // For test: don't print to `effects.get_output_stream()`, as it will
@@ -1126,7 +1268,9 @@ impl<'a> RebasePlanBuilder<'a> {
                 state.constraints.get_constraints_sorted_for_debug(),
             );
         }
-        state.constraints.add_descendant_constraints(&effects)?;
+        state
+            .constraints
+            .add_descendant_constraints(&effects, &self.left_subtree_oids)?;
         if *dump_rebase_constraints {
             // For test: don't print to `effects.get_output_stream()`, as it will
             // be suppressed.
@@ -1279,10 +1423,7 @@ impl<'a> RebasePlanBuilder<'a> {
     ) -> eyre::Result<HashSet<PatchId>> {
         let merge_base_oids: Vec<CommitSet> = dest_oids
             .iter()
-            .map(|dest_oid| {
-                let commit_set: CommitSet = [current_oid, *dest_oid].into_iter().collect();
-                self.dag.query_gca_all(commit_set)
-            })
+            .map(|dest_oid| self.find_merge_bases(repo, current_oid, *dest_oid))
             .try_collect()?;
         let merge_base_oids = union_all(&merge_base_oids);
 
@@ -1338,6 +1479,60 @@ impl<'a> RebasePlanBuilder<'a> {
         Ok(result)
     }
 
+    /// Find all of the merge-bases of the provided commits, using the
+    /// configured [`MergeBaseStrategy`].
+    fn find_merge_bases(
+        &self,
+        repo: &Repo,
+        lhs_oid: NonZeroOid,
+        rhs_oid: NonZeroOid,
+    ) -> eyre::Result<CommitSet> {
+        match &self.permissions.build_options.merge_base_strategy {
+            MergeBaseStrategy::CommitGraph => {
+                let commit_set: CommitSet = [lhs_oid, rhs_oid].into_iter().collect();
+                Ok(self.dag.query_gca_all(commit_set)?)
+            }
+
+            MergeBaseStrategy::Git(git_run_info) => {
+                let GitRunResult {
+                    exit_code,
+                    stdout,
+                    stderr,
+                } = git_run_info.run_silent(
+                    repo,
+                    None,
+                    &[
+                        "merge-base",
+                        "--all",
+                        &lhs_oid.to_string(),
+                        &rhs_oid.to_string(),
+                    ],
+                    GitRunOpts {
+                        treat_git_failure_as_error: false,
+                        ..Default::default()
+                    },
+                )?;
+                // `git merge-base` exits with code 1 if there's no merge-base.
+                if exit_code.0 == 1 && stdout.is_empty() {
+                    return Ok(CommitSet::empty());
+                }
+                if !exit_code.is_success() {
+                    eyre::bail!(
+                        "Could not calculate merge-base of {lhs_oid} and {rhs_oid} (exit code {}): {}",
+                        exit_code.0,
+                        String::from_utf8_lossy(&stderr).trim(),
+                    );
+                }
+                let stdout =
+                    String::from_utf8(stdout).wrap_err("Decoding stdout from git merge-base")?;
+                stdout
+                    .lines()
+                    .map(|line| line.parse::<NonZeroOid>())
+                    .collect()
+            }
+        }
+    }
+
     fn filter_path_to_merge_base_commits(
         &self,
         effects: &Effects,
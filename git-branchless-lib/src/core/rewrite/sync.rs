@@ -0,0 +1,76 @@
+//! Queries shared by `git sync` and the revsets which describe what it would
+//! do, so that the two can't disagree.
+
+use tracing::instrument;
+
+use crate::core::dag::{union_all, CommitSet, Dag};
+use crate::git::{Commit, NonZeroOid, Repo};
+
+/// Get the roots of the stacks of draft commits containing the provided
+/// commits, or the roots of all stacks if no commits are provided. Commits in
+/// `trunk_commits` are never considered part of a stack.
+///
+/// If `include_public_roots` is set, then a stack which is based on commits
+/// in `trunk_commits` which aren't on the main branch also includes those
+/// commits, so that the whole branch is moved along with the stack. The
+/// caller is responsible for making sure that rewriting them is permitted.
+#[instrument]
+pub fn get_stack_roots(
+    dag: &Dag,
+    commit_sets: Vec<CommitSet>,
+    trunk_commits: &CommitSet,
+    include_public_roots: bool,
+) -> eyre::Result<CommitSet> {
+    let draft_commits = dag.query_draft_commits()?.difference(trunk_commits);
+    let draft_commits = if include_public_roots {
+        let draft_roots = dag.query_roots(draft_commits.clone())?;
+        let branch_commits = dag
+            .query_ancestors(dag.query_parents(draft_roots)?)?
+            .intersection(trunk_commits)
+            .difference(dag.query_public_commits_slow()?);
+        draft_commits.union(&branch_commits)
+    } else {
+        draft_commits
+    };
+
+    // FIXME: if two draft roots are ancestors of a single commit (due to a
+    // merge commit), then the entire unit should be treated as one stack and
+    // moved together, rather than attempting two separate rebases. Currently,
+    // this is only done when `--rebase-merges` is passed; see
+    // `group_stack_roots`.
+    let draft_roots = dag.query_roots(draft_commits)?;
+
+    if commit_sets.is_empty() {
+        return Ok(draft_roots);
+    }
+
+    let stack_ancestors = dag.query_range(draft_roots, union_all(&commit_sets))?;
+    dag.query_roots(stack_ancestors)
+        .map_err(|err| eyre::eyre!("Could not query DAG for stack roots: {err}"))
+}
+
+/// Whether the stack rooted at the provided commit needs to be moved onto
+/// `base_oid`, i.e. whether the root isn't already a child of it.
+pub fn stack_needs_sync(root_commit: &Commit, base_oid: NonZeroOid) -> bool {
+    let only_parent_oid = root_commit.get_only_parent_oid();
+    only_parent_oid != Some(base_oid)
+}
+
+/// Get the roots of the stacks which `git sync` with no arguments would move
+/// onto the main branch.
+#[instrument]
+pub fn find_stack_roots_needing_sync(repo: &Repo, dag: &Dag) -> eyre::Result<CommitSet> {
+    let main_branch_oid = match dag.commit_set_to_vec(&dag.main_branch_commit)?.as_slice() {
+        [main_branch_oid] => *main_branch_oid,
+        _ => return Ok(CommitSet::empty()),
+    };
+    let root_oids = get_stack_roots(dag, Vec::new(), &CommitSet::empty(), false)?;
+    let mut result = Vec::new();
+    for root_oid in dag.commit_set_to_vec(&root_oids)? {
+        let root_commit = repo.find_commit_or_fail(root_oid)?;
+        if stack_needs_sync(&root_commit, main_branch_oid) {
+            result.push(root_oid);
+        }
+    }
+    Ok(result.into_iter().collect())
+}
@@ -159,6 +159,7 @@ pub fn update_index(
             GitRunOpts {
                 treat_git_failure_as_error: true,
                 stdin: Some(stdin),
+                working_directory: None,
             },
         )
         .wrap_err("Updating index")?;
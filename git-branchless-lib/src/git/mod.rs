@@ -24,9 +24,12 @@ pub use reference::{
 pub use repo::{
     message_prettify, AmendFastOptions, CherryPickFastOptions, CreateCommitFastError,
     Error as RepoError, GitErrorCode, GitVersion, PatchId, Repo, ResolvedReferenceInfo,
-    Result as RepoResult, Time,
+    Result as RepoResult, Signature, Time,
+};
+pub use run::{
+    GitRunInfo, GitRunOpts, GitRunResult, HookInvocation, BRANCHLESS_COMMAND_ENV_VAR,
+    BRANCHLESS_TRACE_GIT_ENV_VAR,
 };
-pub use run::{GitRunInfo, GitRunOpts, GitRunResult};
 pub use snapshot::{WorkingCopyChangesType, WorkingCopySnapshot};
 pub use status::{FileMode, FileStatus, StatusEntry};
 pub use test::{
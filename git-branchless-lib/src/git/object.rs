@@ -131,6 +131,14 @@ impl<'repo> Commit<'repo> {
         }
     }
 
+    /// Whether this commit carries a cryptographic signature (such as a GPG
+    /// or SSH signature). Rewriting the commit invalidates the signature.
+    #[instrument]
+    pub fn is_signed(&self) -> bool {
+        self.inner.header_field_bytes("gpgsig").is_ok()
+            || self.inner.header_field_bytes("gpgsig-sha256").is_ok()
+    }
+
     /// Get the OID of the `Tree` object associated with this commit.
     #[instrument]
     pub fn get_tree_oid(&self) -> MaybeZeroOid {
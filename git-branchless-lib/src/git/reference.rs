@@ -122,6 +122,20 @@ impl<'repo> Reference<'repo> {
         }
     }
 
+    /// Determine whether this reference points to an annotated tag object,
+    /// rather than directly to a commit.
+    #[instrument]
+    pub fn is_annotated_tag(&self) -> Result<bool> {
+        let target_oid = match self.inner.target() {
+            Some(target_oid) => target_oid,
+            None => return Ok(false),
+        };
+        match self.peel_to_commit()? {
+            Some(commit) => Ok(commit.inner.id() != target_oid),
+            None => Ok(false),
+        }
+    }
+
     /// Delete the reference.
     #[instrument]
     pub fn delete(&mut self) -> Result<()> {
@@ -306,6 +320,44 @@ impl<'repo> Branch<'repo> {
         Ok(target_oid)
     }
 
+    /// If this branch tracks a remote ("upstream") branch, return the name of
+    /// the remote which hosts that branch. For example, if the upstream branch
+    /// is `origin/main`, this will return `origin`. Returns `None` if the
+    /// upstream branch is a local branch, or if the remote can't be determined
+    /// unambiguously from the configured fetch refspecs.
+    #[instrument]
+    pub fn get_upstream_remote_name(&self) -> eyre::Result<Option<String>> {
+        let upstream_branch = match self.get_upstream_branch()? {
+            Some(upstream_branch) => upstream_branch,
+            None => return Ok(None),
+        };
+        upstream_branch.get_remote_name()
+    }
+
+    /// If this is a remote-tracking branch, return the name of the remote
+    /// which hosts it. For example, for `origin/main`, this will return
+    /// `origin`. Returns `None` if this is a local branch, or if the remote
+    /// can't be determined unambiguously from the configured fetch refspecs.
+    #[instrument]
+    pub fn get_remote_name(&self) -> eyre::Result<Option<String>> {
+        if !self.inner.get().is_remote() {
+            return Ok(None);
+        }
+        let reference_name = self.get_reference_name()?;
+        match self.repo.inner.branch_remote_name(reference_name.as_str()) {
+            Ok(remote_name) => Ok(remote_name.as_str().map(ToOwned::to_owned)),
+            Err(err)
+                if matches!(
+                    err.code(),
+                    git2::ErrorCode::NotFound | git2::ErrorCode::Ambiguous
+                ) =>
+            {
+                Ok(None)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
     /// If this branch tracks a remote ("upstream") branch, return the name of
     /// that branch without the leading remote name. For example, if the
     /// upstream branch is `origin/main`, this will return `main`. (Usually,
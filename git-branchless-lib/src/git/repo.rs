@@ -27,6 +27,7 @@ use itertools::Itertools;
 use thiserror::Error;
 use tracing::{instrument, warn};
 
+use crate::core::config::env_vars;
 use crate::core::effects::{Effects, OperationType};
 use crate::core::eventlog::EventTransactionId;
 use crate::core::formatting::Glyphs;
@@ -203,6 +204,13 @@ pub enum Error {
     #[error("could not create reference: {0}")]
     CreateReference(#[source] git2::Error),
 
+    #[error("could not fast-forward '{}' from {old_oid} to {new_oid}, since it is not a descendant", name.as_str())]
+    NotFastForward {
+        name: ReferenceName,
+        old_oid: NonZeroOid,
+        new_oid: NonZeroOid,
+    },
+
     #[error("could not calculate changed paths: {0}")]
     GetChangedPaths(#[source] super::tree::Error),
 
@@ -221,12 +229,21 @@ pub enum Error {
     #[error("could not get remote names: {0}")]
     GetRemoteNames(#[source] git2::Error),
 
+    #[error("could not find remote with name '{name}': {source}")]
+    FindRemote { source: git2::Error, name: String },
+
+    #[error("could not fetch from remote '{name}': {source}")]
+    FetchRemote { source: git2::Error, name: String },
+
     #[error("HEAD is unborn (try making a commit?)")]
     UnbornHead,
 
     #[error("could not create commit signature: {0}")]
     CreateSignature(#[source] git2::Error),
 
+    #[error("could not read signature for current user: {0}")]
+    ReadUserSignature(#[source] git2::Error),
+
     #[error("could not execute git: {0}")]
     ExecGit(#[source] eyre::Error),
 
@@ -353,7 +370,7 @@ impl ResolvedReferenceInfo {
 }
 
 /// The parsed version of Git.
-#[derive(Debug, PartialEq, PartialOrd, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, Eq)]
 pub struct GitVersion(pub isize, pub isize, pub isize);
 
 impl FromStr for GitVersion {
@@ -386,6 +403,13 @@ impl FromStr for GitVersion {
     }
 }
 
+impl std::fmt::Display for GitVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let GitVersion(major, minor, patch) = self;
+        write!(f, "{major}.{minor}.{patch}")
+    }
+}
+
 /// Options for `Repo::cherry_pick_fast`.
 #[derive(Clone, Debug)]
 pub struct CherryPickFastOptions {
@@ -463,6 +487,7 @@ impl<'repo> AmendFastOptions<'repo> {
 /// Wrapper around `git2::Repository`.
 pub struct Repo {
     pub(super) inner: git2::Repository,
+    main_branch_override: Option<String>,
 }
 
 impl std::fmt::Debug for Repo {
@@ -487,14 +512,43 @@ impl Repo {
             }
             Err(err) => return Err(Error::OpenRepo(err)),
         };
-        Ok(Repo { inner: repo })
+        Ok(Repo {
+            inner: repo,
+            main_branch_override: None,
+        })
+    }
+
+    /// Get the Git repository at the given `.git` directory, without
+    /// searching parent directories. If `work_tree` is provided, it overrides
+    /// the working copy path for the repository.
+    #[instrument]
+    pub fn from_git_dir(git_dir: &Path, work_tree: Option<&Path>) -> Result<Self> {
+        let repo = git2::Repository::open(git_dir).map_err(Error::OpenRepo)?;
+        if let Some(work_tree) = work_tree {
+            repo.set_workdir(work_tree, false)
+                .map_err(Error::OpenRepo)?;
+        }
+        Ok(Repo {
+            inner: repo,
+            main_branch_override: None,
+        })
     }
 
     /// Get the Git repository associated with the current directory.
+    ///
+    /// Like Git itself, this honors the `GIT_DIR` and `GIT_WORK_TREE`
+    /// environment variables, if set.
     #[instrument]
     pub fn from_current_dir() -> Result<Self> {
         let path = std::env::current_dir().map_err(Error::Io)?;
-        Repo::from_dir(&path)
+        match std::env::var_os(env_vars::GIT_DIR) {
+            None => Repo::from_dir(&path),
+            Some(git_dir) => {
+                let work_tree =
+                    std::env::var_os(env_vars::GIT_WORK_TREE).map(|work_tree| path.join(work_tree));
+                Repo::from_git_dir(&path.join(git_dir), work_tree.as_deref())
+            }
+        }
     }
 
     /// Open a new copy of the repository.
@@ -502,7 +556,22 @@ impl Repo {
     pub fn try_clone(&self) -> Result<Self> {
         let path = self.get_path();
         let repo = git2::Repository::open(path).map_err(Error::OpenRepo)?;
-        Ok(Repo { inner: repo })
+        Ok(Repo {
+            inner: repo,
+            main_branch_override: self.main_branch_override.clone(),
+        })
+    }
+
+    /// Use the provided branch as the main branch for this handle to the
+    /// repository (and copies of it made with [`Repo::try_clone`]), rather
+    /// than the configured one.
+    pub fn set_main_branch_override(&mut self, branch_name: String) {
+        self.main_branch_override = Some(branch_name);
+    }
+
+    /// Get the branch set with [`Repo::set_main_branch_override`], if any.
+    pub fn get_main_branch_override(&self) -> Option<&str> {
+        self.main_branch_override.as_deref()
     }
 
     /// Get the path to the `.git` directory for the repository.
@@ -606,6 +675,14 @@ impl Repo {
         Ok(Config::from(config))
     }
 
+    /// Get the signature of the current user, as configured with `user.name`
+    /// and `user.email`, timestamped with the current time.
+    #[instrument]
+    pub fn get_user_signature(&self) -> Result<Signature<'static>> {
+        let signature = self.inner.signature().map_err(Error::ReadUserSignature)?;
+        Ok(Signature { inner: signature })
+    }
+
     /// Get the directory where all repo-specific git-branchless state is stored.
     pub fn get_branchless_dir(&self) -> Result<PathBuf> {
         let maybe_worktree_parent_repo = self.open_worktree_parent_repo()?;
@@ -904,6 +981,33 @@ impl Repo {
         Ok(changed_paths)
     }
 
+    /// Determine whether the given commit changes any submodules, either by
+    /// changing `.gitmodules` or by adding, removing, or updating a submodule
+    /// commit pointer.
+    #[instrument]
+    pub fn commit_touches_submodules(&self, commit: &Commit) -> Result<bool> {
+        let touched_paths = self.get_paths_touched_by_commit(commit)?;
+        if touched_paths.contains(Path::new(".gitmodules")) {
+            return Ok(true);
+        }
+
+        let parent_commits = commit.get_parents();
+        let mut trees = vec![commit.get_tree()?];
+        for parent_commit in parent_commits.iter() {
+            trees.push(parent_commit.get_tree()?);
+        }
+        for path in touched_paths {
+            for tree in trees.iter() {
+                if let Some(entry) = tree.get_path(&path).map_err(Error::ReadTreeEntry)? {
+                    if entry.get_filemode() == FileMode::Commit {
+                        return Ok(true);
+                    }
+                }
+            }
+        }
+        Ok(false)
+    }
+
     /// Get the patch ID for this commit.
     #[instrument]
     pub fn get_patch_id(&self, effects: &Effects, commit: &Commit) -> Result<Option<PatchId>> {
@@ -1075,6 +1179,40 @@ impl Repo {
         Ok(Reference { inner: reference })
     }
 
+    /// Update the reference to point to `oid`, which must be a descendant of
+    /// the commit that it currently points to. The reference is created if it
+    /// doesn't exist. Fails without updating the reference if the update
+    /// wouldn't be a fast-forward, or if the reference is concurrently moved
+    /// by another process.
+    #[instrument]
+    pub fn fast_forward_ref(
+        &self,
+        name: &ReferenceName,
+        oid: NonZeroOid,
+        log_message: &str,
+    ) -> Result<Reference> {
+        let old_oid = match self.find_reference(name)? {
+            Some(reference) => reference.peel_to_commit()?.map(|commit| commit.get_oid()),
+            None => None,
+        };
+        let old_oid = match old_oid {
+            Some(old_oid) => old_oid,
+            None => return self.create_reference(name, oid, false, log_message),
+        };
+        if old_oid != oid && self.find_merge_base(old_oid, oid)? != Some(old_oid) {
+            return Err(Error::NotFastForward {
+                name: name.clone(),
+                old_oid,
+                new_oid: oid,
+            });
+        }
+        let reference = self
+            .inner
+            .reference_matching(name.as_str(), oid.inner, true, old_oid.inner, log_message)
+            .map_err(Error::CreateReference)?;
+        Ok(Reference { inner: reference })
+    }
+
     /// Get a list of all remote names.
     #[instrument]
     pub fn get_all_remote_names(&self) -> Result<Vec<String>> {
@@ -1093,6 +1231,146 @@ impl Repo {
             .collect())
     }
 
+    /// Determine whether the given remote can be fetched with
+    /// [`Repo::fetch_remote`]. Remotes which use a transport that libgit2
+    /// wasn't built with (or a remote helper), or which have fetch refspecs
+    /// other than plain mappings of branches to remote-tracking branches,
+    /// should be fetched with `git fetch` instead.
+    #[instrument]
+    pub fn can_fetch_remote_natively(&self, remote_name: &str) -> Result<bool> {
+        let remote = self
+            .inner
+            .find_remote(remote_name)
+            .map_err(|err| Error::FindRemote {
+                source: err,
+                name: remote_name.to_owned(),
+            })?;
+        let url = match remote.url() {
+            Some(url) => url,
+            None => return Ok(false),
+        };
+
+        let config = self.inner.config().map_err(Error::ReadConfig)?;
+        if config
+            .get_entry(&format!("remote.{remote_name}.vcs"))
+            .is_ok()
+        {
+            return Ok(false);
+        }
+
+        let version = git2::Version::get();
+        let is_transport_supported = match url.split_once("://") {
+            Some(("file" | "git" | "http", _)) => true,
+            Some(("https", _)) => version.https(),
+            Some(("ssh", _)) => version.ssh(),
+            Some(_) => false,
+            // Remote helpers are specified as `<transport>::<address>`.
+            None if url.contains("::") => false,
+            None if Path::new(url).is_absolute() || !url.contains(':') => true,
+            // Otherwise, it's an scp-like SSH address such as `host:path`.
+            None => version.ssh(),
+        };
+        if !is_transport_supported {
+            return Ok(false);
+        }
+
+        let refspecs = remote.refspecs().collect_vec();
+        let are_refspecs_supported = !refspecs.is_empty()
+            && refspecs.iter().all(|refspec| {
+                refspec.direction() == git2::Direction::Fetch
+                    && !refspec.str().unwrap_or_default().starts_with('^')
+                    && refspec
+                        .src()
+                        .map_or(false, |src| src.starts_with("refs/heads/"))
+                    && refspec
+                        .dst()
+                        .map_or(false, |dst| dst.starts_with("refs/remotes/"))
+            });
+        Ok(are_refspecs_supported)
+    }
+
+    /// Fetch from the given remote using its configured fetch refspecs, in
+    /// process via libgit2 rather than by running `git fetch`. Credentials are
+    /// obtained from the SSH agent or the configured Git credential helpers.
+    /// If `fetch_tags` is `false`, no tags are fetched (like `git fetch
+    /// --no-tags`); otherwise, the remote's `tagOpt` setting is respected.
+    /// `on_progress` is called with the number of objects received so far and
+    /// the total number of objects to receive.
+    ///
+    /// Returns the references updated by the fetch, along with their old and
+    /// new OIDs. libgit2 doesn't invoke the `reference-transaction` hook, so
+    /// it's up to the caller to record these updates in the event log.
+    #[instrument(skip(on_progress))]
+    pub fn fetch_remote(
+        &self,
+        remote_name: &str,
+        fetch_tags: bool,
+        mut on_progress: impl FnMut(usize, usize),
+    ) -> Result<Vec<(ReferenceName, MaybeZeroOid, MaybeZeroOid)>> {
+        let mut remote = self
+            .inner
+            .find_remote(remote_name)
+            .map_err(|err| Error::FindRemote {
+                source: err,
+                name: remote_name.to_owned(),
+            })?;
+        let config = self.inner.config().map_err(Error::ReadConfig)?;
+
+        let mut ref_updates = Vec::new();
+        {
+            let mut num_credential_attempts = 0;
+            let mut callbacks = git2::RemoteCallbacks::new();
+            callbacks.credentials(|url, username_from_url, allowed_types| {
+                // libgit2 asks again if the provided credentials were rejected,
+                // so give up at some point rather than looping forever.
+                num_credential_attempts += 1;
+                if num_credential_attempts > 3 {
+                    return Err(git2::Error::from_str("authentication failed"));
+                }
+
+                let username = username_from_url.unwrap_or("git");
+                if allowed_types.contains(git2::CredentialType::SSH_KEY) {
+                    git2::Cred::ssh_key_from_agent(username)
+                } else if allowed_types.contains(git2::CredentialType::USER_PASS_PLAINTEXT) {
+                    git2::Cred::credential_helper(&config, url, username_from_url)
+                } else if allowed_types.contains(git2::CredentialType::USERNAME) {
+                    git2::Cred::username(username)
+                } else {
+                    git2::Cred::default()
+                }
+            });
+            callbacks.transfer_progress(|progress| {
+                on_progress(progress.received_objects(), progress.total_objects());
+                true
+            });
+            callbacks.update_tips(|reference_name, old_oid, new_oid| {
+                ref_updates.push((
+                    ReferenceName::from(reference_name),
+                    MaybeZeroOid::from(old_oid),
+                    MaybeZeroOid::from(new_oid),
+                ));
+                true
+            });
+
+            let mut proxy_options = git2::ProxyOptions::new();
+            proxy_options.auto();
+            let mut fetch_options = git2::FetchOptions::new();
+            fetch_options
+                .remote_callbacks(callbacks)
+                .proxy_options(proxy_options);
+            if !fetch_tags {
+                fetch_options.download_tags(git2::AutotagOption::None);
+            }
+            remote
+                .fetch::<&str>(&[], Some(&mut fetch_options), None)
+                .map_err(|err| Error::FetchRemote {
+                    source: err,
+                    name: remote_name.to_owned(),
+                })?;
+        }
+        Ok(ref_updates)
+    }
+
     /// Look up a reference with the given name. Returns `None` if not found.
     #[instrument]
     pub fn find_reference(&self, name: &ReferenceName) -> Result<Option<Reference>> {
@@ -1673,6 +1951,21 @@ impl<'repo> Signature<'repo> {
         Ok(Signature { inner: signature })
     }
 
+    /// Replace the name and email of this signature with those of `identity`,
+    /// keeping this signature's timestamp.
+    #[instrument]
+    pub fn update_identity(self, identity: &Signature) -> Result<Signature<'repo>> {
+        let name = identity.get_name().ok_or(Error::DecodeUtf8 {
+            item: "signature name",
+        })?;
+        let email = identity.get_email().ok_or(Error::DecodeUtf8 {
+            item: "signature email",
+        })?;
+        let signature = git2::Signature::new(name, email, &self.inner.when())
+            .map_err(Error::CreateSignature)?;
+        Ok(Signature { inner: signature })
+    }
+
     /// Get the time when this signature was applied.
     pub fn get_time(&self) -> Time {
         Time {
@@ -1729,4 +2022,17 @@ impl Time {
     pub fn to_naive_date_time(&self) -> Option<NaiveDateTime> {
         NaiveDateTime::from_timestamp_opt(self.inner.seconds(), 0)
     }
+
+    /// Format this time in Git's internal date format (`@<seconds> <offset>`),
+    /// as accepted by environment variables such as `GIT_COMMITTER_DATE`.
+    pub fn to_git_date(&self) -> String {
+        let offset_minutes = self.inner.offset_minutes().abs();
+        format!(
+            "@{} {}{:02}{:02}",
+            self.inner.seconds(),
+            self.inner.sign(),
+            offset_minutes / 60,
+            offset_minutes % 60
+        )
+    }
 }
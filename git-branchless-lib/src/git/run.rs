@@ -2,22 +2,36 @@ use std::collections::HashMap;
 use std::ffi::{OsStr, OsString};
 use std::fmt::Write;
 use std::io::{BufRead, BufReader, Read, Write as WriteIo};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::{Command, ExitStatus, Stdio};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
 use bstr::BString;
-use eyre::{eyre, Context};
+use eyre::Context;
 use itertools::Itertools;
+use once_cell::sync::Lazy;
 use tracing::{instrument, warn};
 
-use crate::core::config::get_main_worktree_hooks_dir;
+use crate::core::config::{env_vars, get_main_worktree_hooks_dir};
 use crate::core::effects::{Effects, OperationType};
 use crate::core::eventlog::{EventTransactionId, BRANCHLESS_TRANSACTION_ID_ENV_VAR};
-use crate::git::repo::Repo;
+use crate::git::repo::{GitVersion, Repo};
 use crate::util::{get_sh, ExitCode, EyreExitOr};
 
+/// The name of the environment variable holding the name of the
+/// `git-branchless` command being run (such as `sync`), so that hooks can tell
+/// which command triggered them.
+pub const BRANCHLESS_COMMAND_ENV_VAR: &str = "BRANCHLESS_COMMAND";
+
+/// The name of the environment variable which, if set in
+/// [`GitRunInfo::env`], makes every Git subprocess be traced: its arguments,
+/// working directory, Git-related environment variables, exit code, and
+/// duration are logged. If it's set to `-`, the trace is written to stderr;
+/// otherwise, it's appended to the file at the path that it's set to.
+pub const BRANCHLESS_TRACE_GIT_ENV_VAR: &str = "BRANCHLESS_TRACE_GIT";
+
 /// Path to the `git` executable on disk to be executed.
 #[derive(Clone)]
 pub struct GitRunInfo {
@@ -49,6 +63,12 @@ pub struct GitRunOpts {
     /// A vector of bytes to write to the Git process's stdin. If `None`,
     /// nothing is written to stdin.
     pub stdin: Option<Vec<u8>>,
+
+    /// The directory to run Git in (such as a submodule or a subdirectory of
+    /// the working copy), used both as the process's working directory and as
+    /// the argument to `-C`. If `None`, Git is run at the root of the
+    /// repository's working copy.
+    pub working_directory: Option<PathBuf>,
 }
 
 impl Default for GitRunOpts {
@@ -56,6 +76,7 @@ impl Default for GitRunOpts {
         Self {
             treat_git_failure_as_error: true,
             stdin: None,
+            working_directory: None,
         }
     }
 }
@@ -86,6 +107,95 @@ impl std::fmt::Debug for GitRunResult {
 }
 
 impl GitRunInfo {
+    /// Constructor.
+    ///
+    /// If `GIT_DIR` or `GIT_WORK_TREE` are set to relative paths in `env`,
+    /// they're made absolute with respect to `working_directory`. Otherwise,
+    /// they would refer to the wrong location when Git is run from a different
+    /// directory (such as with `git -C`).
+    pub fn new(
+        path_to_git: PathBuf,
+        working_directory: PathBuf,
+        mut env: HashMap<OsString, OsString>,
+    ) -> Self {
+        for key in [env_vars::GIT_DIR, env_vars::GIT_WORK_TREE] {
+            if let Some(value) = env.get_mut(OsStr::new(key)) {
+                let path = Path::new(value);
+                if path.is_relative() {
+                    *value = working_directory.join(path).into_os_string();
+                }
+            }
+        }
+        Self {
+            path_to_git,
+            working_directory,
+            env,
+        }
+    }
+
+    /// If tracing is enabled with [`BRANCHLESS_TRACE_GIT_ENV_VAR`], log an
+    /// invocation of Git which has finished. The values of environment
+    /// variables which may contain credentials are redacted.
+    fn trace_invocation(
+        &self,
+        working_directory: &Path,
+        args: &[&OsStr],
+        event_tx_id: Option<EventTransactionId>,
+        exit_code: ExitCode,
+        elapsed: Duration,
+    ) -> eyre::Result<()> {
+        let trace_dest = match self.env.get(OsStr::new(BRANCHLESS_TRACE_GIT_ENV_VAR)) {
+            Some(trace_dest) => trace_dest,
+            None => return Ok(()),
+        };
+
+        let args = args.iter().map(|arg| arg.to_string_lossy()).join(" ");
+        let env = self
+            .env
+            .iter()
+            .map(|(key, value)| (key.to_string_lossy(), value.to_string_lossy()))
+            .filter(|(key, _value)| key.starts_with("GIT_") || key.starts_with("BRANCHLESS_"))
+            .map(|(key, value)| {
+                if is_sensitive_env_var(&key) {
+                    (key, "<redacted>".into())
+                } else {
+                    (key, value)
+                }
+            })
+            .chain(event_tx_id.map(|event_tx_id| {
+                (
+                    BRANCHLESS_TRANSACTION_ID_ENV_VAR.into(),
+                    event_tx_id.to_string().into(),
+                )
+            }))
+            .sorted()
+            .map(|(key, value)| format!("{key}={value}"))
+            .join(" ");
+        let line = format!(
+            "branchless: trace: {} {args} (cwd: {}) (env: {env}) exited with code {} in {:.3}s\n",
+            self.path_to_git.to_string_lossy(),
+            working_directory.to_string_lossy(),
+            exit_code.0,
+            elapsed.as_secs_f64(),
+        );
+
+        if trace_dest == "-" {
+            std::io::stderr()
+                .write_all(line.as_bytes())
+                .wrap_err("Writing Git trace to stderr")?;
+        } else {
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(trace_dest)
+                .and_then(|mut file| file.write_all(line.as_bytes()))
+                .wrap_err_with(|| format!("Writing Git trace to file: {trace_dest:?}"))?;
+        }
+        Ok(())
+    }
+
+    /// Copy the lines of `stream` to `output` as they're produced. If
+    /// `capture` is set, the lines are also returned once the stream ends.
     fn spawn_writer_thread<
         InputStream: Read + Send + 'static,
         OutputStream: Write + Send + 'static,
@@ -93,17 +203,24 @@ impl GitRunInfo {
         &self,
         stream: Option<InputStream>,
         mut output: OutputStream,
-    ) -> JoinHandle<()> {
+        capture: bool,
+    ) -> JoinHandle<Vec<u8>> {
         thread::spawn(move || {
+            let mut captured = Vec::new();
             let stream = match stream {
                 Some(stream) => stream,
-                None => return,
+                None => return captured,
             };
             let reader = BufReader::new(stream);
             for line in reader.lines() {
                 let line = line.expect("Reading line from subprocess");
                 writeln!(output, "{line}").expect("Writing line from subprocess");
+                if capture {
+                    captured.extend_from_slice(line.as_bytes());
+                    captured.push(b'\n');
+                }
             }
+            captured
         })
     }
 
@@ -112,7 +229,8 @@ impl GitRunInfo {
         effects: &Effects,
         event_tx_id: Option<EventTransactionId>,
         args: &[&OsStr],
-    ) -> EyreExitOr<()> {
+        capture_output: bool,
+    ) -> eyre::Result<GitRunResult> {
         let GitRunInfo {
             path_to_git,
             working_directory,
@@ -145,18 +263,21 @@ impl GitRunInfo {
         command.stdout(Stdio::piped());
         command.stderr(Stdio::piped());
 
+        let start_instant = Instant::now();
         let mut child = command.spawn().wrap_err("Spawning Git subprocess")?;
 
         let stdout = child.stdout.take();
-        let stdout_thread = self.spawn_writer_thread(stdout, effects.get_output_stream());
+        let stdout_thread =
+            self.spawn_writer_thread(stdout, effects.get_output_stream(), capture_output);
         let stderr = child.stderr.take();
-        let stderr_thread = self.spawn_writer_thread(stderr, effects.get_error_stream());
+        let stderr_thread =
+            self.spawn_writer_thread(stderr, effects.get_error_stream(), capture_output);
 
         let exit_status = child
             .wait()
             .wrap_err("Waiting for Git subprocess to complete")?;
-        stdout_thread.join().unwrap();
-        stderr_thread.join().unwrap();
+        let stdout = stdout_thread.join().unwrap();
+        let stderr = stderr_thread.join().unwrap();
 
         // On Unix, if the child process was terminated by a signal, we need to call
         // some Unix-specific functions to access the signal that terminated it. For
@@ -165,12 +286,20 @@ impl GitRunInfo {
         let exit_code: isize = exit_code
             .try_into()
             .wrap_err("Converting exit code from i32 to isize")?;
-        let exit_code = ExitCode(exit_code);
-        if exit_code.is_success() {
-            Ok(Ok(()))
-        } else {
-            Ok(Err(exit_code))
+        if let Err(err) = self.trace_invocation(
+            working_directory,
+            args,
+            event_tx_id,
+            ExitCode(exit_code),
+            start_instant.elapsed(),
+        ) {
+            warn!(?err, "Could not trace Git invocation");
         }
+        Ok(GitRunResult {
+            exit_code: ExitCode(exit_code),
+            stdout,
+            stderr,
+        })
     }
 
     /// Run Git in a subprocess, and inform the user.
@@ -190,10 +319,42 @@ impl GitRunInfo {
         event_tx_id: Option<EventTransactionId>,
         args: &[S],
     ) -> EyreExitOr<()> {
+        let GitRunResult {
+            exit_code,
+            stdout: _,
+            stderr: _,
+        } = self.run_inner(
+            effects,
+            event_tx_id,
+            args.iter().map(AsRef::as_ref).collect_vec().as_slice(),
+            false,
+        )?;
+        if exit_code.is_success() {
+            Ok(Ok(()))
+        } else {
+            Ok(Err(exit_code))
+        }
+    }
+
+    /// Like [`GitRunInfo::run`], but also capture the stdout and stderr of Git,
+    /// so that they can be included in error messages. The output is still
+    /// displayed to the user as it's produced.
+    ///
+    /// Unlike [`GitRunInfo::run_silent`], a non-zero exit code isn't treated
+    /// as an error, and the command is announced to the user in the same way
+    /// as with [`GitRunInfo::run`].
+    #[instrument]
+    pub fn run_captured<S: AsRef<OsStr> + std::fmt::Debug>(
+        &self,
+        effects: &Effects,
+        event_tx_id: Option<EventTransactionId>,
+        args: &[S],
+    ) -> eyre::Result<GitRunResult> {
         self.run_inner(
             effects,
             event_tx_id,
             args.iter().map(AsRef::as_ref).collect_vec().as_slice(),
+            true,
         )
     }
 
@@ -297,9 +458,15 @@ impl GitRunInfo {
         let GitRunOpts {
             treat_git_failure_as_error,
             stdin,
+            working_directory: working_directory_override,
         } = opts;
-
-        let repo_path = self.working_directory(repo);
+        let (working_directory, repo_path) = match &working_directory_override {
+            Some(working_directory_override) => (
+                working_directory_override.as_path(),
+                working_directory_override.clone(),
+            ),
+            None => (working_directory.as_path(), self.working_directory(repo)),
+        };
         // Technically speaking, we should be able to work with non-UTF-8 repository
         // paths. Need to make the typechecker accept it.
         let repo_path = repo_path.to_str().ok_or_else(|| {
@@ -330,6 +497,7 @@ impl GitRunInfo {
         command.stdout(Stdio::piped());
         command.stderr(Stdio::piped());
 
+        let start_instant = Instant::now();
         let mut child = command.spawn().wrap_err("Spawning Git subprocess")?;
 
         if let Some(stdin) = stdin {
@@ -345,6 +513,15 @@ impl GitRunInfo {
             .wait_with_output()
             .wrap_err("Spawning Git subprocess")?;
         let exit_code = ExitCode(output.status.code().unwrap_or(1).try_into()?);
+        if let Err(err) = self.trace_invocation(
+            working_directory,
+            &args.iter().map(OsStr::new).collect_vec(),
+            event_tx_id,
+            exit_code,
+            start_instant.elapsed(),
+        ) {
+            warn!(?err, "Could not trace Git invocation");
+        }
         let result = GitRunResult {
             // On Unix, if the child process was terminated by a signal, we need to call
             // some Unix-specific functions to access the signal that terminated it. For
@@ -363,6 +540,34 @@ impl GitRunInfo {
         Ok(result)
     }
 
+    /// Get the version of the Git executable.
+    ///
+    /// The result is cached per Git executable for the lifetime of the
+    /// process, so this is cheap to call repeatedly.
+    #[instrument]
+    pub fn version(&self, repo: &Repo) -> eyre::Result<GitVersion> {
+        static VERSIONS: Lazy<Mutex<HashMap<PathBuf, GitVersion>>> = Lazy::new(Default::default);
+
+        if let Some(version) = VERSIONS.lock().unwrap().get(&self.path_to_git) {
+            return Ok(*version);
+        }
+
+        let GitRunResult { stdout, .. } = self
+            .run_silent(repo, None, &["version"], Default::default())
+            .wrap_err("Determining Git version")?;
+        let version_str =
+            String::from_utf8(stdout).wrap_err("Decoding stdout from Git subprocess")?;
+        let version_str = version_str.trim();
+        let version: GitVersion = version_str
+            .parse()
+            .wrap_err_with(|| format!("Parsing Git version string: {version_str}"))?;
+        VERSIONS
+            .lock()
+            .unwrap()
+            .insert(self.path_to_git.clone(), version);
+        Ok(version)
+    }
+
     /// Run Git silently (don't display output to the user).
     ///
     /// Whenever possible, use `git2`'s bindings to Git instead, as they're
@@ -384,24 +589,32 @@ impl GitRunInfo {
         )
     }
 
-    fn run_hook_inner(
+    fn prepare_hook(
         &self,
-        effects: &Effects,
         repo: &Repo,
         hook_name: &str,
         event_tx_id: EventTransactionId,
         args: &[&str],
         stdin: Option<BString>,
-    ) -> eyre::Result<()> {
+    ) -> eyre::Result<Option<HookInvocation>> {
         let hook_dir = get_main_worktree_hooks_dir(self, repo, Some(event_tx_id))?;
-        if !hook_dir.exists() {
-            warn!(
-                ?hook_dir,
-                ?hook_name,
-                "Git hooks dir did not exist, so could not invoke hook"
-            );
-            return Ok(());
-        }
+        // Like Git, treat a hooks dir which doesn't exist (such as one
+        // configured with `core.hooksPath` but never created) as containing
+        // no hooks, rather than failing to canonicalize it.
+        let hook_dir = match std::fs::canonicalize(&hook_dir) {
+            Ok(hook_dir) => hook_dir,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                warn!(
+                    ?hook_dir,
+                    ?hook_name,
+                    "Git hooks dir did not exist, so could not invoke hook"
+                );
+                return Ok(None);
+            }
+            Err(err) => {
+                return Err(err).wrap_err_with(|| format!("Canonicalizing hook dir: {hook_dir:?}"))
+            }
+        };
 
         let GitRunInfo {
             // We're calling a Git hook, but not Git itself.
@@ -412,8 +625,7 @@ impl GitRunInfo {
             env,
         } = self;
         let path = {
-            let mut path_components: Vec<PathBuf> =
-                vec![std::fs::canonicalize(&hook_dir).wrap_err("Canonicalizing hook dir")?];
+            let mut path_components: Vec<PathBuf> = vec![hook_dir.clone()];
             if let Some(path) = env.get(OsStr::new("PATH")) {
                 path_components.extend(std::env::split_paths(path));
             }
@@ -425,46 +637,109 @@ impl GitRunInfo {
             std::env::join_paths(path_components).wrap_err("Joining path components")?
         };
 
-        if hook_dir.join(hook_name).exists() {
-            let mut child = Command::new(get_sh().ok_or_else(|| eyre!("could not get sh"))?)
-                .current_dir(self.working_directory(repo))
-                .arg("-c")
-                .arg(format!("{hook_name} \"$@\""))
-                .arg(hook_name) // "$@" expands "$1" "$2" "$3" ... but we also must specify $0.
-                .args(args)
-                .env_clear()
-                .envs(env.iter())
-                .env(BRANCHLESS_TRANSACTION_ID_ENV_VAR, event_tx_id.to_string())
-                .env("PATH", &path)
-                .stdin(Stdio::piped())
-                .stdout(Stdio::piped())
-                .stderr(Stdio::piped())
-                .spawn()
-                .wrap_err_with(|| format!("Invoking {} hook with PATH: {:?}", &hook_name, &path))?;
-
-            if let Some(stdin) = stdin {
-                child
-                    .stdin
-                    .as_mut()
-                    .unwrap()
-                    .write_all(&stdin)
-                    .wrap_err("Writing hook process stdin")?;
+        let hook_path = hook_dir.join(hook_name);
+        if !hook_path.exists() {
+            return Ok(None);
+        }
+        let (program, hook_args) = match read_hook_interpreter(&hook_path)? {
+            Some(HookInterpreter {
+                program,
+                args: interpreter_args,
+            }) if !is_shell_interpreter(&program) => {
+                let mut hook_args: Vec<OsString> =
+                    interpreter_args.into_iter().map(OsString::from).collect();
+                hook_args.push(
+                    std::fs::canonicalize(&hook_path)
+                        .wrap_err("Canonicalizing hook path")?
+                        .into_os_string(),
+                );
+                (resolve_interpreter(&program, &path), hook_args)
+            }
+            Some(_) | None => {
+                // If `sh` can't be found, then spawning it will fail, but the
+                // invocation can still be inspected.
+                let program = get_sh().unwrap_or_else(|| PathBuf::from("sh"));
+                let hook_args = vec![
+                    OsString::from("-c"),
+                    OsString::from(format!("{hook_name} \"$@\"")),
+                    // "$@" expands "$1" "$2" "$3" ... but we also must specify $0.
+                    OsString::from(hook_name),
+                ];
+                (program, hook_args)
             }
+        };
 
-            let stdout = child.stdout.take();
-            let stdout_thread = self.spawn_writer_thread(stdout, effects.get_output_stream());
-            let stderr = child.stderr.take();
-            let stderr_thread = self.spawn_writer_thread(stderr, effects.get_error_stream());
+        let mut hook_env = env.clone();
+        hook_env.insert(
+            BRANCHLESS_TRANSACTION_ID_ENV_VAR.into(),
+            event_tx_id.to_string().into(),
+        );
+        hook_env.insert("PATH".into(), path);
+        Ok(Some(HookInvocation {
+            hook_name: hook_name.to_owned(),
+            program,
+            args: hook_args
+                .into_iter()
+                .chain(args.iter().map(OsString::from))
+                .collect(),
+            current_dir: self.working_directory(repo).to_owned(),
+            env: hook_env,
+            stdin,
+        }))
+    }
+
+    fn spawn_hook(&self, effects: &Effects, invocation: HookInvocation) -> eyre::Result<ExitCode> {
+        let HookInvocation {
+            hook_name,
+            program,
+            args,
+            current_dir,
+            env,
+            stdin,
+        } = invocation;
+        let mut child = Command::new(program)
+            .args(args)
+            .current_dir(current_dir)
+            .env_clear()
+            .envs(env.iter())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .wrap_err_with(|| {
+                format!(
+                    "Invoking {} hook with PATH: {:?}",
+                    &hook_name,
+                    env.get(OsStr::new("PATH"))
+                )
+            })?;
 
-            let _ignored: ExitStatus =
-                child.wait().wrap_err("Waiting for child process to exit")?;
-            stdout_thread.join().unwrap();
-            stderr_thread.join().unwrap();
+        if let Some(stdin) = stdin {
+            child
+                .stdin
+                .as_mut()
+                .unwrap()
+                .write_all(&stdin)
+                .wrap_err("Writing hook process stdin")?;
         }
-        Ok(())
+
+        let stdout = child.stdout.take();
+        let stdout_thread = self.spawn_writer_thread(stdout, effects.get_output_stream(), false);
+        let stderr = child.stderr.take();
+        let stderr_thread = self.spawn_writer_thread(stderr, effects.get_error_stream(), false);
+
+        let exit_status: ExitStatus = child.wait().wrap_err("Waiting for child process to exit")?;
+        stdout_thread.join().unwrap();
+        stderr_thread.join().unwrap();
+        let exit_code = ExitCode(exit_status.code().unwrap_or(1).try_into()?);
+        Ok(exit_code)
     }
 
-    /// Run a provided Git hook if it exists for the repository.
+    /// Run a provided Git hook if it exists for the repository, returning its
+    /// exit code. If the hook doesn't exist, success is returned.
+    ///
+    /// The hook can read the name of the triggering `git-branchless` command
+    /// from [`BRANCHLESS_COMMAND_ENV_VAR`], if it was set in `self.env`.
     ///
     /// See the man page for `githooks(5)` for more detail on Git hooks.
     #[instrument]
@@ -476,14 +751,149 @@ impl GitRunInfo {
         event_tx_id: EventTransactionId,
         args: &[S],
         stdin: Option<BString>,
-    ) -> eyre::Result<()> {
-        self.run_hook_inner(
-            effects,
-            repo,
-            hook_name,
-            event_tx_id,
-            args.iter().map(AsRef::as_ref).collect_vec().as_slice(),
-            stdin,
-        )
+    ) -> eyre::Result<ExitCode> {
+        self.run_hook_with(repo, hook_name, event_tx_id, args, stdin, |invocation| {
+            self.spawn_hook(effects, invocation)
+        })
     }
+
+    /// Same as [`GitRunInfo::run_hook`], but rather than spawning the hook
+    /// process, pass the prepared invocation to `run_invocation`. This is
+    /// useful for testing how hooks are invoked without running them.
+    pub fn run_hook_with<S: AsRef<str>>(
+        &self,
+        repo: &Repo,
+        hook_name: &str,
+        event_tx_id: EventTransactionId,
+        args: &[S],
+        stdin: Option<BString>,
+        run_invocation: impl FnOnce(HookInvocation) -> eyre::Result<ExitCode>,
+    ) -> eyre::Result<ExitCode> {
+        let args = args.iter().map(AsRef::as_ref).collect_vec();
+        match self.prepare_hook(repo, hook_name, event_tx_id, &args, stdin)? {
+            Some(invocation) => run_invocation(invocation),
+            None => Ok(ExitCode::success()),
+        }
+    }
+}
+
+/// A prepared invocation of a Git hook. See [`GitRunInfo::run_hook_with`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HookInvocation {
+    /// The name of the hook, such as `post-rewrite`.
+    pub hook_name: String,
+
+    /// The program to run: either the interpreter from the hook's shebang
+    /// line, or `sh`.
+    pub program: PathBuf,
+
+    /// The arguments to pass to `program`, ending with the hook's arguments.
+    pub args: Vec<OsString>,
+
+    /// The directory to run the hook in.
+    pub current_dir: PathBuf,
+
+    /// The complete environment of the hook process.
+    pub env: HashMap<OsString, OsString>,
+
+    /// The contents to write to the hook process's stdin, if any.
+    pub stdin: Option<BString>,
+}
+
+/// The interpreter requested by the shebang (`#!`) line of a hook script.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct HookInterpreter {
+    program: PathBuf,
+    args: Vec<String>,
+}
+
+/// Whether the value of the provided environment variable may contain
+/// credentials, and so shouldn't be logged. Only whole `_`-separated words of
+/// the name are considered, so that e.g. `GIT_AUTHOR_NAME` isn't redacted.
+fn is_sensitive_env_var(key: &str) -> bool {
+    let key = key.to_ascii_uppercase();
+    key == "GIT_CONFIG_PARAMETERS"
+        || key.starts_with("GIT_CONFIG_VALUE_")
+        || key.split('_').any(|word| {
+            matches!(
+                word,
+                "TOKEN"
+                    | "PASSWORD"
+                    | "PASSWD"
+                    | "SECRET"
+                    | "CREDENTIAL"
+                    | "CREDENTIALS"
+                    | "AUTH"
+                    | "AUTHORIZATION"
+            )
+        })
+}
+
+/// Read the shebang line of the hook at `hook_path`, if any. Shebangs of the
+/// form `#!/usr/bin/env <program>` are resolved to `<program>`, so that they
+/// can be honored on platforms without `/usr/bin/env` (i.e. Windows).
+fn read_hook_interpreter(hook_path: &Path) -> eyre::Result<Option<HookInterpreter>> {
+    let file = std::fs::File::open(hook_path)
+        .wrap_err_with(|| format!("Opening hook file: {hook_path:?}"))?;
+    let mut first_line = Vec::new();
+    BufReader::new(file)
+        .read_until(b'\n', &mut first_line)
+        .wrap_err_with(|| format!("Reading hook file: {hook_path:?}"))?;
+    Ok(parse_shebang(&String::from_utf8_lossy(&first_line)))
+}
+
+fn parse_shebang(first_line: &str) -> Option<HookInterpreter> {
+    let line = first_line.strip_prefix("#!")?;
+    let mut words = line.split_whitespace();
+    let mut program = PathBuf::from(words.next()?);
+    let mut args: Vec<String> = words.map(|word| word.to_owned()).collect();
+
+    let is_env = program
+        .file_stem()
+        .map(|file_stem| file_stem == "env")
+        .unwrap_or(false);
+    if is_env {
+        // Skip any options to `env` itself (such as `-S`).
+        let program_index = args.iter().position(|arg| !arg.starts_with('-'))?;
+        args = args.split_off(program_index);
+        program = PathBuf::from(args.remove(0));
+    }
+    Some(HookInterpreter { program, args })
+}
+
+/// Whether the given interpreter is a shell which can be replaced by the shell
+/// from [`get_sh`].
+fn is_shell_interpreter(program: &Path) -> bool {
+    match program.file_stem().and_then(|file_stem| file_stem.to_str()) {
+        Some(file_stem) => matches!(file_stem, "sh" | "bash" | "dash"),
+        None => false,
+    }
+}
+
+/// Find the executable for the given interpreter. If the interpreter doesn't
+/// exist at the named path (e.g. `/usr/bin/python3` on Windows), then look it
+/// up by name in `PATH` instead.
+fn resolve_interpreter(program: &Path, path: &OsStr) -> PathBuf {
+    if program.is_absolute() && program.is_file() {
+        return program.to_owned();
+    }
+    let file_name = match program.file_name() {
+        Some(file_name) => file_name,
+        None => return program.to_owned(),
+    };
+    std::env::split_paths(path)
+        .find_map(|dir| {
+            let candidate = dir.join(file_name);
+            if candidate.is_file() {
+                return Some(candidate);
+            }
+            if cfg!(target_os = "windows") {
+                let candidate = candidate.with_extension("exe");
+                if candidate.is_file() {
+                    return Some(candidate);
+                }
+            }
+            None
+        })
+        .unwrap_or_else(|| program.to_owned())
 }
@@ -12,8 +12,8 @@ use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 
 use crate::core::config::env_vars::{
-    get_git_exec_path, get_path_to_git, should_use_separate_command_binary, TEST_GIT,
-    TEST_SEPARATE_COMMAND_BINARIES,
+    get_git_exec_path, get_path_to_git, should_use_separate_command_binary,
+    BRANCHLESS_DETERMINISTIC, TEST_GIT, TEST_SEPARATE_COMMAND_BINARIES,
 };
 use crate::git::{GitRunInfo, GitVersion, NonZeroOid, Repo};
 use crate::util::get_sh;
@@ -193,6 +193,7 @@ impl Git {
             ("GIT_EXEC_PATH", self.git_exec_path.as_os_str().into()),
             ("PATH", new_path),
             (TEST_GIT, self.path_to_git.as_os_str().into()),
+            (BRANCHLESS_DETERMINISTIC, OsString::from("1")),
             (
                 TEST_SEPARATE_COMMAND_BINARIES,
                 std::env::var_os(TEST_SEPARATE_COMMAND_BINARIES).unwrap_or_default(),
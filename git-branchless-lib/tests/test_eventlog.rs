@@ -1,4 +1,5 @@
 use std::str::FromStr;
+use std::time::SystemTime;
 
 use branchless::core::eventlog::testing::{new_event_cursor, new_event_transaction_id};
 use branchless::core::eventlog::{
@@ -92,6 +93,35 @@ fn test_different_event_transaction_ids() -> eyre::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_get_sync_transaction_ids() -> eyre::Result<()> {
+    let git = make_git()?;
+    git.init_repo()?;
+
+    let repo = git.get_repo()?;
+    let conn = repo.get_db_conn()?;
+    let event_log_db = EventLogDb::new(&conn)?;
+    let now = SystemTime::now();
+    let sync_event_tx_id = event_log_db.make_transaction_id(now, "sync")?;
+    let other_event_tx_id = event_log_db.make_transaction_id(now, "amend")?;
+    let batch_event_tx_id = event_log_db.make_sync_batch_transaction_id(now, sync_event_tx_id)?;
+    let other_sync_event_tx_id = event_log_db.make_transaction_id(now, "sync")?;
+    let other_batch_event_tx_id =
+        event_log_db.make_sync_batch_transaction_id(now, other_sync_event_tx_id)?;
+
+    // Transactions started by other commands (or other syncs) while the sync
+    // was running aren't part of it.
+    let sync_event_tx_ids = event_log_db.get_sync_transaction_ids(sync_event_tx_id)?;
+    assert_eq!(sync_event_tx_ids, vec![sync_event_tx_id, batch_event_tx_id]);
+    assert!(!sync_event_tx_ids.contains(&other_event_tx_id));
+    assert_eq!(
+        event_log_db.get_sync_transaction_ids(other_sync_event_tx_id)?,
+        vec![other_sync_event_tx_id, other_batch_event_tx_id]
+    );
+
+    Ok(())
+}
+
 #[test]
 fn test_advance_cursor_by_transaction() -> eyre::Result<()> {
     let mut event_replayer = new_event_replayer("refs/heads/master".into());
@@ -1,5 +1,13 @@
-use branchless::git::{GitRunInfo, GitRunOpts};
+use std::ffi::{OsStr, OsString};
+use std::time::SystemTime;
+
+use branchless::core::effects::Effects;
+use branchless::core::eventlog::EventLogDb;
+use branchless::core::formatting::Glyphs;
+use branchless::git::{GitRunInfo, GitRunOpts, Repo, BRANCHLESS_TRACE_GIT_ENV_VAR};
 use branchless::testing::make_git;
+use branchless::util::{get_from_path, ExitCode};
+use regex::Regex;
 
 #[test]
 fn test_hook_working_dir() -> eyre::Result<()> {
@@ -62,6 +70,7 @@ fn test_run_silent_failures() -> eyre::Result<()> {
         GitRunOpts {
             treat_git_failure_as_error: true,
             stdin: None,
+            working_directory: None,
         },
     );
     assert!(result.is_err());
@@ -73,6 +82,7 @@ fn test_run_silent_failures() -> eyre::Result<()> {
         GitRunOpts {
             treat_git_failure_as_error: false,
             stdin: None,
+            working_directory: None,
         },
     );
     assert!(result.is_ok());
@@ -80,6 +90,170 @@ fn test_run_silent_failures() -> eyre::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_run_silent_working_directory() -> eyre::Result<()> {
+    let git = make_git()?;
+    git.init_repo()?;
+
+    let subdir = git.repo_path.join("subdir");
+    std::fs::create_dir(&subdir)?;
+
+    let git_run_info = git.get_git_run_info();
+    let result = git_run_info.run_silent(
+        &git.get_repo()?,
+        None,
+        &["rev-parse", "--show-prefix"],
+        GitRunOpts {
+            treat_git_failure_as_error: true,
+            stdin: None,
+            working_directory: Some(subdir),
+        },
+    )?;
+    assert_eq!(String::from_utf8(result.stdout)?.trim(), "subdir/");
+
+    let result = git_run_info.run_silent(
+        &git.get_repo()?,
+        None,
+        &["rev-parse", "--show-prefix"],
+        Default::default(),
+    )?;
+    assert_eq!(String::from_utf8(result.stdout)?.trim(), "");
+
+    Ok(())
+}
+
+#[test]
+fn test_run_captured() -> eyre::Result<()> {
+    let git = make_git()?;
+    git.init_repo()?;
+
+    let effects = Effects::new_suppress_for_test(Glyphs::text());
+    let git_run_info = git.get_git_run_info();
+
+    let result = git_run_info.run_captured(&effects, None, &["log", "--format=%s"])?;
+    assert!(result.exit_code.is_success());
+    insta::assert_snapshot!(String::from_utf8_lossy(&result.stdout), @"create initial.txt");
+
+    let result = git_run_info.run_captured(&effects, None, &["some-nonexistent-command"])?;
+    assert!(!result.exit_code.is_success());
+    insta::assert_snapshot!(String::from_utf8_lossy(&result.stderr), @"git: 'some-nonexistent-command' is not a git command. See 'git --help'.");
+
+    Ok(())
+}
+
+#[test]
+fn test_version() -> eyre::Result<()> {
+    let git = make_git()?;
+    git.init_repo()?;
+
+    let repo = git.get_repo()?;
+    let git_run_info = git.get_git_run_info();
+    let version = git_run_info.version(&repo)?;
+    assert_eq!(version, git.get_version()?);
+
+    // The second call should be served from the cache.
+    assert_eq!(git_run_info.version(&repo)?, version);
+
+    Ok(())
+}
+
+#[test]
+fn test_run_hook_with_python_shebang() -> eyre::Result<()> {
+    let git = make_git()?;
+    git.init_repo()?;
+
+    if get_from_path("python3").is_none() {
+        return Ok(());
+    }
+
+    std::fs::write(
+        git.repo_path
+            .join(".git")
+            .join("hooks")
+            .join("post-rewrite"),
+        r#"#!/usr/bin/env python3
+import sys
+with open("hook-output.txt", "w") as f:
+    f.write("args: " + " ".join(sys.argv[1:]) + "\n")
+    f.write("stdin: " + sys.stdin.read())
+"#,
+    )?;
+
+    // Pass through the environment so that the hook can locate `python3`.
+    let git_run_info = GitRunInfo {
+        env: std::env::vars_os().collect(),
+        ..git.get_git_run_info()
+    };
+
+    let effects = Effects::new_suppress_for_test(Glyphs::text());
+    let repo = git.get_repo()?;
+    let conn = repo.get_db_conn()?;
+    let event_log_db = EventLogDb::new(&conn)?;
+    let event_tx_id = event_log_db.make_transaction_id(SystemTime::now(), "test hook")?;
+    let exit_code = git_run_info.run_hook(
+        &effects,
+        &repo,
+        "post-rewrite",
+        event_tx_id,
+        &["rebase"],
+        Some("foo bar\n".into()),
+    )?;
+    assert!(exit_code.is_success());
+
+    let output = std::fs::read_to_string(git.repo_path.join("hook-output.txt"))?;
+    insta::assert_snapshot!(output, @r###"
+    args: rebase
+    stdin: foo bar
+    "###);
+
+    Ok(())
+}
+
+#[test]
+fn test_run_silent_with_split_git_dir_and_work_tree() -> eyre::Result<()> {
+    let git = make_git()?;
+    git.init_repo()?;
+    git.commit_file("test1", 1)?;
+
+    // Simulate a "bare repository" dotfiles setup, where the `.git` directory
+    // isn't inside the working tree.
+    let temp_dir = tempfile::tempdir()?;
+    let git_dir = temp_dir.path().join("dotfiles.git");
+    std::fs::rename(git.repo_path.join(".git"), &git_dir)?;
+
+    let repo = Repo::from_git_dir(&git_dir, Some(&git.repo_path))?;
+    assert_eq!(
+        repo.get_working_copy_path()
+            .map(std::fs::canonicalize)
+            .transpose()?,
+        Some(std::fs::canonicalize(&git.repo_path)?)
+    );
+
+    // The relative paths should be interpreted relative to the working
+    // directory, even though Git is run with `-C <working copy>`.
+    let git_run_info = GitRunInfo::new(
+        git.path_to_git.clone(),
+        temp_dir.path().to_owned(),
+        [
+            (OsString::from("GIT_DIR"), OsString::from("dotfiles.git")),
+            (
+                OsString::from("GIT_WORK_TREE"),
+                git.repo_path.clone().into_os_string(),
+            ),
+        ]
+        .into_iter()
+        .collect(),
+    );
+    let result =
+        git_run_info.run_silent(&repo, None, &["log", "--format=%s"], GitRunOpts::default())?;
+    insta::assert_snapshot!(String::from_utf8(result.stdout)?, @r###"
+    create test1.txt
+    create initial.txt
+    "###);
+
+    Ok(())
+}
+
 // Creating symlinks on Windows may fail without administrator or developer
 // privileges, so this test is Unix only. See
 // https://doc.rust-lang.org/std/os/windows/fs/fn.symlink_dir.html#limitations
@@ -113,9 +287,287 @@ fn test_run_in_repo_tool_project() -> eyre::Result<()> {
         GitRunOpts {
             treat_git_failure_as_error: true,
             stdin: None,
+            working_directory: None,
         },
     );
     assert!(result.is_ok());
 
     Ok(())
 }
+
+#[test]
+fn test_run_hook_exit_code() -> eyre::Result<()> {
+    let git = make_git()?;
+    git.init_repo()?;
+
+    let hooks_dir = git.repo_path.join(".git").join("hooks");
+    let hook_path = hooks_dir.join("post-rewrite");
+    std::fs::write(&hook_path, "#!/bin/sh\nexit 3\n")?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&hook_path, std::fs::Permissions::from_mode(0o755))?;
+    }
+
+    let git_run_info = git.get_git_run_info();
+    let effects = Effects::new_suppress_for_test(Glyphs::text());
+    let repo = git.get_repo()?;
+    let conn = repo.get_db_conn()?;
+    let event_log_db = EventLogDb::new(&conn)?;
+    let event_tx_id = event_log_db.make_transaction_id(SystemTime::now(), "test hook")?;
+
+    let exit_code = git_run_info.run_hook(
+        &effects,
+        &repo,
+        "post-rewrite",
+        event_tx_id,
+        &["rebase"],
+        None,
+    )?;
+    assert_eq!(exit_code.0, 3);
+
+    // A hook which doesn't exist is treated as having succeeded.
+    let exit_code =
+        git_run_info.run_hook(&effects, &repo, "post-merge", event_tx_id, &["0"], None)?;
+    assert!(exit_code.is_success());
+
+    Ok(())
+}
+
+#[test]
+fn test_run_hook_with_relative_hooks_path() -> eyre::Result<()> {
+    let git = make_git()?;
+    git.init_repo()?;
+    git.run(&["config", "core.hooksPath", ".myhooks"])?;
+
+    let hooks_dir = git.repo_path.join(".myhooks");
+    std::fs::create_dir_all(&hooks_dir)?;
+    let hook_path = hooks_dir.join("post-rewrite");
+    std::fs::write(
+        &hook_path,
+        "#!/bin/sh\necho \"ran hook from .myhooks\" >hook-output.txt\n",
+    )?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&hook_path, std::fs::Permissions::from_mode(0o755))?;
+    }
+
+    // Git should be run from a subdirectory, so that the hooks path would be
+    // resolved incorrectly if it were relative to the working directory.
+    let subdir = git.repo_path.join("subdir");
+    std::fs::create_dir_all(&subdir)?;
+    let git_run_info = GitRunInfo {
+        working_directory: subdir,
+        ..git.get_git_run_info()
+    };
+
+    let effects = Effects::new_suppress_for_test(Glyphs::text());
+    let repo = git.get_repo()?;
+    let conn = repo.get_db_conn()?;
+    let event_log_db = EventLogDb::new(&conn)?;
+    let event_tx_id = event_log_db.make_transaction_id(SystemTime::now(), "test hook")?;
+    let exit_code = git_run_info.run_hook(
+        &effects,
+        &repo,
+        "post-rewrite",
+        event_tx_id,
+        &["rebase"],
+        None,
+    )?;
+    assert!(exit_code.is_success());
+
+    let output = std::fs::read_to_string(git.repo_path.join("hook-output.txt"))?;
+    insta::assert_snapshot!(output, @"ran hook from .myhooks");
+
+    Ok(())
+}
+
+#[test]
+fn test_run_hook_with_missing_hooks_path() -> eyre::Result<()> {
+    let git = make_git()?;
+    git.init_repo()?;
+    git.run(&["config", "core.hooksPath", "does-not-exist"])?;
+
+    let effects = Effects::new_suppress_for_test(Glyphs::text());
+    let git_run_info = git.get_git_run_info();
+    let repo = git.get_repo()?;
+    let conn = repo.get_db_conn()?;
+    let event_log_db = EventLogDb::new(&conn)?;
+    let event_tx_id = event_log_db.make_transaction_id(SystemTime::now(), "test hook")?;
+    let exit_code = git_run_info.run_hook(
+        &effects,
+        &repo,
+        "post-rewrite",
+        event_tx_id,
+        &["rebase"],
+        None,
+    )?;
+    assert!(exit_code.is_success());
+    assert!(!git.repo_path.join("does-not-exist").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_run_hook_with_recorded_invocation() -> eyre::Result<()> {
+    let git = make_git()?;
+    git.init_repo()?;
+
+    let hooks_dir = git.repo_path.join(".git").join("hooks");
+    std::fs::write(hooks_dir.join("post-rewrite"), "#!/bin/sh\nexit 1\n")?;
+    std::fs::write(
+        hooks_dir.join("reference-transaction"),
+        "#!/usr/bin/env python3\nraise SystemExit(1)\n",
+    )?;
+
+    let git_run_info = GitRunInfo {
+        env: [(OsString::from("BRANCHLESS_COMMAND"), OsString::from("sync"))]
+            .into_iter()
+            .collect(),
+        ..git.get_git_run_info()
+    };
+    let repo = git.get_repo()?;
+    let conn = repo.get_db_conn()?;
+    let event_log_db = EventLogDb::new(&conn)?;
+    let event_tx_id = event_log_db.make_transaction_id(SystemTime::now(), "test hook")?;
+
+    let mut invocations = Vec::new();
+    for hook_name in ["post-rewrite", "reference-transaction", "pre-push"] {
+        git_run_info.run_hook_with(
+            &repo,
+            hook_name,
+            event_tx_id,
+            &["rebase"],
+            Some("old new\n".into()),
+            |invocation| {
+                invocations.push(invocation);
+                Ok(ExitCode::success())
+            },
+        )?;
+    }
+
+    // The hook which doesn't exist isn't invoked.
+    let [shell_invocation, python_invocation] = invocations.as_slice() else {
+        panic!("Unexpected hook invocations: {invocations:?}");
+    };
+
+    assert_eq!(shell_invocation.hook_name, "post-rewrite");
+    assert_eq!(shell_invocation.program.file_stem(), Some(OsStr::new("sh")));
+    assert_eq!(
+        shell_invocation.args,
+        ["-c", "post-rewrite \"$@\"", "post-rewrite", "rebase"]
+            .map(OsString::from)
+            .to_vec()
+    );
+    assert_eq!(
+        std::fs::canonicalize(&shell_invocation.current_dir)?,
+        std::fs::canonicalize(&git.repo_path)?
+    );
+    assert_eq!(shell_invocation.stdin, Some("old new\n".into()));
+    assert_eq!(
+        shell_invocation.env.get(OsStr::new("BRANCHLESS_COMMAND")),
+        Some(&OsString::from("sync"))
+    );
+    assert_eq!(
+        shell_invocation
+            .env
+            .get(OsStr::new("BRANCHLESS_TRANSACTION_ID")),
+        Some(&OsString::from(event_tx_id.to_string()))
+    );
+    let path = shell_invocation
+        .env
+        .get(OsStr::new("PATH"))
+        .expect("PATH should be set for hooks");
+    assert_eq!(
+        std::env::split_paths(path).next(),
+        Some(std::fs::canonicalize(&hooks_dir)?)
+    );
+
+    assert_eq!(python_invocation.hook_name, "reference-transaction");
+    assert_eq!(
+        python_invocation.program.file_name(),
+        Some(OsStr::new("python3"))
+    );
+    assert_eq!(
+        python_invocation.args,
+        vec![
+            std::fs::canonicalize(hooks_dir.join("reference-transaction"))?.into_os_string(),
+            OsString::from("rebase"),
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_run_silent_trace_git() -> eyre::Result<()> {
+    let git = make_git()?;
+    git.init_repo()?;
+
+    let trace_dir = tempfile::tempdir()?;
+    let trace_path = trace_dir.path().join("trace.log");
+    let git_run_info = GitRunInfo {
+        env: [
+            (
+                OsString::from(BRANCHLESS_TRACE_GIT_ENV_VAR),
+                trace_path.clone().into_os_string(),
+            ),
+            (
+                OsString::from("GIT_CONFIG_PARAMETERS"),
+                OsString::from("'http.extraHeader'='Authorization: Bearer secret'"),
+            ),
+            (OsString::from("GIT_AUTHOR_NAME"), OsString::from("Testy")),
+            (OsString::from("GIT_AUTH_TOKEN"), OsString::from("secret")),
+            (OsString::from("HOME"), OsString::from("/nonexistent")),
+        ]
+        .into_iter()
+        .collect(),
+        ..git.get_git_run_info()
+    };
+
+    let result = git_run_info.run_silent(
+        &git.get_repo()?,
+        None,
+        &["rev-parse", "--verify", "nonexistent"],
+        GitRunOpts {
+            treat_git_failure_as_error: false,
+            stdin: None,
+            working_directory: None,
+        },
+    )?;
+    assert!(!result.exit_code.is_success());
+
+    let trace = std::fs::read_to_string(&trace_path)?;
+    let trace = trace
+        .replace(
+            &git.path_to_git.to_string_lossy().to_string(),
+            "<git-executable>",
+        )
+        .replace(&git.repo_path.to_string_lossy().to_string(), "<repo-path>")
+        .replace(&trace_path.to_string_lossy().to_string(), "<trace-path>");
+    let trace = Regex::new(r"in [0-9]+\.[0-9]+s")?.replace_all(&trace, "in <elapsed>");
+    insta::assert_snapshot!(trace, @r###"
+    branchless: trace: <git-executable> -C <repo-path> rev-parse --verify nonexistent (cwd: <repo-path>) (env: BRANCHLESS_TRACE_GIT=<trace-path> GIT_AUTHOR_NAME=Testy GIT_AUTH_TOKEN=<redacted> GIT_CONFIG_PARAMETERS=<redacted>) exited with code 128 in <elapsed>
+    "###);
+
+    // Failing to write the trace doesn't fail the invocation itself.
+    let git_run_info = GitRunInfo {
+        env: [(
+            OsString::from(BRANCHLESS_TRACE_GIT_ENV_VAR),
+            trace_dir.path().as_os_str().to_owned(),
+        )]
+        .into_iter()
+        .collect(),
+        ..git.get_git_run_info()
+    };
+    let result = git_run_info.run_silent(
+        &git.get_repo()?,
+        None,
+        &["rev-parse", "HEAD"],
+        Default::default(),
+    )?;
+    assert!(result.exit_code.is_success());
+
+    Ok(())
+}
@@ -1,8 +1,8 @@
 use std::path::PathBuf;
 
 use branchless::git::{
-    AmendFastOptions, BranchType, CherryPickFastOptions, FileMode, FileStatus, GitVersion, Repo,
-    StatusEntry,
+    AmendFastOptions, BranchType, CherryPickFastOptions, FileMode, FileStatus, GitVersion,
+    ReferenceName, Repo, StatusEntry,
 };
 use branchless::testing::{make_git, make_git_worktree, GitWorktreeWrapper};
 
@@ -34,6 +34,29 @@ fn test_parse_git_version_output() {
         "git version 2.33.GIT".parse::<GitVersion>().unwrap(),
         GitVersion(2, 33, 0)
     );
+
+    assert_eq!(
+        "git version 2.40.0.rc1".parse::<GitVersion>().unwrap(),
+        GitVersion(2, 40, 0)
+    );
+    assert_eq!(
+        "git version 2.39.3 (Apple Git-145)"
+            .parse::<GitVersion>()
+            .unwrap(),
+        GitVersion(2, 39, 3)
+    );
+    assert_eq!(
+        "git version 2.42.0.windows.2"
+            .parse::<GitVersion>()
+            .unwrap(),
+        GitVersion(2, 42, 0)
+    );
+    assert!("git version".parse::<GitVersion>().is_err());
+    assert!("git version foo.bar.baz".parse::<GitVersion>().is_err());
+
+    assert!(GitVersion(2, 29, 0) < GitVersion(2, 29, 1));
+    assert!(GitVersion(2, 29, 1) < GitVersion(2, 30, 0));
+    assert!(GitVersion(2, 30, 0) < GitVersion(3, 0, 0));
 }
 
 #[test]
@@ -265,6 +288,45 @@ fn test_branch_debug() -> eyre::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_fast_forward_ref() -> eyre::Result<()> {
+    let git = make_git()?;
+    git.init_repo()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    let test2_oid = git.commit_file("test2", 2)?;
+    git.run(&["checkout", "-b", "other", &test1_oid.to_string()])?;
+    let test3_oid = git.commit_file("test3", 3)?;
+
+    let repo = git.get_repo()?;
+    let name = ReferenceName::from("refs/heads/foo");
+    repo.fast_forward_ref(&name, test1_oid, "test")?;
+    repo.fast_forward_ref(&name, test2_oid, "test")?;
+    assert_eq!(
+        repo.find_reference(&name)?
+            .unwrap()
+            .peel_to_commit()?
+            .unwrap()
+            .get_oid(),
+        test2_oid
+    );
+
+    // Moving the reference to a commit which isn't a descendant would discard
+    // `test2`, so it's refused.
+    let err = repo.fast_forward_ref(&name, test3_oid, "test").unwrap_err();
+    assert!(err.to_string().contains("not a descendant"), "{err}");
+    assert!(repo.fast_forward_ref(&name, test1_oid, "test").is_err());
+    assert_eq!(
+        repo.find_reference(&name)?
+            .unwrap()
+            .peel_to_commit()?
+            .unwrap()
+            .get_oid(),
+        test2_oid
+    );
+
+    Ok(())
+}
+
 #[test]
 fn test_worktree_working_copy_path() -> eyre::Result<()> {
     let git = make_git()?;
@@ -1,6 +1,6 @@
 use std::time::SystemTime;
 
-use branchless::core::dag::Dag;
+use branchless::core::dag::{CommitSet, Dag};
 use branchless::core::effects::Effects;
 use branchless::core::rewrite::testing::{
     get_builder_touched_paths_cache, omnipotent_rebase_plan_permissions,
@@ -12,8 +12,9 @@ use branchless::core::eventlog::{EventLogDb, EventReplayer};
 use branchless::core::formatting::Glyphs;
 use branchless::core::repo_ext::RepoExt;
 use branchless::core::rewrite::{
-    execute_rebase_plan, BuildRebasePlanOptions, ExecuteRebasePlanOptions, ExecuteRebasePlanResult,
-    RebasePlan, RebasePlanBuilder, RepoResource,
+    execute_rebase_plan, BuildRebasePlanError, BuildRebasePlanOptions, ExecuteRebasePlanOptions,
+    ExecuteRebasePlanResult, RebaseCommand, RebasePlan, RebasePlanBuilder, RebasePlanPermissions,
+    RepoResource, SignOption,
 };
 use branchless::testing::{make_git, Git};
 
@@ -46,6 +47,7 @@ fn test_cache_shared_between_builders() -> eyre::Result<()> {
         dump_rebase_constraints: false,
         dump_rebase_plan: false,
         detect_duplicate_commits_via_patch_id: true,
+        merge_base_strategy: Default::default(),
     };
     let permissions = omnipotent_rebase_plan_permissions(&dag, build_options)?;
     let pool = ThreadPoolBuilder::new().build()?;
@@ -62,6 +64,127 @@ fn test_cache_shared_between_builders() -> eyre::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_plan_find_reordered_commits() -> eyre::Result<()> {
+    let git = make_git()?;
+    git.init_repo()?;
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    let test2_oid = git.commit_file("test2", 2)?;
+    let test3_oid = git.commit_file("test3", 3)?;
+
+    let effects = Effects::new_suppress_for_test(Glyphs::text());
+    let repo = git.get_repo()?;
+    let conn = repo.get_db_conn()?;
+    let event_log_db = EventLogDb::new(&conn)?;
+    let event_replayer = EventReplayer::from_event_log_db(&effects, &repo, &event_log_db)?;
+    let event_cursor = event_replayer.make_default_cursor();
+    let references_snapshot = repo.get_references_snapshot()?;
+    let dag = Dag::open_and_sync(
+        &effects,
+        &repo,
+        &event_replayer,
+        event_cursor,
+        &references_snapshot,
+    )?;
+
+    let build_options = BuildRebasePlanOptions {
+        force_rewrite_public_commits: true,
+        dump_rebase_constraints: false,
+        dump_rebase_plan: false,
+        detect_duplicate_commits_via_patch_id: true,
+        merge_base_strategy: Default::default(),
+    };
+    let permissions = omnipotent_rebase_plan_permissions(&dag, build_options)?;
+    let pool = ThreadPoolBuilder::new().build()?;
+    let repo_pool = RepoResource::new_pool(&repo)?;
+
+    {
+        let mut builder = RebasePlanBuilder::new(&dag, permissions.clone());
+        builder.move_subtree(test2_oid, vec![test1_oid])?;
+        let rebase_plan = builder
+            .build(&effects, &pool, &repo_pool)?
+            .unwrap()
+            .unwrap();
+        assert_eq!(rebase_plan.find_reordered_commits(&dag)?, Vec::new());
+    }
+
+    {
+        let mut builder = RebasePlanBuilder::new(&dag, permissions);
+        builder.move_commit(test1_oid, test3_oid)?;
+        let rebase_plan = builder
+            .build(&effects, &pool, &repo_pool)?
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            rebase_plan.find_reordered_commits(&dag)?,
+            vec![test2_oid, test3_oid]
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_plan_leave_subtree() -> eyre::Result<()> {
+    let git = make_git()?;
+    git.init_repo()?;
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    let test3_oid = git.commit_file("test3", 3)?;
+    let test4_oid = git.commit_file("test4", 4)?;
+    git.commit_file("test5", 5)?;
+
+    let effects = Effects::new_suppress_for_test(Glyphs::text());
+    let repo = git.get_repo()?;
+    let conn = repo.get_db_conn()?;
+    let event_log_db = EventLogDb::new(&conn)?;
+    let event_replayer = EventReplayer::from_event_log_db(&effects, &repo, &event_log_db)?;
+    let event_cursor = event_replayer.make_default_cursor();
+    let references_snapshot = repo.get_references_snapshot()?;
+    let dag = Dag::open_and_sync(
+        &effects,
+        &repo,
+        &event_replayer,
+        event_cursor,
+        &references_snapshot,
+    )?;
+
+    let build_options = BuildRebasePlanOptions {
+        force_rewrite_public_commits: true,
+        dump_rebase_constraints: false,
+        dump_rebase_plan: false,
+        detect_duplicate_commits_via_patch_id: true,
+        merge_base_strategy: Default::default(),
+    };
+    let permissions = omnipotent_rebase_plan_permissions(&dag, build_options)?;
+    let pool = ThreadPoolBuilder::new().build()?;
+    let repo_pool = RepoResource::new_pool(&repo)?;
+
+    let mut builder = RebasePlanBuilder::new(&dag, permissions);
+    builder.move_subtree(test3_oid, vec![test1_oid])?;
+    builder.leave_subtree(test4_oid)?;
+    let rebase_plan = builder
+        .build(&effects, &pool, &repo_pool)?
+        .unwrap()
+        .unwrap();
+    let picked_oids: Vec<_> = rebase_plan
+        .commands
+        .iter()
+        .filter_map(|command| match command {
+            RebaseCommand::Pick {
+                original_commit_oid,
+                commits_to_apply_oids: _,
+            } => Some(*original_commit_oid),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(picked_oids, vec![test3_oid]);
+
+    Ok(())
+}
+
 #[test]
 fn test_plan_moving_subtree_again_overrides_previous_move() -> eyre::Result<()> {
     let git = make_git()?;
@@ -707,6 +830,78 @@ fn test_plan_fixup_parent_into_child() -> eyre::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_verify_rewrite_set_permitting_public_commits() -> eyre::Result<()> {
+    let git = make_git()?;
+    git.init_repo()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    let test2_oid = git.commit_file("test2", 2)?;
+
+    let effects = Effects::new_suppress_for_test(Glyphs::text());
+    let repo = git.get_repo()?;
+    let conn = repo.get_db_conn()?;
+    let event_log_db = EventLogDb::new(&conn)?;
+    let event_replayer = EventReplayer::from_event_log_db(&effects, &repo, &event_log_db)?;
+    let event_cursor = event_replayer.make_default_cursor();
+    let references_snapshot = repo.get_references_snapshot()?;
+    let dag = Dag::open_and_sync(
+        &effects,
+        &repo,
+        &event_replayer,
+        event_cursor,
+        &references_snapshot,
+    )?;
+
+    let build_options = BuildRebasePlanOptions {
+        force_rewrite_public_commits: false,
+        dump_rebase_constraints: false,
+        dump_rebase_plan: false,
+        detect_duplicate_commits_via_patch_id: true,
+        merge_base_strategy: Default::default(),
+    };
+
+    // Without any permitted commits, rewriting a public commit is rejected.
+    let result = RebasePlanPermissions::verify_rewrite_set(
+        &dag,
+        build_options.clone(),
+        &CommitSet::from(test2_oid),
+    )?;
+    assert!(matches!(
+        result,
+        Err(BuildRebasePlanError::MovePublicCommits { .. })
+    ));
+
+    // The explicitly-permitted public commit can be rewritten.
+    let result = RebasePlanPermissions::verify_rewrite_set_permitting_public_commits(
+        &dag,
+        build_options.clone(),
+        &CommitSet::from(test2_oid),
+        &CommitSet::from(test2_oid),
+    )?;
+    assert!(result.is_ok());
+
+    // Public descendants which weren't permitted are still rejected.
+    let result = RebasePlanPermissions::verify_rewrite_set_permitting_public_commits(
+        &dag,
+        build_options,
+        &CommitSet::from(test1_oid),
+        &CommitSet::from(test1_oid),
+    )?;
+    match result {
+        Err(BuildRebasePlanError::MovePublicCommits {
+            public_commits_to_move,
+        }) => {
+            assert_eq!(
+                dag.commit_set_to_vec(&public_commits_to_move)?,
+                vec![test2_oid]
+            );
+        }
+        other => eyre::bail!("Expected public commits to be rejected: {:?}", other),
+    }
+
+    Ok(())
+}
+
 /// Helper function to handle the boilerplate involved in creating, building
 /// and executing the rebase plan.
 fn create_and_execute_plan(
@@ -736,6 +931,7 @@ fn create_and_execute_plan(
         dump_rebase_constraints: false,
         dump_rebase_plan: false,
         detect_duplicate_commits_via_patch_id: true,
+        merge_base_strategy: Default::default(),
     };
     let permissions = omnipotent_rebase_plan_permissions(&dag, build_options)?;
     let mut builder = RebasePlanBuilder::new(&dag, permissions);
@@ -765,6 +961,12 @@ fn create_and_execute_plan(
             reset: false,
             render_smartlog: false,
         },
+        reapply_empty_commits: false,
+        preserve_committer: false,
+        reset_author: false,
+        sign_option: SignOption::Disable,
+        no_verify: false,
+        commit_trailers: Vec::new(),
     };
     let git_run_info = git.get_git_run_info();
     let result = execute_rebase_plan(
@@ -777,7 +979,10 @@ fn create_and_execute_plan(
     )?;
     assert!(matches!(
         result,
-        ExecuteRebasePlanResult::Succeeded { rewritten_oids: _ }
+        ExecuteRebasePlanResult::Succeeded {
+            rewritten_oids: _,
+            post_rewrite_hook_exit_code: _,
+        }
     ));
 
     Ok(())
@@ -33,7 +33,7 @@ use lib::core::effects::Effects;
 use lib::core::eventlog::{EventLogDb, EventReplayer};
 use lib::core::rewrite::{
     execute_rebase_plan, BuildRebasePlanOptions, ExecuteRebasePlanOptions, ExecuteRebasePlanResult,
-    MergeConflictRemediation, RebasePlanBuilder, RebasePlanPermissions, RepoResource,
+    MergeConflictRemediation, RebasePlanBuilder, RebasePlanPermissions, RepoResource, SignOption,
 };
 use lib::git::{GitRunInfo, NonZeroOid, Repo};
 
@@ -272,6 +272,7 @@ pub fn r#move(
             dump_rebase_constraints,
             dump_rebase_plan,
             detect_duplicate_commits_via_patch_id,
+            merge_base_strategy: Default::default(),
         };
         let permissions = {
             let commits_to_move = &source_oids;
@@ -486,6 +487,12 @@ pub fn r#move(
                 force_on_disk,
                 resolve_merge_conflicts,
                 check_out_commit_options: Default::default(),
+                reapply_empty_commits: false,
+                preserve_committer: false,
+                reset_author: false,
+                sign_option: SignOption::Disable,
+                no_verify: false,
+                commit_trailers: Vec::new(),
             };
             execute_rebase_plan(
                 effects,
@@ -503,7 +510,10 @@ pub fn r#move(
     };
 
     match result {
-        ExecuteRebasePlanResult::Succeeded { rewritten_oids: _ } => Ok(Ok(())),
+        ExecuteRebasePlanResult::Succeeded {
+            rewritten_oids: _,
+            post_rewrite_hook_exit_code: _,
+        } => Ok(Ok(())),
 
         ExecuteRebasePlanResult::DeclinedToMerge { failed_merge_info } => {
             failed_merge_info.describe(effects, &repo, MergeConflictRemediation::Retry)?;
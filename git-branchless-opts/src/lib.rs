@@ -11,6 +11,7 @@
 
 use std::ffi::OsString;
 use std::fmt::Display;
+use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
@@ -36,6 +37,37 @@ impl Display for Revset {
     }
 }
 
+/// A commit message trailer, written as `KEY=VALUE`.
+#[derive(Clone, Debug)]
+pub struct Trailer {
+    /// The trailer key, such as `Rebased-onto`.
+    pub key: String,
+
+    /// The trailer value.
+    pub value: String,
+}
+
+impl FromStr for Trailer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((key, value))
+                if !key.is_empty()
+                    && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') =>
+            {
+                Ok(Self {
+                    key: key.to_string(),
+                    value: value.to_string(),
+                })
+            }
+            Some(_) | None => Err(format!(
+                "expected KEY=VALUE, where KEY consists of letters, digits, and hyphens: {s}"
+            )),
+        }
+    }
+}
+
 /// A command wrapped by `git-branchless wrap`. The arguments are forwarded to
 /// `git`.
 #[derive(Debug, Parser)]
@@ -46,7 +78,7 @@ pub enum WrappedCommand {
 }
 
 /// Options for resolving revset expressions.
-#[derive(Args, Debug, Default)]
+#[derive(Args, Clone, Debug, Default)]
 pub struct ResolveRevsetOptions {
     /// Include hidden commits in the results of evaluating revset expressions.
     #[clap(action, long = "hidden")]
@@ -54,7 +86,7 @@ pub struct ResolveRevsetOptions {
 }
 
 /// Options for moving commits.
-#[derive(Args, Debug)]
+#[derive(Args, Clone, Debug)]
 pub struct MoveOptions {
     /// Force moving public commits, even though other people may have access to
     /// those commits.
@@ -93,6 +125,21 @@ pub struct MoveOptions {
     pub dump_rebase_plan: bool,
 }
 
+impl Default for MoveOptions {
+    /// The same options as when no flags are passed on the command-line.
+    fn default() -> Self {
+        Self {
+            force_rewrite_public_commits: false,
+            force_in_memory: false,
+            force_on_disk: false,
+            detect_duplicate_commits_via_patch_id: true,
+            resolve_merge_conflicts: false,
+            dump_rebase_constraints: false,
+            dump_rebase_plan: false,
+        }
+    }
+}
+
 /// Options for traversing commits.
 #[derive(Args, Debug)]
 pub struct TraverseCommitsOptions {
@@ -412,6 +459,535 @@ pub struct SubmitArgs {
     pub dry_run: bool,
 }
 
+/// Options for fetching before syncing.
+#[derive(Args, Clone, Debug, Default)]
+pub struct SyncFetchOptions {
+    /// Run `git fetch` to update remote references before carrying out the
+    /// sync.
+    #[clap(
+        action,
+        short = 'p',
+        long = "pull",
+        visible_short_alias = 'u',
+        visible_alias = "--update"
+    )]
+    pub pull: bool,
+
+    /// Don't fetch tags when pulling, which can be slow in repositories with
+    /// many tags. Defaults to the opposite of `branchless.sync.fetchTags`.
+    #[clap(action, long = "no-fetch-tags", requires = "pull")]
+    pub no_fetch_tags: bool,
+
+    /// Use the provided commit as the upstream main branch when pulling,
+    /// rather than the upstream branch tracked by the local main branch.
+    /// Must resolve to exactly one commit.
+    #[clap(value_parser, long = "upstream", value_name = "REF", requires = "pull")]
+    pub upstream: Option<Revset>,
+
+    /// Fetch only this remote, and update the main branch to that remote's
+    /// copy of it (unless `--upstream` is passed). Takes precedence over
+    /// `branchless.sync.remote`. If neither is set, the remote tracked by
+    /// the main branch is fetched, or every remote if it doesn't track one.
+    #[clap(value_parser, long = "fetch-remote", value_name = "REMOTE")]
+    pub fetch_remote: Option<String>,
+
+    /// Update the main branch to its upstream branch as with `--pull`, but
+    /// without fetching first, such as when remote references are fetched
+    /// by a separate process.
+    #[clap(action, long = "update-main-no-fetch", conflicts_with = "pull")]
+    pub update_main_no_fetch: bool,
+
+    /// Only update the main branch to its upstream branch (after fetching,
+    /// if `--pull` is also passed), without moving any stacks. Exits with a
+    /// non-zero code if the main branch couldn't be updated.
+    #[clap(
+        action,
+        long = "upstream-only",
+        conflicts_with_all(&["onto", "revsets", "publish", "print_plan_only"])
+    )]
+    pub upstream_only: bool,
+
+    /// Don't ask for confirmation before rebasing the commits on the local
+    /// main branch which aren't on its upstream branch. Has no effect when
+    /// not running interactively, since no confirmation is asked for then.
+    #[clap(action, short = 'y', long = "yes")]
+    pub yes: bool,
+}
+
+/// Options for selecting which stacks to sync.
+#[derive(Args, Clone, Debug, Default)]
+pub struct SyncSelectOptions {
+    /// Permit rewriting the provided public commits, even though other
+    /// people may have access to them. Unlike `--force-rewrite`, any other
+    /// public commits are still protected.
+    #[clap(action(clap::ArgAction::Append), long = "include-public")]
+    pub include_public: Vec<Revset>,
+
+    /// Don't sync the stacks containing any of the provided commits. Stacks
+    /// are selected as usual (e.g. by the positional revsets) before these
+    /// are excluded.
+    #[clap(action(clap::ArgAction::Append), long = "exclude")]
+    pub exclude: Vec<Revset>,
+
+    /// Only sync stacks which touch a path matching one of the provided
+    /// glob patterns. Patterns prefixed with `!` exclude matching paths.
+    #[clap(
+        action(clap::ArgAction::Append),
+        long = "touched-paths",
+        value_name = "GLOB"
+    )]
+    pub touched_paths: Vec<String>,
+
+    /// Only sync the stacks which couldn't be moved due to merge conflicts
+    /// the last time that they were synced, such as after resolving the
+    /// underlying issue on the main branch.
+    #[clap(action, long = "only-conflicting", conflicts_with = "onto")]
+    pub only_conflicting: bool,
+
+    /// Sync at most this many stacks which need to be moved. The remaining
+    /// stacks can be synced by running the command again.
+    #[clap(value_parser, long = "limit", value_name = "N")]
+    pub limit: Option<usize>,
+
+    /// Move only the bottom N commits of each stack. The commits above them
+    /// are left on top of the original versions of those commits, to be
+    /// moved by a later `git restack`.
+    #[clap(
+        value_parser,
+        long = "max-depth",
+        value_name = "N",
+        conflicts_with = "onto"
+    )]
+    pub max_depth: Option<NonZeroUsize>,
+
+    /// Build the sync plans against the state of the repository as of the
+    /// provided event ID, rather than the current state. This is useful
+    /// to reproduce an earlier sync for debugging. The plans are only
+    /// reported, not executed.
+    #[clap(value_parser, long = "at-event", value_name = "EVENT-ID")]
+    pub at_event: Option<isize>,
+
+    /// Only sync the stacks containing commits which were created or
+    /// rewritten after the provided event ID, such as the latest event ID
+    /// recorded by a previous run of an automated sync.
+    #[clap(value_parser, long = "since-event", value_name = "EVENT-ID")]
+    pub since_event: Option<isize>,
+
+    /// Sync every draft stack when no revsets are provided, ignoring the
+    /// revsets configured with `branchless.sync.defaultRevsets`.
+    #[clap(action, long = "all", conflicts_with_all(&["revsets", "describe_plan"]))]
+    pub all: bool,
+
+    /// Move stacks which share no history with the commit they'd be moved
+    /// onto (such as stacks created with `git checkout --orphan`), by
+    /// making that commit the parent of the stack's root. By default, such
+    /// stacks are skipped.
+    #[clap(action, long = "allow-unrelated-histories")]
+    pub allow_unrelated_histories: bool,
+
+    /// When a stack is based on commits of a trunk branch (see
+    /// `--base-auto`) which aren't on the main branch, move those commits
+    /// along with the stack, so that the whole branch is moved onto the
+    /// main branch. Requires `--force-rewrite`, since this rewrites the
+    /// trunk branch.
+    #[clap(
+        action,
+        long = "include-public-roots",
+        requires = "base_auto",
+        requires = "force_rewrite_public_commits"
+    )]
+    pub include_public_roots: bool,
+}
+
+/// Options for rewriting the commits in the synced stacks.
+#[derive(Args, Clone, Debug, Default)]
+pub struct SyncRewriteOptions {
+    /// Preserve the merge commits in stacks which contain them. Stacks
+    /// which are joined by a merge commit are moved together, and the
+    /// merge commits are recreated on top of the main branch. Since merge
+    /// commits can't be rebased in-memory, such stacks are rebased on-disk.
+    #[clap(action, long = "rebase-merges", conflicts_with = "force_in_memory")]
+    pub rebase_merges: bool,
+
+    /// If a stack contains public commits, prompt for confirmation before
+    /// rewriting them, rather than aborting. Stacks which aren't confirmed
+    /// are skipped. Has no effect when not running interactively.
+    #[clap(action, long = "confirm")]
+    pub confirm: bool,
+
+    /// If a stack can't be moved due to merge conflicts, make sure that its
+    /// branches are left at the commits they pointed to before the sync.
+    #[clap(action, long = "keep-branches-on-conflict")]
+    pub keep_branches_on_conflict: bool,
+
+    /// Keep going after a stack fails to sync, in both phases of the sync:
+    /// stacks whose rebase plans can't be built are skipped (all such errors
+    /// are reported together), and stacks with merge conflicts are skipped,
+    /// while the remaining stacks are still synced. Overrides an earlier
+    /// `--fail-fast`.
+    #[clap(
+        action,
+        long = "keep-going",
+        visible_alias = "no-fail-fast",
+        overrides_with = "fail_fast"
+    )]
+    pub keep_going: bool,
+
+    /// Stop at the first stack which fails to sync, in both phases of the
+    /// sync: if a stack's rebase plan can't be built, the sync is aborted
+    /// before any stack is moved, and once a stack has merge conflicts, the
+    /// remaining stacks aren't attempted. Overrides an earlier `--keep-going`.
+    ///
+    /// If neither option is passed, the sync is aborted if a stack's rebase
+    /// plan can't be built, but stacks with merge conflicts are skipped while
+    /// the remaining stacks are still synced.
+    #[clap(action, long = "fail-fast", overrides_with = "keep_going")]
+    pub fail_fast: bool,
+
+    /// Keep commits which become empty when moved onto the main branch,
+    /// rather than dropping them. Useful when empty commits are used as
+    /// placeholders.
+    #[clap(action, long = "reapply-empty")]
+    pub reapply_empty: bool,
+
+    /// Keep the original committer name, email, and timestamp on rewritten
+    /// commits, rather than updating them. Only in-memory rebases are
+    /// attempted, since `git rebase` always sets the committer itself.
+    #[clap(action, long = "preserve-committer", conflicts_with = "force_on_disk")]
+    pub preserve_committer: bool,
+
+    /// Set the author of each rewritten commit to the current user (as
+    /// configured with `user.name` and `user.email`), like `git commit
+    /// --amend --reset-author`, such as when taking over someone else's
+    /// stack. Commits which aren't rewritten keep their author. Only
+    /// in-memory rebases are attempted, since `git rebase` can't reset the
+    /// author.
+    #[clap(action, long = "reset-author", conflicts_with = "force_on_disk")]
+    pub reset_author: bool,
+
+    /// Sign the rewritten commits, with the provided key if any, or else
+    /// the key configured with `user.signingKey`. Defaults to the value of
+    /// `commit.gpgSign`. Otherwise, the signatures of rewritten commits are
+    /// dropped.
+    #[clap(
+        value_parser,
+        long = "gpg-sign",
+        value_name = "KEYID",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = ""
+    )]
+    pub gpg_sign: Option<String>,
+
+    /// Skip the user-facing hooks which Git would otherwise run during the
+    /// sync: `prepare-commit-msg` and `post-commit` for each rebased commit,
+    /// and `pre-push` when publishing. The `post-rewrite`,
+    /// `reference-transaction`, and `post-checkout` hooks still run, since
+    /// git-branchless relies on them to keep its event log up to date. Only
+    /// in-memory rebases are attempted.
+    #[clap(action, long = "no-verify", conflicts_with = "force_on_disk")]
+    pub no_verify: bool,
+
+    /// Fail the sync if the `post-rewrite` hook fails for any of the synced
+    /// stacks. By default, such failures are reported once the sync
+    /// finishes, but don't fail it. Hook failures are only detected for
+    /// stacks rebased in-memory, since `git rebase` ignores them.
+    #[clap(action, long = "strict-hooks")]
+    pub strict_hooks: bool,
+
+    /// If the working copy has uncommitted changes, stash them before syncing
+    /// and restore them once the sync has finished, like `git rebase
+    /// --autostash`. Defaults to the value of `rebase.autoStash`.
+    #[clap(action, long = "autostash", overrides_with = "no_autostash")]
+    pub autostash: bool,
+
+    /// Don't stash uncommitted changes before syncing, even if
+    /// `rebase.autoStash` is set.
+    #[clap(action, long = "no-autostash", overrides_with = "autostash")]
+    pub no_autostash: bool,
+
+    /// Move tags which point to rewritten commits so that they point to the
+    /// rewritten commits instead. Annotated tags are not moved, since moving
+    /// them would change the tag object.
+    #[clap(action, long = "update-tags")]
+    pub update_tags: bool,
+
+    /// After syncing stacks which change submodules, run `git submodule
+    /// update --recursive` to check out the submodule commits. (Stacks which
+    /// change submodules are always rebased on-disk.)
+    #[clap(action, long = "recurse-submodules")]
+    pub recurse_submodules: bool,
+
+    /// An additional argument to pass to `git checkout` when the sync
+    /// checks out a rewritten commit, such as `--recurse-submodules`. May
+    /// be passed multiple times.
+    #[clap(
+        action(clap::ArgAction::Append),
+        long = "checkout-arg",
+        value_name = "ARG",
+        allow_hyphen_values = true
+    )]
+    pub checkout_args: Vec<String>,
+
+    /// After each stack is synced, check out each of its rewritten commits
+    /// and run the provided shell command on it. If the command fails on
+    /// any commit, the stack is reported as failed and moved back to its
+    /// original commits.
+    #[clap(value_parser, long = "verify-after", value_name = "COMMAND")]
+    pub verify_after: Option<String>,
+
+    /// Append the trailer `KEY: VALUE` to the message of each rewritten
+    /// commit, such as `--add-trailer 'Rebased-onto={onto}'`. In the value,
+    /// `{onto}` is replaced with the hash of the commit which the stack was
+    /// moved onto. May be passed multiple times. Only in-memory rebases are
+    /// attempted. A trailer which the commit message already ends with isn't
+    /// added again, but existing trailers are only recognized if they use
+    /// `:` as the separator and the last paragraph consists only of
+    /// trailers.
+    #[clap(value_parser, long = "add-trailer", value_name = "KEY=VALUE")]
+    pub add_trailers: Vec<Trailer>,
+
+    /// Record the rewrites of each batch of this many stacks as a separate
+    /// event, rather than recording the whole sync as one event. `git undo`
+    /// then undoes one batch at a time, which makes it possible to undo only
+    /// the last part of a large sync.
+    #[clap(value_parser, long = "batch-size", value_name = "N")]
+    pub batch_size: Option<NonZeroUsize>,
+
+    /// Before moving any branches, record the commit that each of them
+    /// points to under `refs/branchless/backup/`, so that the pre-sync state
+    /// can be found with `--list-backups` and restored with
+    /// `--restore-backup`.
+    #[clap(action, long = "record-backup-refs")]
+    pub record_backup_refs: bool,
+
+    /// How to compute the merge-bases used to find the upstream commits
+    /// which the stacks' commits may already have been applied to.
+    #[clap(value_enum, long = "merge-base-strategy")]
+    pub merge_base_strategy: Option<SyncMergeBaseStrategy>,
+}
+
+/// Options for reporting on the sync.
+#[derive(Args, Clone, Debug, Default)]
+pub struct SyncOutputOptions {
+    /// Only print the computed rebase plans, without executing them. Each
+    /// plan is printed as `plan <root-oid>`, followed by one indented line
+    /// per command (e.g. `pick <oid>`), using full commit hashes so that the
+    /// output can be diffed across runs.
+    #[clap(action, long = "print-plan-only")]
+    pub print_plan_only: bool,
+
+    /// The format in which to print the plans when `--print-plan-only` is
+    /// passed.
+    #[clap(value_enum, long = "format", requires = "print_plan_only")]
+    pub format: Option<SyncPlanFormat>,
+
+    /// Write the result to the provided file, replacing it if it exists. With
+    /// `--print-plan-only`, the plans are written to the file instead of
+    /// being printed. Otherwise, progress is printed as usual, and once the
+    /// sync finishes, a JSON object listing the stacks which were synced and
+    /// which had merge conflicts is written to the file. The file is written
+    /// atomically, so a partially-written file is never left behind.
+    #[clap(
+        value_parser,
+        long = "output-file",
+        value_name = "PATH",
+        conflicts_with = "describe_plan"
+    )]
+    pub output_file: Option<PathBuf>,
+
+    /// How to report progress while stacks are being moved.
+    #[clap(value_parser, long = "progress")]
+    pub progress: Option<SyncProgressFormat>,
+
+    /// After syncing, print statistics about the rebases which were
+    /// executed, such as how many commits were rewritten or skipped.
+    #[clap(action, long = "statistics")]
+    pub statistics: bool,
+
+    /// Report how long each stack took to sync.
+    #[clap(action, long = "timing")]
+    pub timing: bool,
+
+    /// Group the synced stacks by the commit that they were moved onto, such
+    /// as when syncing with `--base-auto`, rather than listing them in a
+    /// single list.
+    #[clap(action, long = "group-by-base")]
+    pub group_by_base: bool,
+
+    /// As each stack finishes, print a line containing a JSON object
+    /// describing the result, such as
+    /// `{"root_oid":"...","status":"synced","summary":"..."}`.
+    #[clap(action, long = "json-lines")]
+    pub json_lines: bool,
+
+    /// When a stack isn't synced due to merge conflicts, print the
+    /// conflicting regions of each conflicting file, with conflict markers.
+    /// Requires Git v2.40 or later.
+    #[clap(action, long = "show-conflicted-diff")]
+    pub show_conflicted_diff: bool,
+
+    /// After syncing, list the stacks which weren't moved but whose commits
+    /// all appear to have been applied to the main branch (as determined by
+    /// patch ID), since they can likely be hidden. Nothing is hidden
+    /// automatically.
+    #[clap(action, long = "report-merged")]
+    pub report_merged: bool,
+
+    /// Log each Git subprocess run during the sync, with its arguments,
+    /// working directory, Git-related environment variables, exit code, and
+    /// duration. The log is written to stderr if no file (or `-`) is
+    /// provided, and otherwise appended to the provided file. Environment
+    /// variables which may contain credentials are redacted.
+    #[clap(
+        value_parser,
+        long = "trace-git",
+        value_name = "FILE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = ""
+    )]
+    pub trace_git: Option<String>,
+
+    /// Record a timing profile of the sync, covering each phase (fetching,
+    /// planning, rebasing, garbage collection, etc.), and write it to the
+    /// provided file in the Chrome tracing format. To view it, open the file
+    /// in https://ui.perfetto.dev or at `chrome://tracing` in Chromium-based
+    /// browsers. Set `RUST_PROFILE_INCLUDE_ARGS=1` to also record the
+    /// arguments of each traced function.
+    #[clap(value_parser, long = "profile", value_name = "PATH")]
+    pub profile: Option<PathBuf>,
+
+    /// Write the part of the commit graph relevant to the sync (the stacks
+    /// being synced, the main branch and its upstream branch, and the
+    /// commits connecting them) to the provided file as JSON. Attaching it
+    /// to a bug report lets the sync be reproduced without the repository.
+    #[clap(value_parser, long = "dump-dag", value_name = "PATH")]
+    pub dump_dag: Option<PathBuf>,
+
+    /// Explain what would happen to the stacks containing the provided
+    /// commits, and why, without moving them: whether each stack is
+    /// skipped or moved (and onto which commit), and whether moving it
+    /// would cause merge conflicts.
+    #[clap(
+        value_parser,
+        long = "describe-plan",
+        value_name = "REVSET",
+        conflicts_with_all(&["revsets", "onto", "print_plan_only", "pull", "update_main_no_fetch", "publish"])
+    )]
+    pub describe_plan: Option<Revset>,
+}
+
+/// Move any local commit stacks on top of the main branch.
+#[derive(Debug, Default, Parser)]
+pub struct SyncArgs {
+    /// Options for fetching before syncing.
+    #[clap(flatten)]
+    pub fetch_options: SyncFetchOptions,
+
+    /// Options for moving commits.
+    #[clap(flatten)]
+    pub move_options: MoveOptions,
+
+    /// Options for selecting which stacks to sync.
+    #[clap(flatten)]
+    pub select_options: SyncSelectOptions,
+
+    /// Options for rewriting the commits in the synced stacks.
+    #[clap(flatten)]
+    pub rewrite_options: SyncRewriteOptions,
+
+    /// Options for reporting on the sync.
+    #[clap(flatten)]
+    pub output_options: SyncOutputOptions,
+
+    /// After syncing, pack the loose objects if there are more of them than
+    /// the `gc.auto` threshold. Defaults to the value of
+    /// `branchless.sync.autoGc`.
+    #[clap(action, long = "gc")]
+    pub gc: bool,
+
+    /// After syncing, refresh Git's commit-graph file with `git commit-graph
+    /// write --reachable` if at least
+    /// `branchless.sync.writeCommitGraphMinCommits` (default: 100) commits
+    /// were rewritten, which speeds up later commit graph queries. Skipped if
+    /// `core.commitGraph` is disabled. Defaults to the value of
+    /// `branchless.sync.writeCommitGraph`.
+    #[clap(action, long = "commit-graph-refresh")]
+    pub commit_graph_refresh: bool,
+
+    /// When the sync finishes, send a desktop notification saying how many
+    /// stacks were synced and how many had merge conflicts, such as for
+    /// long-running syncs. Nothing is sent if no notifier is available (e.g.
+    /// `notify-send` on Linux). Defaults to the value of
+    /// `branchless.sync.notify`.
+    #[clap(action, long = "notify")]
+    pub notify: bool,
+
+    /// After syncing, force-push the branches in each successfully-synced
+    /// stack to the default push remote under a per-user namespace, so
+    /// that shared branches aren't clobbered. The remote reference name is
+    /// configured with `branchless.sync.publishRefTemplate` (default:
+    /// `refs/heads/users/{user}/{branch}`). A remote reference is only
+    /// overwritten if it hasn't changed since it was last fetched or
+    /// published.
+    #[clap(action, long = "publish")]
+    pub publish: bool,
+
+    /// Instead of syncing, list the backups recorded by
+    /// `--record-backup-refs`, newest first. Backups older than
+    /// `branchless.sync.backupExpiryDays` (default: 30) are deleted.
+    #[clap(action, long = "list-backups", conflicts_with = "restore_backup")]
+    pub list_backups: bool,
+
+    /// Instead of syncing, recreate the branch backed up by the given backup
+    /// (as printed by `--list-backups`) at the commit that it pointed to.
+    #[clap(value_parser, long = "restore-backup", value_name = "BACKUP")]
+    pub restore_backup: Option<String>,
+
+    /// Move only the provided commits (and their descendants) onto this
+    /// commit, rather than moving whole stacks onto the main branch. The
+    /// provided commits must form a contiguous range within one stack, such
+    /// as `<commit>..<tip>`; the stack is cut below the earliest of them,
+    /// and the commits underneath are left in place. Options which select
+    /// or publish whole stacks can't be used with `--onto`.
+    #[clap(
+        value_parser,
+        long = "onto",
+        value_name = "REVSET",
+        requires = "revsets",
+        conflicts_with_all(&["exclude", "touched_paths", "confirm", "at_event", "since_event", "publish", "keep_branches_on_conflict"])
+    )]
+    pub onto: Option<Revset>,
+
+    /// Like `--onto`, but move the provided commits onto the commit that the
+    /// given tag points to. Annotated tags are peeled to the commit which
+    /// they tag.
+    #[clap(
+        value_parser,
+        long = "onto-tag",
+        value_name = "TAG",
+        requires = "revsets",
+        conflicts_with_all(&["onto", "exclude", "touched_paths", "confirm", "at_event", "since_event", "publish", "keep_branches_on_conflict"])
+    )]
+    pub onto_tag: Option<String>,
+
+    /// For each stack, choose the base to move it onto among the main branch
+    /// and the branches listed in `branchless.sync.trunks`, picking the one
+    /// whose merge-base with the stack is nearest.
+    #[clap(action, long = "base-auto", conflicts_with_all(&["onto", "onto_tag"]))]
+    pub base_auto: bool,
+
+    /// The commits whose stacks will be moved on top of the main branch. If
+    /// no commits are provided, all draft commits will be synced.
+    #[clap(value_parser)]
+    pub revsets: Vec<Revset>,
+
+    /// Options for resolving revset expressions.
+    #[clap(flatten)]
+    pub resolve_revset_options: ResolveRevsetOptions,
+}
+
 /// Run a command on each commit in a given set and aggregate the results.
 #[derive(Debug, Parser)]
 pub struct TestArgs {
@@ -421,6 +997,9 @@ pub struct TestArgs {
 }
 
 /// FIXME: write man-page text
+// The `Sync` variant has many more options than the others, but only one
+// `Command` is ever constructed, so its size doesn't matter.
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Parser)]
 pub enum Command {
     /// Amend the current HEAD commit.
@@ -649,31 +1228,7 @@ pub enum Command {
     },
 
     /// Move any local commit stacks on top of the main branch.
-    Sync {
-        /// Run `git fetch` to update remote references before carrying out the
-        /// sync.
-        #[clap(
-            action,
-            short = 'p',
-            long = "pull",
-            visible_short_alias = 'u',
-            visible_alias = "--update"
-        )]
-        pull: bool,
-
-        /// Options for moving commits.
-        #[clap(flatten)]
-        move_options: MoveOptions,
-
-        /// The commits whose stacks will be moved on top of the main branch. If
-        /// no commits are provided, all draft commits will be synced.
-        #[clap(value_parser)]
-        revsets: Vec<Revset>,
-
-        /// Options for resolving revset expressions.
-        #[clap(flatten)]
-        resolve_revset_options: ResolveRevsetOptions,
-    },
+    Sync(SyncArgs),
 
     /// Run a command on each commit in a given set and aggregate the results.
     Test(TestArgs),
@@ -729,6 +1284,45 @@ pub enum ColorSetting {
     Never,
 }
 
+/// How to print the plans computed by `git sync --print-plan-only`.
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum SyncPlanFormat {
+    /// Default. Print each plan as a list of rebase commands.
+    #[default]
+    Text,
+
+    /// Print a Graphviz (DOT) graph of the stacks, with a dashed edge from
+    /// the root of each stack to where it will be moved.
+    Graphviz,
+}
+
+/// How `git sync` computes merge-bases when building the plans.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum SyncMergeBaseStrategy {
+    /// Default. Query the commit graph maintained by `git-branchless`, which
+    /// is fast even for large histories.
+    #[default]
+    CommitGraph,
+
+    /// Run `git merge-base` for each merge-base. This is slower, but can be
+    /// used to cross-check the results of the commit graph.
+    Git,
+}
+
+/// How to report the progress of `git sync` while it moves stacks.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum SyncProgressFormat {
+    /// Default. Display a progress bar in the terminal.
+    #[default]
+    Tui,
+
+    /// Print a line as each stack finishes, which is suitable for logs.
+    Plain,
+
+    /// Don't report progress.
+    None,
+}
+
 /// How to execute tests.
 #[derive(Clone, Copy, Debug, ValueEnum)]
 pub enum TestExecutionStrategy {
@@ -87,7 +87,7 @@ fn test_query_eval_error() -> eyre::Result<()> {
             },
         )?;
         insta::assert_snapshot!(stderr, @r###"
-        Evaluation error for expression 'foo()': no function with the name 'foo' could be found; these functions are available: all, ancestors, ancestors.nth, author.date, author.email, author.name, branches, children, committer.date, committer.email, committer.name, current, descendants, difference, draft, exactly, heads, intersection, main, merges, message, none, not, only, parents, parents.nth, paths.changed, public, range, roots, siblings, stack, tests.failed, tests.fixable, tests.passed, union
+        Evaluation error for expression 'foo()': no function with the name 'foo' could be found; these functions are available: all, ancestors, ancestors.nth, author.date, author.email, author.name, branches, children, committer.date, committer.email, committer.name, current, descendants, difference, draft, exactly, heads, intersection, main, merges, message, needs_sync, none, not, only, parents, parents.nth, paths.changed, public, range, roots, siblings, stack, synced, tests.failed, tests.fixable, tests.passed, union
         "###);
         insta::assert_snapshot!(stdout, @"");
     }
@@ -129,6 +129,62 @@ fn test_query_legacy_git_syntax() -> eyre::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_query_revset_aliases() -> eyre::Result<()> {
+    let git = make_git()?;
+    git.init_repo()?;
+
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.commit_file("test3", 3)?;
+
+    git.run(&["config", "branchless.revsetAlias.top", "HEAD"])?;
+    git.run(&["config", "branchless.revsetAlias.base", "@top~2"])?;
+    {
+        let (stdout, stderr) = git.branchless("query", &["@base::@top"])?;
+        insta::assert_snapshot!(stderr, @"");
+        insta::assert_snapshot!(stdout, @r###"
+        62fc20d create test1.txt
+        96d1c37 create test2.txt
+        70deb1e create test3.txt
+        "###);
+    }
+
+    git.run(&["config", "branchless.revsetAlias.foo", "@bar | HEAD"])?;
+    git.run(&["config", "branchless.revsetAlias.bar", "parents(@foo)"])?;
+    {
+        let (stdout, stderr) = git.branchless_with_options(
+            "query",
+            &["@base | @foo"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stderr, @"Parse error for expression '@base | @foo': revset alias '@foo' refers to itself: @foo -> @bar -> @foo");
+        insta::assert_snapshot!(stdout, @"");
+    }
+
+    git.run(&["config", "branchless.revsetAlias.broken", "foo("])?;
+    {
+        let (stdout, stderr) = git.branchless_with_options(
+            "query",
+            &["@broken"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stderr, @r###"
+        Parse error for expression '@broken': failed to parse revset alias '@broken' = 'foo(': parse error: Unrecognized EOF found at 4
+        Expected one of "(", ")", "..", ":", "::", a commit/branch/tag or a string literal
+        "###);
+        insta::assert_snapshot!(stdout, @"");
+    }
+
+    Ok(())
+}
+
 #[test]
 fn test_query_branches() -> eyre::Result<()> {
     let git = make_git()?;
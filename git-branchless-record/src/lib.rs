@@ -28,7 +28,7 @@ use lib::core::repo_ext::RepoExt;
 use lib::core::rewrite::{
     execute_rebase_plan, BuildRebasePlanError, BuildRebasePlanOptions, ExecuteRebasePlanOptions,
     ExecuteRebasePlanResult, MergeConflictRemediation, RebasePlanBuilder, RebasePlanPermissions,
-    RepoResource,
+    RepoResource, SignOption,
 };
 use lib::git::{
     process_diff_for_record, update_index, CategorizedReferenceName, FileMode, GitRunInfo,
@@ -461,6 +461,7 @@ fn insert_before_siblings(
         dump_rebase_constraints: false,
         dump_rebase_plan: false,
         detect_duplicate_commits_via_patch_id: true,
+        merge_base_strategy: Default::default(),
     };
 
     let rebase_plan_result =
@@ -548,6 +549,12 @@ To proceed anyways, run: git move -f -s 'siblings(.)",
         force_on_disk: false,
         resolve_merge_conflicts: false,
         check_out_commit_options: Default::default(),
+        reapply_empty_commits: false,
+        preserve_committer: false,
+        reset_author: false,
+        sign_option: SignOption::Disable,
+        no_verify: false,
+        commit_trailers: Vec::new(),
     };
     let result = execute_rebase_plan(
         effects,
@@ -558,7 +565,10 @@ To proceed anyways, run: git move -f -s 'siblings(.)",
         &execute_options,
     )?;
     match result {
-        ExecuteRebasePlanResult::Succeeded { rewritten_oids: _ } => Ok(Ok(())),
+        ExecuteRebasePlanResult::Succeeded {
+            rewritten_oids: _,
+            post_rewrite_hook_exit_code: _,
+        } => Ok(Ok(())),
         ExecuteRebasePlanResult::DeclinedToMerge { failed_merge_info } => {
             failed_merge_info.describe(effects, &repo, MergeConflictRemediation::Insert)?;
             Ok(Ok(()))
@@ -0,0 +1,76 @@
+//! Expansion of revset aliases, which are configured with
+//! `branchless.revsetAlias.<name>` and referred to as `@<name>`.
+
+use std::borrow::Cow;
+
+use itertools::Itertools;
+use lib::git::{ConfigRead, Repo};
+use tracing::instrument;
+
+use super::parser::{parse, ParseError};
+use super::Expr;
+
+/// Replace each `@<name>` in the expression with the expression configured as
+/// `branchless.revsetAlias.<name>`. Aliases may refer to other aliases, but not
+/// (directly or indirectly) to themselves. Names for which no alias is
+/// configured are left as-is.
+#[instrument]
+pub fn expand_aliases(repo: &Repo, expr: &Expr) -> Result<Expr<'static>, ParseError> {
+    expand_aliases_inner(repo, expr, &mut Vec::new())
+}
+
+fn expand_aliases_inner(
+    repo: &Repo,
+    expr: &Expr,
+    expanding_names: &mut Vec<String>,
+) -> Result<Expr<'static>, ParseError> {
+    match expr {
+        Expr::Name(name) => {
+            let alias_name = match name.strip_prefix('@') {
+                Some(alias_name) if !alias_name.is_empty() => alias_name,
+                _ => return Ok(expr.clone().into_owned()),
+            };
+            let alias = match read_alias(repo, alias_name)? {
+                Some(alias) => alias,
+                None => return Ok(expr.clone().into_owned()),
+            };
+            if let Some(cycle_start) = expanding_names
+                .iter()
+                .position(|expanding_name| expanding_name == alias_name)
+            {
+                let mut names = expanding_names[cycle_start..].to_vec();
+                names.push(alias_name.to_owned());
+                return Err(ParseError::AliasCycle { names });
+            }
+
+            let alias_expr = parse(&alias).map_err(|err| ParseError::ParseAlias {
+                name: alias_name.to_owned(),
+                alias: alias.clone(),
+                source: Box::new(err),
+            })?;
+            expanding_names.push(alias_name.to_owned());
+            let result = expand_aliases_inner(repo, &alias_expr, expanding_names);
+            expanding_names.pop();
+            result
+        }
+
+        Expr::FunctionCall(name, args) => {
+            let args = args
+                .iter()
+                .map(|arg| expand_aliases_inner(repo, arg, expanding_names))
+                .try_collect()?;
+            Ok(Expr::FunctionCall(Cow::Owned(name.to_string()), args))
+        }
+    }
+}
+
+fn read_alias(repo: &Repo, name: &str) -> Result<Option<String>, ParseError> {
+    let read_alias_error = |source: eyre::Error| ParseError::ReadAlias {
+        name: name.to_owned(),
+        source,
+    };
+    repo.get_readonly_config()
+        .map_err(|err| read_alias_error(err.into()))?
+        .get(format!("branchless.revsetAlias.{name}"))
+        .map_err(read_alias_error)
+}
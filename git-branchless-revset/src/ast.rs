@@ -46,4 +46,16 @@ impl<'input> Expr<'input> {
             }
         }
     }
+
+    /// Convert this expression into one which doesn't borrow from the input
+    /// string it was parsed from.
+    pub fn into_owned(self) -> Expr<'static> {
+        match self {
+            Expr::Name(name) => Expr::Name(Cow::Owned(name.into_owned())),
+            Expr::FunctionCall(name, args) => Expr::FunctionCall(
+                Cow::Owned(name.into_owned()),
+                args.into_iter().map(Expr::into_owned).collect(),
+            ),
+        }
+    }
 }
@@ -2,9 +2,9 @@ use bstr::ByteSlice;
 use eden_dag::nameset::hints::Hints;
 
 use lib::core::dag::CommitSet;
-use lib::core::eventlog::{EventLogDb, EventReplayer};
+use lib::core::eventlog::{Event, EventLogDb, EventReplayer};
 use lib::core::repo_ext::RepoExt;
-use lib::core::rewrite::find_rewrite_target;
+use lib::core::rewrite::{find_rewrite_target, find_stack_roots_needing_sync};
 use lib::git::{
     get_latest_test_command_path, get_test_tree_dir, CategorizedReferenceName, Commit,
     MaybeZeroOid, Repo, SerializedNonZeroOid, SerializedTestResult, TEST_ABORT_EXIT_CODE,
@@ -12,6 +12,7 @@ use lib::git::{
 };
 use std::borrow::Cow;
 use std::collections::HashMap;
+use std::fmt::Write;
 use tracing::{instrument, warn};
 
 use eyre::Context as EyreContext;
@@ -52,6 +53,7 @@ lazy_static! {
             ("draft", &fn_draft),
             ("stack", &fn_stack),
             ("message", &fn_message),
+            ("trailer", &fn_trailer),
             ("paths.changed", &fn_path_changed),
             ("author.name", &fn_author_name),
             ("author.email", &fn_author_email),
@@ -62,6 +64,8 @@ lazy_static! {
             ("exactly", &fn_exactly),
             ("current", &fn_current),
             ("merges", &fn_merges),
+            ("synced", &fn_synced),
+            ("needs_sync", &fn_needs_sync),
             ("tests.passed", &fn_tests_passed),
             ("tests.failed", &fn_tests_failed),
             ("tests.fixable", &fn_tests_fixable),
@@ -364,6 +368,61 @@ fn fn_message(ctx: &mut Context, name: &str, args: &[Expr]) -> EvalResult {
     )
 }
 
+/// Match draft commits with a trailer (such as `Reviewed-on: <url>`) whose
+/// key is the first argument (compared case-insensitively, like Git does) and
+/// whose value matches the second argument. The value pattern is a glob
+/// unless it has a prefix such as `substring:`.
+#[instrument]
+fn fn_trailer(ctx: &mut Context, name: &str, args: &[Expr]) -> EvalResult {
+    let (key, pattern) = match args {
+        [Expr::Name(key), Expr::Name(pattern)] => {
+            (key.to_string(), Pattern::new_glob_by_default(pattern)?)
+        }
+        [Expr::FunctionCall(function_name, _args), _]
+        | [_, Expr::FunctionCall(function_name, _args)] => {
+            return Err(EvalError::ExpectedPatternNotFunction {
+                function_name: function_name.clone().into_owned(),
+            })
+        }
+        args => {
+            return Err(EvalError::ArityMismatch {
+                function_name: name.to_string(),
+                expected_arities: vec![2],
+                actual_arity: args.len(),
+            })
+        }
+    };
+
+    // Public commits can be numerous, and aren't synced anyways, so only
+    // scan the messages of draft commits.
+    let draft_commits = ctx
+        .dag
+        .query_draft_commits()
+        .map_err(EvalError::OtherError)?
+        .clone();
+    make_pattern_matcher_for_set(
+        ctx,
+        name,
+        args,
+        Box::new(move |_repo, commit| {
+            let trailers = match commit.get_trailers() {
+                Ok(trailers) => trailers,
+                Err(err) => {
+                    warn!(?commit, ?err, "Commit trailers could not be read");
+                    return Ok(false);
+                }
+            };
+            // A commit may have several trailers with the same key, such as
+            // when it was reviewed more than once.
+            let result = trailers.iter().any(|(trailer_key, trailer_value)| {
+                trailer_key.eq_ignore_ascii_case(&key) && pattern.matches_text(trailer_value)
+            });
+            Ok(result)
+        }),
+        Some(draft_commits),
+    )
+}
+
 #[instrument]
 fn fn_path_changed(ctx: &mut Context, name: &str, args: &[Expr]) -> EvalResult {
     let pattern = eval1_pattern(ctx, name, args)?;
@@ -560,6 +619,57 @@ fn fn_merges(ctx: &mut Context, name: &str, args: &[Expr]) -> EvalResult {
     )
 }
 
+/// The commits created by the most recent `git sync`.
+#[instrument]
+fn fn_synced(ctx: &mut Context, name: &str, args: &[Expr]) -> EvalResult {
+    eval0(ctx, name, args)?;
+
+    let conn = ctx.repo.get_db_conn()?;
+    let event_log_db = EventLogDb::new(&conn)
+        .wrap_err("Connecting to event log")
+        .map_err(EvalError::OtherError)?;
+    let event_tx_id = match event_log_db
+        .get_latest_transaction_id_with_message("sync")
+        .map_err(EvalError::OtherError)?
+    {
+        Some(event_tx_id) => event_tx_id,
+        None => {
+            writeln!(
+                ctx.effects.get_error_stream(),
+                "No sync has been recorded in the event log, so `{name}()` is empty."
+            )
+            .map_err(|err| EvalError::OtherError(err.into()))?;
+            return Ok(CommitSet::empty());
+        }
+    };
+
+    // With `--batch-size`, each batch after the first is recorded under its
+    // own transaction.
+    let event_tx_ids = event_log_db
+        .get_sync_transaction_ids(event_tx_id)
+        .map_err(EvalError::OtherError)?;
+    let events = event_log_db.get_events().map_err(EvalError::OtherError)?;
+    let synced_oids = events.into_iter().filter_map(|event| match event {
+        Event::RewriteEvent {
+            event_tx_id: rewrite_event_tx_id,
+            old_commit_oid: _,
+            new_commit_oid: MaybeZeroOid::NonZero(new_commit_oid),
+            timestamp: _,
+        } if event_tx_ids.contains(&rewrite_event_tx_id) => Some(new_commit_oid),
+        _ => None,
+    });
+    Ok(synced_oids.collect::<CommitSet>())
+}
+
+/// The roots of the stacks which `git sync` with no arguments would move,
+/// i.e. the draft roots which aren't already children of the main branch
+/// commit.
+#[instrument]
+fn fn_needs_sync(ctx: &mut Context, name: &str, args: &[Expr]) -> EvalResult {
+    eval0(ctx, name, args)?;
+    find_stack_roots_needing_sync(ctx.repo, ctx.dag).map_err(EvalError::OtherError)
+}
+
 fn read_all_test_results(repo: &Repo, commit: &Commit) -> Option<Vec<SerializedTestResult>> {
     let commit_test_dir = get_test_tree_dir(repo, commit).ok()?;
     let mut all_results = Vec::new();
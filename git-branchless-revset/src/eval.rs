@@ -1266,6 +1266,157 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_eval_synced() -> eyre::Result<()> {
+        let git = make_git()?;
+
+        if !git.supports_reference_transactions()? {
+            return Ok(());
+        }
+        git.init_repo()?;
+
+        git.detach_head()?;
+        git.commit_file("test1", 1)?;
+        git.commit_file("test2", 2)?;
+        git.run(&["checkout", "master"])?;
+        git.commit_file("test3", 3)?;
+
+        let effects = Effects::new_suppress_for_test(Glyphs::text());
+        let repo = git.get_repo()?;
+        let expr = Expr::FunctionCall(Cow::Borrowed("synced"), vec![]);
+
+        {
+            let conn = repo.get_db_conn()?;
+            let event_log_db = EventLogDb::new(&conn)?;
+            let event_replayer = EventReplayer::from_event_log_db(&effects, &repo, &event_log_db)?;
+            let event_cursor = event_replayer.make_default_cursor();
+            let references_snapshot = repo.get_references_snapshot()?;
+            let mut dag = Dag::open_and_sync(
+                &effects,
+                &repo,
+                &event_replayer,
+                event_cursor,
+                &references_snapshot,
+            )?;
+            insta::assert_debug_snapshot!(eval_and_sort(&effects, &repo, &mut dag, &expr), @r###"
+            Ok(
+                [],
+            )
+            "###);
+        }
+
+        git.run(&["sync"])?;
+
+        {
+            let conn = repo.get_db_conn()?;
+            let event_log_db = EventLogDb::new(&conn)?;
+            let event_replayer = EventReplayer::from_event_log_db(&effects, &repo, &event_log_db)?;
+            let event_cursor = event_replayer.make_default_cursor();
+            let references_snapshot = repo.get_references_snapshot()?;
+            let mut dag = Dag::open_and_sync(
+                &effects,
+                &repo,
+                &event_replayer,
+                event_cursor,
+                &references_snapshot,
+            )?;
+            insta::assert_debug_snapshot!(eval_and_sort(&effects, &repo, &mut dag, &expr), @r###"
+            Ok(
+                [
+                    Commit {
+                        inner: Commit {
+                            id: 4b9ce31bb58aed39e2329c3993ef97b0c4680e49,
+                            summary: "create test1.txt",
+                        },
+                    },
+                    Commit {
+                        inner: Commit {
+                            id: 9f77bc5fa2a01bc869e783c338a757c3b31e32ef,
+                            summary: "create test2.txt",
+                        },
+                    },
+                ],
+            )
+            "###);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_eval_needs_sync() -> eyre::Result<()> {
+        let git = make_git()?;
+
+        if !git.supports_reference_transactions()? {
+            return Ok(());
+        }
+        git.init_repo()?;
+
+        git.detach_head()?;
+        git.commit_file("test1", 1)?;
+        git.commit_file("test2", 2)?;
+        git.run(&["checkout", "master"])?;
+        git.commit_file("test3", 3)?;
+        git.detach_head()?;
+        git.commit_file("test4", 4)?;
+        git.run(&["checkout", "master"])?;
+
+        let effects = Effects::new_suppress_for_test(Glyphs::text());
+        let repo = git.get_repo()?;
+        let expr = Expr::FunctionCall(Cow::Borrowed("needs_sync"), vec![]);
+
+        {
+            let conn = repo.get_db_conn()?;
+            let event_log_db = EventLogDb::new(&conn)?;
+            let event_replayer = EventReplayer::from_event_log_db(&effects, &repo, &event_log_db)?;
+            let event_cursor = event_replayer.make_default_cursor();
+            let references_snapshot = repo.get_references_snapshot()?;
+            let mut dag = Dag::open_and_sync(
+                &effects,
+                &repo,
+                &event_replayer,
+                event_cursor,
+                &references_snapshot,
+            )?;
+            insta::assert_debug_snapshot!(eval_and_sort(&effects, &repo, &mut dag, &expr), @r###"
+            Ok(
+                [
+                    Commit {
+                        inner: Commit {
+                            id: 62fc20d2a290daea0d52bdc2ed2ad4be6491010e,
+                            summary: "create test1.txt",
+                        },
+                    },
+                ],
+            )
+            "###);
+        }
+
+        git.run(&["sync"])?;
+
+        {
+            let conn = repo.get_db_conn()?;
+            let event_log_db = EventLogDb::new(&conn)?;
+            let event_replayer = EventReplayer::from_event_log_db(&effects, &repo, &event_log_db)?;
+            let event_cursor = event_replayer.make_default_cursor();
+            let references_snapshot = repo.get_references_snapshot()?;
+            let mut dag = Dag::open_and_sync(
+                &effects,
+                &repo,
+                &event_replayer,
+                event_cursor,
+                &references_snapshot,
+            )?;
+            insta::assert_debug_snapshot!(eval_and_sort(&effects, &repo, &mut dag, &expr), @r###"
+            Ok(
+                [],
+            )
+            "###);
+        }
+
+        Ok(())
+    }
+
     #[test]
     fn test_eval_branches_with_pattern() -> eyre::Result<()> {
         let git = make_git()?;
@@ -1345,6 +1496,127 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_eval_trailer() -> eyre::Result<()> {
+        let git = make_git()?;
+        git.init_repo()?;
+
+        git.detach_head()?;
+        git.commit_file("test1", 1)?;
+        git.run(&[
+            "commit",
+            "--amend",
+            "-m",
+            "create test1.txt\n\nReviewed-on: https://review.example.com/payments/1",
+        ])?;
+        git.commit_file("test2", 2)?;
+        git.run(&[
+            "commit",
+            "--amend",
+            "-m",
+            "create test2.txt\n\nReviewed-on: https://review.example.com/billing/2\nReviewed-on: https://review.example.com/payments/2",
+        ])?;
+        git.commit_file("test3", 3)?;
+        git.run(&[
+            "commit",
+            "--amend",
+            "-m",
+            "create test3.txt\n\nThis mentions payments, but not in a trailer.",
+        ])?;
+        git.commit_file("test4", 4)?;
+        git.run(&[
+            "commit",
+            "--amend",
+            "-m",
+            "create test4.txt\n\nReviewed-on: https://review.example.com/billing/4",
+        ])?;
+
+        let effects = Effects::new_suppress_for_test(Glyphs::text());
+        let repo = git.get_repo()?;
+        let conn = repo.get_db_conn()?;
+        let event_log_db = EventLogDb::new(&conn)?;
+        let event_replayer = EventReplayer::from_event_log_db(&effects, &repo, &event_log_db)?;
+        let event_cursor = event_replayer.make_default_cursor();
+        let references_snapshot = repo.get_references_snapshot()?;
+        let mut dag = Dag::open_and_sync(
+            &effects,
+            &repo,
+            &event_replayer,
+            event_cursor,
+            &references_snapshot,
+        )?;
+
+        let summaries = |commits: Vec<Commit>| -> eyre::Result<Vec<String>> {
+            commits
+                .iter()
+                .map(|commit| Ok(commit.get_summary()?.to_string()))
+                .collect()
+        };
+
+        {
+            let expr = Expr::FunctionCall(
+                Cow::Borrowed("trailer"),
+                vec![
+                    Expr::Name(Cow::Borrowed("Reviewed-on")),
+                    Expr::Name(Cow::Borrowed("*payments*")),
+                ],
+            );
+            let commits = eval_and_sort(&effects, &repo, &mut dag, &expr)?;
+            assert_eq!(
+                summaries(commits)?,
+                vec!["create test1.txt", "create test2.txt"]
+            );
+        }
+
+        {
+            // Trailer keys are compared case-insensitively.
+            let expr = Expr::FunctionCall(
+                Cow::Borrowed("trailer"),
+                vec![
+                    Expr::Name(Cow::Borrowed("reviewed-on")),
+                    Expr::Name(Cow::Borrowed("substring:billing")),
+                ],
+            );
+            let commits = eval_and_sort(&effects, &repo, &mut dag, &expr)?;
+            assert_eq!(
+                summaries(commits)?,
+                vec!["create test2.txt", "create test4.txt"]
+            );
+        }
+
+        {
+            let expr = Expr::FunctionCall(
+                Cow::Borrowed("trailer"),
+                vec![
+                    Expr::Name(Cow::Borrowed("Change-Id")),
+                    Expr::Name(Cow::Borrowed("*")),
+                ],
+            );
+            let commits = eval_and_sort(&effects, &repo, &mut dag, &expr)?;
+            assert!(commits.is_empty());
+        }
+
+        {
+            let expr = Expr::FunctionCall(
+                Cow::Borrowed("trailer"),
+                vec![Expr::Name(Cow::Borrowed("Reviewed-on"))],
+            );
+            insta::assert_debug_snapshot!(eval_and_sort(&effects, &repo, &mut dag, &expr), @r###"
+            Err(
+                ArityMismatch {
+                    function_name: "trailer",
+                    expected_arities: [
+                        2,
+                    ],
+                    actual_arity: 1,
+                },
+            )
+            "###);
+        }
+
+        Ok(())
+    }
+
     #[test]
     fn test_eval_aliases() -> eyre::Result<()> {
         let git = make_git()?;
@@ -10,6 +10,7 @@
 )]
 #![allow(clippy::too_many_arguments, clippy::blocks_in_if_conditions)]
 
+mod alias;
 mod ast;
 mod builtins;
 mod eval;
@@ -20,7 +21,10 @@ mod resolve;
 pub use ast::Expr;
 pub use eval::eval;
 pub use parser::parse;
-pub use resolve::{check_revset_syntax, resolve_commits, resolve_default_smartlog_commits};
+pub use resolve::{
+    check_revset_syntax, check_revset_syntax_with_references, resolve_commits,
+    resolve_commits_or_git_revisions, resolve_default_smartlog_commits,
+};
 
 use lalrpop_util::lalrpop_mod;
 lalrpop_mod!(
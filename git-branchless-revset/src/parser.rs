@@ -1,3 +1,4 @@
+use itertools::Itertools;
 use lazy_static::lazy_static;
 use regex::Regex;
 use thiserror::Error;
@@ -6,10 +7,24 @@ use tracing::instrument;
 use super::grammar::ExprParser;
 use super::Expr;
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Error)]
 pub enum ParseError {
     #[error("parse error: {0}")]
     ParseError(String),
+
+    #[error("failed to parse revset alias '@{name}' = '{alias}': {source}")]
+    ParseAlias {
+        name: String,
+        alias: String,
+        source: Box<ParseError>,
+    },
+
+    #[error("revset alias '@{}' refers to itself: {}", names[0], names.iter().map(|name| format!("@{name}")).join(" -> "))]
+    AliasCycle { names: Vec<String> },
+
+    #[error("could not read revset alias '@{name}': {source}")]
+    ReadAlias { name: String, source: eyre::Error },
 }
 
 /// Parse a string representing a revset expression into an [Expr].
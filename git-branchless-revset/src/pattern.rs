@@ -123,6 +123,25 @@ impl Pattern {
 
         Ok(Pattern::Substring(pattern.to_owned()))
     }
+
+    /// Like [`Pattern::new`], but a pattern without a prefix is treated as a
+    /// glob rather than as a substring.
+    pub fn new_glob_by_default(pattern: &str) -> Result<Self, PatternError> {
+        const PREFIXES: &[&str] = &[
+            "exact:",
+            "substring:",
+            "substr:",
+            "glob:",
+            "regex:",
+            "before:",
+            "after:",
+        ];
+        if PREFIXES.iter().any(|prefix| pattern.starts_with(prefix)) {
+            Self::new(pattern)
+        } else {
+            Self::new(&format!("glob:{pattern}"))
+        }
+    }
 }
 
 pub(super) trait PatternMatcher: Sync + Send {
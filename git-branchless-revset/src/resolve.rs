@@ -1,14 +1,18 @@
+use std::collections::HashSet;
 use std::fmt::Write;
+use std::str::FromStr;
 
 use eyre::WrapErr;
 use git_branchless_opts::{ResolveRevsetOptions, Revset};
 use lib::core::config::get_smartlog_default_revset;
 use lib::core::dag::{CommitSet, Dag};
 use lib::core::effects::Effects;
-use lib::git::Repo;
+use lib::core::repo_ext::RepoReferencesSnapshot;
+use lib::git::{GitRunInfo, GitRunOpts, GitRunResult, NonZeroOid, Repo};
 use thiserror::Error;
 use tracing::instrument;
 
+use crate::alias::expand_aliases;
 use crate::eval::EvalError;
 use crate::parser::ParseError;
 use crate::Expr;
@@ -55,16 +59,53 @@ impl ResolveError {
 }
 
 /// Check for syntax errors in the provided revsets without actually evaluating them.
+///
+/// Revsets which look like Git revisions aren't reported, since they may be
+/// resolved by [`resolve_commits_or_git_revisions`].
 pub fn check_revset_syntax(repo: &Repo, revsets: &[Revset]) -> Result<(), ParseError> {
+    check_revset_syntax_with_references(repo, None, revsets)
+}
+
+/// Like [`check_revset_syntax`], but revsets which name a local branch in the
+/// provided references snapshot are accepted without looking them up in the
+/// repository again.
+pub fn check_revset_syntax_with_references(
+    repo: &Repo,
+    references_snapshot: Option<&RepoReferencesSnapshot>,
+    revsets: &[Revset],
+) -> Result<(), ParseError> {
+    let branch_names: HashSet<&str> = match references_snapshot {
+        Some(references_snapshot) => references_snapshot
+            .branch_oid_to_names
+            .values()
+            .flatten()
+            .filter_map(|name| name.as_str().strip_prefix("refs/heads/"))
+            .collect(),
+        None => HashSet::new(),
+    };
     for Revset(revset) in revsets {
+        if branch_names.contains(revset.as_str()) {
+            continue;
+        }
         if let Ok(Some(_)) = repo.revparse_single_commit(revset) {
             continue;
         }
-        let _expr: Expr = parse(revset)?;
+        let expr: Expr = match parse(revset) {
+            Ok(expr) => expr,
+            Err(_) if looks_like_git_revision(revset) => continue,
+            Err(err) => return Err(err),
+        };
+        let _expr: Expr = expand_aliases(repo, &expr)?;
     }
     Ok(())
 }
 
+/// Whether the provided revset might be a Git revision, such as `@{push}`,
+/// rather than an expression in the revset language.
+fn looks_like_git_revision(revset: &str) -> bool {
+    !revset.is_empty() && !revset.contains(|c: char| c.is_whitespace() || "()|&,'\"".contains(c))
+}
+
 /// Parse strings which refer to commits, such as:
 ///
 /// - Full OIDs.
@@ -102,10 +143,12 @@ pub fn resolve_commits(
             continue;
         }
 
-        let expr = parse(revset).map_err(|err| ResolveError::ParseError {
-            expr: revset.clone(),
-            source: err,
-        })?;
+        let expr = parse(revset)
+            .and_then(|expr| expand_aliases(repo, &expr))
+            .map_err(|err| ResolveError::ParseError {
+                expr: revset.clone(),
+                source: err,
+            })?;
         let commits = eval(effects, repo, dag, &expr).map_err(|err| ResolveError::EvalError {
             expr: revset.clone(),
             source: err,
@@ -116,6 +159,77 @@ pub fn resolve_commits(
     Ok(commit_sets)
 }
 
+/// Resolve the provided revsets as with [`resolve_commits`], but fall back to
+/// `git rev-parse` for revsets which look like Git revisions and can't be
+/// parsed. This handles Git syntax which neither the revset language nor
+/// `libgit2` supports, such as `@{push}`.
+#[instrument]
+pub fn resolve_commits_or_git_revisions(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    repo: &Repo,
+    dag: &mut Dag,
+    revsets: &[Revset],
+    options: &ResolveRevsetOptions,
+) -> Result<Vec<CommitSet>, ResolveError> {
+    let mut commit_sets = Vec::new();
+    for revset in revsets {
+        match resolve_commits(effects, repo, dag, std::slice::from_ref(revset), options) {
+            Ok(revset_commit_sets) => commit_sets.extend(revset_commit_sets),
+            Err(ResolveError::ParseError { expr, source })
+                if looks_like_git_revision(&revset.0) =>
+            {
+                let commit_oid = match rev_parse_commit(git_run_info, repo, &revset.0)
+                    .map_err(|err| ResolveError::OtherError { source: err })?
+                {
+                    Some(commit_oid) => commit_oid,
+                    None => return Err(ResolveError::ParseError { expr, source }),
+                };
+                let commit_set = CommitSet::from(commit_oid);
+                dag.sync_from_oids(effects, repo, CommitSet::empty(), commit_set.clone())
+                    .map_err(|err| ResolveError::OtherError { source: err })?;
+                commit_sets.push(commit_set);
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(commit_sets)
+}
+
+/// Resolve the provided Git revision to a commit with `git rev-parse`.
+/// Returns `None` if Git couldn't resolve it.
+fn rev_parse_commit(
+    git_run_info: &GitRunInfo,
+    repo: &Repo,
+    revision: &str,
+) -> eyre::Result<Option<NonZeroOid>> {
+    let GitRunResult {
+        exit_code,
+        stdout,
+        stderr: _,
+    } = git_run_info.run_silent(
+        repo,
+        None,
+        &[
+            "rev-parse",
+            "--verify",
+            "--quiet",
+            &format!("{revision}^{{commit}}"),
+        ],
+        GitRunOpts {
+            treat_git_failure_as_error: false,
+            stdin: None,
+            working_directory: None,
+        },
+    )?;
+    if !exit_code.is_success() {
+        return Ok(None);
+    }
+    let stdout = String::from_utf8(stdout).wrap_err("Decoding output of git rev-parse")?;
+    let commit_oid = NonZeroOid::from_str(stdout.trim())?;
+    Ok(Some(commit_oid))
+}
+
 /// Resolve the set of commits that would appear in the smartlog by default (if
 /// the user doesn't specify a revset).
 pub fn resolve_default_smartlog_commits(
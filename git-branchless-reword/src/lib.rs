@@ -38,7 +38,7 @@ use lib::core::formatting::{Glyphs, Pluralize};
 use lib::core::node_descriptors::{render_node_descriptors, CommitOidDescriptor, NodeObject};
 use lib::core::rewrite::{
     execute_rebase_plan, BuildRebasePlanOptions, ExecuteRebasePlanOptions, ExecuteRebasePlanResult,
-    RebasePlanBuilder, RebasePlanPermissions, RepoResource,
+    RebasePlanBuilder, RebasePlanPermissions, RepoResource, SignOption,
 };
 use lib::git::{message_prettify, Commit, GitRunInfo, MaybeZeroOid, NonZeroOid, Repo};
 
@@ -120,6 +120,7 @@ pub fn reword(
         dump_rebase_constraints: false,
         dump_rebase_plan: false,
         detect_duplicate_commits_via_patch_id: false,
+        merge_base_strategy: Default::default(),
     };
     let permissions = match RebasePlanPermissions::verify_rewrite_set(
         &dag,
@@ -295,6 +296,12 @@ pub fn reword(
             reset: false,
             render_smartlog: false,
         },
+        reapply_empty_commits: false,
+        preserve_committer: false,
+        reset_author: false,
+        sign_option: SignOption::Disable,
+        no_verify: false,
+        commit_trailers: Vec::new(),
     };
     let result = execute_rebase_plan(
         effects,
@@ -308,12 +315,14 @@ pub fn reword(
     match result {
         ExecuteRebasePlanResult::Succeeded {
             rewritten_oids: Some(rewritten_oids),
+            post_rewrite_hook_exit_code: _,
         } => {
             render_status_report(&repo, effects, &commits, &rewritten_oids)?;
             Ok(Ok(()))
         }
         ExecuteRebasePlanResult::Succeeded {
             rewritten_oids: None,
+            post_rewrite_hook_exit_code: _,
         } => Ok(Ok(())),
         ExecuteRebasePlanResult::DeclinedToMerge {
             failed_merge_info: _,
@@ -24,7 +24,7 @@ use lib::core::eventlog::EventLogDb;
 use lib::core::formatting::StyledStringBuilder;
 use lib::core::rewrite::{
     execute_rebase_plan, BuildRebasePlanError, BuildRebasePlanOptions, ExecuteRebasePlanOptions,
-    ExecuteRebasePlanResult, RebasePlanBuilder, RebasePlanPermissions, RepoResource,
+    ExecuteRebasePlanResult, RebasePlanBuilder, RebasePlanPermissions, RepoResource, SignOption,
 };
 use lib::git::{Commit, GitRunInfo, MaybeZeroOid, NonZeroOid, Repo, RepoError, TestCommand};
 use lib::try_exit_code;
@@ -347,6 +347,7 @@ impl Forge for PhabricatorForge<'_> {
             dump_rebase_constraints: false,
             dump_rebase_plan: false,
             detect_duplicate_commits_via_patch_id: false,
+            merge_base_strategy: Default::default(),
         };
         let execute_options = ExecuteRebasePlanOptions {
             now,
@@ -359,6 +360,12 @@ impl Forge for PhabricatorForge<'_> {
                 render_smartlog: false,
                 ..Default::default()
             },
+            reapply_empty_commits: false,
+            preserve_committer: false,
+            reset_author: false,
+            sign_option: SignOption::Disable,
+            no_verify: false,
+            commit_trailers: Vec::new(),
         };
         let permissions =
             RebasePlanPermissions::verify_rewrite_set(self.dag, build_options, &commit_set)
@@ -482,9 +489,11 @@ Differential Revision: https://phabricator.example.com/D000$(git rev-list --coun
         )? {
             ExecuteRebasePlanResult::Succeeded {
                 rewritten_oids: Some(rewritten_oids),
+                post_rewrite_hook_exit_code: _,
             } => rewritten_oids,
             ExecuteRebasePlanResult::Succeeded {
                 rewritten_oids: None,
+                post_rewrite_hook_exit_code: _,
             } => {
                 warn!("No rewritten commit OIDs were produced by rebase plan execution");
                 Default::default()
@@ -595,6 +604,7 @@ Differential Revision: https://phabricator.example.com/D000$(git rev-list --coun
             dump_rebase_constraints: false,
             dump_rebase_plan: false,
             detect_duplicate_commits_via_patch_id: false,
+            merge_base_strategy: Default::default(),
         };
         let execute_options = ExecuteRebasePlanOptions {
             now,
@@ -607,6 +617,12 @@ Differential Revision: https://phabricator.example.com/D000$(git rev-list --coun
                 render_smartlog: false,
                 ..Default::default()
             },
+            reapply_empty_commits: false,
+            preserve_committer: false,
+            reset_author: false,
+            sign_option: SignOption::Disable,
+            no_verify: false,
+            commit_trailers: Vec::new(),
         };
         let permissions =
             RebasePlanPermissions::verify_rewrite_set(self.dag, build_options, &commit_set)
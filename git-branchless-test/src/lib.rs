@@ -47,7 +47,7 @@ use lib::core::formatting::{Glyphs, Pluralize, StyledStringBuilder};
 use lib::core::repo_ext::RepoExt;
 use lib::core::rewrite::{
     execute_rebase_plan, BuildRebasePlanOptions, ExecuteRebasePlanOptions, ExecuteRebasePlanResult,
-    RebaseCommand, RebasePlan, RebasePlanBuilder, RebasePlanPermissions, RepoResource,
+    RebaseCommand, RebasePlan, RebasePlanBuilder, RebasePlanPermissions, RepoResource, SignOption,
 };
 use lib::git::{
     get_latest_test_command_path, get_test_locks_dir, get_test_tree_dir, get_test_worktrees_dir,
@@ -404,6 +404,7 @@ BUG: Expected resolved_interactive ({resolved_interactive:?}) to match interacti
                 dump_rebase_constraints: *dump_rebase_constraints,
                 dump_rebase_plan: *dump_rebase_plan,
                 detect_duplicate_commits_via_patch_id: *detect_duplicate_commits_via_patch_id,
+                merge_base_strategy: Default::default(),
             };
             let execute_options = ExecuteRebasePlanOptions {
                 now,
@@ -416,6 +417,12 @@ BUG: Expected resolved_interactive ({resolved_interactive:?}) to match interacti
                     render_smartlog: false,
                     ..Default::default()
                 },
+                reapply_empty_commits: false,
+                preserve_committer: false,
+                reset_author: false,
+                sign_option: SignOption::Disable,
+                no_verify: false,
+                commit_trailers: Vec::new(),
             };
             let permissions =
                 match RebasePlanPermissions::verify_rewrite_set(dag, build_options, commits)? {
@@ -731,9 +738,18 @@ fn set_abort_trap(
                 render_smartlog: false,
                 ..Default::default()
             },
+            reapply_empty_commits: false,
+            preserve_committer: false,
+            reset_author: false,
+            sign_option: SignOption::Disable,
+            no_verify: false,
+            commit_trailers: Vec::new(),
         },
     )? {
-        ExecuteRebasePlanResult::Succeeded { rewritten_oids: _ } => {
+        ExecuteRebasePlanResult::Succeeded {
+            rewritten_oids: _,
+            post_rewrite_hook_exit_code: _,
+        } => {
             // Do nothing.
         }
         ExecuteRebasePlanResult::DeclinedToMerge { failed_merge_info } => {
@@ -2090,7 +2106,10 @@ fn apply_fixes(
             &rebase_plan,
             execute_options,
         )? {
-            ExecuteRebasePlanResult::Succeeded { rewritten_oids } => rewritten_oids,
+            ExecuteRebasePlanResult::Succeeded {
+                rewritten_oids,
+                post_rewrite_hook_exit_code: _,
+            } => rewritten_oids,
             ExecuteRebasePlanResult::DeclinedToMerge { failed_merge_info } => {
                 writeln!(effects.get_output_stream(), "BUG: encountered merge conflicts during git test fix, but we should not be applying any patches: {failed_merge_info:?}")?;
                 return Ok(Err(ExitCode(1)));
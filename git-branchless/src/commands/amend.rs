@@ -24,7 +24,7 @@ use lib::core::gc::mark_commit_reachable;
 use lib::core::repo_ext::RepoExt;
 use lib::core::rewrite::{
     execute_rebase_plan, move_branches, BuildRebasePlanOptions, ExecuteRebasePlanOptions,
-    ExecuteRebasePlanResult, RebasePlanBuilder, RebasePlanPermissions, RepoResource,
+    ExecuteRebasePlanResult, RebasePlanBuilder, RebasePlanPermissions, RepoResource, SignOption,
 };
 use lib::git::{AmendFastOptions, GitRunInfo, MaybeZeroOid, Repo, ResolvedReferenceInfo};
 use lib::try_exit_code;
@@ -84,6 +84,7 @@ pub fn amend(
         dump_rebase_constraints: move_options.dump_rebase_constraints,
         dump_rebase_plan: move_options.dump_rebase_plan,
         detect_duplicate_commits_via_patch_id: move_options.detect_duplicate_commits_via_patch_id,
+        merge_base_strategy: Default::default(),
     };
     let commits_to_verify = dag.query_descendants(CommitSet::from(head_oid))?;
     let commits_to_verify = dag.filter_visible_commits(commits_to_verify)?;
@@ -214,6 +215,7 @@ pub fn amend(
             force_rewrite_public_commits: move_options.force_rewrite_public_commits,
             detect_duplicate_commits_via_patch_id: move_options
                 .detect_duplicate_commits_via_patch_id,
+            merge_base_strategy: Default::default(),
             dump_rebase_constraints: move_options.dump_rebase_constraints,
             dump_rebase_plan: move_options.dump_rebase_plan,
         };
@@ -300,6 +302,12 @@ pub fn amend(
                 reset: false,
                 render_smartlog: false,
             },
+            reapply_empty_commits: false,
+            preserve_committer: false,
+            reset_author: false,
+            sign_option: SignOption::Disable,
+            no_verify: false,
+            commit_trailers: Vec::new(),
         };
         match execute_rebase_plan(
             effects,
@@ -311,10 +319,12 @@ pub fn amend(
         )? {
             ExecuteRebasePlanResult::Succeeded {
                 rewritten_oids: None,
+                post_rewrite_hook_exit_code: _,
             } => {}
 
             ExecuteRebasePlanResult::Succeeded {
                 rewritten_oids: Some(rewritten_oids),
+                post_rewrite_hook_exit_code: _,
             } => {
                 writeln!(
                     effects.get_output_stream(),
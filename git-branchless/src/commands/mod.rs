@@ -6,7 +6,7 @@ mod hide;
 mod repair;
 mod restack;
 mod snapshot;
-mod sync;
+pub mod sync;
 mod wrap;
 
 use git_branchless_invoke::CommandContext;
@@ -181,19 +181,7 @@ fn command_main(ctx: CommandContext, opts: Opts) -> EyreExitOr<()> {
 
         Command::Submit(args) => git_branchless_submit::command_main(ctx, args)?,
 
-        Command::Sync {
-            pull,
-            move_options,
-            revsets,
-            resolve_revset_options,
-        } => sync::sync(
-            &effects,
-            &git_run_info,
-            pull,
-            &move_options,
-            revsets,
-            &resolve_revset_options,
-        )?,
+        Command::Sync(args) => sync::sync(&effects, &git_run_info, args)?,
 
         Command::Test(args) => git_branchless_test::command_main(ctx, args)?,
 
@@ -77,6 +77,7 @@ use lib::core::rewrite::{
     execute_rebase_plan, find_abandoned_children, find_rewrite_target, move_branches,
     BuildRebasePlanOptions, ExecuteRebasePlanOptions, ExecuteRebasePlanResult,
     MergeConflictRemediation, RebasePlanBuilder, RebasePlanPermissions, RepoPool, RepoResource,
+    SignOption,
 };
 use lib::git::{GitRunInfo, NonZeroOid, Repo};
 
@@ -178,7 +179,10 @@ fn restack_commits(
         execute_options,
     )?;
     match execute_rebase_plan_result {
-        ExecuteRebasePlanResult::Succeeded { rewritten_oids: _ } => {
+        ExecuteRebasePlanResult::Succeeded {
+            rewritten_oids: _,
+            post_rewrite_hook_exit_code: _,
+        } => {
             writeln!(effects.get_output_stream(), "Finished restacking commits.")?;
             Ok(Ok(()))
         }
@@ -318,6 +322,7 @@ pub fn restack(
         dump_rebase_constraints,
         dump_rebase_plan,
         detect_duplicate_commits_via_patch_id,
+        merge_base_strategy: Default::default(),
     };
     let execute_options = ExecuteRebasePlanOptions {
         now,
@@ -331,6 +336,12 @@ pub fn restack(
             reset: false,
             render_smartlog: false,
         },
+        reapply_empty_commits: false,
+        preserve_committer: false,
+        reset_author: false,
+        sign_option: SignOption::Disable,
+        no_verify: false,
+        commit_trailers: Vec::new(),
     };
     let pool = ThreadPoolBuilder::new().build()?;
     let repo_pool = RepoResource::new_pool(&repo)?;
@@ -1,434 +1,5675 @@
 //! Implements the `git sync` command.
+//!
+//! Output convention: results (moved stacks, prompts, `--describe-plan`
+//! output, error reports and their descriptions) are written to the output
+//! stream, while warnings and notes about stacks or steps which were left
+//! alone (skipped, truncated, nothing to do) are written to the warning
+//! stream.
 
 use cursive_core::theme::BaseColor;
 use lib::try_exit_code;
+use std::cell::Cell;
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::OsString;
 use std::fmt::Write;
-use std::time::SystemTime;
+use std::io::{stdin, BufRead};
+use std::num::NonZeroUsize;
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant, SystemTime};
 
+use bstr::BString;
+use eyre::Context;
 use itertools::Itertools;
 use lib::core::check_out::CheckOutCommitOptions;
-use lib::core::repo_ext::RepoExt;
+use lib::core::repo_ext::{RepoExt, RepoReferencesSnapshot};
 use lib::util::{ExitCode, EyreExitOr};
-use rayon::{ThreadPool, ThreadPoolBuilder};
+use rayon::{prelude::*, ThreadPool, ThreadPoolBuilder};
+use serde::{Deserialize, Serialize};
+use signal_hook::consts::SIGINT;
+use signal_hook::SigId;
+use tracing::{instrument, warn};
 
-use git_branchless_opts::{MoveOptions, ResolveRevsetOptions, Revset};
-use git_branchless_revset::{check_revset_syntax, resolve_commits};
-use lib::core::config::get_restack_preserve_timestamps;
+use git_branchless_opts::{
+    MoveOptions, ResolveRevsetOptions, Revset, SyncArgs, SyncFetchOptions, SyncMergeBaseStrategy,
+    SyncOutputOptions, SyncPlanFormat, SyncProgressFormat, SyncRewriteOptions, SyncSelectOptions,
+    Trailer,
+};
+use git_branchless_revset::{
+    check_revset_syntax, check_revset_syntax_with_references, resolve_commits,
+    resolve_commits_or_git_revisions,
+};
+use lib::core::config::env_vars::should_be_deterministic;
+use lib::core::config::{
+    get_commit_gpg_sign, get_main_branch_name, get_rebase_autostash,
+    get_restack_preserve_timestamps, get_sync_auto_gc, get_sync_backup_expiry_days,
+    get_sync_default_revsets, get_sync_fetch_tags, get_sync_main_branch_override,
+    get_sync_native_fetch, get_sync_notify, get_sync_on_rewrite, get_sync_publish_ref_template,
+    get_sync_remote, get_sync_trunks, get_sync_write_commit_graph,
+    get_sync_write_commit_graph_min_commits, is_branchless_initialized,
+    SYNC_DIRECTORY_CONFIG_FILE_NAME,
+};
 use lib::core::dag::{sorted_commit_set, union_all, CommitSet, Dag};
-use lib::core::effects::{Effects, OperationType, WithProgress};
-use lib::core::eventlog::{EventLogDb, EventReplayer};
-use lib::core::formatting::{Pluralize, StyledStringBuilder};
+use lib::core::effects::{Effects, OperationType, ProgressHandle};
+use lib::core::eventlog::{Event, EventCursor, EventLogDb, EventReplayer, EventTransactionId};
+use lib::core::formatting::{Glyphs, Pluralize, StyledStringBuilder};
+use lib::core::node_descriptors::RelativeTimeDescriptor;
 use lib::core::rewrite::{
-    execute_rebase_plan, BuildRebasePlanError, BuildRebasePlanOptions, ExecuteRebasePlanOptions,
-    ExecuteRebasePlanResult, FailedMergeInfo, RebasePlan, RebasePlanBuilder, RebasePlanPermissions,
-    RepoPool, RepoResource,
+    check_rebase_plan_conflicts, execute_rebase_plan, get_stack_roots, move_branches,
+    stack_needs_sync, BuildRebasePlanError, BuildRebasePlanOptions, ExecuteRebasePlanOptions,
+    ExecuteRebasePlanResult, FailedMergeInfo, MergeBaseStrategy, RebaseCommand, RebasePlan,
+    RebasePlanBuilder, RebasePlanPermissions, RepoPool, RepoResource, SignOption,
 };
 use lib::core::task::ResourcePool;
 use lib::git::{
-    CategorizedReferenceName, Commit, GitRunInfo, NonZeroOid, Repo, ResolvedReferenceInfo,
+    BranchType, CategorizedReferenceName, Commit, ConfigRead, GitErrorCode, GitRunInfo, GitRunOpts,
+    GitRunResult, GitVersion, MaybeZeroOid, NonZeroOid, PatchId, ReferenceName, Repo, RepoError,
+    ResolvedReferenceInfo, SerializedNonZeroOid, BRANCHLESS_TRACE_GIT_ENV_VAR,
 };
 
-fn get_stack_roots(dag: &Dag, commit_sets: Vec<CommitSet>) -> eyre::Result<CommitSet> {
-    let draft_commits = dag.query_draft_commits()?;
+/// Group together stack roots whose stacks are joined by a merge commit (i.e.
+/// which have a descendant in common), so that they can be moved as a unit.
+/// Otherwise, each stack would be rebased separately, and the merge commit
+/// would be duplicated.
+fn group_stack_roots(
+    dag: &Dag,
+    root_commit_oids: Vec<NonZeroOid>,
+) -> eyre::Result<Vec<Vec<NonZeroOid>>> {
+    let mut groups: Vec<(CommitSet, Vec<NonZeroOid>)> = Vec::new();
+    for root_commit_oid in root_commit_oids {
+        let mut stack_commits = dag.query_descendants(CommitSet::from(root_commit_oid))?;
+        let mut group_root_oids = Vec::new();
+
+        let mut disjoint_groups = Vec::new();
+        for (group_commits, root_oids) in groups {
+            if dag.set_is_empty(&group_commits.intersection(&stack_commits))? {
+                disjoint_groups.push((group_commits, root_oids));
+            } else {
+                stack_commits = stack_commits.union(&group_commits);
+                group_root_oids.extend(root_oids);
+            }
+        }
+        group_root_oids.push(root_commit_oid);
+        groups = disjoint_groups;
+        groups.push((stack_commits, group_root_oids));
+    }
+
+    Ok(groups
+        .into_iter()
+        .map(|(_group_commits, root_oids)| root_oids)
+        .collect())
+}
+
+/// Get the candidate bases for `git sync --base-auto`: the main branch,
+/// followed by the branches configured in `branchless.sync.trunks`, along with
+/// the commits they point to.
+fn get_trunks(
+    effects: &Effects,
+    repo: &Repo,
+    references_snapshot: &RepoReferencesSnapshot,
+) -> eyre::Result<Vec<(String, NonZeroOid)>> {
+    let main_branch_name = get_main_branch_name(repo)?;
+    let mut trunks = vec![(
+        main_branch_name.clone(),
+        references_snapshot.main_branch_oid,
+    )];
+    for trunk_name in get_sync_trunks(repo)? {
+        if trunk_name == main_branch_name
+            || trunks
+                .iter()
+                .any(|(existing_name, _)| existing_name == &trunk_name)
+        {
+            continue;
+        }
+        let reference_name = ReferenceName::from(format!("refs/heads/{trunk_name}"));
+        let trunk_oid = references_snapshot
+            .branch_oid_to_names
+            .iter()
+            .find_map(|(oid, names)| names.contains(&reference_name).then_some(*oid));
+        match trunk_oid {
+            Some(trunk_oid) => trunks.push((trunk_name, trunk_oid)),
+            None => writeln!(
+                effects.get_warning_stream(),
+                "Warning: trunk branch {trunk_name} does not exist, so stacks won't be moved onto it."
+            )?,
+        }
+    }
+    Ok(trunks)
+}
+
+/// Choose the trunk to move the stack with the given roots onto: the one whose
+/// merge-base with the stack is nearest to the stack. Ties are broken by the
+/// number of trunk commits the stack would be rebased over, and then by the
+/// order of the trunks. Returns `None` if there are no trunks which the stack
+/// could be moved onto.
+fn choose_stack_base(
+    dag: &Dag,
+    root_commit_oids: &[NonZeroOid],
+    trunks: &[(String, NonZeroOid)],
+) -> eyre::Result<Option<(String, NonZeroOid)>> {
+    let root_commits: CommitSet = root_commit_oids.iter().copied().collect();
+    let base_commits = dag.query_parents(root_commits.clone())?;
+    let mut trunk_distances = Vec::new();
+    for trunk in trunks {
+        let (_trunk_name, trunk_oid) = trunk;
+        let trunk_commits = CommitSet::from(*trunk_oid);
+        // A stack can't be moved onto a trunk which contains it, which is the
+        // case when the trunk's commits are moved along with the stack.
+        if !dag.set_is_empty(
+            &dag.query_ancestors(trunk_commits.clone())?
+                .intersection(&root_commits),
+        )? {
+            continue;
+        }
+        // The commits between the merge-base and the stack, which aren't on
+        // the trunk.
+        let merge_base_distance =
+            dag.set_count(&dag.query_only(base_commits.clone(), trunk_commits.clone())?)?;
+        // The commits on the trunk which aren't in the stack's history.
+        let rebase_distance =
+            dag.set_count(&dag.query_only(trunk_commits, root_commits.clone())?)?;
+        trunk_distances.push(((merge_base_distance, rebase_distance), trunk));
+    }
+    Ok(trunk_distances
+        .into_iter()
+        .min_by_key(|(distance, _trunk)| *distance)
+        .map(|(_distance, trunk)| trunk.clone()))
+}
+
+/// Truncate the stack at `root_commit_oid` to the commits which are at most
+/// `max_depth` commits from its root, for `--max-depth`. Returns the roots of
+/// the subtrees above the cutoff, which should be left in place, along with
+/// the number of commits which will be moved and the number of commits in the
+/// stack.
+///
+/// A commit is moved only if all of its ancestors in the stack are moved, so
+/// that none of the moved commits are left on top of unmoved commits.
+fn truncate_stack(
+    dag: &Dag,
+    root_commit_oid: NonZeroOid,
+    max_depth: NonZeroUsize,
+) -> eyre::Result<(Vec<NonZeroOid>, usize, usize)> {
+    let stack_commits =
+        dag.filter_visible_commits(dag.query_descendants(CommitSet::from(root_commit_oid))?)?;
+    let mut left_commits = CommitSet::empty();
+    for commit_oid in dag.commit_set_to_vec(&stack_commits)? {
+        let depth = dag.set_count(
+            &dag.query_ancestors(CommitSet::from(commit_oid))?
+                .intersection(&stack_commits),
+        )?;
+        if depth > max_depth.get() {
+            left_commits = left_commits.union(&CommitSet::from(commit_oid));
+        }
+    }
+    let num_commits = dag.set_count(&stack_commits)?;
+    let num_left_commits = dag.set_count(&left_commits)?;
+    let left_subtree_oids = dag.commit_set_to_vec(&dag.query_roots(left_commits)?)?;
+    Ok((
+        left_subtree_oids,
+        num_commits - num_left_commits,
+        num_commits,
+    ))
+}
+
+/// For each stack which would rewrite public commits, prompt the user to
+/// confirm whether to proceed. Returns the roots of the stacks which should be
+/// synced, along with the public commits which the user agreed to rewrite.
+fn confirm_public_commit_rewrites<'repo>(
+    effects: &Effects,
+    dag: &Dag,
+    root_commits: Vec<Commit<'repo>>,
+    permitted_public_commits: CommitSet,
+) -> eyre::Result<(Vec<Commit<'repo>>, CommitSet)> {
+    let public_commits = dag.query_public_commits_slow()?;
+    let mut permitted_public_commits = permitted_public_commits;
+    let mut confirmed_root_commits = Vec::new();
+    for root_commit in root_commits {
+        let public_commits_to_move = dag
+            .query_descendants(CommitSet::from(root_commit.get_oid()))?
+            .intersection(public_commits)
+            .difference(&permitted_public_commits);
+        if dag.set_is_empty(&public_commits_to_move)? {
+            confirmed_root_commits.push(root_commit);
+            continue;
+        }
+
+        let root_commit_description = effects
+            .get_glyphs()
+            .render(root_commit.friendly_describe(effects.get_glyphs())?)?;
+        if confirm_public_commit_rewrite(
+            effects,
+            &root_commit_description,
+            dag.set_count(&public_commits_to_move)?,
+            stdin().lock(),
+        )? {
+            permitted_public_commits = permitted_public_commits.union(&public_commits_to_move);
+            confirmed_root_commits.push(root_commit);
+        } else {
+            writeln!(
+                effects.get_warning_stream(),
+                "Skipping stack (not confirmed): {root_commit_description}"
+            )?;
+        }
+    }
+    Ok((confirmed_root_commits, permitted_public_commits))
+}
+
+/// Ask whether to rewrite the `num_public_commits` public commits in the stack
+/// described by `root_commit_description`. Anything other than a yes,
+/// including the input ending, is taken as a no.
+fn confirm_public_commit_rewrite(
+    effects: &Effects,
+    root_commit_description: &str,
+    num_public_commits: usize,
+    mut in_: impl BufRead,
+) -> eyre::Result<bool> {
+    write!(
+        effects.get_output_stream(),
+        "The stack at {} will rewrite {}; proceed? [y/N] ",
+        root_commit_description,
+        Pluralize {
+            determiner: None,
+            amount: num_public_commits,
+            unit: ("public commit", "public commits"),
+        },
+    )?;
+    let mut user_input = String::new();
+    match in_.read_line(&mut user_input) {
+        Ok(_size) => Ok(matches!(user_input.trim(), "y" | "Y")),
+        Err(_) => Ok(false),
+    }
+}
+
+/// Whether to keep going after a stack fails to sync, according to
+/// `--keep-going` and `--fail-fast`, which override each other. If neither was
+/// passed, then `default` is used, since the phases of the sync differ in
+/// whether they keep going by default.
+fn should_keep_going(rewrite_options: &SyncRewriteOptions, default: bool) -> bool {
+    let SyncRewriteOptions {
+        keep_going,
+        fail_fast,
+        ..
+    } = rewrite_options;
+    match (keep_going, fail_fast) {
+        (true, _) => true,
+        (_, true) => false,
+        (false, false) => default,
+    }
+}
+
+/// Glob patterns used to select stacks based on the paths that they touch.
+#[derive(Debug)]
+struct TouchedPathsFilter {
+    include_patterns: Vec<glob::Pattern>,
+    exclude_patterns: Vec<glob::Pattern>,
+}
+
+impl TouchedPathsFilter {
+    /// Parse the provided patterns. Patterns prefixed with `!` exclude
+    /// matching paths. Returns `None` if no patterns were provided.
+    fn new(patterns: &[String]) -> Result<Option<Self>, glob::PatternError> {
+        if patterns.is_empty() {
+            return Ok(None);
+        }
+
+        let mut include_patterns = Vec::new();
+        let mut exclude_patterns = Vec::new();
+        for pattern in patterns {
+            match pattern.strip_prefix('!') {
+                Some(pattern) => exclude_patterns.push(glob::Pattern::new(pattern)?),
+                None => include_patterns.push(glob::Pattern::new(pattern)?),
+            }
+        }
+        Ok(Some(Self {
+            include_patterns,
+            exclude_patterns,
+        }))
+    }
+
+    /// Whether the given path is selected by this filter. If only exclusion
+    /// patterns were provided, then all other paths are selected.
+    fn matches(&self, path: &Path) -> bool {
+        let is_included = self.include_patterns.is_empty()
+            || self
+                .include_patterns
+                .iter()
+                .any(|pattern| pattern.matches_path(path));
+        let is_excluded = self
+            .exclude_patterns
+            .iter()
+            .any(|pattern| pattern.matches_path(path));
+        is_included && !is_excluded
+    }
+
+    /// Whether any commit in the stack rooted at `root_commit_oid` touches a
+    /// path selected by this filter.
+    fn matches_stack(
+        &self,
+        repo: &Repo,
+        dag: &Dag,
+        root_commit_oid: NonZeroOid,
+    ) -> eyre::Result<bool> {
+        let stack_commits = dag
+            .query_descendants(CommitSet::from(root_commit_oid))?
+            .intersection(dag.query_draft_commits()?);
+        for commit_oid in dag.commit_set_to_vec(&stack_commits)? {
+            let commit = repo.find_commit_or_fail(commit_oid)?;
+            let touched_paths = repo.get_paths_touched_by_commit(&commit)?;
+            if touched_paths.iter().any(|path| self.matches(path)) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+fn get_sync_cache_path(repo: &Repo) -> eyre::Result<PathBuf> {
+    Ok(repo.get_branchless_dir()?.join("sync-cache"))
+}
+
+/// Compute a key summarizing the repository state relevant to `git sync`, as
+/// well as the options it was invoked with. If the key is unchanged since the
+/// last successful sync, then syncing again would be a no-op.
+///
+/// This is intended to be cheap to compute, so it doesn't open the DAG or
+/// replay the event log. Instead, it relies on the fact that any change to the
+/// commit graph will either move a reference or add an event to the event log.
+///
+/// The key is persisted between invocations, so it's a digest of an explicit
+/// description of the state, rather than of e.g. `Debug` output or a
+/// `DefaultHasher`, neither of which is stable across versions.
+fn compute_sync_cache_key(
+    repo: &Repo,
+    references_snapshot: &RepoReferencesSnapshot,
+    event_log_db: &EventLogDb,
+    args: &SyncArgs,
+) -> eyre::Result<String> {
+    let RepoReferencesSnapshot {
+        head_oid,
+        main_branch_oid,
+        branch_oid_to_names,
+    } = references_snapshot;
+    let branches = branch_oid_to_names
+        .iter()
+        .flat_map(|(oid, names)| names.iter().map(move |name| (name.as_str(), oid)))
+        .sorted()
+        .collect_vec();
+    let latest_event_id = event_log_db.get_latest_event_id()?;
+
+    let mut description = String::new();
+    match head_oid {
+        Some(head_oid) => writeln!(description, "head {head_oid}")?,
+        None => writeln!(description, "head")?,
+    }
+    writeln!(description, "main {main_branch_oid}")?;
+    for (name, oid) in branches {
+        writeln!(description, "branch {oid} {name}")?;
+    }
+    match latest_event_id {
+        Some(latest_event_id) => writeln!(description, "event {latest_event_id}")?,
+        None => writeln!(description, "event")?,
+    }
+    describe_sync_options(&mut description, args)?;
+    if args.base_auto {
+        for trunk in get_sync_trunks(repo)? {
+            writeln!(description, "trunk {trunk}")?;
+        }
+    }
+    Ok(format!("{:016x}", fnv1a_64(description.as_bytes())))
+}
+
+/// Describe the options which determine the result of a sync, one per line,
+/// for use in the sync cache key. Options which only affect how the sync is
+/// reported, and those which bypass the cache altogether, aren't included.
+fn describe_sync_options(description: &mut String, args: &SyncArgs) -> std::fmt::Result {
+    let SyncArgs {
+        fetch_options: _,
+        move_options:
+            MoveOptions {
+                force_rewrite_public_commits,
+                force_in_memory,
+                force_on_disk,
+                detect_duplicate_commits_via_patch_id,
+                resolve_merge_conflicts,
+                dump_rebase_constraints: _,
+                dump_rebase_plan: _,
+            },
+        select_options:
+            SyncSelectOptions {
+                include_public,
+                exclude,
+                touched_paths,
+                only_conflicting,
+                limit,
+                max_depth,
+                at_event,
+                since_event,
+                all,
+                allow_unrelated_histories,
+                include_public_roots,
+            },
+        rewrite_options:
+            SyncRewriteOptions {
+                rebase_merges,
+                confirm,
+                keep_branches_on_conflict,
+                keep_going,
+                fail_fast,
+                reapply_empty,
+                preserve_committer,
+                reset_author,
+                gpg_sign,
+                no_verify,
+                strict_hooks,
+                autostash,
+                no_autostash,
+                update_tags,
+                recurse_submodules,
+                checkout_args,
+                verify_after,
+                add_trailers,
+                batch_size,
+                record_backup_refs,
+                merge_base_strategy,
+            },
+        output_options: _,
+        gc: _,
+        commit_graph_refresh: _,
+        notify: _,
+        publish: _,
+        list_backups: _,
+        restore_backup: _,
+        onto,
+        onto_tag,
+        base_auto,
+        revsets,
+        resolve_revset_options: ResolveRevsetOptions {
+            show_hidden_commits,
+        },
+    } = args;
+
+    for (name, value) in [
+        ("force-rewrite", force_rewrite_public_commits),
+        ("in-memory", force_in_memory),
+        ("on-disk", force_on_disk),
+        ("deduplicate-commits", detect_duplicate_commits_via_patch_id),
+        ("merge", resolve_merge_conflicts),
+        ("only-conflicting", only_conflicting),
+        ("all", all),
+        ("allow-unrelated-histories", allow_unrelated_histories),
+        ("include-public-roots", include_public_roots),
+        ("rebase-merges", rebase_merges),
+        ("confirm", confirm),
+        ("keep-branches-on-conflict", keep_branches_on_conflict),
+        ("keep-going", keep_going),
+        ("fail-fast", fail_fast),
+        ("reapply-empty", reapply_empty),
+        ("preserve-committer", preserve_committer),
+        ("reset-author", reset_author),
+        ("no-verify", no_verify),
+        ("strict-hooks", strict_hooks),
+        ("autostash", autostash),
+        ("no-autostash", no_autostash),
+        ("update-tags", update_tags),
+        ("recurse-submodules", recurse_submodules),
+        ("record-backup-refs", record_backup_refs),
+        ("base-auto", base_auto),
+        ("hidden", show_hidden_commits),
+    ] {
+        writeln!(description, "option {name}={value}")?;
+    }
+    for (name, value) in [
+        ("limit", limit.map(|limit| limit.to_string())),
+        (
+            "max-depth",
+            max_depth.map(|max_depth| max_depth.to_string()),
+        ),
+        ("at-event", at_event.map(|at_event| at_event.to_string())),
+        (
+            "since-event",
+            since_event.map(|since_event| since_event.to_string()),
+        ),
+        ("gpg-sign", gpg_sign.clone()),
+        ("verify-after", verify_after.clone()),
+        (
+            "batch-size",
+            batch_size.map(|batch_size| batch_size.to_string()),
+        ),
+        (
+            "merge-base-strategy",
+            merge_base_strategy.map(|strategy| format!("{strategy:?}")),
+        ),
+        ("onto", onto.as_ref().map(|Revset(onto)| onto.clone())),
+        ("onto-tag", onto_tag.clone()),
+    ] {
+        match value {
+            Some(value) => writeln!(description, "option {name}={value}")?,
+            None => writeln!(description, "option {name}")?,
+        }
+    }
+    for Revset(revset) in include_public {
+        writeln!(description, "include-public {revset}")?;
+    }
+    for Revset(revset) in exclude {
+        writeln!(description, "exclude {revset}")?;
+    }
+    for touched_path in touched_paths {
+        writeln!(description, "touched-path {touched_path}")?;
+    }
+    for checkout_arg in checkout_args {
+        writeln!(description, "checkout-arg {checkout_arg}")?;
+    }
+    for Trailer { key, value } in add_trailers {
+        writeln!(description, "trailer {key}={value}")?;
+    }
+    for Revset(revset) in revsets {
+        writeln!(description, "revset {revset}")?;
+    }
+    Ok(())
+}
+
+/// The 64-bit FNV-1a hash, which is stable across platforms and versions
+/// (unlike `DefaultHasher`).
+fn fnv1a_64(bytes: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+    bytes.iter().fold(OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(PRIME)
+    })
+}
+
+fn read_sync_cache(repo: &Repo) -> eyre::Result<Option<String>> {
+    let path = get_sync_cache_path(repo)?;
+    match std::fs::read_to_string(&path) {
+        Ok(contents) => Ok(Some(contents.trim().to_owned())),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err).wrap_err_with(|| format!("Reading sync cache at {path:?}")),
+    }
+}
+
+fn write_sync_cache(repo: &Repo, key: Option<&str>) -> eyre::Result<()> {
+    let path = get_sync_cache_path(repo)?;
+    match key {
+        Some(key) => std::fs::write(&path, format!("{key}\n"))
+            .wrap_err_with(|| format!("Writing sync cache at {path:?}")),
+        None => match std::fs::remove_file(&path) {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err).wrap_err_with(|| format!("Removing sync cache at {path:?}")),
+        },
+    }
+}
+
+fn get_sync_conflicts_path(repo: &Repo) -> eyre::Result<PathBuf> {
+    Ok(repo.get_branchless_dir()?.join("sync-conflicts"))
+}
+
+/// Read the root commits of the stacks which had merge conflicts the last
+/// time that they were synced.
+fn read_sync_conflicts(repo: &Repo) -> eyre::Result<HashSet<NonZeroOid>> {
+    let path = get_sync_conflicts_path(repo)?;
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(HashSet::new()),
+        Err(err) => {
+            return Err(err).wrap_err_with(|| format!("Reading sync conflicts at {path:?}"))
+        }
+    };
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            line.trim()
+                .parse()
+                .wrap_err_with(|| format!("Parsing sync conflicts at {path:?}"))
+        })
+        .collect()
+}
+
+fn write_sync_conflicts(repo: &Repo, root_commit_oids: &HashSet<NonZeroOid>) -> eyre::Result<()> {
+    let path = get_sync_conflicts_path(repo)?;
+    if root_commit_oids.is_empty() {
+        return match std::fs::remove_file(&path) {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err).wrap_err_with(|| format!("Removing sync conflicts at {path:?}")),
+        };
+    }
+    let contents: String = root_commit_oids
+        .iter()
+        .map(|root_commit_oid| root_commit_oid.to_string())
+        .sorted()
+        .map(|root_commit_oid| format!("{root_commit_oid}\n"))
+        .collect();
+    std::fs::write(&path, contents).wrap_err_with(|| format!("Writing sync conflicts at {path:?}"))
+}
+
+/// The state of a sync which is in progress. This is saved once the rebase
+/// plans have been built, so that the sync can be resumed if it's interrupted
+/// (or crashes) before all of the stacks have been synced.
+#[derive(Debug, Deserialize, Serialize)]
+struct SyncState {
+    /// The event transaction which the sync's rewrites are recorded under.
+    event_tx_id: String,
+
+    /// The location of `HEAD` before the sync started.
+    head_oid: Option<SerializedNonZeroOid>,
+
+    /// The location of the main branch before the sync started.
+    main_branch_oid: SerializedNonZeroOid,
+
+    /// The locations of the branches before the sync started.
+    branches: BTreeMap<String, SerializedNonZeroOid>,
+
+    /// The roots of the stacks which haven't been synced yet, along with their
+    /// rebase plans.
+    remaining_stacks: Vec<(SerializedNonZeroOid, Option<RebasePlan>)>,
+}
+
+impl SyncState {
+    fn new(
+        event_tx_id: EventTransactionId,
+        references_snapshot: &RepoReferencesSnapshot,
+        root_commit_and_plans: &[(NonZeroOid, Option<RebasePlan>)],
+    ) -> Self {
+        let RepoReferencesSnapshot {
+            head_oid,
+            main_branch_oid,
+            branch_oid_to_names,
+        } = references_snapshot;
+        Self {
+            event_tx_id: event_tx_id.to_string(),
+            head_oid: head_oid.map(SerializedNonZeroOid),
+            main_branch_oid: SerializedNonZeroOid(*main_branch_oid),
+            branches: branch_oid_to_names
+                .iter()
+                .flat_map(|(oid, names)| {
+                    names
+                        .iter()
+                        .map(|name| (name.as_str().to_owned(), SerializedNonZeroOid(*oid)))
+                })
+                .collect(),
+            remaining_stacks: root_commit_and_plans
+                .iter()
+                .map(|(root_commit_oid, rebase_plan)| {
+                    (SerializedNonZeroOid(*root_commit_oid), rebase_plan.clone())
+                })
+                .collect(),
+        }
+    }
+
+    fn get_event_tx_id(&self) -> eyre::Result<EventTransactionId> {
+        self.event_tx_id
+            .parse()
+            .wrap_err_with(|| format!("Parsing event transaction ID: {:?}", self.event_tx_id))
+    }
+
+    /// Get the references as they were before the sync started.
+    fn get_references_snapshot(&self) -> RepoReferencesSnapshot {
+        let mut branch_oid_to_names: HashMap<NonZeroOid, HashSet<ReferenceName>> = HashMap::new();
+        for (name, SerializedNonZeroOid(oid)) in &self.branches {
+            branch_oid_to_names
+                .entry(*oid)
+                .or_default()
+                .insert(ReferenceName::from(name.as_str()));
+        }
+        RepoReferencesSnapshot {
+            head_oid: self.head_oid.as_ref().map(|SerializedNonZeroOid(oid)| *oid),
+            main_branch_oid: self.main_branch_oid.0,
+            branch_oid_to_names,
+        }
+    }
+
+    /// Record that the stack rooted at the provided commit has been processed.
+    fn finish_stack(&mut self, root_commit_oid: NonZeroOid) {
+        self.remaining_stacks
+            .retain(|(SerializedNonZeroOid(oid), _rebase_plan)| *oid != root_commit_oid);
+    }
+}
+
+fn get_sync_state_path(repo: &Repo) -> eyre::Result<PathBuf> {
+    Ok(repo.get_branchless_dir()?.join("sync-state.json"))
+}
+
+fn read_sync_state(repo: &Repo) -> eyre::Result<Option<SyncState>> {
+    let path = get_sync_state_path(repo)?;
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err).wrap_err_with(|| format!("Reading sync state at {path:?}")),
+    };
+    let sync_state = serde_json::from_str(&contents)
+        .wrap_err_with(|| format!("Parsing sync state at {path:?}"))?;
+    Ok(Some(sync_state))
+}
+
+/// Save the provided sync state, or remove the saved state if there are no
+/// stacks left to sync.
+fn write_sync_state(repo: &Repo, sync_state: Option<&SyncState>) -> eyre::Result<()> {
+    let path = get_sync_state_path(repo)?;
+    match sync_state {
+        Some(sync_state) if !sync_state.remaining_stacks.is_empty() => {
+            // Write to a temporary file first, so that a crash while writing
+            // doesn't leave a truncated state file behind.
+            let temp_path = path.with_extension("json.tmp");
+            std::fs::write(&temp_path, serde_json::to_string(sync_state)?)
+                .wrap_err_with(|| format!("Writing sync state at {temp_path:?}"))?;
+            std::fs::rename(&temp_path, &path)
+                .wrap_err_with(|| format!("Moving sync state to {path:?}"))
+        }
+        Some(_) | None => match std::fs::remove_file(&path) {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err).wrap_err_with(|| format!("Removing sync state at {path:?}")),
+        },
+    }
+}
+
+/// Ask whether to resume an interrupted sync with `num_remaining_stacks`
+/// stacks left to sync, asking again until the answer is either yes or no.
+/// Returns `None` if the input ends before the user answers.
+fn confirm_resume_sync(
+    effects: &Effects,
+    num_remaining_stacks: usize,
+    mut in_: impl BufRead,
+) -> eyre::Result<Option<bool>> {
+    loop {
+        write!(
+            effects.get_output_stream(),
+            "A previous sync was interrupted with {} remaining. Resume it (y) or discard it (n)? [y/n] ",
+            Pluralize {
+                determiner: None,
+                amount: num_remaining_stacks,
+                unit: ("stack", "stacks"),
+            },
+        )?;
+        let mut user_input = String::new();
+        match in_.read_line(&mut user_input) {
+            Ok(0) | Err(_) => return Ok(None),
+            Ok(_size) => match user_input.trim() {
+                "y" | "Y" => return Ok(Some(true)),
+                "n" | "N" => return Ok(Some(false)),
+                _ => writeln!(effects.get_output_stream(), "Please answer y or n.")?,
+            },
+        }
+    }
+}
+
+/// The namespace under which `--record-backup-refs` records each branch, as
+/// `refs/branchless/backup/<unix-timestamp>/<branch>`.
+const BACKUP_REF_PREFIX: &str = "refs/branchless/backup/";
+
+/// A branch recorded by `--record-backup-refs`.
+#[derive(Debug)]
+struct Backup {
+    /// The name of the backup reference, without [`BACKUP_REF_PREFIX`]. This
+    /// is what `--list-backups` prints and `--restore-backup` accepts.
+    name: String,
+    reference_name: ReferenceName,
+    time: SystemTime,
+    branch_name: ReferenceName,
+    commit_oid: NonZeroOid,
+}
+
+/// Record the commit that each of the provided branches points to under
+/// [`BACKUP_REF_PREFIX`], before the branches are moved.
+fn record_backup_refs(
+    repo: &Repo,
+    now: SystemTime,
+    stack_branches: &HashMap<NonZeroOid, Vec<(ReferenceName, NonZeroOid)>>,
+) -> eyre::Result<()> {
+    let timestamp = now.duration_since(SystemTime::UNIX_EPOCH)?.as_secs();
+    for (branch_name, commit_oid) in stack_branches.values().flatten() {
+        let backup_reference_name = ReferenceName::from(format!(
+            "{BACKUP_REF_PREFIX}{timestamp}/{}",
+            CategorizedReferenceName::new(branch_name).render_suffix()
+        ));
+        repo.create_reference(
+            &backup_reference_name,
+            *commit_oid,
+            true,
+            "branchless: recording sync backup",
+        )?;
+    }
+    Ok(())
+}
+
+/// Get the backups recorded by `--record-backup-refs`, newest first. References
+/// under [`BACKUP_REF_PREFIX`] which weren't created by it are ignored.
+fn get_backups(repo: &Repo) -> eyre::Result<Vec<Backup>> {
+    let mut backups = Vec::new();
+    for reference in repo.get_all_references()? {
+        let reference_name = reference.get_name()?;
+        let name = match reference_name.as_str().strip_prefix(BACKUP_REF_PREFIX) {
+            Some(name) => name.to_owned(),
+            None => continue,
+        };
+        let (timestamp, branch_name) = match name.split_once('/') {
+            Some((timestamp, branch_name)) => match timestamp.parse::<u64>() {
+                Ok(timestamp) => (timestamp, branch_name),
+                Err(_) => continue,
+            },
+            None => continue,
+        };
+        let commit_oid = match reference.peel_to_commit()? {
+            Some(commit) => commit.get_oid(),
+            None => continue,
+        };
+        backups.push(Backup {
+            branch_name: ReferenceName::from(format!("refs/heads/{branch_name}")),
+            name,
+            reference_name,
+            time: SystemTime::UNIX_EPOCH + Duration::from_secs(timestamp),
+            commit_oid,
+        });
+    }
+    backups.sort_by(|lhs, rhs| {
+        rhs.time
+            .cmp(&lhs.time)
+            .then_with(|| lhs.name.cmp(&rhs.name))
+    });
+    Ok(backups)
+}
+
+/// Print the backups recorded by `--record-backup-refs`, after deleting the
+/// ones older than `branchless.sync.backupExpiryDays`.
+fn list_backups(effects: &Effects, repo: &Repo) -> eyre::Result<()> {
+    let now = SystemTime::now();
+    let expiry_days = u64::try_from(get_sync_backup_expiry_days(repo)?.max(0))?;
+    let expiry = Duration::from_secs(expiry_days * 24 * 60 * 60);
+    let (expired_backups, backups): (Vec<_>, Vec<_>) =
+        get_backups(repo)?.into_iter().partition(|backup| {
+            now.duration_since(backup.time)
+                .is_ok_and(|age| age > expiry)
+        });
+    for backup in &expired_backups {
+        if let Some(mut reference) = repo.find_reference(&backup.reference_name)? {
+            reference.delete()?;
+        }
+    }
+
+    let glyphs = effects.get_glyphs();
+    if backups.is_empty() {
+        writeln!(
+            effects.get_output_stream(),
+            "No backups recorded. Pass --record-backup-refs to git sync to record them."
+        )?;
+    }
+    for backup in &backups {
+        let commit = repo.find_commit_or_fail(backup.commit_oid)?;
+        writeln!(
+            effects.get_output_stream(),
+            "{} ({} ago): {} was at {}",
+            backup.name,
+            RelativeTimeDescriptor::describe_time_delta(now, backup.time)?,
+            glyphs.render(
+                StyledStringBuilder::new()
+                    .append_styled(
+                        CategorizedReferenceName::new(&backup.branch_name).friendly_describe(),
+                        BaseColor::Green.dark(),
+                    )
+                    .build(),
+            )?,
+            glyphs.render(commit.friendly_describe(glyphs)?)?,
+        )?;
+    }
+    if !expired_backups.is_empty() {
+        writeln!(
+            effects.get_output_stream(),
+            "Deleted {} older than {}.",
+            Pluralize {
+                determiner: None,
+                amount: expired_backups.len(),
+                unit: ("expired backup", "expired backups"),
+            },
+            Pluralize {
+                determiner: None,
+                amount: usize::try_from(expiry_days)?,
+                unit: ("day", "days"),
+            },
+        )?;
+    }
+    Ok(())
+}
+
+/// Recreate the branch recorded by the backup named `backup_name` at the
+/// commit that it pointed to.
+fn restore_backup(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    repo: &Repo,
+    event_log_db: &EventLogDb,
+    backup_name: &str,
+) -> EyreExitOr<()> {
+    let backup = match get_backups(repo)?
+        .into_iter()
+        .find(|backup| backup.name == backup_name)
+    {
+        Some(backup) => backup,
+        None => {
+            writeln!(
+                effects.get_error_stream(),
+                "No backup named {backup_name}. Run git sync --list-backups to see the recorded backups."
+            )?;
+            return Ok(Err(ExitCode(1)));
+        }
+    };
+
+    // Update the branch with `git update-ref` rather than directly, so that
+    // the `reference-transaction` hook records the update (e.g. for `git
+    // undo`), and so that it works even if the branch is checked out.
+    let event_tx_id = event_log_db.make_transaction_id(SystemTime::now(), "sync restore")?;
+    try_exit_code!(git_run_info.run(
+        effects,
+        Some(event_tx_id),
+        &[
+            "update-ref",
+            "-m",
+            "branchless: restoring sync backup",
+            backup.branch_name.as_str(),
+            &backup.commit_oid.to_string(),
+        ],
+    )?);
+
+    let glyphs = effects.get_glyphs();
+    let commit = repo.find_commit_or_fail(backup.commit_oid)?;
+    writeln!(
+        effects.get_output_stream(),
+        "Restored {} to {}",
+        glyphs.render(
+            StyledStringBuilder::new()
+                .append_styled(
+                    CategorizedReferenceName::new(&backup.branch_name).friendly_describe(),
+                    BaseColor::Green.dark(),
+                )
+                .build(),
+        )?,
+        glyphs.render(commit.friendly_describe(glyphs)?)?,
+    )?;
+    Ok(Ok(()))
+}
+
+/// The arguments which can be passed to `git checkout` via `--checkout-arg`.
+/// Arguments which would change what gets checked out (such as `-b`) or which
+/// would discard local changes (such as `--force`) aren't permitted.
+const PERMITTED_CHECKOUT_ARGS: &[&str] = &[
+    "--recurse-submodules",
+    "--no-recurse-submodules",
+    "-q",
+    "--quiet",
+    "--progress",
+    "--no-progress",
+    "-m",
+    "--merge",
+    "--conflict=<style>",
+    "--ignore-other-worktrees",
+    "--overwrite-ignore",
+    "--no-overwrite-ignore",
+];
+
+fn is_permitted_checkout_arg(arg: &str) -> bool {
+    match arg.strip_prefix("--conflict=") {
+        Some(style) => !style.is_empty(),
+        None => PERMITTED_CHECKOUT_ARGS.contains(&arg),
+    }
+}
+
+/// The exit code used when `git sync` is run outside of a Git repository.
+pub const NOT_IN_REPO_EXIT_CODE: ExitCode = ExitCode(3);
+
+/// The exit code used when `git sync` is run in a repository where
+/// `git branchless init` hasn't been run.
+pub const NOT_INITIALIZED_EXIT_CODE: ExitCode = ExitCode(4);
+
+/// The result of a sync which ran to completion.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SyncOutcome {
+    /// Whether every stack was moved onto the main branch, i.e. none of them
+    /// were skipped due to merge conflicts, because their rebase plans
+    /// couldn't be built (with `--keep-going`), because they weren't
+    /// confirmed, or because one of their branches was deleted during the
+    /// sync.
+    /// Always `false` when only printing the plans.
+    pub is_fully_synced: bool,
+
+    /// The root commits (before the sync) of the stacks which were moved.
+    pub synced_root_commit_oids: Vec<NonZeroOid>,
+
+    /// The root commits of the stacks which weren't moved due to merge
+    /// conflicts.
+    pub conflicted_root_commit_oids: Vec<NonZeroOid>,
+
+    /// The number of commits which were rewritten by the sync.
+    pub num_commits_rewritten: usize,
+
+    /// The root commits (before the sync) of the stacks which were moved, but
+    /// for which the `post-rewrite` hook failed.
+    pub hook_failed_root_commit_oids: Vec<NonZeroOid>,
+}
+
+/// The result of syncing a single stack, as reported to the callback set with
+/// [`SyncBuilder::on_stack_result`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncStatus {
+    /// The stack was moved.
+    Synced,
+
+    /// The stack wasn't moved due to merge conflicts.
+    Conflicted,
+
+    /// The stack didn't need to be moved, or wasn't confirmed.
+    Skipped,
+
+    /// The stack wasn't moved because the sync was interrupted before it was
+    /// started.
+    Interrupted,
+
+    /// The stack was moved, but the `--verify-after` command failed on one of
+    /// its rewritten commits.
+    VerifyFailed,
+}
+
+/// Reports the progress of a sync as each stack is processed. Implement this
+/// to display progress in an embedding tool, and set it with
+/// [`SyncBuilder::progress`].
+pub trait SyncProgress {
+    /// Start reporting progress for a run which will process `num_stacks`
+    /// stacks. The returned operation's effects are used while processing
+    /// them.
+    fn start<'e>(
+        &mut self,
+        effects: &'e Effects,
+        num_stacks: usize,
+    ) -> eyre::Result<Box<dyn SyncProgressOperation + 'e>>;
+}
+
+/// A run started with [`SyncProgress::start`]. Progress reporting for the run
+/// ends when this is dropped.
+pub trait SyncProgressOperation {
+    /// The effects to use while processing the stacks.
+    fn effects(&self) -> &Effects;
+
+    /// Called as each stack finishes, with its root commit (as it was before
+    /// the sync).
+    fn on_stack_finished(&mut self, root_commit: &Commit, status: SyncStatus) -> eyre::Result<()>;
+}
+
+/// Displays a progress bar in the terminal (`--progress tui`).
+#[derive(Debug, Default)]
+pub struct TuiSyncProgress;
+
+impl SyncProgress for TuiSyncProgress {
+    fn start<'e>(
+        &mut self,
+        effects: &'e Effects,
+        num_stacks: usize,
+    ) -> eyre::Result<Box<dyn SyncProgressOperation + 'e>> {
+        let (effects, progress) = effects.start_operation(OperationType::SyncCommits);
+        progress.notify_progress(0, num_stacks);
+        Ok(Box::new(TuiSyncProgressOperation {
+            effects,
+            progress,
+            num_stacks,
+            num_finished: 0,
+        }))
+    }
+}
+
+struct TuiSyncProgressOperation<'e> {
+    effects: Effects,
+    progress: ProgressHandle<'e>,
+    num_stacks: usize,
+    num_finished: usize,
+}
+
+impl SyncProgressOperation for TuiSyncProgressOperation<'_> {
+    fn effects(&self) -> &Effects {
+        &self.effects
+    }
+
+    fn on_stack_finished(
+        &mut self,
+        _root_commit: &Commit,
+        _status: SyncStatus,
+    ) -> eyre::Result<()> {
+        self.num_finished += 1;
+        self.progress
+            .notify_progress(self.num_finished, self.num_stacks);
+        Ok(())
+    }
+}
+
+/// Prints a line to stderr as each stack finishes, which is suitable for logs
+/// and non-interactive terminals (`--progress plain`).
+#[derive(Debug, Default)]
+pub struct PlainSyncProgress;
+
+impl SyncProgress for PlainSyncProgress {
+    fn start<'e>(
+        &mut self,
+        effects: &'e Effects,
+        num_stacks: usize,
+    ) -> eyre::Result<Box<dyn SyncProgressOperation + 'e>> {
+        Ok(Box::new(PlainSyncProgressOperation {
+            effects: effects.clone(),
+            num_stacks,
+            num_finished: 0,
+        }))
+    }
+}
+
+struct PlainSyncProgressOperation {
+    effects: Effects,
+    num_stacks: usize,
+    num_finished: usize,
+}
+
+impl SyncProgressOperation for PlainSyncProgressOperation {
+    fn effects(&self) -> &Effects {
+        &self.effects
+    }
+
+    fn on_stack_finished(&mut self, root_commit: &Commit, status: SyncStatus) -> eyre::Result<()> {
+        self.num_finished += 1;
+        let status = match status {
+            SyncStatus::Synced => "synced",
+            SyncStatus::Conflicted => "conflicted",
+            SyncStatus::Skipped => "skipped",
+            SyncStatus::Interrupted => "interrupted",
+            SyncStatus::VerifyFailed => "verify failed",
+        };
+        let glyphs = self.effects.get_glyphs();
+        writeln!(
+            self.effects.get_error_stream(),
+            "[{}/{}] {}: {}",
+            self.num_finished,
+            self.num_stacks,
+            status,
+            glyphs.render(root_commit.friendly_describe(glyphs)?)?,
+        )?;
+        Ok(())
+    }
+}
+
+/// Doesn't report progress (`--progress none`).
+#[derive(Debug, Default)]
+pub struct NoSyncProgress;
+
+impl SyncProgress for NoSyncProgress {
+    fn start<'e>(
+        &mut self,
+        effects: &'e Effects,
+        _num_stacks: usize,
+    ) -> eyre::Result<Box<dyn SyncProgressOperation + 'e>> {
+        Ok(Box::new(NoSyncProgressOperation {
+            effects: effects.clone(),
+        }))
+    }
+}
+
+struct NoSyncProgressOperation {
+    effects: Effects,
+}
+
+impl SyncProgressOperation for NoSyncProgressOperation {
+    fn effects(&self) -> &Effects {
+        &self.effects
+    }
+
+    fn on_stack_finished(
+        &mut self,
+        _root_commit: &Commit,
+        _status: SyncStatus,
+    ) -> eyre::Result<()> {
+        Ok(())
+    }
+}
+
+/// Builder for running `git sync` programmatically, such as from a tool which
+/// embeds git-branchless. The options are the same as those of the `git sync`
+/// command-line interface (see [`SyncArgs`]), and the defaults are the same
+/// as when no flags are passed.
+///
+/// ```no_run
+/// # use git_branchless::commands::sync::SyncBuilder;
+/// # use git_branchless_opts::{Revset, SyncRewriteOptions};
+/// # use lib::core::effects::Effects;
+/// # use lib::core::formatting::Glyphs;
+/// # use lib::git::GitRunInfo;
+/// # fn example(git_run_info: &GitRunInfo) -> eyre::Result<()> {
+/// let effects = Effects::new(Glyphs::text());
+/// let outcome = SyncBuilder::new(&effects, git_run_info)
+///     .pull(true)
+///     .rewrite_options(SyncRewriteOptions {
+///         rebase_merges: true,
+///         ..Default::default()
+///     })
+///     .revsets(vec![Revset("stack()".to_string())])
+///     .run()?;
+/// if let Ok(outcome) = outcome {
+///     println!("Synced {} stack(s)", outcome.synced_root_commit_oids.len());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct SyncBuilder<'a> {
+    effects: &'a Effects,
+    git_run_info: &'a GitRunInfo,
+    args: SyncArgs,
+    on_stack_result: Option<Box<dyn FnMut(NonZeroOid, SyncStatus) + 'a>>,
+    progress: Option<Box<dyn SyncProgress + 'a>>,
+    dag: Option<&'a mut Dag>,
+}
+
+impl<'a> SyncBuilder<'a> {
+    /// Constructor. The sync runs in the repository containing the current
+    /// directory.
+    pub fn new(effects: &'a Effects, git_run_info: &'a GitRunInfo) -> Self {
+        Self {
+            effects,
+            git_run_info,
+            args: SyncArgs::default(),
+            on_stack_result: None,
+            progress: None,
+            dag: None,
+        }
+    }
+
+    /// Fetch from the remotes and update the main branch before syncing
+    /// (`--pull`).
+    pub fn pull(mut self, pull: bool) -> Self {
+        self.args.fetch_options.pull = pull;
+        self
+    }
+
+    /// The options for fetching before syncing.
+    pub fn fetch_options(mut self, fetch_options: SyncFetchOptions) -> Self {
+        self.args.fetch_options = fetch_options;
+        self
+    }
+
+    /// The options to use when moving commits.
+    pub fn move_options(mut self, move_options: MoveOptions) -> Self {
+        self.args.move_options = move_options;
+        self
+    }
+
+    /// The options for selecting which stacks to sync.
+    pub fn select_options(mut self, select_options: SyncSelectOptions) -> Self {
+        self.args.select_options = select_options;
+        self
+    }
+
+    /// The options for rewriting the commits in the synced stacks.
+    pub fn rewrite_options(mut self, rewrite_options: SyncRewriteOptions) -> Self {
+        self.args.rewrite_options = rewrite_options;
+        self
+    }
+
+    /// The options for reporting on the sync.
+    pub fn output_options(mut self, output_options: SyncOutputOptions) -> Self {
+        self.args.output_options = output_options;
+        self
+    }
+
+    /// Run garbage collection after syncing (`--gc`).
+    pub fn gc(mut self, gc: bool) -> Self {
+        self.args.gc = gc;
+        self
+    }
+
+    /// Push the synced branches to the default push remote (`--publish`).
+    pub fn publish(mut self, publish: bool) -> Self {
+        self.args.publish = publish;
+        self
+    }
+
+    /// Move the commits given by [`SyncBuilder::revsets`] onto this commit
+    /// rather than the main branch (`--onto`).
+    pub fn onto(mut self, onto: Revset) -> Self {
+        self.args.onto = Some(onto);
+        self
+    }
+
+    /// Move the commits given by [`SyncBuilder::revsets`] onto the commit
+    /// that this tag points to (`--onto-tag`).
+    pub fn onto_tag(mut self, tag: impl Into<String>) -> Self {
+        self.args.onto_tag = Some(tag.into());
+        self
+    }
+
+    /// Only sync the stacks containing these commits. If empty, all stacks
+    /// are synced.
+    pub fn revsets(mut self, revsets: Vec<Revset>) -> Self {
+        self.args.revsets = revsets;
+        self
+    }
+
+    /// The options to use when resolving revsets.
+    pub fn resolve_revset_options(mut self, resolve_revset_options: ResolveRevsetOptions) -> Self {
+        self.args.resolve_revset_options = resolve_revset_options;
+        self
+    }
+
+    /// Call the provided function with the root commit of each stack (as it
+    /// was before the sync) as soon as that stack has been processed, rather
+    /// than waiting for the whole sync to finish. If the function panics, the
+    /// panic is reported and the function isn't called again.
+    pub fn on_stack_result(
+        mut self,
+        on_stack_result: impl FnMut(NonZeroOid, SyncStatus) + 'a,
+    ) -> Self {
+        self.on_stack_result = Some(Box::new(on_stack_result));
+        self
+    }
+
+    /// Report progress with the provided reporter rather than one of the
+    /// built-in ones.
+    pub fn progress(mut self, progress: impl SyncProgress + 'a) -> Self {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    /// Use the provided DAG to find and plan the stacks to sync, rather than
+    /// opening the DAG again, for callers which already have one.
+    ///
+    /// The DAG must have been opened (e.g. with [`Dag::open_and_sync`]) for
+    /// the same repository since the last change to its references or event
+    /// log, since the visible and obsolete commits are the ones computed when
+    /// it was opened. Only the commits referenced by the current branches and
+    /// `HEAD` are added to it. It isn't used when pulling (since the main
+    /// branch may have moved) or with `--at-event`, and it doesn't reflect the
+    /// rewritten commits afterwards, so it should be reopened after the sync.
+    pub fn dag(mut self, dag: &'a mut Dag) -> Self {
+        self.dag = Some(dag);
+        self
+    }
+
+    /// Run the sync. Errors which are reported to the user (such as an
+    /// invalid revset) result in an exit code rather than an `Err`.
+    pub fn run(self) -> EyreExitOr<SyncOutcome> {
+        // With `--print-plan-only`, the plans are written to the output file
+        // instead.
+        let output_file = match &self.args.output_options {
+            SyncOutputOptions {
+                print_plan_only: false,
+                output_file: Some(output_file),
+                ..
+            } => Some(output_file.clone()),
+            _ => None,
+        };
+        let strict_hooks = self.args.rewrite_options.strict_hooks;
+        // Errors opening the repository are reported when syncing.
+        let autostash = match Repo::from_current_dir() {
+            Ok(repo) => Autostash::new(&repo, &self.args)?,
+            Err(_) => Autostash::default(),
+        };
+        let effects = self.effects;
+        let git_run_info = self.git_run_info;
+        // The stash is restored even if the sync fails partway through.
+        let result = self.run_sync(&autostash);
+        autostash.restore(effects, git_run_info)?;
+        let outcome = try_exit_code!(result?);
+        if let Some(output_file) = output_file {
+            let json = serde_json::to_string_pretty(&SyncOutcomeJson {
+                is_fully_synced: outcome.is_fully_synced,
+                synced_root_oids: outcome
+                    .synced_root_commit_oids
+                    .iter()
+                    .map(NonZeroOid::to_string)
+                    .collect(),
+                conflicted_root_oids: outcome
+                    .conflicted_root_commit_oids
+                    .iter()
+                    .map(NonZeroOid::to_string)
+                    .collect(),
+                num_commits_rewritten: outcome.num_commits_rewritten,
+            })?;
+            write_output_file(&output_file, &format!("{json}\n"))?;
+        }
+        if strict_hooks && !outcome.hook_failed_root_commit_oids.is_empty() {
+            return Ok(Err(ExitCode(1)));
+        }
+        Ok(Ok(outcome))
+    }
+
+    #[instrument(skip_all)]
+    fn run_sync(self, autostash: &Autostash) -> EyreExitOr<SyncOutcome> {
+        let Self {
+            effects,
+            git_run_info,
+            mut args,
+            mut on_stack_result,
+            progress,
+            dag,
+        } = self;
+        // Start the top-level operation before anything else, so that fetching,
+        // syncing the main branch and syncing each stack are all displayed
+        // nested under it.
+        let (effects, _progress) = effects.start_operation(OperationType::Sync);
+        let effects = &effects;
+        let traced_git_run_info;
+        let git_run_info = match &args.output_options.trace_git {
+            Some(trace_git) => {
+                // Hooks are run from the root of the working copy, so a
+                // relative path to the log file would refer to a different
+                // file in the subprocesses which inherit the variable.
+                let trace_dest = if trace_git.is_empty() || trace_git == "-" {
+                    OsString::from("-")
+                } else {
+                    std::env::current_dir()?.join(trace_git).into_os_string()
+                };
+                let mut git_run_info = git_run_info.clone();
+                git_run_info
+                    .env
+                    .insert(OsString::from(BRANCHLESS_TRACE_GIT_ENV_VAR), trace_dest);
+                traced_git_run_info = git_run_info;
+                &traced_git_run_info
+            }
+            None => git_run_info,
+        };
+
+        // Describing the plan considers only the stacks containing the
+        // provided commits, and otherwise behaves like printing the plan.
+        if let Some(describe_plan) = &args.output_options.describe_plan {
+            args.revsets = vec![describe_plan.clone()];
+            args.output_options.print_plan_only = true;
+        }
+
+        let mut repo = match Repo::from_current_dir() {
+            Ok(repo) => repo,
+            Err(RepoError::OpenRepo(err)) if err.code() == GitErrorCode::NotFound => {
+                writeln!(
+                    effects.get_error_stream(),
+                    "Error: not in a Git repository. Run `git sync` from inside a Git repository."
+                )?;
+                return Ok(Err(NOT_IN_REPO_EXIT_CODE));
+            }
+            Err(err) => return Err(err.into()),
+        };
+        if !is_branchless_initialized(&repo)? {
+            writeln!(
+                effects.get_error_stream(),
+                "Error: git-branchless is not initialized in this repository. Run `git branchless init` first."
+            )?;
+            return Ok(Err(NOT_INITIALIZED_EXIT_CODE));
+        }
+        // In a monorepo, a project may have its own trunk, which is synced
+        // onto in place of the repository's main branch when syncing from
+        // within the project.
+        if let Some(main_branch_name) =
+            get_sync_main_branch_override(&repo, &std::env::current_dir()?)?
+        {
+            if repo
+                .find_branch(&main_branch_name, BranchType::Local)?
+                .is_none()
+            {
+                writeln!(
+                    effects.get_error_stream(),
+                    "Error: the main branch {main_branch_name} set by branchless.sync.mainBranch in {SYNC_DIRECTORY_CONFIG_FILE_NAME} doesn't exist."
+                )?;
+                return Ok(Err(ExitCode(1)));
+            }
+            repo.set_main_branch_override(main_branch_name);
+        }
+        // Moving commits or branches in the middle of another multi-step
+        // operation would leave that operation's state inconsistent.
+        if let Some(operation_type) = repo.get_current_operation_type() {
+            writeln!(
+                effects.get_error_stream(),
+                "Finish or abort the in-progress {operation_type} before syncing."
+            )?;
+            return Ok(Err(ExitCode(1)));
+        }
+        if args.list_backups {
+            list_backups(effects, &repo)?;
+            return Ok(Ok(SyncOutcome::default()));
+        }
+        if let Some(backup_name) = &args.restore_backup {
+            let conn = repo.get_db_conn()?;
+            let event_log_db = EventLogDb::new(&conn)?;
+            try_exit_code!(restore_backup(
+                effects,
+                git_run_info,
+                &repo,
+                &event_log_db,
+                backup_name
+            )?);
+            return Ok(Ok(SyncOutcome::default()));
+        }
+        // Verifying checks out the rewritten commits, which would carry over
+        // (or refuse to overwrite) any uncommitted changes.
+        if args.rewrite_options.verify_after.is_some()
+            && !args.output_options.print_plan_only
+            && repo.has_changed_files(effects, git_run_info)?
+        {
+            writeln!(
+                effects.get_error_stream(),
+                "Can't run --verify-after with uncommitted changes. Commit or stash them first."
+            )?;
+            return Ok(Err(ExitCode(1)));
+        }
+        let sync_state = match read_sync_state(&repo)? {
+            Some(sync_state)
+                if !args.output_options.print_plan_only && !args.fetch_options.upstream_only =>
+            {
+                // There's no one to answer the prompt when running from a
+                // script, and syncing anyway would leave the saved plans
+                // stale, so leave the interrupted sync for the user.
+                if !console::user_attended() {
+                    writeln!(
+                        effects.get_error_stream(),
+                        "Not syncing, since a previous sync was interrupted with {} remaining. Run git sync from a terminal to resume or discard it.",
+                        Pluralize {
+                            determiner: None,
+                            amount: sync_state.remaining_stacks.len(),
+                            unit: ("stack", "stacks"),
+                        },
+                    )?;
+                    return Ok(Err(ExitCode(1)));
+                }
+                match confirm_resume_sync(
+                    effects,
+                    sync_state.remaining_stacks.len(),
+                    stdin().lock(),
+                )? {
+                    Some(true) => Some(sync_state),
+                    Some(false) => {
+                        write_sync_state(&repo, None)?;
+                        writeln!(
+                            effects.get_output_stream(),
+                            "Discarded the interrupted sync."
+                        )?;
+                        None
+                    }
+                    None => {
+                        writeln!(
+                            effects.get_error_stream(),
+                            "Not syncing. Run git sync again and answer y to resume the interrupted sync, or n to discard it."
+                        )?;
+                        return Ok(Err(ExitCode(1)));
+                    }
+                }
+            }
+            _ => None,
+        };
+        // Without any revsets, sync the ones configured by the user, if any.
+        if args.revsets.is_empty() && !args.select_options.all {
+            let default_revsets = get_sync_default_revsets(&repo)?
+                .into_iter()
+                .map(Revset)
+                .collect_vec();
+            if let Err(err) = check_revset_syntax(&repo, &default_revsets) {
+                writeln!(
+                    effects.get_error_stream(),
+                    "Invalid revset in branchless.sync.defaultRevsets: {err}"
+                )?;
+                return Ok(Err(ExitCode(1)));
+            }
+            args.revsets = default_revsets;
+        }
+
+        let SyncArgs {
+            fetch_options:
+                SyncFetchOptions {
+                    pull,
+                    no_fetch_tags,
+                    upstream,
+                    fetch_remote,
+                    update_main_no_fetch,
+                    upstream_only,
+                    yes,
+                },
+            move_options,
+            select_options,
+            rewrite_options,
+            output_options,
+            gc,
+            commit_graph_refresh,
+            notify,
+            publish,
+            onto,
+            onto_tag,
+            revsets,
+            resolve_revset_options,
+            ..
+        } = &args;
+
+        let on_stack_result: Option<&mut dyn FnMut(NonZeroOid, SyncStatus)> =
+            match &mut on_stack_result {
+                Some(on_stack_result) => Some(on_stack_result),
+                None => None,
+            };
+        let mut progress =
+            progress.unwrap_or_else(|| match output_options.progress.unwrap_or_default() {
+                SyncProgressFormat::Tui => Box::new(TuiSyncProgress),
+                SyncProgressFormat::Plain => Box::new(PlainSyncProgress),
+                SyncProgressFormat::None => Box::new(NoSyncProgress),
+            });
+        let progress = progress.as_mut();
+        let conn = repo.get_db_conn()?;
+        let event_log_db = EventLogDb::new(&conn)?;
+        // Read the references once up front, since reading them is expensive in
+        // repositories with many references (e.g. tags).
+        let references_snapshot = repo.get_references_snapshot()?;
+
+        let conflicted_root_commit_oids = if select_options.only_conflicting {
+            let conflicted_root_commit_oids = read_sync_conflicts(&repo)?;
+            if conflicted_root_commit_oids.is_empty() {
+                writeln!(
+                    effects.get_warning_stream(),
+                    "There are no stacks which had merge conflicts when they were last synced."
+                )?;
+                return Ok(Ok(SyncOutcome {
+                    is_fully_synced: true,
+                    ..Default::default()
+                }));
+            }
+            Some(conflicted_root_commit_oids)
+        } else {
+            None
+        };
+        if *pull || *update_main_no_fetch || *upstream_only {
+            // The main branch may be updated from its remote reference, which
+            // isn't part of the sync cache key, so the previous sync can't be
+            // reused.
+            write_sync_cache(&repo, None)?;
+        } else if sync_state.is_none()
+            && !output_options.print_plan_only
+            && !publish
+            // The actions requested explicitly for after the sync are carried
+            // out even if there's nothing to sync.
+            && !(*gc || *commit_graph_refresh || *notify)
+            && read_sync_cache(&repo)?.as_deref()
+                == Some(
+                    compute_sync_cache_key(&repo, &references_snapshot, &event_log_db, &args)?
+                        .as_str(),
+                )
+        {
+            writeln!(
+                effects.get_warning_stream(),
+                "Nothing to sync (no changes since the last sync)."
+            )?;
+            return Ok(Ok(SyncOutcome {
+                is_fully_synced: true,
+                ..Default::default()
+            }));
+        }
+        // This replays the whole event log, so it's only done once it's known
+        // that there's something to sync. (If the branches were changed
+        // outside of git-branchless, then the sync cache key has changed too.)
+        //
+        // Only inspecting the repository (possibly as of a past event) shouldn't
+        // add to the event log.
+        let is_read_only = output_options.print_plan_only
+            || output_options.dump_dag.is_some()
+            || select_options.at_event.is_some();
+        if !is_read_only {
+            reconcile_event_log(effects, &repo, &event_log_db, &references_snapshot)?;
+        }
+
+        // The `reference-transaction` hook, which is needed to track the branch
+        // updates made during a sync (e.g. for `git undo`), was added in Git v2.29.
+        let git_version = git_run_info.version(&repo)?;
+        if git_version < GitVersion(2, 29, 0) {
+            writeln!(
+                effects.get_warning_stream(),
+                "Warning: `git sync` requires Git v2.29 or later to track branch updates, but your Git version is: {git_version}"
+            )?;
+        }
+
+        let now = SystemTime::now();
+        let event_tx_id = event_log_db.make_transaction_id(now, "sync fetch")?;
+
+        // Try to surface parse errors early, before potentially doing commit graph or network
+        // side-effects.
+        check_revset_syntax_with_references(&repo, Some(&references_snapshot), revsets)?;
+        check_revset_syntax_with_references(
+            &repo,
+            Some(&references_snapshot),
+            &select_options.exclude,
+        )?;
+        check_revset_syntax_with_references(
+            &repo,
+            Some(&references_snapshot),
+            &select_options.include_public,
+        )?;
+        for revset in upstream.iter().chain(onto.iter()) {
+            check_revset_syntax_with_references(
+                &repo,
+                Some(&references_snapshot),
+                std::slice::from_ref(revset),
+            )?;
+        }
+        let touched_paths_filter = match TouchedPathsFilter::new(&select_options.touched_paths) {
+            Ok(touched_paths_filter) => touched_paths_filter,
+            Err(err) => {
+                writeln!(
+                    effects.get_error_stream(),
+                    "Invalid glob pattern for --touched-paths: {err}"
+                )?;
+                return Ok(Err(ExitCode(1)));
+            }
+        };
+        if let Some(checkout_arg) = rewrite_options
+            .checkout_args
+            .iter()
+            .find(|checkout_arg| !is_permitted_checkout_arg(checkout_arg))
+        {
+            writeln!(
+                effects.get_error_stream(),
+                "Unsupported --checkout-arg: {checkout_arg} (supported arguments are: {})",
+                PERMITTED_CHECKOUT_ARGS.join(", ")
+            )?;
+            return Ok(Err(ExitCode(1)));
+        }
+        let fetch_remote = match fetch_remote {
+            Some(fetch_remote) => Some(fetch_remote.clone()),
+            None => get_sync_remote(&repo)?,
+        };
+        if let Some(fetch_remote) = &fetch_remote {
+            if !repo.get_all_remote_names()?.contains(fetch_remote) {
+                writeln!(
+                    effects.get_error_stream(),
+                    "Remote to fetch does not exist: {fetch_remote}"
+                )?;
+                return Ok(Err(ExitCode(1)));
+            }
+        }
+
+        if *pull {
+            // Only the remote hosting the main branch's upstream branch is
+            // needed to fast-forward the main branch, so don't fetch every
+            // remote when it's known.
+            let main_remote_name = match &fetch_remote {
+                Some(fetch_remote) => Some(fetch_remote.clone()),
+                None => {
+                    let main_branch = repo.get_main_branch()?;
+                    match CategorizedReferenceName::new(&main_branch.get_reference_name()?) {
+                        CategorizedReferenceName::RemoteBranch { .. } => {
+                            main_branch.get_remote_name()?
+                        }
+                        CategorizedReferenceName::LocalBranch { .. }
+                        | CategorizedReferenceName::OtherRef { .. } => {
+                            main_branch.get_upstream_remote_name()?
+                        }
+                    }
+                }
+            };
+            let fetch_tags = !no_fetch_tags && get_sync_fetch_tags(&repo)?;
+            let is_fetched = get_sync_native_fetch(&repo)?
+                && native_fetch(
+                    effects,
+                    &repo,
+                    &event_log_db,
+                    now,
+                    event_tx_id,
+                    main_remote_name.as_deref(),
+                    fetch_tags,
+                )?;
+            if !is_fetched {
+                let mut args = vec!["fetch", main_remote_name.as_deref().unwrap_or("--all")];
+                if !fetch_tags {
+                    args.push("--no-tags");
+                }
+                let GitRunResult {
+                    exit_code,
+                    stdout: _,
+                    stderr,
+                } = git_run_info.run_captured(effects, Some(event_tx_id), &args)?;
+                if !exit_code.is_success() {
+                    // Git's output has already been displayed, so only repeat
+                    // the line explaining the failure.
+                    let stderr = String::from_utf8_lossy(&stderr);
+                    let reason = stderr
+                        .lines()
+                        .find(|line| line.starts_with("fatal:") || line.starts_with("error:"))
+                        .or_else(|| stderr.lines().rev().find(|line| !line.trim().is_empty()));
+                    writeln!(
+                        effects.get_error_stream(),
+                        "Fetching from {} failed with exit code {}{}",
+                        main_remote_name.as_deref().unwrap_or("all remotes"),
+                        exit_code.0,
+                        match reason {
+                            Some(reason) => format!(": {}", reason.trim()),
+                            None => ".".to_string(),
+                        }
+                    )?;
+                    return Ok(Err(exit_code));
+                }
+            }
+        }
+
+        let MoveOptions {
+            force_rewrite_public_commits,
+            force_in_memory,
+            force_on_disk,
+            detect_duplicate_commits_via_patch_id,
+            resolve_merge_conflicts,
+            dump_rebase_constraints,
+            dump_rebase_plan,
+        } = *move_options;
+        let build_options = BuildRebasePlanOptions {
+            force_rewrite_public_commits,
+            detect_duplicate_commits_via_patch_id,
+            merge_base_strategy: match rewrite_options.merge_base_strategy.unwrap_or_default() {
+                SyncMergeBaseStrategy::CommitGraph => MergeBaseStrategy::CommitGraph,
+                SyncMergeBaseStrategy::Git => MergeBaseStrategy::Git(git_run_info.clone()),
+            },
+            dump_rebase_constraints,
+            dump_rebase_plan,
+        };
+        let now = SystemTime::now();
+        // A resumed sync records its rewrites under the original transaction.
+        let event_tx_id = match &sync_state {
+            Some(sync_state) => sync_state.get_event_tx_id()?,
+            None => event_log_db.make_transaction_id(now, "sync")?,
+        };
+        let execute_options = ExecuteRebasePlanOptions {
+            now,
+            event_tx_id,
+            preserve_timestamps: get_restack_preserve_timestamps(&repo)?,
+            force_in_memory,
+            force_on_disk,
+            resolve_merge_conflicts,
+            check_out_commit_options: CheckOutCommitOptions {
+                additional_args: rewrite_options
+                    .checkout_args
+                    .iter()
+                    .map(OsString::from)
+                    .collect(),
+                reset: false,
+                render_smartlog: false,
+            },
+            reapply_empty_commits: rewrite_options.reapply_empty,
+            preserve_committer: rewrite_options.preserve_committer,
+            reset_author: rewrite_options.reset_author,
+            sign_option: match &rewrite_options.gpg_sign {
+                Some(key_id) if key_id.is_empty() => SignOption::UseConfig,
+                Some(key_id) => SignOption::KeyOverride(key_id.clone()),
+                None if get_commit_gpg_sign(&repo)? => SignOption::UseConfig,
+                None => SignOption::Disable,
+            },
+            no_verify: rewrite_options.no_verify,
+            commit_trailers: rewrite_options
+                .add_trailers
+                .iter()
+                .map(|Trailer { key, value }| (key.clone(), value.clone()))
+                .collect(),
+        };
+
+        let thread_pool = make_thread_pool(effects)?;
+        let repo_pool = RepoResource::new_pool(&repo)?;
+
+        if let Some(sync_state) = sync_state {
+            let outcome = try_exit_code!(resume_sync_plans(
+                effects,
+                git_run_info,
+                &repo,
+                &event_log_db,
+                &execute_options,
+                &thread_pool,
+                &repo_pool,
+                rewrite_options,
+                output_options,
+                on_stack_result,
+                autostash,
+                progress,
+                sync_state,
+            )?);
+            if rewrite_options.update_tags {
+                update_rewritten_tags(effects, git_run_info, &repo, &event_log_db, event_tx_id)?;
+            }
+
+            return Ok(Ok(outcome));
+        }
+
+        // Fetching or updating the main branch may move references, in which
+        // case they have to be read again before building the sync plans.
+        let references_snapshot = if *pull || *update_main_no_fetch || *upstream_only {
+            None
+        } else {
+            Some(references_snapshot)
+        };
+
+        let head_info = repo.get_head_info()?;
+        if (*pull || *update_main_no_fetch || *upstream_only) && !output_options.print_plan_only {
+            try_exit_code!(execute_main_branch_sync_plan(
+                effects,
+                git_run_info,
+                &repo,
+                &event_log_db,
+                &build_options,
+                &execute_options,
+                &thread_pool,
+                &repo_pool,
+                &head_info,
+                upstream.as_ref(),
+                fetch_remote.as_deref(),
+                *yes,
+                resolve_revset_options,
+                autostash,
+                progress,
+            )?);
+        }
+        if *upstream_only {
+            return Ok(Ok(SyncOutcome::default()));
+        }
+
+        // The tag is resolved only now, since fetching might have created or
+        // moved it.
+        let onto = match onto_tag {
+            Some(tag) => {
+                let onto_oid = try_exit_code!(resolve_onto_tag(effects, &repo, tag)?);
+                Some(Revset(onto_oid.to_string()))
+            }
+            None => onto.clone(),
+        };
+        if let Some(onto) = &onto {
+            let outcome = try_exit_code!(execute_onto_sync_plan(
+                effects,
+                git_run_info,
+                &repo,
+                &event_log_db,
+                build_options,
+                &execute_options,
+                &thread_pool,
+                &repo_pool,
+                references_snapshot,
+                &args,
+                onto,
+                on_stack_result,
+                autostash,
+                progress,
+            )?);
+            if rewrite_options.update_tags && !output_options.print_plan_only {
+                update_rewritten_tags(effects, git_run_info, &repo, &event_log_db, event_tx_id)?;
+            }
+            return Ok(Ok(outcome));
+        }
+
+        // The main branch might have changed since we synced with `master`, so read its information again.
+
+        let outcome = try_exit_code!(execute_sync_plans(
+            effects,
+            git_run_info,
+            &repo,
+            &event_log_db,
+            build_options,
+            &execute_options,
+            &thread_pool,
+            &repo_pool,
+            references_snapshot,
+            // The main branch may have been updated or overridden, in which
+            // case the provided DAG's public commits are stale.
+            dag.filter(|_| {
+                !(*pull || *update_main_no_fetch) && repo.get_main_branch_override().is_none()
+            }),
+            &args,
+            touched_paths_filter.as_ref(),
+            conflicted_root_commit_oids.as_ref(),
+            on_stack_result,
+            autostash,
+            progress,
+        )?);
+        if outcome.is_fully_synced {
+            let sync_cache_key = compute_sync_cache_key(
+                &repo,
+                &repo.get_references_snapshot()?,
+                &event_log_db,
+                &args,
+            )?;
+
+            write_sync_cache(&repo, Some(&sync_cache_key))?;
+        }
+        if !output_options.print_plan_only {
+            if rewrite_options.update_tags {
+                update_rewritten_tags(effects, git_run_info, &repo, &event_log_db, event_tx_id)?;
+            }
+        }
+
+        if !output_options.print_plan_only
+            && (*commit_graph_refresh || get_sync_write_commit_graph(&repo)?)
+        {
+            refresh_commit_graph(
+                effects,
+                git_run_info,
+                &repo,
+                event_tx_id,
+                outcome.num_commits_rewritten,
+            )?;
+        }
+        if !output_options.print_plan_only && (*gc || get_sync_auto_gc(&repo)?) {
+            try_exit_code!(run_auto_gc(effects, git_run_info, &repo, event_tx_id)?);
+        }
+        if !output_options.print_plan_only && (*notify || get_sync_notify(&repo)?) {
+            send_sync_notification(&outcome);
+        }
+        Ok(Ok(outcome))
+    }
+}
+
+/// Move all commit stacks on top of the main branch.
+#[instrument]
+pub fn sync(effects: &Effects, git_run_info: &GitRunInfo, args: SyncArgs) -> EyreExitOr<()> {
+    let builder = SyncBuilder {
+        effects,
+        git_run_info,
+        args,
+        on_stack_result: None,
+        progress: None,
+        dag: None,
+    };
+    let _outcome: SyncOutcome = try_exit_code!(builder.run()?);
+    Ok(Ok(()))
+}
+
+/// The default value of Git's `gc.auto` setting.
+const DEFAULT_GC_AUTO_THRESHOLD: i32 = 6700;
+
+fn count_loose_objects(git_run_info: &GitRunInfo, repo: &Repo) -> eyre::Result<usize> {
+    let GitRunResult { stdout, .. } =
+        git_run_info.run_silent(repo, None, &["count-objects"], Default::default())?;
+    let stdout = String::from_utf8(stdout).wrap_err("Decoding stdout from Git subprocess")?;
+    // The output looks like `123 objects, 456 kilobytes`.
+    stdout
+        .split_whitespace()
+        .next()
+        .and_then(|num_objects| num_objects.parse().ok())
+        .ok_or_else(|| eyre::eyre!("Could not parse output of `git count-objects`: {stdout:?}"))
+}
+
+/// Pack the loose objects if there are more of them than the `gc.auto`
+/// threshold, so that repeatedly syncing doesn't bloat the object store.
+#[instrument(skip_all)]
+fn run_auto_gc(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    repo: &Repo,
+    event_tx_id: EventTransactionId,
+) -> EyreExitOr<()> {
+    let threshold: i32 = repo
+        .get_readonly_config()?
+        .get_or("gc.auto", DEFAULT_GC_AUTO_THRESHOLD)?;
+    let num_loose_objects_before = count_loose_objects(git_run_info, repo)?;
+    let threshold_exceeded = match usize::try_from(threshold) {
+        Ok(threshold) => threshold > 0 && num_loose_objects_before > threshold,
+        Err(_) => false,
+    };
+    if !threshold_exceeded {
+        writeln!(
+            effects.get_warning_stream(),
+            "Skipping garbage collection: {} (gc.auto threshold is {threshold}).",
+            Pluralize {
+                determiner: None,
+                amount: num_loose_objects_before,
+                unit: ("loose object", "loose objects"),
+            },
+        )?;
+        return Ok(Ok(()));
+    }
+
+    // Use an incremental repack (only the loose objects are packed) rather
+    // than `git gc`, which would repack the entire repository. Also, `git gc
+    // --auto` only estimates the number of loose objects by sampling, so it
+    // may decline to do anything even though the threshold was exceeded.
+    try_exit_code!(git_run_info.run(effects, Some(event_tx_id), &["repack", "-d", "-q"])?);
+    let num_loose_objects_after = count_loose_objects(git_run_info, repo)?;
+    writeln!(
+        effects.get_output_stream(),
+        "Packed {} ({} remaining).",
+        Pluralize {
+            determiner: None,
+            amount: num_loose_objects_before.saturating_sub(num_loose_objects_after),
+            unit: ("loose object", "loose objects"),
+        },
+        num_loose_objects_after,
+    )?;
+    Ok(Ok(()))
+}
+
+/// Rewrite Git's commit-graph file if enough commits were rewritten, since
+/// the rewritten commits aren't in it, which slows down later commit graph
+/// queries until Git next refreshes it (e.g. during `git gc`).
+#[instrument(skip_all)]
+fn refresh_commit_graph(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    repo: &Repo,
+    event_tx_id: EventTransactionId,
+    num_commits_rewritten: usize,
+) -> eyre::Result<()> {
+    if !repo
+        .get_readonly_config()?
+        .get_or("core.commitGraph", true)?
+    {
+        writeln!(
+            effects.get_warning_stream(),
+            "Skipping commit-graph refresh: core.commitGraph is disabled."
+        )?;
+        return Ok(());
+    }
+    let min_commits = get_sync_write_commit_graph_min_commits(repo)?;
+    if usize::try_from(min_commits).is_ok_and(|min_commits| num_commits_rewritten < min_commits) {
+        writeln!(
+            effects.get_warning_stream(),
+            "Skipping commit-graph refresh: {} rewritten (threshold is {min_commits}).",
+            Pluralize {
+                determiner: None,
+                amount: num_commits_rewritten,
+                unit: ("commit", "commits"),
+            },
+        )?;
+        return Ok(());
+    }
+
+    git_run_info.run_silent(
+        repo,
+        Some(event_tx_id),
+        &["commit-graph", "write", "--reachable"],
+        Default::default(),
+    )?;
+    writeln!(
+        effects.get_output_stream(),
+        "Refreshed the commit-graph after rewriting {}.",
+        Pluralize {
+            determiner: None,
+            amount: num_commits_rewritten,
+            unit: ("commit", "commits"),
+        },
+    )?;
+    Ok(())
+}
+
+/// Send a desktop notification summarizing the sync, for when it was started
+/// and left running. This is best-effort: if there's no notifier for this
+/// platform, or it fails, nothing is reported.
+fn send_sync_notification(outcome: &SyncOutcome) {
+    let SyncOutcome {
+        is_fully_synced: _,
+        synced_root_commit_oids,
+        conflicted_root_commit_oids,
+        num_commits_rewritten: _,
+        hook_failed_root_commit_oids: _,
+    } = outcome;
+    let title = "git sync";
+    let message = format!(
+        "Synced {}, {} with merge conflicts.",
+        Pluralize {
+            determiner: None,
+            amount: synced_root_commit_oids.len(),
+            unit: ("stack", "stacks"),
+        },
+        conflicted_root_commit_oids.len(),
+    );
+
+    let mut command = if cfg!(target_os = "macos") {
+        let mut command = Command::new("osascript");
+        command.arg("-e").arg(format!(
+            "display notification {message:?} with title {title:?}"
+        ));
+        command
+    } else if cfg!(target_os = "windows") {
+        let mut command = Command::new("msg");
+        command.arg("*").arg(format!("{title}: {message}"));
+        command
+    } else {
+        let mut command = Command::new("notify-send");
+        command.arg(title).arg(message);
+        command
+    };
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    if let Err(err) = command.status() {
+        warn!(?err, "Could not send desktop notification");
+    }
+}
+
+/// Create the thread pool used to build the rebase plans. If it can't be
+/// created with the default number of threads, e.g. because of a limit on the
+/// number of threads in a container, fall back to a single thread.
+fn make_thread_pool(effects: &Effects) -> eyre::Result<ThreadPool> {
+    if should_be_deterministic() {
+        return ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .wrap_err("Starting a thread to build the rebase plans");
+    }
+    make_thread_pool_with(effects, |num_threads| {
+        let builder = ThreadPoolBuilder::new();
+        match num_threads {
+            Some(num_threads) => builder.num_threads(num_threads),
+            None => builder,
+        }
+        .build()
+    })
+}
+
+fn make_thread_pool_with<E: std::error::Error + Send + Sync + 'static>(
+    effects: &Effects,
+    build: impl Fn(Option<usize>) -> Result<ThreadPool, E>,
+) -> eyre::Result<ThreadPool> {
+    let err = match build(None) {
+        Ok(thread_pool) => return Ok(thread_pool),
+        Err(err) => err,
+    };
+    writeln!(
+        effects.get_warning_stream(),
+        "Warning: could not start the default number of threads ({err}), so only one thread will be used. This is usually caused by a limit on the number of threads (see `ulimit -u`). Set RAYON_NUM_THREADS to a lower number to silence this warning."
+    )?;
+    build(Some(1)).wrap_err(
+        "Starting a thread to build the rebase plans (the number of threads may be limited, see `ulimit -u`)",
+    )
+}
+
+/// Record events for any branches which were changed without the event log
+/// noticing (e.g. by a Git version without the `reference-transaction` hook,
+/// or with hooks disabled), so that the DAG isn't built from a stale view of
+/// the branches.
+fn reconcile_event_log(
+    effects: &Effects,
+    repo: &Repo,
+    event_log_db: &EventLogDb,
+    references_snapshot: &RepoReferencesSnapshot,
+) -> eyre::Result<()> {
+    fn branch_name_to_oid(
+        references_snapshot: &RepoReferencesSnapshot,
+    ) -> HashMap<ReferenceName, NonZeroOid> {
+        references_snapshot
+            .branch_oid_to_names
+            .iter()
+            .flat_map(|(oid, names)| names.iter().map(move |name| (name.clone(), *oid)))
+            .collect()
+    }
+
+    let event_replayer = EventReplayer::from_event_log_db(effects, repo, event_log_db)?;
+    let event_cursor = event_replayer.make_default_cursor();
+    let logged_branches =
+        branch_name_to_oid(&event_replayer.get_references_snapshot(repo, event_cursor)?);
+    let actual_branches = branch_name_to_oid(references_snapshot);
+    let ref_updates = logged_branches
+        .keys()
+        .chain(actual_branches.keys())
+        .unique()
+        .sorted()
+        .filter_map(|ref_name| {
+            let old_oid = logged_branches.get(ref_name).copied();
+            let new_oid = actual_branches.get(ref_name).copied();
+            if old_oid == new_oid {
+                None
+            } else {
+                Some((
+                    ref_name.clone(),
+                    MaybeZeroOid::from(old_oid),
+                    MaybeZeroOid::from(new_oid),
+                ))
+            }
+        })
+        .collect_vec();
+    if ref_updates.is_empty() {
+        return Ok(());
+    }
+
+    let now = SystemTime::now();
+    let timestamp = now.duration_since(SystemTime::UNIX_EPOCH)?.as_secs_f64();
+    let event_tx_id = event_log_db.make_transaction_id(now, "sync reconcile")?;
+    let num_ref_updates = ref_updates.len();
+    let events = ref_updates
+        .into_iter()
+        .map(|(ref_name, old_oid, new_oid)| Event::RefUpdateEvent {
+            timestamp,
+            event_tx_id,
+            ref_name,
+            old_oid,
+            new_oid,
+            message: None,
+        })
+        .collect_vec();
+    event_log_db.add_events(events)?;
+    writeln!(
+        effects.get_warning_stream(),
+        "Warning: {} changed outside of git-branchless; recorded {} in the event log.",
+        Pluralize {
+            determiner: None,
+            amount: num_ref_updates,
+            unit: ("branch was", "branches were"),
+        },
+        if num_ref_updates == 1 {
+            "the change"
+        } else {
+            "the changes"
+        },
+    )?;
+    Ok(())
+}
+
+/// Fetch the given remote in-process via libgit2, which avoids the overhead of
+/// spawning `git fetch`. If no remote is given, only handles the common case
+/// of a single remote. The remote must be one which libgit2 can fetch by
+/// itself. Returns `false` if the remote wasn't fetched, in which case the
+/// caller should fall back to `git fetch`.
+#[instrument(skip_all)]
+fn native_fetch(
+    effects: &Effects,
+    repo: &Repo,
+    event_log_db: &EventLogDb,
+    now: SystemTime,
+    event_tx_id: EventTransactionId,
+    remote_name: Option<&str>,
+    fetch_tags: bool,
+) -> eyre::Result<bool> {
+    let remote_name = match (remote_name, repo.get_all_remote_names()?.as_slice()) {
+        (Some(remote_name), _) => remote_name.to_owned(),
+        (None, [remote_name]) => remote_name.clone(),
+        (None, _) => return Ok(false),
+    };
+    if !repo.can_fetch_remote_natively(&remote_name)? {
+        return Ok(false);
+    }
+
+    let ref_updates = {
+        let (effects, progress) =
+            effects.start_operation(OperationType::FetchRemote(Arc::new(remote_name.clone())));
+        match repo.fetch_remote(&remote_name, fetch_tags, |current, total| {
+            progress.notify_progress(current, total)
+        }) {
+            Ok(ref_updates) => ref_updates,
+            Err(err) => {
+                writeln!(
+                    effects.get_warning_stream(),
+                    "Could not fetch from {remote_name} natively, falling back to `git fetch`: {err}"
+                )?;
+                return Ok(false);
+            }
+        }
+    };
+
+    // libgit2 doesn't invoke the `reference-transaction` hook, so record the
+    // updated remote-tracking branches ourselves.
+    let timestamp = now.duration_since(SystemTime::UNIX_EPOCH)?.as_secs_f64();
+    let events = ref_updates
+        .into_iter()
+        .map(|(ref_name, old_oid, new_oid)| Event::RefUpdateEvent {
+            timestamp,
+            event_tx_id,
+            ref_name,
+            old_oid,
+            new_oid,
+            message: None,
+        })
+        .collect_vec();
+    event_log_db.add_events(events)?;
+    Ok(true)
+}
+
+/// Print the stacks with the given roots whose commits have all been applied
+/// to the main branch (as determined by patch ID), since they can likely be
+/// hidden. Nothing is hidden automatically.
+///
+/// There's no way to prune such stacks with `git sync` itself, so `git hide
+/// -r` is suggested instead.
+fn report_merged_stacks(
+    effects: &Effects,
+    repo: &Repo,
+    dag: &Dag,
+    main_branch_oid: NonZeroOid,
+    root_commit_oids: &CommitSet,
+) -> eyre::Result<()> {
+    let draft_commits = dag.query_draft_commits()?;
+    let mut patch_ids: HashMap<NonZeroOid, Option<PatchId>> = HashMap::new();
+    let mut get_patch_id = |commit_oid: NonZeroOid| -> eyre::Result<Option<PatchId>> {
+        if let Some(patch_id) = patch_ids.get(&commit_oid) {
+            return Ok(*patch_id);
+        }
+        let commit = repo.find_commit_or_fail(commit_oid)?;
+        let patch_id = repo.get_patch_id(effects, &commit)?;
+        patch_ids.insert(commit_oid, patch_id);
+        Ok(patch_id)
+    };
+
+    // Only the main branch commits which some stack hasn't been moved onto
+    // yet can contain its commits, so compute their patch IDs just once.
+    let main_commits =
+        dag.query_only(CommitSet::from(main_branch_oid), root_commit_oids.clone())?;
+    let main_patch_ids: HashSet<PatchId> = dag
+        .commit_set_to_vec(&main_commits)?
+        .into_iter()
+        .map(&mut get_patch_id)
+        .filter_map_ok(|patch_id| patch_id)
+        .try_collect()?;
+    if main_patch_ids.is_empty() {
+        return Ok(());
+    }
+
+    let mut merged_root_commit_oids = Vec::new();
+    for root_commit_oid in dag.commit_set_to_vec(root_commit_oids)? {
+        let stack_commits = dag
+            .query_descendants(CommitSet::from(root_commit_oid))?
+            .intersection(draft_commits);
+        let is_merged = dag
+            .commit_set_to_vec(&stack_commits)?
+            .into_iter()
+            .map(|commit_oid| -> eyre::Result<bool> {
+                Ok(get_patch_id(commit_oid)?
+                    .map_or(false, |patch_id| main_patch_ids.contains(&patch_id)))
+            })
+            .fold_ok(true, |acc, is_commit_merged| acc && is_commit_merged)?;
+        if is_merged {
+            merged_root_commit_oids.push(root_commit_oid);
+        }
+    }
+
+    if merged_root_commit_oids.is_empty() {
+        return Ok(());
+    }
+    writeln!(
+        effects.get_output_stream(),
+        "Found {} whose commits all appear to have been merged into the main branch (consider hiding with `git hide -r`):",
+        Pluralize {
+            determiner: None,
+            amount: merged_root_commit_oids.len(),
+            unit: ("stack", "stacks"),
+        },
+    )?;
+    for root_commit in sorted_commit_set(repo, dag, &merged_root_commit_oids.into_iter().collect())?
+    {
+        writeln!(
+            effects.get_output_stream(),
+            "  {}",
+            effects
+                .get_glyphs()
+                .render(root_commit.friendly_describe(effects.get_glyphs())?)?,
+        )?;
+    }
+    Ok(())
+}
+
+/// Resolve the revset passed for the given command-line option, which must
+/// evaluate to exactly one commit.
+fn resolve_single_commit(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    repo: &Repo,
+    dag: &mut Dag,
+    option_name: &str,
+    revset: &Revset,
+    resolve_revset_options: &ResolveRevsetOptions,
+) -> EyreExitOr<NonZeroOid> {
+    let commit_sets = match resolve_commits_or_git_revisions(
+        effects,
+        git_run_info,
+        repo,
+        dag,
+        std::slice::from_ref(revset),
+        resolve_revset_options,
+    ) {
+        Ok(commit_sets) => commit_sets,
+        Err(err) => {
+            err.describe(effects)?;
+            return Ok(Err(ExitCode(1)));
+        }
+    };
+    match dag.commit_set_to_vec(&union_all(&commit_sets))?.as_slice() {
+        [commit_oid] => Ok(Ok(*commit_oid)),
+        commit_oids => {
+            writeln!(
+                effects.get_error_stream(),
+                "The {option_name} expression {revset} must resolve to exactly one commit, but it resolved to {}.",
+                Pluralize {
+                    determiner: None,
+                    amount: commit_oids.len(),
+                    unit: ("commit", "commits"),
+                },
+            )?;
+            Ok(Err(ExitCode(1)))
+        }
+    }
+}
+
+/// The uncommitted changes stashed for the duration of the sync (`--autostash`
+/// or `rebase.autoStash`). Nothing is stashed until the sync is about to
+/// modify the working copy (see [`may_modify_working_copy`]), so a sync which
+/// fails validation, has nothing to do, or only rebases in-memory leaves the
+/// working copy alone.
+#[derive(Debug, Default)]
+struct Autostash {
+    is_enabled: bool,
+
+    /// The stash commit created by [`Autostash::stash`], if any.
+    stash_oid: Cell<Option<NonZeroOid>>,
+}
+
+impl Autostash {
+    fn new(repo: &Repo, args: &SyncArgs) -> eyre::Result<Self> {
+        let SyncArgs {
+            rewrite_options,
+            output_options,
+            ..
+        } = args;
+        let is_enabled = if output_options.print_plan_only || output_options.describe_plan.is_some()
+        {
+            false
+        } else if rewrite_options.autostash {
+            true
+        } else if rewrite_options.no_autostash {
+            false
+        } else {
+            get_rebase_autostash(repo)?
+        };
+        Ok(Self {
+            is_enabled,
+            stash_oid: Default::default(),
+        })
+    }
+
+    /// Stash the uncommitted changes in the working copy, if enabled and they
+    /// haven't been stashed already.
+    fn stash(&self, effects: &Effects, git_run_info: &GitRunInfo, repo: &Repo) -> EyreExitOr<()> {
+        if !self.is_enabled
+            || self.stash_oid.get().is_some()
+            || !repo.has_changed_files(effects, git_run_info)?
+        {
+            return Ok(Ok(()));
+        }
+        let stash_reference_name = ReferenceName::from("refs/stash");
+        let get_stash_oid = || -> eyre::Result<Option<NonZeroOid>> {
+            Ok(match repo.find_reference(&stash_reference_name)? {
+                Some(reference) => reference.peel_to_commit()?.map(|commit| commit.get_oid()),
+                None => None,
+            })
+        };
+        let previous_stash_oid = get_stash_oid()?;
+        try_exit_code!(git_run_info.run(
+            effects,
+            None,
+            &[
+                "stash",
+                "push",
+                "--quiet",
+                "--message",
+                "branchless: automatic stash before sync",
+            ],
+        )?);
+        // Git doesn't create a stash if there was nothing to stash after all.
+        let stash_oid = get_stash_oid()?;
+        if stash_oid != previous_stash_oid {
+            self.stash_oid.set(stash_oid);
+        }
+        Ok(Ok(()))
+    }
+
+    /// Restore the changes stashed by [`Autostash::stash`], if any. If the
+    /// sync left a rebase in progress, or the changes can't be applied
+    /// cleanly, they're left in the stash for the user to restore.
+    fn restore(&self, effects: &Effects, git_run_info: &GitRunInfo) -> eyre::Result<()> {
+        let stash_oid = match self.stash_oid.take() {
+            Some(stash_oid) => stash_oid,
+            None => return Ok(()),
+        };
+        let repo = Repo::from_current_dir()?;
+        if let Some(operation_type) = repo.get_current_operation_type() {
+            writeln!(
+                effects.get_output_stream(),
+                "Your uncommitted changes were stashed as {stash_oid}, and won't be restored while the {operation_type} is in progress."
+            )?;
+            writeln!(
+                effects.get_output_stream(),
+                "Run git stash pop to restore them once the {operation_type} has finished."
+            )?;
+            return Ok(());
+        }
+
+        // Other stashes may have been pushed since (e.g. by a hook), so pop
+        // the entry for our stash commit rather than the latest one.
+        let GitRunResult { stdout, .. } = git_run_info.run_silent(
+            &repo,
+            None,
+            &["stash", "list", "--format=%H"],
+            Default::default(),
+        )?;
+        let stash_index = String::from_utf8_lossy(&stdout)
+            .lines()
+            .position(|line| line == stash_oid.to_string());
+        let stash_index = match stash_index {
+            Some(stash_index) => stash_index,
+            None => {
+                writeln!(
+                    effects.get_output_stream(),
+                    "Your uncommitted changes were stashed as {stash_oid}, but the stash entry no longer exists. Run git stash apply {stash_oid} to restore them."
+                )?;
+                return Ok(());
+            }
+        };
+        let stash_name = format!("stash@{{{stash_index}}}");
+        match git_run_info.run(effects, None, &["stash", "pop", "--quiet", &stash_name])? {
+            Ok(()) => {}
+            Err(_exit_code) => {
+                writeln!(
+                    effects.get_output_stream(),
+                    "Restoring your stashed uncommitted changes resulted in conflicts."
+                )?;
+                writeln!(
+                    effects.get_output_stream(),
+                    "Your changes are safe in the stash. Resolve the conflicts and then run git stash drop."
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether executing the provided plan may modify the working copy, either by
+/// rebasing on-disk or by checking out the rewritten `HEAD` commit.
+fn may_modify_working_copy(
+    repo: &Repo,
+    execute_options: &ExecuteRebasePlanOptions,
+    rebase_plan: &RebasePlan,
+) -> eyre::Result<bool> {
+    if execute_options.may_rebase_on_disk() {
+        return Ok(true);
+    }
+    let head_oid = match repo.get_head_info()?.oid {
+        Some(head_oid) => head_oid,
+        None => return Ok(false),
+    };
+    Ok(rebase_plan.commands.iter().any(|command| match command {
+        RebaseCommand::Pick {
+            original_commit_oid: commit_oid,
+            commits_to_apply_oids: _,
+        }
+        | RebaseCommand::Merge {
+            commit_oid,
+            commits_to_merge: _,
+        }
+        | RebaseCommand::Replace {
+            commit_oid,
+            replacement_commit_oid: _,
+            parents: _,
+        } => *commit_oid == head_oid,
+        _ => false,
+    }))
+}
+
+/// Get the commits which were created or rewritten after the provided cursor
+/// (`--since-event`).
+fn get_commits_changed_since(event_replayer: &EventReplayer, cursor: EventCursor) -> CommitSet {
+    event_replayer
+        .get_events_since_cursor(cursor)
+        .iter()
+        .filter_map(|event| match event {
+            Event::CommitEvent { commit_oid, .. } | Event::UnobsoleteEvent { commit_oid, .. } => {
+                Some(*commit_oid)
+            }
+            Event::RewriteEvent {
+                new_commit_oid: MaybeZeroOid::NonZero(new_commit_oid),
+                ..
+            } => Some(*new_commit_oid),
+            Event::RewriteEvent { .. }
+            | Event::RefUpdateEvent { .. }
+            | Event::ObsoleteEvent { .. }
+            | Event::WorkingCopySnapshot { .. } => None,
+        })
+        .collect()
+}
+
+/// Resolve the tag given to `--onto-tag` to the commit that it points to,
+/// peeling annotated tags.
+fn resolve_onto_tag(effects: &Effects, repo: &Repo, tag: &str) -> EyreExitOr<NonZeroOid> {
+    let tag_suffix = tag.strip_prefix("refs/tags/").unwrap_or(tag);
+    let reference_name = ReferenceName::from(format!("refs/tags/{tag_suffix}"));
+    let commit = match repo.find_reference(&reference_name)? {
+        Some(reference) => reference.peel_to_commit()?,
+        None => {
+            writeln!(
+                effects.get_error_stream(),
+                "Error: tag {tag_suffix} does not exist."
+            )?;
+            return Ok(Err(ExitCode(1)));
+        }
+    };
+    match commit {
+        Some(commit) => Ok(Ok(commit.get_oid())),
+        None => {
+            writeln!(
+                effects.get_error_stream(),
+                "Error: tag {tag_suffix} does not point to a commit."
+            )?;
+            Ok(Err(ExitCode(1)))
+        }
+    }
+}
+
+/// Move the provided commits, and their descendants, onto the `onto` commit,
+/// leaving the rest of their stack in place. The commits must form a
+/// contiguous range within a single stack; the earliest of them is where the
+/// stack is cut.
+#[instrument(skip_all)]
+fn execute_onto_sync_plan(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    repo: &Repo,
+    event_log_db: &EventLogDb,
+    build_options: BuildRebasePlanOptions,
+    execute_options: &ExecuteRebasePlanOptions,
+    thread_pool: &ThreadPool,
+    repo_pool: &RepoPool,
+    references_snapshot: Option<RepoReferencesSnapshot>,
+    args: &SyncArgs,
+    onto: &Revset,
+    on_stack_result: Option<&mut dyn FnMut(NonZeroOid, SyncStatus)>,
+    autostash: &Autostash,
+    progress: &mut dyn SyncProgress,
+) -> EyreExitOr<SyncOutcome> {
+    let SyncArgs {
+        select_options,
+        rewrite_options,
+        output_options,
+        revsets,
+        resolve_revset_options,
+        ..
+    } = args;
+    let event_replayer = EventReplayer::from_event_log_db(effects, repo, event_log_db)?;
+    let event_cursor = event_replayer.make_default_cursor();
+    let references_snapshot = match references_snapshot {
+        Some(references_snapshot) => references_snapshot,
+        None => repo.get_references_snapshot()?,
+    };
+    let mut dag = Dag::open_and_sync(
+        effects,
+        repo,
+        &event_replayer,
+        event_cursor,
+        &references_snapshot,
+    )?;
+
+    let onto_oid = try_exit_code!(resolve_single_commit(
+        effects,
+        git_run_info,
+        repo,
+        &mut dag,
+        "--onto",
+        onto,
+        resolve_revset_options,
+    )?);
+    let commits = match resolve_commits(effects, repo, &mut dag, revsets, resolve_revset_options) {
+        Ok(commit_sets) => union_all(&commit_sets),
+        Err(err) => {
+            err.describe(effects)?;
+            return Ok(Err(ExitCode(1)));
+        }
+    };
+    let root_commit_oid = match dag
+        .commit_set_to_vec(&dag.query_roots(commits.clone())?)?
+        .as_slice()
+    {
+        [root_commit_oid]
+            if dag.set_count(&dag.query_range(
+                CommitSet::from(*root_commit_oid),
+                dag.query_heads(commits.clone())?,
+            )?)? == dag.set_count(&commits)? =>
+        {
+            *root_commit_oid
+        }
+        _ => {
+            writeln!(
+                effects.get_error_stream(),
+                "The commits to move with --onto must form a contiguous range within a single stack."
+            )?;
+            return Ok(Err(ExitCode(1)));
+        }
+    };
+    // The descendants of the moved commits are moved along with them, so
+    // moving them onto one of those descendants would introduce a cycle.
+    if dag.query_is_ancestor(root_commit_oid, onto_oid)? {
+        writeln!(
+            effects.get_error_stream(),
+            "Error: cannot rebase a stack onto its own descendant."
+        )?;
+        return Ok(Err(ExitCode(1)));
+    }
+
+    let permitted_public_commits = match resolve_commits(
+        effects,
+        repo,
+        &mut dag,
+        &select_options.include_public,
+        resolve_revset_options,
+    ) {
+        Ok(commit_sets) => union_all(&commit_sets),
+        Err(err) => {
+            err.describe(effects)?;
+            return Ok(Err(ExitCode(1)));
+        }
+    };
+    let permissions = match RebasePlanPermissions::verify_rewrite_set_permitting_public_commits(
+        &dag,
+        build_options,
+        &commits,
+        &permitted_public_commits,
+    )? {
+        Ok(permissions) => permissions,
+        Err(err) => {
+            err.describe(effects, repo, &dag)?;
+            return Ok(Err(ExitCode(1)));
+        }
+    };
+    let mut builder = RebasePlanBuilder::new(&dag, permissions);
+    builder.move_subtree(root_commit_oid, vec![onto_oid])?;
+    let rebase_plan = match builder.build(effects, thread_pool, repo_pool)? {
+        Ok(rebase_plan) => rebase_plan,
+        Err(err) => {
+            err.describe(effects, repo, &dag)?;
+            return Ok(Err(ExitCode(1)));
+        }
+    };
+    let root_commit_and_plans = vec![(root_commit_oid, rebase_plan)];
+    if output_options.print_plan_only {
+        output_plans(effects, repo, output_options, &root_commit_and_plans)?;
+        return Ok(Ok(SyncOutcome::default()));
+    }
+    let ExecutePlansResult {
+        is_fully_synced,
+        synced_root_commit_oids,
+        conflicted_root_commit_oids,
+        interrupted_root_commit_oids: _,
+        num_commits_rewritten,
+        hook_failed_root_commit_oids,
+    } = try_exit_code!(execute_plans(
+        effects,
+        git_run_info,
+        repo,
+        event_log_db,
+        execute_options,
+        rewrite_options,
+        output_options,
+        None,
+        autostash,
+        on_stack_result,
+        None,
+        None,
+        progress,
+        root_commit_and_plans,
+    )?);
+    Ok(Ok(SyncOutcome {
+        is_fully_synced,
+        synced_root_commit_oids,
+        conflicted_root_commit_oids,
+        num_commits_rewritten,
+        hook_failed_root_commit_oids,
+    }))
+}
+
+/// The number of times to try fast-forwarding the main branch if another
+/// process updates it at the same time.
+const FAST_FORWARD_MAIN_BRANCH_ATTEMPTS: usize = 3;
+
+/// Fast-forward the main branch (which isn't checked out) to `oid`. If
+/// another process is updating the main branch at the same time, the update is
+/// retried a few times, re-reading where the main branch points and checking
+/// that the update is still a fast-forward each time.
+fn fast_forward_main_branch(
+    effects: &Effects,
+    repo: &Repo,
+    reference_name: &ReferenceName,
+    description: &str,
+    oid: NonZeroOid,
+) -> EyreExitOr<()> {
+    let mut attempt = 1;
+    loop {
+        match repo.fast_forward_ref(reference_name, oid, "sync") {
+            Ok(_reference) => return Ok(Ok(())),
+            Err(RepoError::CreateReference(err))
+                if matches!(err.code(), GitErrorCode::Modified | GitErrorCode::Locked) =>
+            {
+                if attempt == FAST_FORWARD_MAIN_BRANCH_ATTEMPTS {
+                    break;
+                }
+                attempt += 1;
+                std::thread::sleep(Duration::from_millis(100));
+            }
+            // The main branch was moved somewhere else since it was read.
+            Err(RepoError::NotFastForward { .. }) => break,
+            Err(err) => return Err(err.into()),
+        }
+    }
+    writeln!(
+        effects.get_error_stream(),
+        "Error: the main branch changed concurrently, so {description} wasn't fast-forwarded. Run git sync again to retry."
+    )?;
+    Ok(Err(ExitCode(1)))
+}
+
+#[instrument(skip_all)]
+fn execute_main_branch_sync_plan(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    repo: &Repo,
+    event_log_db: &EventLogDb,
+    build_options: &BuildRebasePlanOptions,
+    execute_options: &ExecuteRebasePlanOptions,
+    thread_pool: &ThreadPool,
+    repo_pool: &RepoPool,
+    head_info: &ResolvedReferenceInfo,
+    upstream: Option<&Revset>,
+    upstream_remote: Option<&str>,
+    yes: bool,
+    resolve_revset_options: &ResolveRevsetOptions,
+    autostash: &Autostash,
+    progress: &mut dyn SyncProgress,
+) -> EyreExitOr<()> {
+    let event_replayer = EventReplayer::from_event_log_db(effects, repo, event_log_db)?;
+    let event_cursor = event_replayer.make_default_cursor();
+    let references_snapshot = repo.get_references_snapshot()?;
+    let mut dag = Dag::open_and_sync(
+        effects,
+        repo,
+        &event_replayer,
+        event_cursor,
+        &references_snapshot,
+    )?;
+
+    let local_main_branch = repo.get_main_branch()?;
+    let local_main_branch_oid = local_main_branch.get_oid()?;
+    let local_main_branch_reference_name = local_main_branch.get_reference_name()?;
+    match CategorizedReferenceName::new(&local_main_branch_reference_name) {
+        CategorizedReferenceName::LocalBranch { .. } => {}
+        // If the main branch is a remote-tracking branch, then there's no
+        // local branch to fast-forward: the remote-tracking branch is updated
+        // by fetching, and the stacks are then moved directly onto it.
+        CategorizedReferenceName::RemoteBranch { .. }
+        | CategorizedReferenceName::OtherRef { .. } => return Ok(Ok(())),
+    }
+    let local_main_branch_description = effects.get_glyphs().render(
+        StyledStringBuilder::new()
+            .append_styled(
+                CategorizedReferenceName::new(&local_main_branch_reference_name)
+                    .friendly_describe(),
+                BaseColor::Green.dark(),
+            )
+            .build(),
+    )?;
+
+    let upstream_main_branch_oid = match (upstream, upstream_remote) {
+        (Some(upstream), _) => try_exit_code!(resolve_single_commit(
+            effects,
+            git_run_info,
+            repo,
+            &mut dag,
+            "--upstream",
+            upstream,
+            resolve_revset_options,
+        )?),
+
+        (None, Some(upstream_remote)) => {
+            let main_branch_name = get_main_branch_name(repo)?;
+            let upstream_main_branch_reference_name =
+                ReferenceName::from(format!("refs/remotes/{upstream_remote}/{main_branch_name}"));
+            let upstream_main_branch_oid =
+                match repo.find_reference(&upstream_main_branch_reference_name)? {
+                    Some(reference) => reference.peel_to_commit()?.map(|commit| commit.get_oid()),
+                    None => None,
+                };
+            match upstream_main_branch_oid {
+                Some(upstream_main_branch_oid) => upstream_main_branch_oid,
+                None => {
+                    writeln!(
+                        effects.get_output_stream(),
+                        "{upstream_remote}/{main_branch_name} does not exist, so not pulling."
+                    )?;
+                    return Ok(Ok(()));
+                }
+            }
+        }
+
+        (None, None) => {
+            let upstream_main_branch = match local_main_branch.get_upstream_branch()? {
+                Some(upstream_main_branch) => upstream_main_branch,
+                None => {
+                    writeln!(
+                        effects.get_output_stream(),
+                        "{local_main_branch_description} does not track an upstream branch, so not pulling."
+                    )?;
+                    return Ok(Ok(()));
+                }
+            };
+            match upstream_main_branch.get_oid()? {
+                Some(upstream_main_branch_oid) => upstream_main_branch_oid,
+                None => return Ok(Ok(())),
+            }
+        }
+    };
+    dag.sync_from_oids(
+        effects,
+        repo,
+        CommitSet::from(upstream_main_branch_oid),
+        CommitSet::empty(),
+    )?;
+    let local_main_branch_commits = dag.query_only(
+        local_main_branch_oid.into_iter().collect(),
+        CommitSet::from(upstream_main_branch_oid),
+    )?;
+    if dag.set_is_empty(&local_main_branch_commits)? {
+        if local_main_branch_oid == Some(upstream_main_branch_oid) {
+            let local_main_branch_commit = repo.find_commit_or_fail(upstream_main_branch_oid)?;
+            writeln!(
+                effects.get_output_stream(),
+                "Not updating {} at {}",
+                local_main_branch_description,
+                effects
+                    .get_glyphs()
+                    .render(local_main_branch_commit.friendly_describe(effects.get_glyphs())?)?,
+            )?;
+        } else {
+            let remote_main_branch_commit = repo.find_commit_or_fail(upstream_main_branch_oid)?;
+            writeln!(
+                effects.get_output_stream(),
+                "Fast-forwarding {} to {}",
+                local_main_branch_description,
+                effects
+                    .get_glyphs()
+                    .render(remote_main_branch_commit.friendly_describe(effects.get_glyphs())?)?,
+            )?;
+        }
+
+        if local_main_branch_oid == Some(upstream_main_branch_oid) {
+            // Nothing to do.
+        } else if head_info.reference_name.as_ref() == Some(&local_main_branch_reference_name) {
+            // If the main branch is checked out, make sure to update the index
+            // and working copy as well as the reference itself. Otherwise,
+            // staged changes will appear in `git status`. A fast-forward merge
+            // carries over uncommitted changes, unless they would be
+            // overwritten by the update, in which case Git refuses to proceed
+            // (unless they were stashed).
+            try_exit_code!(autostash.stash(effects, git_run_info, repo)?);
+            try_exit_code!(git_run_info.run(
+                effects,
+                Some(execute_options.event_tx_id),
+                &["merge", "--ff-only", &upstream_main_branch_oid.to_string()],
+            )?);
+        } else {
+            try_exit_code!(fast_forward_main_branch(
+                effects,
+                repo,
+                &local_main_branch_reference_name,
+                &local_main_branch_description,
+                upstream_main_branch_oid,
+            )?);
+        }
+
+        return Ok(Ok(()));
+    } else {
+        // The local main branch's own commits are rewritten, which may be
+        // surprising, so list them first.
+        let local_commits = sorted_commit_set(repo, &dag, &local_main_branch_commits)?;
+        writeln!(
+            effects.get_warning_stream(),
+            "Warning: {} has diverged from its upstream branch; rebasing {}:",
+            local_main_branch_description,
+            Pluralize {
+                determiner: None,
+                amount: local_commits.len(),
+                unit: ("local commit", "local commits"),
+            },
+        )?;
+        for commit in &local_commits {
+            writeln!(
+                effects.get_warning_stream(),
+                "  {}",
+                effects
+                    .get_glyphs()
+                    .render(commit.friendly_describe(effects.get_glyphs())?)?
+            )?;
+        }
+        if !yes && console::user_attended() {
+            write!(effects.get_output_stream(), "Proceed? [y/N] ")?;
+            let mut user_input = String::new();
+            let confirmed = match stdin().lock().read_line(&mut user_input) {
+                Ok(_size) => {
+                    let user_input = user_input.trim();
+                    user_input == "y" || user_input == "Y"
+                }
+                Err(_) => false,
+            };
+            if !confirmed {
+                writeln!(
+                    effects.get_output_stream(),
+                    "Not syncing {local_main_branch_description}"
+                )?;
+                return Ok(Ok(()));
+            }
+        }
+        writeln!(
+            effects.get_output_stream(),
+            "Syncing {local_main_branch_description}"
+        )?;
+    }
+
+    let build_options = BuildRebasePlanOptions {
+        // Since we're syncing the main branch, by definition, any commits on it would be public, so
+        // we need to set this to `true` to get the rebase to succeed.
+        force_rewrite_public_commits: true,
+        ..build_options.clone()
+    };
+    let permissions = match RebasePlanPermissions::verify_rewrite_set(
+        &dag,
+        build_options,
+        &local_main_branch_commits,
+    )? {
+        Ok(permissions) => permissions,
+        Err(err) => {
+            err.describe(effects, repo, &dag)?;
+            return Ok(Err(ExitCode(1)));
+        }
+    };
+    let mut builder = RebasePlanBuilder::new(&dag, permissions);
+    let local_main_branch_roots = dag.query_roots(local_main_branch_commits)?;
+    let root_commit_oid = match dag
+        .commit_set_to_vec(&local_main_branch_roots)?
+        .into_iter()
+        .exactly_one()
+    {
+        Ok(root_oid) => root_oid,
+        Err(_) => return Ok(Ok(())),
+    };
+    builder.move_subtree(root_commit_oid, vec![upstream_main_branch_oid])?;
+    let rebase_plan = match builder.build(effects, thread_pool, repo_pool)? {
+        Ok(rebase_plan) => rebase_plan,
+        Err(err) => {
+            err.describe(effects, repo, &dag)?;
+            return Ok(Err(ExitCode(1)));
+        }
+    };
+    let rebase_plan = match rebase_plan {
+        Some(rebase_plan) => rebase_plan,
+        None => return Ok(Ok(())),
+    };
+
+    let execute_options = ExecuteRebasePlanOptions {
+        // The main branch's own commits aren't part of any stack, so they
+        // don't get the `--add-trailer` trailers, and the `--reset-author` and
+        // `--preserve-committer` options meant for the stacks don't apply
+        // to them either.
+        commit_trailers: Vec::new(),
+        reset_author: false,
+        preserve_committer: false,
+        ..execute_options.clone()
+    };
+    try_exit_code!(execute_plans(
+        effects,
+        git_run_info,
+        repo,
+        event_log_db,
+        &execute_options,
+        &SyncRewriteOptions::default(),
+        &SyncOutputOptions::default(),
+        None,
+        autostash,
+        None,
+        None,
+        None,
+        progress,
+        vec![(root_commit_oid, Some(rebase_plan))],
+    )?);
+    Ok(Ok(()))
+}
+
+#[instrument(skip_all)]
+fn execute_sync_plans(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    repo: &Repo,
+    event_log_db: &EventLogDb,
+    build_options: BuildRebasePlanOptions,
+    execute_options: &ExecuteRebasePlanOptions,
+    thread_pool: &ThreadPool,
+    repo_pool: &ResourcePool<RepoResource>,
+    references_snapshot: Option<RepoReferencesSnapshot>,
+    dag: Option<&mut Dag>,
+    args: &SyncArgs,
+    touched_paths_filter: Option<&TouchedPathsFilter>,
+    conflicted_root_commit_oids: Option<&HashSet<NonZeroOid>>,
+    on_stack_result: Option<&mut dyn FnMut(NonZeroOid, SyncStatus)>,
+
+    autostash: &Autostash,
+    progress: &mut dyn SyncProgress,
+) -> EyreExitOr<SyncOutcome> {
+    let SyncArgs {
+        select_options:
+            SyncSelectOptions {
+                include_public,
+                exclude,
+                touched_paths: _,
+                only_conflicting: _,
+                limit,
+                max_depth,
+                at_event,
+                since_event,
+                all: _,
+                allow_unrelated_histories,
+                include_public_roots,
+            },
+        rewrite_options,
+        output_options,
+        publish,
+        base_auto,
+        revsets,
+        resolve_revset_options,
+        ..
+    } = args;
+    let describe_plan = output_options.describe_plan.is_some();
+    let event_replayer = EventReplayer::from_event_log_db(effects, repo, event_log_db)?;
+    let (event_cursor, references_snapshot) = match *at_event {
+        None => (
+            event_replayer.make_default_cursor(),
+            match references_snapshot {
+                Some(references_snapshot) => references_snapshot,
+                None => repo.get_references_snapshot()?,
+            },
+        ),
+        Some(event_id) => match event_replayer.make_cursor_checked(event_id) {
+            Some(event_cursor) => (
+                event_cursor,
+                event_replayer.get_references_snapshot(repo, event_cursor)?,
+            ),
+            None => {
+                writeln!(
+                    effects.get_error_stream(),
+                    "There is no event with ID {event_id} in the event log."
+                )?;
+                return Ok(Err(ExitCode(1)));
+            }
+        },
+    };
+    let changed_commits = match *since_event {
+        None => None,
+        Some(event_id) => match event_replayer.make_cursor_checked(event_id) {
+            Some(since_cursor) => Some(get_commits_changed_since(&event_replayer, since_cursor)),
+            None => {
+                writeln!(
+                    effects.get_error_stream(),
+                    "There is no event with ID {event_id} in the event log."
+                )?;
+                return Ok(Err(ExitCode(1)));
+            }
+        },
+    };
+    let mut opened_dag = None;
+    let dag: &mut Dag = match dag {
+        Some(dag) if at_event.is_none() => {
+            let RepoReferencesSnapshot {
+                head_oid,
+                main_branch_oid,
+                branch_oid_to_names,
+            } = &references_snapshot;
+            dag.sync_from_oids(
+                effects,
+                repo,
+                CommitSet::from(*main_branch_oid),
+                head_oid
+                    .iter()
+                    .chain(branch_oid_to_names.keys())
+                    .copied()
+                    .collect(),
+            )?;
+            dag
+        }
+        _ => opened_dag.insert(Dag::open_and_sync(
+            effects,
+            repo,
+            &event_replayer,
+            event_cursor,
+            &references_snapshot,
+        )?),
+    };
+    let commit_sets = match resolve_commits(effects, repo, dag, revsets, resolve_revset_options) {
+        Ok(commit_sets) => commit_sets,
+        Err(err) => {
+            err.describe(effects)?;
+            return Ok(Err(ExitCode(1)));
+        }
+    };
+    let permitted_public_commits =
+        match resolve_commits(effects, repo, dag, include_public, resolve_revset_options) {
+            Ok(commit_sets) => union_all(&commit_sets),
+            Err(err) => {
+                err.describe(effects)?;
+                return Ok(Err(ExitCode(1)));
+            }
+        };
+    let excluded_commits =
+        match resolve_commits(effects, repo, dag, exclude, resolve_revset_options) {
+            Ok(commit_sets) => union_all(&commit_sets),
+            Err(err) => {
+                err.describe(effects)?;
+                return Ok(Err(ExitCode(1)));
+            }
+        };
+    let dag: &Dag = dag;
+    let main_branch_oid = references_snapshot.main_branch_oid;
+    let trunks = if *base_auto {
+        get_trunks(effects, repo, &references_snapshot)?
+    } else {
+        Vec::new()
+    };
+    // Commits on the trunk branches are bases to move stacks onto, not parts of
+    // stacks themselves.
+    let trunk_commits = dag.query_ancestors(trunks.iter().map(|(_, oid)| *oid).collect())?;
+    let describe_commits = union_all(&commit_sets);
+    let root_commit_oids =
+        get_stack_roots(&dag, commit_sets, &trunk_commits, *include_public_roots)?;
+
+    if describe_plan {
+        let stack_commits = dag.query_descendants(root_commit_oids.clone())?;
+        for commit in sorted_commit_set(repo, &dag, &describe_commits.difference(&stack_commits))? {
+            writeln!(
+                effects.get_output_stream(),
+                "Not moving commit (not part of a draft stack): {}",
+                effects
+                    .get_glyphs()
+                    .render(commit.friendly_describe(effects.get_glyphs())?)?
+            )?;
+        }
+    }
+    let root_commits = {
+        let mut root_commits = Vec::new();
+        for root_commit in sorted_commit_set(repo, &dag, &root_commit_oids)? {
+            let stack_commits = dag.query_descendants(CommitSet::from(root_commit.get_oid()))?;
+            if dag.set_is_empty(&stack_commits.intersection(&excluded_commits))? {
+                root_commits.push(root_commit);
+            } else {
+                writeln!(
+                    effects.get_warning_stream(),
+                    "Skipping stack (excluded): {}",
+                    effects
+                        .get_glyphs()
+                        .render(root_commit.friendly_describe(effects.get_glyphs())?)?
+                )?;
+            }
+        }
+        root_commits
+    };
+    let root_commits = match conflicted_root_commit_oids {
+        None => root_commits,
+        Some(conflicted_root_commit_oids) => root_commits
+            .into_iter()
+            .filter(|root_commit| conflicted_root_commit_oids.contains(&root_commit.get_oid()))
+            .collect(),
+    };
+    let root_commits = match (changed_commits, since_event) {
+        (Some(changed_commits), Some(event_id)) => {
+            let mut changed_root_commits = Vec::new();
+            let mut num_unchanged_stacks = 0;
+            for root_commit in root_commits {
+                let stack_commits =
+                    dag.query_descendants(CommitSet::from(root_commit.get_oid()))?;
+                if dag.set_is_empty(&stack_commits.intersection(&changed_commits))? {
+                    num_unchanged_stacks += 1;
+                } else {
+                    changed_root_commits.push(root_commit);
+                }
+            }
+            if num_unchanged_stacks > 0 {
+                writeln!(
+                    effects.get_warning_stream(),
+                    "Skipping {} with no changes since event {event_id}.",
+                    Pluralize {
+                        determiner: None,
+                        amount: num_unchanged_stacks,
+                        unit: ("stack", "stacks"),
+                    },
+                )?;
+            }
+            changed_root_commits
+        }
+        _ => root_commits,
+    };
+    let num_stacks = root_commits.len();
+    let (root_commits, permitted_public_commits) = if rewrite_options.confirm
+        && console::user_attended()
+        && matches!(
+            RebasePlanPermissions::verify_rewrite_set_permitting_public_commits(
+                dag,
+                build_options.clone(),
+                &root_commits
+                    .iter()
+                    .map(|root_commit| root_commit.get_oid())
+                    .collect::<CommitSet>(),
+                &permitted_public_commits,
+            )?,
+            Err(BuildRebasePlanError::MovePublicCommits { .. })
+        ) {
+        confirm_public_commit_rewrites(effects, dag, root_commits, permitted_public_commits)?
+    } else {
+        (root_commits, permitted_public_commits)
+    };
+    let are_all_stacks_confirmed = root_commits.len() == num_stacks;
+    let root_commit_oids: CommitSet = root_commits
+        .iter()
+        .map(|root_commit| root_commit.get_oid())
+        .collect();
+    let permissions = match RebasePlanPermissions::verify_rewrite_set_permitting_public_commits(
+        dag,
+        build_options,
+        &root_commit_oids,
+        &permitted_public_commits,
+    )? {
+        Ok(permissions) => permissions,
+        Err(err) => {
+            err.describe(effects, repo, dag)?;
+            return Ok(Err(ExitCode(1)));
+        }
+    };
+    let builder = RebasePlanBuilder::new(dag, permissions);
+
+    let root_commit_oids = match touched_paths_filter {
+        None => root_commits
+            .into_iter()
+            .map(|commit| commit.get_oid())
+            .collect_vec(),
+        Some(touched_paths_filter) => {
+            let mut root_commit_oids = Vec::new();
+            for root_commit in root_commits {
+                if touched_paths_filter.matches_stack(repo, &dag, root_commit.get_oid())? {
+                    root_commit_oids.push(root_commit.get_oid());
+                } else {
+                    writeln!(
+                        effects.get_warning_stream(),
+                        "Skipping stack (no matching paths): {}",
+                        effects
+                            .get_glyphs()
+                            .render(root_commit.friendly_describe(effects.get_glyphs())?)?
+                    )?;
+                }
+            }
+            root_commit_oids
+        }
+    };
+    if let Some(dump_dag_path) = &output_options.dump_dag {
+        let dag_dump = make_dag_dump(repo, dag, main_branch_oid, &root_commit_oids)?;
+        let json = serde_json::to_string_pretty(&dag_dump)?;
+        write_output_file(dump_dag_path, &format!("{json}\n"))?;
+    }
+    let root_commit_groups = if rewrite_options.rebase_merges {
+        group_stack_roots(&dag, root_commit_oids)?
+    } else {
+        root_commit_oids
+            .into_iter()
+            .map(|root_commit_oid| vec![root_commit_oid])
+            .collect()
+    };
+    // Determine which stacks need to be moved, and onto which base, so that
+    // `--limit` only counts those stacks. Otherwise, running again would select
+    // the same (now up-to-date) stacks and never make progress.
+    let main_branch_name =
+        CategorizedReferenceName::new(&repo.get_main_branch()?.get_reference_name()?)
+            .render_suffix();
+    let mut stack_moves = Vec::new();
+    let mut num_stacks_to_move = 0;
+    let mut num_remaining_stacks = 0;
+    let mut num_unrelated_stacks = 0;
+    let mut num_onto_descendant_stacks = 0;
+    for root_commit_oids in root_commit_groups {
+        let (base_name, base_oid) = match choose_stack_base(&dag, &root_commit_oids, &trunks)? {
+            Some((base_name, base_oid)) => (Some(base_name), base_oid),
+            None => (None, main_branch_oid),
+        };
+        let mut root_commits_to_move = Vec::new();
+        for root_commit_oid in root_commit_oids.iter().copied() {
+            let root_commit = repo.find_commit_or_fail(root_commit_oid)?;
+            if stack_needs_sync(&root_commit, base_oid) {
+                root_commits_to_move.push(root_commit_oid);
+            }
+        }
+        if !*allow_unrelated_histories {
+            // `stack_needs_sync` can't tell a root with no parents (such as the
+            // first commit of an orphan branch) apart from a merge commit, so
+            // check for it explicitly. Moving such a root would re-add all of
+            // its files on top of the base.
+            let mut parentless_root_commit = None;
+            for root_commit_oid in root_commits_to_move.iter().copied() {
+                let root_commit = repo.find_commit_or_fail(root_commit_oid)?;
+                if root_commit.get_parent_count() == 0 {
+                    parentless_root_commit = Some(root_commit);
+                    break;
+                }
+            }
+            if let Some(root_commit) = parentless_root_commit {
+                num_unrelated_stacks += 1;
+                writeln!(
+                    effects.get_warning_stream(),
+                    "Skipping stack (root commit has no parents): {}",
+                    effects
+                        .get_glyphs()
+                        .render(root_commit.friendly_describe(effects.get_glyphs())?)?
+                )?;
+                continue;
+            }
+
+            let mut is_unrelated = false;
+            for root_commit_oid in root_commits_to_move.iter().copied() {
+                let commits: CommitSet = [root_commit_oid, base_oid].into_iter().collect();
+                if dag.query_gca_one(commits)?.is_none() {
+                    is_unrelated = true;
+                }
+            }
+            if is_unrelated {
+                num_unrelated_stacks += 1;
+                let root_commit = repo.find_commit_or_fail(root_commit_oids[0])?;
+                writeln!(
+                    effects.get_warning_stream(),
+                    "Skipping stack (no common history with {}): {}",
+                    base_name.as_deref().unwrap_or(&main_branch_name),
+                    effects
+                        .get_glyphs()
+                        .render(root_commit.friendly_describe(effects.get_glyphs())?)?
+                )?;
+                continue;
+            }
+        }
+        // A base such as a trunk branch may have been built on top of the
+        // stack, in which case moving the stack onto it would introduce a
+        // cycle.
+        let mut is_onto_own_descendant = false;
+        for root_commit_oid in root_commits_to_move.iter().copied() {
+            if dag.query_is_ancestor(root_commit_oid, base_oid)? {
+                is_onto_own_descendant = true;
+            }
+        }
+        if is_onto_own_descendant {
+            num_onto_descendant_stacks += 1;
+            let root_commit = repo.find_commit_or_fail(root_commit_oids[0])?;
+            writeln!(
+                effects.get_warning_stream(),
+                "Skipping stack (cannot rebase a stack onto its own descendant): {}",
+                effects
+                    .get_glyphs()
+                    .render(root_commit.friendly_describe(effects.get_glyphs())?)?
+            )?;
+            continue;
+        }
+        if !root_commits_to_move.is_empty() {
+            if limit.map_or(false, |limit| num_stacks_to_move >= limit) {
+                num_remaining_stacks += 1;
+                if describe_plan {
+                    let root_commit = repo.find_commit_or_fail(root_commit_oids[0])?;
+                    writeln!(
+                        effects.get_warning_stream(),
+                        "Not moving stack (over --limit): {}",
+                        effects
+                            .get_glyphs()
+                            .render(root_commit.friendly_describe(effects.get_glyphs())?)?
+                    )?;
+                }
+                continue;
+            }
+            num_stacks_to_move += 1;
+        }
+        stack_moves.push((
+            root_commit_oids[0],
+            base_name,
+            base_oid,
+            root_commits_to_move,
+        ));
+    }
+
+    let stack_bases: HashMap<NonZeroOid, String> = {
+        stack_moves
+            .iter()
+            .map(
+                |(root_commit_oid, base_name, _base_oid, _root_commits_to_move)| {
+                    (
+                        *root_commit_oid,
+                        base_name
+                            .clone()
+                            .unwrap_or_else(|| main_branch_name.clone()),
+                    )
+                },
+            )
+            .collect()
+    };
+    // The number of commits moved and the number of commits in each stack
+    // truncated by `--max-depth`.
+    let mut truncated_stacks: HashMap<NonZeroOid, (usize, usize)> = HashMap::new();
+    let keep_going_on_build_error = should_keep_going(rewrite_options, false);
+    let (root_commit_and_plans, build_errors) = thread_pool.install(|| -> eyre::Result<_> {
+        // The roots are processed serially, so a single repository handle can
+        // be shared by all of them rather than checking one out per root.
+        let repo = repo_pool.try_create()?;
+        let mut root_commit_and_plans: Vec<(NonZeroOid, Option<RebasePlan>)> = Vec::new();
+        let mut build_errors: Vec<(NonZeroOid, BuildRebasePlanError)> = Vec::new();
+        // Don't parallelize for now, since the status updates don't render well.
+        for (root_commit_oid, base_name, base_oid, root_commits_to_move) in stack_moves {
+            if root_commits_to_move.is_empty() {
+                root_commit_and_plans.push((root_commit_oid, None));
+                continue;
+            }
+
+            // Keep access to the same underlying caches by cloning the same instance of the builder.
+            let mut builder = builder.clone();
+
+            if let Some(base_name) = base_name {
+                let root_commit = repo.find_commit_or_fail(root_commit_oid)?;
+                writeln!(
+                    effects.get_output_stream(),
+                    "Moving stack onto {}: {}",
+                    base_name,
+                    effects
+                        .get_glyphs()
+                        .render(root_commit.friendly_describe(effects.get_glyphs())?)?
+                )?;
+            }
+            for moved_root_commit_oid in root_commits_to_move {
+                builder.move_subtree(moved_root_commit_oid, vec![base_oid])?;
+                if let Some(max_depth) = max_depth {
+                    let (left_subtree_oids, num_moved_commits, num_commits) =
+                        truncate_stack(&dag, moved_root_commit_oid, *max_depth)?;
+                    if !left_subtree_oids.is_empty() {
+                        for left_subtree_oid in left_subtree_oids {
+                            builder.leave_subtree(left_subtree_oid)?;
+                        }
+                        let (total_moved_commits, total_commits) =
+                            truncated_stacks.entry(root_commit_oid).or_default();
+                        *total_moved_commits += num_moved_commits;
+                        *total_commits += num_commits;
+                    }
+                }
+            }
+            match builder.build(effects, thread_pool, repo_pool)? {
+                Ok(rebase_plan) => {
+                    if let Some(rebase_plan) = &rebase_plan {
+                        warn_reordered_commits(effects, &repo, &dag, rebase_plan)?;
+                    }
+                    root_commit_and_plans.push((root_commit_oid, rebase_plan));
+                }
+                Err(err) => {
+                    build_errors.push((root_commit_oid, err));
+                    if !keep_going_on_build_error && !describe_plan {
+                        break;
+                    }
+                }
+            }
+        }
+        Ok((root_commit_and_plans, build_errors))
+    })?;
+
+    if !keep_going_on_build_error && !describe_plan {
+        if let Some((_root_commit_oid, err)) = build_errors.first() {
+            err.describe(effects, repo, &dag)?;
+            return Ok(Err(ExitCode(1)));
+        }
+    }
+    for (root_commit_oid, err) in &build_errors {
+        let root_commit = repo.find_commit_or_fail(*root_commit_oid)?;
+        writeln!(
+            effects.get_warning_stream(),
+            "Skipping stack (can't build rebase plan): {}",
+            effects
+                .get_glyphs()
+                .render(root_commit.friendly_describe(effects.get_glyphs())?)?
+        )?;
+        err.describe(effects, repo, &dag)?;
+    }
+    if describe_plan {
+        for (root_commit_oid, rebase_plan) in &root_commit_and_plans {
+            describe_stack_plan(
+                effects,
+                git_run_info,
+                repo,
+                execute_options,
+                *root_commit_oid,
+                &stack_bases[root_commit_oid],
+                rebase_plan.as_ref(),
+            )?;
+        }
+        return Ok(Ok(SyncOutcome::default()));
+    }
+    if output_options.print_plan_only {
+        output_plans(effects, repo, output_options, &root_commit_and_plans)?;
+        return Ok(Ok(SyncOutcome::default()));
+    }
+    // The plans were built against a past state of the repository, so executing
+    // them could move branches back to where they were at that time.
+    if let Some(event_id) = at_event {
+        for (root_commit_oid, rebase_plan) in &root_commit_and_plans {
+            let root_commit = repo.find_commit_or_fail(*root_commit_oid)?;
+            writeln!(
+                effects.get_output_stream(),
+                "{} {}",
+                match rebase_plan {
+                    Some(_) => "Would sync",
+                    None => "Would not move up-to-date stack at",
+                },
+                effects
+                    .get_glyphs()
+                    .render(root_commit.friendly_describe(effects.get_glyphs())?)?
+            )?;
+        }
+        writeln!(
+            effects.get_warning_stream(),
+            "Not syncing, since the plans were built as of event {event_id}. Pass --print-plan-only to print them."
+        )?;
+        return Ok(Ok(SyncOutcome::default()));
+    }
+    let stack_branches = get_stack_branches(&references_snapshot, &root_commit_and_plans);
+    if rewrite_options.record_backup_refs {
+        record_backup_refs(repo, SystemTime::now(), &stack_branches)?;
+    }
+    let mut sync_state = SyncState::new(
+        execute_options.event_tx_id,
+        &references_snapshot,
+        &root_commit_and_plans,
+    );
+    write_sync_state(repo, Some(&sync_state))?;
+    let execute_plans_result = {
+        let interrupt_guard = InterruptGuard::new()?;
+        try_exit_code!(execute_plans(
+            effects,
+            git_run_info,
+            repo,
+            event_log_db,
+            execute_options,
+            rewrite_options,
+            output_options,
+            Some(&interrupt_guard),
+            autostash,
+            on_stack_result,
+            Some(&mut sync_state),
+            Some(&stack_branches),
+            progress,
+            root_commit_and_plans,
+        )?)
+    };
+    try_exit_code!(finish_executed_plans(
+        effects,
+        git_run_info,
+        repo,
+        event_log_db,
+        thread_pool,
+        repo_pool,
+        execute_options.event_tx_id,
+        rewrite_options.keep_branches_on_conflict,
+        &stack_branches,
+        &execute_plans_result,
+    )?);
+    let ExecutePlansResult {
+        is_fully_synced,
+        synced_root_commit_oids,
+        conflicted_root_commit_oids,
+        interrupted_root_commit_oids: _,
+        num_commits_rewritten,
+        hook_failed_root_commit_oids,
+    } = execute_plans_result;
+    if *publish {
+        let branch_names = synced_root_commit_oids
+            .iter()
+            .filter_map(|root_commit_oid| stack_branches.get(root_commit_oid))
+            .flatten()
+            .map(|(branch_name, _commit_oid)| {
+                CategorizedReferenceName::new(branch_name).render_suffix()
+            })
+            .sorted()
+            .collect_vec();
+        try_exit_code!(publish_branches(
+            effects,
+            git_run_info,
+            repo,
+            execute_options.event_tx_id,
+            execute_options.no_verify,
+            &branch_names,
+        )?);
+    }
+    for root_commit_oid in &synced_root_commit_oids {
+        if let Some((num_moved_commits, num_commits)) = truncated_stacks.get(root_commit_oid) {
+            let root_commit = repo.find_commit_or_fail(*root_commit_oid)?;
+            writeln!(
+                effects.get_warning_stream(),
+                "Moved bottom {num_moved_commits} of {num_commits} commits for this stack: {}",
+                effects
+                    .get_glyphs()
+                    .render(root_commit.friendly_describe(effects.get_glyphs())?)?
+            )?;
+        }
+    }
+    if output_options.report_merged {
+        // The stacks which were just moved would have had any commits already
+        // applied to the main branch skipped, so only check the others. Their
+        // commits are unchanged, so the DAG from before the sync still
+        // describes them.
+        let unmoved_root_commit_oids = get_stack_roots(dag, Vec::new(), &trunk_commits, false)?
+            .difference(&synced_root_commit_oids.iter().copied().collect());
+        report_merged_stacks(
+            effects,
+            repo,
+            dag,
+            main_branch_oid,
+            &unmoved_root_commit_oids,
+        )?;
+    }
+    if num_unrelated_stacks > 0 {
+        writeln!(
+            effects.get_warning_stream(),
+            "Pass --allow-unrelated-histories to move stacks with no common history anyway."
+        )?;
+    }
+    if num_remaining_stacks > 0 {
+        writeln!(
+            effects.get_warning_stream(),
+            "Run again to sync the remaining {}.",
+            Pluralize {
+                determiner: None,
+                amount: num_remaining_stacks,
+                unit: ("stack", "stacks"),
+            },
+        )?;
+    }
+    Ok(Ok(SyncOutcome {
+        is_fully_synced: is_fully_synced
+            && are_all_stacks_confirmed
+            && num_remaining_stacks == 0
+            && num_unrelated_stacks == 0
+            && num_onto_descendant_stacks == 0
+            && build_errors.is_empty(),
+        synced_root_commit_oids,
+        conflicted_root_commit_oids,
+        num_commits_rewritten,
+        hook_failed_root_commit_oids,
+    }))
+}
+
+/// Restore the branches of the stacks which weren't synced and run the
+/// `branchless.sync.onRewrite` commands, once the plans have been executed.
+/// Exits with code 130 if the sync was interrupted.
+fn finish_executed_plans(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    repo: &Repo,
+    event_log_db: &EventLogDb,
+    thread_pool: &ThreadPool,
+    repo_pool: &ResourcePool<RepoResource>,
+    event_tx_id: EventTransactionId,
+    keep_branches_on_conflict: bool,
+    stack_branches: &HashMap<NonZeroOid, Vec<(ReferenceName, NonZeroOid)>>,
+    execute_plans_result: &ExecutePlansResult,
+) -> EyreExitOr<()> {
+    let ExecutePlansResult {
+        is_fully_synced: _,
+        synced_root_commit_oids,
+        conflicted_root_commit_oids,
+        interrupted_root_commit_oids,
+        num_commits_rewritten: _,
+        hook_failed_root_commit_oids: _,
+    } = execute_plans_result;
+    if keep_branches_on_conflict {
+        for root_commit_oid in conflicted_root_commit_oids {
+            if let Some(branches) = stack_branches.get(root_commit_oid) {
+                restore_branches(effects, repo, branches)?;
+            }
+        }
+    }
+    let is_interrupted = !interrupted_root_commit_oids.is_empty();
+    if is_interrupted {
+        for root_commit_oid in interrupted_root_commit_oids {
+            if let Some(branches) = stack_branches.get(root_commit_oid) {
+                restore_branches(effects, repo, branches)?;
+            }
+        }
+        writeln!(
+            effects.get_output_stream(),
+            "Sync interrupted; {} synced, {} remaining.",
+            Pluralize {
+                determiner: None,
+                amount: synced_root_commit_oids.len(),
+                unit: ("stack", "stacks"),
+            },
+            interrupted_root_commit_oids.len(),
+        )?;
+        writeln!(
+            effects.get_output_stream(),
+            "Run git sync again to resume syncing the remaining stacks."
+        )?;
+    }
+    run_on_rewrite_commands(
+        effects,
+        git_run_info,
+        repo,
+        event_log_db,
+        thread_pool,
+        repo_pool,
+        event_tx_id,
+    )?;
+    if is_interrupted {
+        return Ok(Err(ExitCode(130)));
+    }
+    Ok(Ok(()))
+}
+
+/// Resume the sync described by `sync_state`, which was interrupted before
+/// all of its stacks were synced.
+fn resume_sync_plans(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    repo: &Repo,
+    event_log_db: &EventLogDb,
+    execute_options: &ExecuteRebasePlanOptions,
+    thread_pool: &ThreadPool,
+    repo_pool: &ResourcePool<RepoResource>,
+    rewrite_options: &SyncRewriteOptions,
+    output_options: &SyncOutputOptions,
+    on_stack_result: Option<&mut dyn FnMut(NonZeroOid, SyncStatus)>,
+    autostash: &Autostash,
+    progress: &mut dyn SyncProgress,
+    mut sync_state: SyncState,
+) -> EyreExitOr<SyncOutcome> {
+    // A stack may have been rewritten by the sync before it was interrupted,
+    // such as by an on-disk rebase which was finished by hand, in which case
+    // it shouldn't be moved again.
+    let sync_event_tx_ids = event_log_db.get_sync_transaction_ids(execute_options.event_tx_id)?;
+    let rewritten_commit_oids: HashSet<NonZeroOid> = event_log_db
+        .get_events()?
+        .into_iter()
+        .filter_map(|event| match event {
+            Event::RewriteEvent {
+                timestamp: _,
+                event_tx_id: rewrite_event_tx_id,
+                old_commit_oid: MaybeZeroOid::NonZero(old_commit_oid),
+                new_commit_oid: _,
+            } if sync_event_tx_ids.contains(&rewrite_event_tx_id) => Some(old_commit_oid),
+            _ => None,
+        })
+        .collect();
+    let mut root_commit_and_plans = Vec::new();
+    for (SerializedNonZeroOid(root_commit_oid), rebase_plan) in &sync_state.remaining_stacks {
+        let root_commit = match repo.find_commit(*root_commit_oid)? {
+            Some(root_commit) => root_commit,
+            None => {
+                writeln!(
+                    effects.get_error_stream(),
+                    "Can't resume the interrupted sync, since commit {root_commit_oid} no longer exists."
+                )?;
+                write_sync_state(repo, None)?;
+                return Ok(Err(ExitCode(1)));
+            }
+        };
+        if rewritten_commit_oids.contains(root_commit_oid) {
+            writeln!(
+                effects.get_warning_stream(),
+                "Skipping stack (already synced): {}",
+                effects
+                    .get_glyphs()
+                    .render(root_commit.friendly_describe(effects.get_glyphs())?)?
+            )?;
+        } else {
+            root_commit_and_plans.push((*root_commit_oid, rebase_plan.clone()));
+        }
+    }
+    sync_state
+        .remaining_stacks
+        .retain(|(SerializedNonZeroOid(root_commit_oid), _)| {
+            !rewritten_commit_oids.contains(root_commit_oid)
+        });
+    write_sync_state(repo, Some(&sync_state))?;
+
+    let stack_branches = get_stack_branches(
+        &sync_state.get_references_snapshot(),
+        &root_commit_and_plans,
+    );
+    let execute_plans_result = {
+        let interrupt_guard = InterruptGuard::new()?;
+        try_exit_code!(execute_plans(
+            effects,
+            git_run_info,
+            repo,
+            event_log_db,
+            execute_options,
+            rewrite_options,
+            output_options,
+            Some(&interrupt_guard),
+            autostash,
+            on_stack_result,
+            Some(&mut sync_state),
+            Some(&stack_branches),
+            progress,
+            root_commit_and_plans,
+        )?)
+    };
+    try_exit_code!(finish_executed_plans(
+        effects,
+        git_run_info,
+        repo,
+        event_log_db,
+        thread_pool,
+        repo_pool,
+        execute_options.event_tx_id,
+        rewrite_options.keep_branches_on_conflict,
+        &stack_branches,
+        &execute_plans_result,
+    )?);
+    let ExecutePlansResult {
+        is_fully_synced,
+        synced_root_commit_oids,
+        conflicted_root_commit_oids,
+        interrupted_root_commit_oids: _,
+        num_commits_rewritten,
+        hook_failed_root_commit_oids,
+    } = execute_plans_result;
+    Ok(Ok(SyncOutcome {
+        is_fully_synced,
+        synced_root_commit_oids,
+        conflicted_root_commit_oids,
+        num_commits_rewritten,
+        hook_failed_root_commit_oids,
+    }))
+}
+
+/// Explain what syncing would do with the stack rooted at the provided commit,
+/// for `--describe-plan`.
+fn describe_stack_plan(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    repo: &Repo,
+    execute_options: &ExecuteRebasePlanOptions,
+    root_commit_oid: NonZeroOid,
+    base_name: &str,
+    rebase_plan: Option<&RebasePlan>,
+) -> eyre::Result<()> {
+    let glyphs = effects.get_glyphs();
+    let root_commit = repo.find_commit_or_fail(root_commit_oid)?;
+    let rebase_plan = match rebase_plan {
+        Some(rebase_plan) => rebase_plan,
+        None => {
+            writeln!(
+                effects.get_output_stream(),
+                "Not moving stack (its root is already on {}): {}",
+                base_name,
+                glyphs.render(root_commit.friendly_describe(glyphs)?)?
+            )?;
+            return Ok(());
+        }
+    };
+
+    writeln!(
+        effects.get_output_stream(),
+        "Would move stack onto {} ({}): {}",
+        base_name,
+        Pluralize {
+            determiner: None,
+            amount: get_rewritten_commit_oids(rebase_plan).count(),
+            unit: ("commit", "commits"),
+        },
+        glyphs.render(root_commit.friendly_describe(glyphs)?)?
+    )?;
+    match check_rebase_plan_conflicts(effects, git_run_info, repo, rebase_plan, execute_options)? {
+        None => {
+            writeln!(
+                effects.get_output_stream(),
+                "  No merge conflicts expected."
+            )?;
+        }
+        Some(FailedMergeInfo::Conflict {
+            commit_oid,
+            onto_oid: _,
+            conflicting_paths,
+        }) => {
+            let commit = repo.find_commit_or_fail(commit_oid)?;
+            writeln!(
+                effects.get_output_stream(),
+                "  Would conflict ({}) when moving: {}",
+                Pluralize {
+                    determiner: None,
+                    amount: conflicting_paths.len(),
+                    unit: ("file", "files"),
+                },
+                glyphs.render(commit.friendly_describe(glyphs)?)?
+            )?;
+            for path in conflicting_paths.iter().sorted() {
+                writeln!(effects.get_output_stream(), "    {}", path.display())?;
+            }
+        }
+        Some(FailedMergeInfo::CannotRebaseMergeInMemory { commit_oid }) => {
+            let commit = repo.find_commit_or_fail(commit_oid)?;
+            writeln!(
+                effects.get_output_stream(),
+                "  Can't check for merge conflicts, since merge commits can't be moved in-memory: {}",
+                glyphs.render(commit.friendly_describe(glyphs)?)?
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Warn if the rebase plan for a stack would apply some of its commits before
+/// their original ancestors. This doesn't prevent the plan from being executed.
+fn warn_reordered_commits(
+    effects: &Effects,
+    repo: &Repo,
+    dag: &Dag,
+    rebase_plan: &RebasePlan,
+) -> eyre::Result<()> {
+    let reordered_commit_oids = rebase_plan.find_reordered_commits(dag)?;
+    if reordered_commit_oids.is_empty() {
+        return Ok(());
+    }
+
+    writeln!(
+        effects.get_warning_stream(),
+        "Warning: sync will reorder commits in this stack:"
+    )?;
+    for commit_oid in reordered_commit_oids {
+        let commit = repo.find_commit_or_fail(commit_oid)?;
+        writeln!(
+            effects.get_warning_stream(),
+            "  {}",
+            effects
+                .get_glyphs()
+                .render(commit.friendly_describe(effects.get_glyphs())?)?
+        )?;
+    }
+    Ok(())
+}
+
+/// Run the `branchless.sync.onRewrite` command, if any, for each commit
+/// rewritten by the sync which started with the provided transaction.
+/// Failures are reported, but don't cause the sync to fail.
+#[instrument(skip_all)]
+fn run_on_rewrite_commands(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    repo: &Repo,
+    event_log_db: &EventLogDb,
+    thread_pool: &ThreadPool,
+    repo_pool: &RepoPool,
+    event_tx_id: EventTransactionId,
+) -> eyre::Result<()> {
+    let command_template = match get_sync_on_rewrite(repo)? {
+        Some(command_template) => command_template,
+        None => return Ok(()),
+    };
+
+    // Read the rewrites from the event log rather than from the results of
+    // the rebases, since on-disk rebases don't report them.
+    let sync_event_tx_ids = event_log_db.get_sync_transaction_ids(event_tx_id)?;
+    let rewritten_oids = event_log_db
+        .get_events()?
+        .into_iter()
+        .filter_map(|event| match event {
+            Event::RewriteEvent {
+                timestamp: _,
+                event_tx_id: rewrite_event_tx_id,
+                old_commit_oid: MaybeZeroOid::NonZero(old_commit_oid),
+                new_commit_oid,
+            } if sync_event_tx_ids.contains(&rewrite_event_tx_id) => {
+                Some((old_commit_oid, new_commit_oid))
+            }
+            _ => None,
+        })
+        // Sort so that failures are reported in a consistent order.
+        .sorted()
+        .collect_vec();
+
+    let failures = thread_pool.install(|| {
+        rewritten_oids
+            .into_par_iter()
+            .map(
+                |(old_commit_oid, new_commit_oid)| -> eyre::Result<Option<String>> {
+                    let repo = repo_pool.try_create()?;
+                    let command = command_template
+                        .replace("{old}", &old_commit_oid.to_string())
+                        .replace("{new}", &new_commit_oid.to_string());
+                    // Run the command as a Git shell alias, so that it's
+                    // executed with the same shell and environment that Git
+                    // would use, from the root of the working copy.
+                    let GitRunResult {
+                        exit_code,
+                        stdout: _,
+                        stderr,
+                    } = git_run_info.run_silent(
+                        &repo,
+                        None,
+                        &[
+                            "-c",
+                            &format!("alias.branchless-sync-on-rewrite=!{command}"),
+                            "branchless-sync-on-rewrite",
+                        ],
+                        GitRunOpts {
+                            treat_git_failure_as_error: false,
+                            stdin: None,
+                            working_directory: None,
+                        },
+                    )?;
+                    if exit_code.is_success() {
+                        Ok(None)
+                    } else {
+                        Ok(Some(format!(
+                            "Command for rewrite of {old_commit_oid} to {new_commit_oid} failed with exit code {}: {}",
+                            exit_code.0,
+                            String::from_utf8_lossy(&stderr).trim(),
+                        )))
+                    }
+                },
+            )
+            .filter_map(|result| result.transpose())
+            .collect::<eyre::Result<Vec<String>>>()
+    })?;
+
+    if !failures.is_empty() {
+        writeln!(
+            effects.get_error_stream(),
+            "Failed to run branchless.sync.onRewrite for {}:",
+            Pluralize {
+                determiner: None,
+                amount: failures.len(),
+                unit: ("rewritten commit", "rewritten commits"),
+            },
+        )?;
+        for failure in failures {
+            writeln!(effects.get_error_stream(), "  {failure}")?;
+        }
+    }
+    Ok(())
+}
+
+/// Move the tags which point to commits rewritten by the sync which started
+/// with the provided transaction so that they point to the rewritten commits
+/// instead (`--update-tags`).
+/// Annotated tags are left in place, since moving them would change the tag
+/// object.
+fn update_rewritten_tags(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    repo: &Repo,
+    event_log_db: &EventLogDb,
+    event_tx_id: EventTransactionId,
+) -> eyre::Result<()> {
+    // Enumerating and peeling every tag is expensive in repositories with many
+    // tags, so it's only done when requested.
+    let tag_oid_to_names = repo.get_tag_oid_to_names()?;
+    if tag_oid_to_names.is_empty() {
+        return Ok(());
+    }
+
+    let sync_event_tx_ids = event_log_db.get_sync_transaction_ids(event_tx_id)?;
+    let tags_to_move = event_log_db
+        .get_events()?
+        .into_iter()
+        .filter_map(|event| match event {
+            Event::RewriteEvent {
+                timestamp: _,
+                event_tx_id: rewrite_event_tx_id,
+                old_commit_oid: MaybeZeroOid::NonZero(old_commit_oid),
+                new_commit_oid: MaybeZeroOid::NonZero(new_commit_oid),
+            } if sync_event_tx_ids.contains(&rewrite_event_tx_id) => tag_oid_to_names
+                .get(&old_commit_oid)
+                .map(|names| (names, old_commit_oid, new_commit_oid)),
+            _ => None,
+        })
+        .flat_map(|(names, old_commit_oid, new_commit_oid)| {
+            names
+                .iter()
+                .map(move |name| (name.clone(), old_commit_oid, new_commit_oid))
+        })
+        .sorted()
+        .collect_vec();
 
-    // FIXME: if two draft roots are ancestors of a single commit (due to a
-    // merge commit), then the entire unit should be treated as one stack and
-    // moved together, rather than attempting two separate rebases.
-    let draft_roots = dag.query_roots(draft_commits.clone())?;
+    let mut tag_moves = Vec::new();
+    for (tag_name, old_commit_oid, new_commit_oid) in tags_to_move {
+        let tag_suffix = tag_name
+            .as_str()
+            .strip_prefix("refs/tags/")
+            .unwrap_or(tag_name.as_str());
+        let is_annotated_tag = match repo.find_reference(&tag_name)? {
+            Some(reference) => reference.is_annotated_tag()?,
+            None => continue,
+        };
+        if is_annotated_tag {
+            writeln!(
+                effects.get_warning_stream(),
+                "Warning: not moving annotated tag {tag_suffix}, since moving it would change the tag object. Recreate it with `git tag -f -a {tag_suffix} {new_commit_oid}`."
+            )?;
+            continue;
+        }
 
-    if commit_sets.is_empty() {
-        return Ok(draft_roots);
+        repo.create_reference(
+            &tag_name,
+            new_commit_oid,
+            true,
+            "branchless: moving tag for synced commit",
+        )?;
+        tag_moves.push(format!(
+            "{old_commit_oid} {new_commit_oid} {tag_name}\n",
+            tag_name = tag_name.as_str()
+        ));
+        let commit = repo.find_commit_or_fail(new_commit_oid)?;
+        writeln!(
+            effects.get_output_stream(),
+            "Moved tag {tag_suffix} to {}",
+            effects
+                .get_glyphs()
+                .render(commit.friendly_describe(effects.get_glyphs())?)?,
+        )?;
     }
 
-    let stack_ancestors = dag.query_range(draft_roots, union_all(&commit_sets))?;
-    dag.query_roots(stack_ancestors)
-        .map_err(|err| eyre::eyre!("Could not query DAG for stack roots: {err}"))
+    // The tags were moved with libgit2, which doesn't invoke the
+    // `reference-transaction` hook, so invoke it ourselves (as
+    // `move_branches` does) to record the moves under the sync's transaction
+    // and make them undoable.
+    if !tag_moves.is_empty() {
+        let tag_moves_stdin = BString::from(tag_moves.concat());
+        // Like Git, ignore the exit code of the hook, since the references
+        // have already been updated.
+        let _exit_code: ExitCode = git_run_info.run_hook(
+            effects,
+            repo,
+            "reference-transaction",
+            event_tx_id,
+            &["committed"],
+            Some(tag_moves_stdin),
+        )?;
+    }
+    Ok(())
 }
 
-/// Move all commit stacks on top of the main branch.
-pub fn sync(
-    effects: &Effects,
-    git_run_info: &GitRunInfo,
-    pull: bool,
-    move_options: &MoveOptions,
-    revsets: Vec<Revset>,
-    resolve_revset_options: &ResolveRevsetOptions,
-) -> EyreExitOr<()> {
-    let repo = Repo::from_current_dir()?;
-    let conn = repo.get_db_conn()?;
-    let event_log_db = EventLogDb::new(&conn)?;
-    let now = SystemTime::now();
-    let event_tx_id = event_log_db.make_transaction_id(now, "sync fetch")?;
+/// Get the local branches which will be moved by each of the provided plans,
+/// keyed by the root commit of the stack.
+fn get_stack_branches(
+    references_snapshot: &RepoReferencesSnapshot,
+    root_commit_and_plans: &[(NonZeroOid, Option<RebasePlan>)],
+) -> HashMap<NonZeroOid, Vec<(ReferenceName, NonZeroOid)>> {
+    root_commit_and_plans
+        .iter()
+        .filter_map(|(root_commit_oid, rebase_plan)| {
+            rebase_plan.as_ref().map(|rebase_plan| {
+                (
+                    *root_commit_oid,
+                    get_moved_branches(references_snapshot, rebase_plan),
+                )
+            })
+        })
+        .collect()
+}
 
-    // Try to surface parse errors early, before potentially doing commit graph or network
-    // side-effects.
-    check_revset_syntax(&repo, &revsets)?;
+/// Get the local branches which point to commits that will be moved by the
+/// provided rebase plan, along with the commits that they point to.
+fn get_moved_branches(
+    references_snapshot: &RepoReferencesSnapshot,
+    rebase_plan: &RebasePlan,
+) -> Vec<(ReferenceName, NonZeroOid)> {
+    rebase_plan
+        .commands
+        .iter()
+        .filter_map(|command| match command {
+            RebaseCommand::Pick {
+                original_commit_oid: commit_oid,
+                commits_to_apply_oids: _,
+            }
+            | RebaseCommand::Merge {
+                commit_oid,
+                commits_to_merge: _,
+            } => references_snapshot
+                .branch_oid_to_names
+                .get(commit_oid)
+                .map(|names| (commit_oid, names)),
+            _ => None,
+        })
+        .flat_map(|(commit_oid, names)| {
+            names
+                .iter()
+                .filter(|name| {
+                    matches!(
+                        CategorizedReferenceName::new(name),
+                        CategorizedReferenceName::LocalBranch { .. }
+                    )
+                })
+                .map(|name| (name.clone(), *commit_oid))
+        })
+        .collect()
+}
 
-    if pull {
-        try_exit_code!(git_run_info.run(effects, Some(event_tx_id), &["fetch", "--all"])?);
+/// Get the first of the provided branches which no longer exists, if any.
+fn find_deleted_branch(
+    repo: &Repo,
+    branches: &[(ReferenceName, NonZeroOid)],
+) -> eyre::Result<Option<ReferenceName>> {
+    for (branch_name, _commit_oid) in branches {
+        if repo.find_reference(branch_name)?.is_none() {
+            return Ok(Some(branch_name.clone()));
+        }
     }
+    Ok(None)
+}
 
-    let MoveOptions {
-        force_rewrite_public_commits,
-        force_in_memory,
-        force_on_disk,
-        detect_duplicate_commits_via_patch_id,
-        resolve_merge_conflicts,
-        dump_rebase_constraints,
-        dump_rebase_plan,
-    } = *move_options;
-    let build_options = BuildRebasePlanOptions {
-        force_rewrite_public_commits,
-        detect_duplicate_commits_via_patch_id,
-        dump_rebase_constraints,
-        dump_rebase_plan,
-    };
-    let now = SystemTime::now();
-    let event_tx_id = event_log_db.make_transaction_id(now, "sync")?;
-    let execute_options = ExecuteRebasePlanOptions {
-        now,
-        event_tx_id,
-        preserve_timestamps: get_restack_preserve_timestamps(&repo)?,
-        force_in_memory,
-        force_on_disk,
-        resolve_merge_conflicts,
-        check_out_commit_options: CheckOutCommitOptions {
-            additional_args: Default::default(),
-            reset: false,
-            render_smartlog: false,
-        },
-    };
-    let thread_pool = ThreadPoolBuilder::new().build()?;
-    let repo_pool = RepoResource::new_pool(&repo)?;
+/// Reset the provided branches to the provided commits, if they were moved.
+fn restore_branches(
+    effects: &Effects,
+    repo: &Repo,
+    branches: &[(ReferenceName, NonZeroOid)],
+) -> eyre::Result<()> {
+    let references_snapshot = repo.get_references_snapshot()?;
+    for (branch_name, commit_oid) in branches {
+        let is_unchanged = references_snapshot
+            .branch_oid_to_names
+            .get(commit_oid)
+            .map(|names| names.contains(branch_name))
+            .unwrap_or(false);
+        if is_unchanged {
+            continue;
+        }
 
-    let head_info = repo.get_head_info()?;
-    if pull {
-        try_exit_code!(execute_main_branch_sync_plan(
-            effects,
-            git_run_info,
-            &repo,
-            &event_log_db,
-            &build_options,
-            &execute_options,
-            &thread_pool,
-            &repo_pool,
-            &head_info,
-        )?);
+        repo.create_reference(
+            branch_name,
+            *commit_oid,
+            true,
+            "branchless: restoring branch for unsynced stack",
+        )?;
+        let commit = repo.find_commit_or_fail(*commit_oid)?;
+        writeln!(
+            effects.get_output_stream(),
+            "Restored {} to {}",
+            CategorizedReferenceName::new(branch_name).friendly_describe(),
+            effects
+                .get_glyphs()
+                .render(commit.friendly_describe(effects.get_glyphs())?)?,
+        )?;
     }
-
-    // The main branch might have changed since we synced with `master`, so read its information again.
-
-    execute_sync_plans(
-        effects,
-        git_run_info,
-        &repo,
-        &event_log_db,
-        build_options,
-        &execute_options,
-        &thread_pool,
-        &repo_pool,
-        revsets,
-        resolve_revset_options,
-    )
+    Ok(())
 }
 
-fn execute_main_branch_sync_plan(
+/// Force-push the provided local branches to the default push remote, under
+/// the names given by `branchless.sync.publishRefTemplate`.
+#[instrument(skip_all)]
+fn publish_branches(
     effects: &Effects,
     git_run_info: &GitRunInfo,
     repo: &Repo,
-    event_log_db: &EventLogDb,
-    build_options: &BuildRebasePlanOptions,
-    execute_options: &ExecuteRebasePlanOptions,
-    thread_pool: &ThreadPool,
-    repo_pool: &RepoPool,
-    head_info: &ResolvedReferenceInfo,
+    event_tx_id: EventTransactionId,
+    no_verify: bool,
+    branch_names: &[String],
 ) -> EyreExitOr<()> {
-    let event_replayer = EventReplayer::from_event_log_db(effects, repo, event_log_db)?;
-    let event_cursor = event_replayer.make_default_cursor();
-    let references_snapshot = repo.get_references_snapshot()?;
-    let mut dag = Dag::open_and_sync(
-        effects,
-        repo,
-        &event_replayer,
-        event_cursor,
-        &references_snapshot,
-    )?;
+    if branch_names.is_empty() {
+        writeln!(
+            effects.get_output_stream(),
+            "No synced branches to publish."
+        )?;
+        return Ok(Ok(()));
+    }
 
-    let local_main_branch = repo.get_main_branch()?;
-    let local_main_branch_oid = local_main_branch.get_oid()?;
-    let local_main_branch_reference_name = local_main_branch.get_reference_name()?;
-    let local_main_branch_description = effects.get_glyphs().render(
-        StyledStringBuilder::new()
-            .append_styled(
-                CategorizedReferenceName::new(&local_main_branch_reference_name)
+    let push_remote = match repo.get_default_push_remote()? {
+        Some(push_remote) => push_remote,
+        None => {
+            writeln!(
+                effects.get_output_stream(),
+                "\
+No upstream repository was associated with {} and no value was
+specified for `remote.pushDefault`, so cannot publish these branches: {}
+Configure a value with: git config remote.pushDefault <remote>
+These remotes are available: {}",
+                CategorizedReferenceName::new(&repo.get_main_branch()?.get_reference_name()?)
                     .friendly_describe(),
-                BaseColor::Green.dark(),
-            )
-            .build(),
-    )?;
+                branch_names.join(", "),
+                repo.get_all_remote_names()?.join(", "),
+            )?;
+            return Ok(Err(ExitCode(1)));
+        }
+    };
 
-    let upstream_main_branch = match local_main_branch.get_upstream_branch()? {
-        Some(upstream_main_branch) => upstream_main_branch,
+    let user_email: Option<String> = repo.get_readonly_config()?.get("user.email")?;
+    let user = match user_email
+        .as_deref()
+        .and_then(|user_email| user_email.split('@').next())
+        .filter(|user| !user.is_empty())
+    {
+        Some(user) => user.to_owned(),
         None => {
             writeln!(
                 effects.get_output_stream(),
-                "{local_main_branch_description} does not track an upstream branch, so not pulling."
+                "Cannot publish branches because no value was specified for `user.email`."
             )?;
-            return Ok(Ok(()));
+            return Ok(Err(ExitCode(1)));
         }
     };
-    let upstream_main_branch_oid = match upstream_main_branch.get_oid()? {
-        Some(upstream_main_branch_oid) => upstream_main_branch_oid,
-        None => return Ok(Ok(())),
-    };
-    dag.sync_from_oids(
-        effects,
-        repo,
-        CommitSet::from(upstream_main_branch_oid),
-        CommitSet::empty(),
-    )?;
-    let local_main_branch_commits = dag.query_only(
-        local_main_branch_oid.into_iter().collect(),
-        CommitSet::from(upstream_main_branch_oid),
+
+    let ref_template = get_sync_publish_ref_template(repo)?;
+    let mut leases = Vec::new();
+    let mut refspecs = Vec::new();
+    for branch_name in branch_names {
+        let remote_ref_name = ref_template
+            .replace("{user}", &user)
+            .replace("{branch}", branch_name);
+        // Only overwrite the remote reference if it's still where it was when
+        // last fetched or published, so that others' updates to it aren't lost.
+        // If there's no remote-tracking reference, then the remote reference
+        // must not exist yet.
+        let expected_oid = match remote_ref_name.strip_prefix("refs/heads/") {
+            Some(remote_branch_name) => repo
+                .find_reference(&ReferenceName::from(format!(
+                    "refs/remotes/{push_remote}/{remote_branch_name}"
+                )))?
+                .map(|reference| reference.peel_to_commit())
+                .transpose()?
+                .flatten()
+                .map(|commit| commit.get_oid().to_string()),
+            None => None,
+        };
+        leases.push(format!(
+            "--force-with-lease={remote_ref_name}:{}",
+            expected_oid.unwrap_or_default()
+        ));
+        refspecs.push(format!("refs/heads/{branch_name}:{remote_ref_name}"));
+    }
+    let mut args = vec!["push"];
+    args.extend(leases.iter().map(|lease| lease.as_str()));
+    if no_verify {
+        args.push("--no-verify");
+    }
+    args.push(&push_remote);
+
+    args.extend(refspecs.iter().map(|refspec| refspec.as_str()));
+
+    let (effects, progress) = effects.start_operation(OperationType::PushCommits);
+    progress.notify_progress(0, branch_names.len());
+    try_exit_code!(git_run_info.run(&effects, Some(event_tx_id), &args)?);
+
+    writeln!(
+        effects.get_output_stream(),
+        "Published {} to {push_remote}.",
+        Pluralize {
+            determiner: None,
+            amount: branch_names.len(),
+            unit: ("branch", "branches"),
+        },
     )?;
-    if dag.set_is_empty(&local_main_branch_commits)? {
-        if local_main_branch_oid == Some(upstream_main_branch_oid) {
-            let local_main_branch_commit = repo.find_commit_or_fail(upstream_main_branch_oid)?;
+    Ok(Ok(()))
+}
+
+/// Print the provided rebase plans in the format selected with `--format`, or
+/// write them to the file provided with `--output-file`.
+fn output_plans(
+    effects: &Effects,
+    repo: &Repo,
+    output_options: &SyncOutputOptions,
+    root_commit_and_plans: &[(NonZeroOid, Option<RebasePlan>)],
+) -> eyre::Result<()> {
+    let mut output = String::new();
+    match output_options.format.unwrap_or_default() {
+        SyncPlanFormat::Text => print_plans(&mut output, root_commit_and_plans)?,
+        SyncPlanFormat::Graphviz => print_plans_graphviz(&mut output, repo, root_commit_and_plans)?,
+    }
+    match &output_options.output_file {
+        Some(output_file) => write_output_file(output_file, &output)?,
+        None => effects.get_output_stream().write_str(&output)?,
+    }
+    Ok(())
+}
+
+/// Print the provided rebase plans as a Graphviz (DOT) graph. Each commit in a
+/// stack has a solid edge to each of its current parents, and the root of each
+/// stack has a dashed edge to the commit that it will be moved onto. Nodes
+/// and edges are emitted in a deterministic order.
+fn print_plans_graphviz(
+    output: &mut impl Write,
+    repo: &Repo,
+    root_commit_and_plans: &[(NonZeroOid, Option<RebasePlan>)],
+) -> eyre::Result<()> {
+    let glyphs = Glyphs::text();
+    let mut nodes: BTreeMap<NonZeroOid, String> = BTreeMap::new();
+    let mut add_node = |commit_oid: NonZeroOid| -> eyre::Result<()> {
+        if let Entry::Vacant(entry) = nodes.entry(commit_oid) {
+            let commit = repo.find_commit_or_fail(commit_oid)?;
+            entry.insert(glyphs.render(commit.friendly_describe(&glyphs)?)?);
+        }
+        Ok(())
+    };
+
+    let mut edges: Vec<(NonZeroOid, NonZeroOid, bool)> = Vec::new();
+    for (root_commit_oid, rebase_plan) in root_commit_and_plans {
+        let stack_commit_oids = match rebase_plan {
+            None => vec![*root_commit_oid],
+            Some(RebasePlan {
+                first_dest_oid: _,
+                commands,
+            }) => commands
+                .iter()
+                .filter_map(|command| match command {
+                    RebaseCommand::Pick {
+                        original_commit_oid: commit_oid,
+                        commits_to_apply_oids: _,
+                    }
+                    | RebaseCommand::Merge {
+                        commit_oid,
+                        commits_to_merge: _,
+                    } => Some(*commit_oid),
+                    _ => None,
+                })
+                .collect(),
+        };
+        for commit_oid in stack_commit_oids {
+            add_node(commit_oid)?;
+            for parent_oid in repo.find_commit_or_fail(commit_oid)?.get_parent_oids() {
+                add_node(parent_oid)?;
+                edges.push((commit_oid, parent_oid, false));
+            }
+        }
+        if let Some(rebase_plan) = rebase_plan {
+            add_node(rebase_plan.first_dest_oid)?;
+            edges.push((*root_commit_oid, rebase_plan.first_dest_oid, true));
+        }
+    }
+
+    writeln!(output, "digraph sync {{")?;
+    writeln!(output, "  rankdir=BT;")?;
+    for (commit_oid, label) in nodes {
+        let label = label.replace('\\', "\\\\").replace('"', "\\\"");
+        writeln!(output, "  \"{commit_oid}\" [label=\"{label}\"];")?;
+    }
+    for (commit_oid, target_oid, is_move) in edges.into_iter().unique() {
+        if is_move {
             writeln!(
-                effects.get_output_stream(),
-                "Not updating {} at {}",
-                local_main_branch_description,
-                effects
-                    .get_glyphs()
-                    .render(local_main_branch_commit.friendly_describe(effects.get_glyphs())?)?,
+                output,
+                "  \"{commit_oid}\" -> \"{target_oid}\" [style=dashed];"
             )?;
         } else {
-            let remote_main_branch_commit = repo.find_commit_or_fail(upstream_main_branch_oid)?;
+            writeln!(output, "  \"{commit_oid}\" -> \"{target_oid}\";")?;
+        }
+    }
+    writeln!(output, "}}")?;
+    Ok(())
+}
+
+/// Print the provided rebase plans in a stable format suitable for review and
+/// for diffing across runs. Each plan is printed as:
+///
+/// ```text
+/// plan <root-oid>
+///   onto <first-dest-oid>
+///   <command>
+///   ...
+/// ```
+///
+/// where each `<command>` is one of:
+///
+/// - `label <name>`
+/// - `reset <oid-or-label>`
+/// - `pick <oid>`, or `pick <oid> <applied-oid>...` if other commits' patches
+///   are applied in place of (or squashed into) the commit's own patch
+/// - `merge <oid> <oid-or-label>...`
+/// - `replace <oid> <replacement-oid> <parent-oid-or-label>...`
+/// - `break`
+/// - `detect-empty <oid>`
+/// - `skip-upstream-applied <oid>`
+///
+/// Stacks which don't need to be moved are printed as `plan <root-oid>`
+/// followed by `  up-to-date`. Commands which are only used internally to
+/// drive on-disk rebases are omitted.
+fn print_plans(
+    output: &mut impl Write,
+    root_commit_and_plans: &[(NonZeroOid, Option<RebasePlan>)],
+) -> eyre::Result<()> {
+    for (root_commit_oid, rebase_plan) in root_commit_and_plans {
+        writeln!(output, "plan {root_commit_oid}")?;
+        let RebasePlan {
+            first_dest_oid,
+            commands,
+        } = match rebase_plan {
+            Some(rebase_plan) => rebase_plan,
+            None => {
+                writeln!(output, "  up-to-date")?;
+                continue;
+            }
+        };
+        writeln!(output, "  onto {first_dest_oid}")?;
+        for command in commands {
+            let line = match command {
+                RebaseCommand::CreateLabel { label_name } => format!("label {label_name}"),
+                RebaseCommand::Reset { target } => format!("reset {target}"),
+                RebaseCommand::Pick {
+                    original_commit_oid,
+                    commits_to_apply_oids,
+                } => {
+                    if commits_to_apply_oids.as_slice() == [*original_commit_oid] {
+                        format!("pick {original_commit_oid}")
+                    } else {
+                        format!(
+                            "pick {original_commit_oid} {}",
+                            commits_to_apply_oids.iter().join(" ")
+                        )
+                    }
+                }
+                RebaseCommand::Merge {
+                    commit_oid,
+                    commits_to_merge,
+                } => format!("merge {commit_oid} {}", commits_to_merge.iter().join(" ")),
+                RebaseCommand::Replace {
+                    commit_oid,
+                    replacement_commit_oid,
+                    parents,
+                } => format!(
+                    "replace {commit_oid} {replacement_commit_oid} {}",
+                    parents.iter().join(" ")
+                ),
+                RebaseCommand::Break => "break".to_string(),
+                RebaseCommand::RegisterExtraPostRewriteHook => continue,
+                RebaseCommand::DetectEmptyCommit { commit_oid } => {
+                    format!("detect-empty {commit_oid}")
+                }
+                RebaseCommand::SkipUpstreamAppliedCommit { commit_oid } => {
+                    format!("skip-upstream-applied {commit_oid}")
+                }
+            };
+            writeln!(output, "  {line}")?;
+        }
+    }
+    Ok(())
+}
+
+/// Print the conflicting regions of the provided paths, as they would appear
+/// with conflict markers after applying `commit_oid` onto `onto_oid`, for
+/// `--show-conflicted-diff`. The conflicts are recomputed with `git merge-tree`,
+/// since the in-memory rebase only records which paths were in conflict.
+fn print_conflicted_diff(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    repo: &Repo,
+    commit_oid: NonZeroOid,
+    onto_oid: NonZeroOid,
+    conflicting_paths: &HashSet<PathBuf>,
+) -> eyre::Result<()> {
+    let commit = repo.find_commit_or_fail(commit_oid)?;
+    let parent_oid = match commit.get_only_parent_oid() {
+        Some(parent_oid) => parent_oid,
+        None => return Ok(()),
+    };
+    let GitRunResult {
+        exit_code,
+        stdout,
+        stderr: _,
+    } = git_run_info.run_silent(
+        repo,
+        None,
+        &[
+            "merge-tree",
+            "--write-tree",
+            &format!("--merge-base={parent_oid}"),
+            &onto_oid.to_string(),
+            &commit_oid.to_string(),
+        ],
+        GitRunOpts {
+            treat_git_failure_as_error: false,
+            stdin: None,
+            working_directory: None,
+        },
+    )?;
+    // `git merge-tree` exits with code 1 if there were conflicts, and
+    // with another code if the merge couldn't be attempted.
+    let merged_tree_oid = String::from_utf8_lossy(&stdout)
+        .lines()
+        .next()
+        .and_then(|line| line.parse::<NonZeroOid>().ok());
+    let merged_tree = match merged_tree_oid {
+        Some(merged_tree_oid) if exit_code.0 <= 1 => repo.find_tree_or_fail(merged_tree_oid)?,
+        _ => {
             writeln!(
                 effects.get_output_stream(),
-                "Fast-forwarding {} to {}",
-                local_main_branch_description,
-                effects
-                    .get_glyphs()
-                    .render(remote_main_branch_commit.friendly_describe(effects.get_glyphs())?)?,
+                "  Could not compute the conflicting regions (requires Git v2.40 or later)."
             )?;
+            return Ok(());
         }
+    };
 
-        if head_info.reference_name.as_ref() == Some(&local_main_branch_reference_name) {
-            // If the main branch is checked out, make sure to update the index
-            // as well as the reference itself. Otherwise, staged changes will
-            // appear in `git status`.
-            //
-            // FIXME: this is suboptimal since it relies on the working copy.
-            // However, handling e.g. dirty working copy changes is error-prone
-            // and I haven't implemented it for now.
-            try_exit_code!(git_run_info.run(
-                effects,
-                Some(execute_options.event_tx_id),
-                &["rebase", &upstream_main_branch_oid.to_string()],
-            )?);
-        } else {
-            repo.create_reference(
-                &local_main_branch_reference_name,
-                upstream_main_branch_oid,
-                true,
-                "sync",
+    for path in conflicting_paths.iter().sorted() {
+        let blob = match merged_tree.get_oid_for_path(path)? {
+            Some(MaybeZeroOid::NonZero(blob_oid)) => repo.find_blob(blob_oid)?,
+            Some(MaybeZeroOid::Zero) | None => None,
+        };
+        let contents = match &blob {
+            Some(blob) => String::from_utf8_lossy(blob.get_content()).into_owned(),
+            None => String::new(),
+        };
+        let mut conflict_lines = Vec::new();
+        let mut is_in_conflict = false;
+        for line in contents.lines() {
+            if line.starts_with("<<<<<<<") {
+                is_in_conflict = true;
+            }
+            if is_in_conflict {
+                conflict_lines.push(line);
+            }
+            if line.starts_with(">>>>>>>") {
+                is_in_conflict = false;
+            }
+        }
+        if conflict_lines.is_empty() {
+            writeln!(
+                effects.get_output_stream(),
+                "  Conflict in {} (no conflict markers)",
+                path.display()
             )?;
+            continue;
         }
-
-        return Ok(Ok(()));
-    } else {
         writeln!(
             effects.get_output_stream(),
-            "Syncing {local_main_branch_description}"
+            "  Conflict in {}:",
+            path.display()
         )?;
+        for line in conflict_lines {
+            writeln!(effects.get_output_stream(), "    {line}")?;
+        }
     }
+    Ok(())
+}
 
-    let build_options = BuildRebasePlanOptions {
-        // Since we're syncing the main branch, by definition, any commits on it would be public, so
-        // we need to set this to `true` to get the rebase to succeed.
-        force_rewrite_public_commits: true,
-        ..build_options.clone()
-    };
-    let permissions = match RebasePlanPermissions::verify_rewrite_set(
-        &dag,
-        build_options,
-        &local_main_branch_commits,
-    )? {
-        Ok(permissions) => permissions,
-        Err(err) => {
-            err.describe(effects, repo, &dag)?;
-            return Ok(Err(ExitCode(1)));
-        }
-    };
-    let mut builder = RebasePlanBuilder::new(&dag, permissions);
-    let local_main_branch_roots = dag.query_roots(local_main_branch_commits)?;
-    let root_commit_oid = match dag
-        .commit_set_to_vec(&local_main_branch_roots)?
-        .into_iter()
-        .exactly_one()
-    {
-        Ok(root_oid) => root_oid,
-        Err(_) => return Ok(Ok(())),
-    };
-    builder.move_subtree(root_commit_oid, vec![upstream_main_branch_oid])?;
-    let rebase_plan = match builder.build(effects, thread_pool, repo_pool)? {
-        Ok(rebase_plan) => rebase_plan,
-        Err(err) => {
-            err.describe(effects, repo, &dag)?;
-            return Ok(Err(ExitCode(1)));
+/// The result of [`execute_plans`].
+struct ExecutePlansResult {
+    /// Whether all of the plans were executed successfully, i.e. none of them
+    /// were skipped due to merge conflicts or deleted branches.
+    is_fully_synced: bool,
+
+    /// The root commits of the stacks which were moved successfully.
+    synced_root_commit_oids: Vec<NonZeroOid>,
+
+    /// The root commits of the stacks which weren't moved due to merge
+    /// conflicts.
+    conflicted_root_commit_oids: Vec<NonZeroOid>,
+
+    /// The root commits of the stacks which weren't moved because the sync was
+    /// interrupted before they were started.
+    interrupted_root_commit_oids: Vec<NonZeroOid>,
+
+    /// The number of commits which were rewritten by the executed plans.
+    num_commits_rewritten: usize,
+
+    /// The root commits of the stacks which were moved, but for which the
+    /// `post-rewrite` hook failed.
+    hook_failed_root_commit_oids: Vec<NonZeroOid>,
+}
+
+/// Statistics about the rebases executed by [`execute_plans`], printed with
+/// `--statistics`. Only deterministic quantities are included, so that the
+/// output is stable between runs.
+#[derive(Debug, Default)]
+struct SyncStatistics {
+    num_in_memory_rebases: usize,
+    num_on_disk_rebases: usize,
+    num_conflicts: usize,
+    num_commits_picked: usize,
+    num_commits_skipped_empty: usize,
+    num_commits_skipped_duplicate: usize,
+    num_commits_rewritten: usize,
+}
+
+impl SyncStatistics {
+    /// Record the result of executing the provided plan.
+    fn record(&mut self, rebase_plan: &RebasePlan, result: &ExecuteRebasePlanResult) {
+        let (rewritten_oids, num_commits_picked, num_commits_skipped_duplicate) = match result {
+            ExecuteRebasePlanResult::Succeeded {
+                rewritten_oids,
+                post_rewrite_hook_exit_code: _,
+            } => {
+                let mut num_commits_picked = 0;
+                let mut num_commits_skipped_duplicate = 0;
+                for command in rebase_plan.commands.iter() {
+                    match command {
+                        RebaseCommand::Pick {
+                            original_commit_oid: _,
+                            commits_to_apply_oids,
+                        } => num_commits_picked += commits_to_apply_oids.len(),
+                        RebaseCommand::Merge { .. } => num_commits_picked += 1,
+                        RebaseCommand::SkipUpstreamAppliedCommit { .. } => {
+                            num_commits_skipped_duplicate += 1
+                        }
+                        RebaseCommand::CreateLabel { .. }
+                        | RebaseCommand::Reset { .. }
+                        | RebaseCommand::Replace { .. }
+                        | RebaseCommand::Break
+                        | RebaseCommand::RegisterExtraPostRewriteHook
+                        | RebaseCommand::DetectEmptyCommit { .. } => {}
+                    }
+                }
+                (
+                    rewritten_oids,
+                    num_commits_picked,
+                    num_commits_skipped_duplicate,
+                )
+            }
+            ExecuteRebasePlanResult::DeclinedToMerge {
+                failed_merge_info: _,
+            } => {
+                self.num_conflicts += 1;
+                return;
+            }
+            ExecuteRebasePlanResult::Failed { exit_code: _ } => return,
+        };
+
+        self.num_commits_picked += num_commits_picked;
+        self.num_commits_skipped_duplicate += num_commits_skipped_duplicate;
+        match rewritten_oids {
+            Some(rewritten_oids) => {
+                // In-memory rebases report which commits were dropped, which
+                // includes the duplicate commits as well as the empty ones.
+                self.num_in_memory_rebases += 1;
+                let num_dropped_commits = rewritten_oids
+                    .values()
+                    .filter(|new_oid| matches!(new_oid, MaybeZeroOid::Zero))
+                    .count();
+                self.num_commits_skipped_empty +=
+                    num_dropped_commits.saturating_sub(num_commits_skipped_duplicate);
+                self.num_commits_rewritten += rewritten_oids.len() - num_dropped_commits;
+            }
+            None => {
+                // On-disk rebases don't report which commits were dropped for
+                // being empty, so assume that every picked commit was rewritten.
+                self.num_on_disk_rebases += 1;
+                self.num_commits_rewritten += num_commits_picked;
+            }
         }
-    };
-    let rebase_plan = match rebase_plan {
-        Some(rebase_plan) => rebase_plan,
-        None => return Ok(Ok(())),
-    };
+    }
 
-    execute_plans(
-        effects,
-        git_run_info,
-        repo,
-        event_log_db,
-        execute_options,
-        vec![(root_commit_oid, Some(rebase_plan))],
-    )
+    /// Print the statistics as a table.
+    fn write(&self, effects: &Effects) -> eyre::Result<()> {
+        let Self {
+            num_in_memory_rebases,
+            num_on_disk_rebases,
+            num_conflicts,
+            num_commits_picked,
+            num_commits_skipped_empty,
+            num_commits_skipped_duplicate,
+            num_commits_rewritten,
+        } = self;
+        let rows = [
+            ("Stacks rebased in-memory", num_in_memory_rebases),
+            ("Stacks rebased on-disk", num_on_disk_rebases),
+            ("Stacks with conflicts", num_conflicts),
+            ("Commits picked", num_commits_picked),
+            ("Commits skipped (empty)", num_commits_skipped_empty),
+            ("Commits skipped (duplicate)", num_commits_skipped_duplicate),
+            ("Commits rewritten", num_commits_rewritten),
+        ];
+        let label_width = rows
+            .iter()
+            .map(|(label, _value)| label.len())
+            .max()
+            .unwrap_or_default();
+        writeln!(effects.get_output_stream(), "Sync statistics:")?;
+        for (label, value) in rows {
+            writeln!(
+                effects.get_output_stream(),
+                "  {label:<label_width$}  {value}"
+            )?;
+        }
+        Ok(())
+    }
 }
 
-fn execute_sync_plans(
+/// Whether an interrupt should terminate the process, as it would if no
+/// handlers were installed. This is set whenever no [`InterruptGuard`] is
+/// alive. The handler which reads it is registered once per process, since
+/// unregistering a handler doesn't reinstate the default one.
+static TERMINATE_ON_INTERRUPT: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+
+/// Catches interrupts (Ctrl-C) for as long as it's alive, so that the sync can
+/// stop between stacks rather than being killed partway through one. A second
+/// interrupt terminates the process as usual.
+struct InterruptGuard {
+    is_interrupted: Arc<AtomicBool>,
+    sig_ids: Vec<SigId>,
+}
+
+impl InterruptGuard {
+    fn new() -> eyre::Result<Self> {
+        let terminate_on_interrupt = TERMINATE_ON_INTERRUPT.get_or_init(|| {
+            let terminate_on_interrupt = Arc::new(AtomicBool::new(false));
+            if let Err(err) = signal_hook::flag::register_conditional_default(
+                SIGINT,
+                Arc::clone(&terminate_on_interrupt),
+            ) {
+                warn!(?err, "Could not register default interrupt handler");
+            }
+            terminate_on_interrupt
+        });
+        terminate_on_interrupt.store(false, Ordering::SeqCst);
+
+        let is_interrupted = Arc::new(AtomicBool::new(false));
+        let sig_ids = vec![
+            signal_hook::flag::register_conditional_default(SIGINT, Arc::clone(&is_interrupted))?,
+            signal_hook::flag::register(SIGINT, Arc::clone(&is_interrupted))?,
+        ];
+        Ok(Self {
+            is_interrupted,
+            sig_ids,
+        })
+    }
+
+    fn is_interrupted(&self) -> bool {
+        self.is_interrupted.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        for sig_id in self.sig_ids.drain(..) {
+            signal_hook::low_level::unregister(sig_id);
+        }
+        if let Some(terminate_on_interrupt) = TERMINATE_ON_INTERRUPT.get() {
+            terminate_on_interrupt.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Check out each commit rewritten by the provided plan, in the order that the
+/// plan applies them, and run `command` on it (`--verify-after`). Returns the
+/// rewritten commit which the command failed on, along with its exit code.
+/// `HEAD` is restored afterwards. On failure, the caller should revert the
+/// stack with `revert_synced_stack`.
+fn verify_synced_stack(
     effects: &Effects,
     git_run_info: &GitRunInfo,
     repo: &Repo,
     event_log_db: &EventLogDb,
-    build_options: BuildRebasePlanOptions,
-    execute_options: &ExecuteRebasePlanOptions,
-    thread_pool: &ThreadPool,
-    repo_pool: &ResourcePool<RepoResource>,
-    revsets: Vec<Revset>,
-    resolve_revset_options: &ResolveRevsetOptions,
-) -> EyreExitOr<()> {
-    let event_replayer = EventReplayer::from_event_log_db(effects, repo, event_log_db)?;
-    let event_cursor = event_replayer.make_default_cursor();
-    let references_snapshot = repo.get_references_snapshot()?;
-    let mut dag = Dag::open_and_sync(
-        effects,
-        repo,
-        &event_replayer,
-        event_cursor,
-        &references_snapshot,
-    )?;
-    let commit_sets =
-        match resolve_commits(effects, repo, &mut dag, &revsets, resolve_revset_options) {
-            Ok(commit_sets) => commit_sets,
-            Err(err) => {
-                err.describe(effects)?;
-                return Ok(Err(ExitCode(1)));
+    event_tx_id: EventTransactionId,
+    rebase_plan: &RebasePlan,
+    command: &str,
+) -> eyre::Result<Option<(NonZeroOid, ExitCode)>> {
+    let rewritten_oids: HashMap<NonZeroOid, NonZeroOid> = event_log_db
+        .get_events()?
+        .into_iter()
+        .filter_map(|event| match event {
+            Event::RewriteEvent {
+                timestamp: _,
+                event_tx_id: rewrite_event_tx_id,
+                old_commit_oid: MaybeZeroOid::NonZero(old_commit_oid),
+                new_commit_oid: MaybeZeroOid::NonZero(new_commit_oid),
+            } if rewrite_event_tx_id == event_tx_id => Some((old_commit_oid, new_commit_oid)),
+            _ => None,
+        })
+        .collect();
+    let commit_oids = rebase_plan
+        .commands
+        .iter()
+        .filter_map(|command| match command {
+            RebaseCommand::Pick {
+                original_commit_oid: commit_oid,
+                commits_to_apply_oids: _,
             }
-        };
-    let main_branch_oid = repo.get_main_branch_oid()?;
-    let root_commit_oids = get_stack_roots(&dag, commit_sets)?;
-    let root_commits = sorted_commit_set(repo, &dag, &root_commit_oids)?;
-    let permissions =
-        match RebasePlanPermissions::verify_rewrite_set(&dag, build_options, &root_commit_oids)? {
-            Ok(permissions) => permissions,
-            Err(err) => {
-                err.describe(effects, repo, &dag)?;
-                return Ok(Err(ExitCode(1)));
+            | RebaseCommand::Merge {
+                commit_oid,
+                commits_to_merge: _,
             }
-        };
-    let builder = RebasePlanBuilder::new(&dag, permissions);
+            | RebaseCommand::Replace {
+                commit_oid,
+                replacement_commit_oid: _,
+                parents: _,
+            } => rewritten_oids.get(commit_oid).copied(),
+            _ => None,
+        })
+        .collect_vec();
+
+    let head_info = repo.get_head_info()?;
+    let mut failure = None;
+    for commit_oid in commit_oids {
+        let commit_oid_str = commit_oid.to_string();
+        if let Err(exit_code) =
+            git_run_info.run(effects, Some(event_tx_id), &["checkout", &commit_oid_str])?
+        {
+            failure = Some((commit_oid, exit_code));
+            break;
+        }
+        // Run the command as a Git shell alias, so that it's executed with the
+        // same shell and environment that Git would use, from the root of the
+        // working copy.
+        if let Err(exit_code) = git_run_info.run(
+            effects,
+            Some(event_tx_id),
+            &[
+                "-c",
+                &format!("alias.branchless-sync-verify-after=!{command}"),
+                "branchless-sync-verify-after",
+            ],
+        )? {
+            failure = Some((commit_oid, exit_code));
+            break;
+        }
+    }
+
+    let head_target = match (head_info.get_branch_name()?, head_info.oid) {
+        (Some(branch_name), _) => Some(branch_name.to_owned()),
+        (None, Some(head_oid)) => Some(head_oid.to_string()),
+        (None, None) => None,
+    };
+    if let Some(head_target) = head_target {
+        git_run_info
+            .run(effects, Some(event_tx_id), &["checkout", &head_target])?
+            .map_err(|exit_code| {
+                eyre::eyre!(
+                    "Could not check out {head_target} again after verifying (exit code {})",
+                    exit_code.0
+                )
+            })?;
+    }
+    Ok(failure)
+}
 
-    let root_commit_oids = root_commits
+/// Undo the rewrites which the provided plan made in the given transaction,
+/// after its stack failed `--verify-after`: the stack's branches are moved
+/// back to the original commits, which are made visible again, and `HEAD` is
+/// checked out at its original commit if it was on the stack.
+fn revert_synced_stack(
+    effects: &Effects,
+    git_run_info: &GitRunInfo,
+    repo: &Repo,
+    event_log_db: &EventLogDb,
+    event_tx_id: EventTransactionId,
+    rebase_plan: &RebasePlan,
+) -> eyre::Result<()> {
+    let stack_commit_oids: HashSet<NonZeroOid> =
+        get_rewritten_commit_oids(rebase_plan).copied().collect();
+    let reverted_oids: HashMap<NonZeroOid, MaybeZeroOid> = event_log_db
+        .get_events()?
         .into_iter()
-        .map(|commit| commit.get_oid())
+        .filter_map(|event| match event {
+            Event::RewriteEvent {
+                timestamp: _,
+                event_tx_id: rewrite_event_tx_id,
+                old_commit_oid: MaybeZeroOid::NonZero(old_commit_oid),
+                new_commit_oid: MaybeZeroOid::NonZero(new_commit_oid),
+            } if rewrite_event_tx_id == event_tx_id
+                && stack_commit_oids.contains(&old_commit_oid) =>
+            {
+                Some((new_commit_oid, MaybeZeroOid::NonZero(old_commit_oid)))
+            }
+            _ => None,
+        })
+        .collect();
+
+    let head_info = repo.get_head_info()?;
+    let head_target = match head_info
+        .oid
+        .and_then(|head_oid| reverted_oids.get(&head_oid))
+    {
+        Some(original_oid) => match head_info.get_branch_name()? {
+            Some(branch_name) => Some(branch_name.to_owned()),
+            None => Some(original_oid.to_string()),
+        },
+        None => None,
+    };
+    if head_target.is_some() {
+        // Avoid moving the branch which HEAD points to, or else the index will
+        // show a lot of changes in the working copy.
+        repo.detach_head(&head_info)?;
+    }
+    move_branches(effects, git_run_info, repo, event_tx_id, &reverted_oids)?;
+
+    let now = SystemTime::now();
+    let timestamp = now.duration_since(SystemTime::UNIX_EPOCH)?.as_secs_f64();
+    let events = reverted_oids
+        .iter()
+        .sorted()
+        .map(|(new_commit_oid, old_commit_oid)| Event::RewriteEvent {
+            timestamp,
+            event_tx_id,
+            old_commit_oid: MaybeZeroOid::NonZero(*new_commit_oid),
+            new_commit_oid: *old_commit_oid,
+        })
         .collect_vec();
-    let root_commit_and_plans = thread_pool.install(|| -> eyre::Result<_> {
-        let result = root_commit_oids
-            // Don't parallelize for now, since the status updates don't render well.
-            .into_iter()
-            .map(
-                |root_commit_oid| -> eyre::Result<
-                    Result<(NonZeroOid, Option<RebasePlan>), BuildRebasePlanError>,
-                > {
-                    // Keep access to the same underlying caches by cloning the same instance of the builder.
-                    let mut builder = builder.clone();
+    event_log_db.add_events(events)?;
 
-                    let repo = repo_pool.try_create()?;
-                    let root_commit = repo.find_commit_or_fail(root_commit_oid)?;
+    if let Some(head_target) = head_target {
+        git_run_info
+            .run(effects, Some(event_tx_id), &["checkout", &head_target])?
+            .map_err(|exit_code| {
+                eyre::eyre!(
+                    "Could not check out {head_target} again after reverting (exit code {})",
+                    exit_code.0
+                )
+            })?;
+    }
+    Ok(())
+}
 
-                    let only_parent_id =
-                        root_commit.get_only_parent().map(|parent| parent.get_oid());
-                    if only_parent_id == Some(main_branch_oid) {
-                        return Ok(Ok((root_commit_oid, None)));
-                    }
+/// Get the commits which are rewritten by the provided plan.
+fn get_rewritten_commit_oids(rebase_plan: &RebasePlan) -> impl Iterator<Item = &NonZeroOid> {
+    rebase_plan
+        .commands
+        .iter()
+        .flat_map(|command| match command {
+            RebaseCommand::Pick {
+                original_commit_oid: _,
+                commits_to_apply_oids,
+            } => commits_to_apply_oids.as_slice(),
+            RebaseCommand::Merge {
+                commit_oid,
+                commits_to_merge: _,
+            }
+            | RebaseCommand::Replace {
+                commit_oid,
+                replacement_commit_oid: _,
+                parents: _,
+            } => std::slice::from_ref(commit_oid),
+            RebaseCommand::CreateLabel { .. }
+            | RebaseCommand::Reset { .. }
+            | RebaseCommand::Break
+            | RebaseCommand::RegisterExtraPostRewriteHook
+            | RebaseCommand::DetectEmptyCommit { .. }
+            | RebaseCommand::SkipUpstreamAppliedCommit { .. } => &[],
+        })
+}
 
-                    builder.move_subtree(root_commit.get_oid(), vec![main_branch_oid])?;
-                    let rebase_plan = builder.build(effects, thread_pool, repo_pool)?;
-                    Ok(rebase_plan.map(|rebase_plan| (root_commit_oid, rebase_plan)))
-                },
-            )
-            .collect::<eyre::Result<Vec<_>>>()?
-            .into_iter()
-            .collect::<Result<Vec<_>, BuildRebasePlanError>>();
-        Ok(result)
-    })?;
+/// Count the commits rewritten by the provided plan which are signed, since
+/// their signatures are dropped unless the rewritten commits are re-signed.
+fn count_signed_commits(repo: &Repo, rebase_plan: &RebasePlan) -> eyre::Result<usize> {
+    let mut num_signed_commits = 0;
+    for commit_oid in get_rewritten_commit_oids(rebase_plan) {
+        if repo.find_commit_or_fail(*commit_oid)?.is_signed() {
+            num_signed_commits += 1;
+        }
+    }
+    Ok(num_signed_commits)
+}
 
-    let root_commit_and_plans = match root_commit_and_plans {
-        Ok(root_commit_and_plans) => root_commit_and_plans,
-        Err(err) => {
-            err.describe(effects, repo, &dag)?;
-            return Ok(Err(ExitCode(1)));
+/// Determine whether any of the commits rewritten by the provided plan change
+/// submodules. Such plans are executed on-disk, so that Git can handle the
+/// submodules.
+fn plan_touches_submodules(repo: &Repo, rebase_plan: &RebasePlan) -> eyre::Result<bool> {
+    for commit_oid in get_rewritten_commit_oids(rebase_plan) {
+        if repo.commit_touches_submodules(&repo.find_commit_or_fail(*commit_oid)?)? {
+            return Ok(true);
         }
+    }
+    Ok(false)
+}
+
+/// The result of the sync, as written to the file provided with
+/// `--output-file`.
+#[derive(Serialize)]
+struct SyncOutcomeJson {
+    is_fully_synced: bool,
+    synced_root_oids: Vec<String>,
+    conflicted_root_oids: Vec<String>,
+    num_commits_rewritten: usize,
+}
+
+/// Write `contents` to the file at `path`, replacing it if it exists. The
+/// contents are written to a temporary file in the same directory first and
+/// then renamed into place, so that a crash while writing doesn't leave a
+/// truncated file behind.
+fn write_output_file(path: &Path, contents: &str) -> eyre::Result<()> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+    std::fs::write(&temp_path, contents)
+        .wrap_err_with(|| format!("Writing output file at {temp_path:?}"))?;
+    std::fs::rename(&temp_path, path).wrap_err_with(|| format!("Moving output file to {path:?}"))
+}
+
+/// The part of the commit graph relevant to a sync, as written to the file
+/// provided with `--dump-dag`: the stacks being synced, the main branch
+/// commit, the commit of the main branch's upstream branch (if known), and
+/// their common ancestors. Commits between these are omitted, so each commit
+/// lists its nearest ancestors in the dump rather than its actual parents.
+///
+/// To reproduce a sync in a test, deserialize the file into this type, create
+/// a commit for each entry of [`DagDump::commits`] in order (its parents are
+/// always created before it) on top of the commits created for its
+/// [`DagDumpCommit::parent_oids`], point the main branch at the commit
+/// created for [`DagDump::main_branch_oid`], and then sync.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DagDump {
+    /// The OID of the main branch commit.
+    pub main_branch_oid: String,
+
+    /// The OID of the main branch's upstream branch commit, if any.
+    pub upstream_oid: Option<String>,
+
+    /// The OIDs of the root commits of the stacks being synced.
+    pub root_oids: Vec<String>,
+
+    /// The commits in the dump, with ancestors before their descendants.
+    pub commits: Vec<DagDumpCommit>,
+}
+
+/// A commit in a [`DagDump`].
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DagDumpCommit {
+    /// The OID of the commit.
+    pub oid: String,
+
+    /// The OIDs of the nearest ancestors of the commit which are also in the
+    /// dump, in sorted order.
+    pub parent_oids: Vec<String>,
+
+    /// The first line of the commit message.
+    pub summary: String,
+
+    /// Whether the commit is an ancestor of the main branch.
+    pub is_public: bool,
+}
+
+/// Collect the part of the commit graph relevant to syncing the stacks with
+/// the provided roots (`--dump-dag`).
+fn make_dag_dump(
+    repo: &Repo,
+    dag: &Dag,
+    main_branch_oid: NonZeroOid,
+    root_commit_oids: &[NonZeroOid],
+) -> eyre::Result<DagDump> {
+    // The upstream branch may not be in the DAG if it hasn't been pulled.
+    let upstream_oid = match repo.get_main_branch()?.get_upstream_branch()? {
+        Some(upstream_branch) => match upstream_branch.get_oid()? {
+            Some(upstream_oid) if dag.set_contains(&dag.query_all()?, upstream_oid)? => {
+                Some(upstream_oid)
+            }
+            Some(_) | None => None,
+        },
+        None => None,
     };
-    execute_plans(
-        effects,
-        git_run_info,
-        repo,
-        event_log_db,
-        execute_options,
-        root_commit_and_plans,
-    )
+
+    let root_commits: CommitSet = root_commit_oids.iter().copied().collect();
+    let base_commits = dag
+        .query_parents(root_commits.clone())?
+        .union(&CommitSet::from(main_branch_oid))
+        .union(&upstream_oid.into_iter().collect());
+    let dump_commits = dag
+        .query_descendants(root_commits)?
+        .union(&dag.query_gca_all(base_commits.clone())?)
+        .union(&base_commits);
+
+    let mut commits = Vec::new();
+    for commit_oid in dag.sort(&dump_commits)? {
+        let commit = repo.find_commit_or_fail(commit_oid)?;
+        let ancestors = dag.query_ancestors(dag.query_parents(CommitSet::from(commit_oid))?)?;
+        let parent_oids =
+            dag.commit_set_to_vec(&dag.query_heads(ancestors.intersection(&dump_commits))?)?;
+        commits.push(DagDumpCommit {
+            oid: commit_oid.to_string(),
+            parent_oids: parent_oids
+                .iter()
+                .map(NonZeroOid::to_string)
+                .sorted()
+                .collect(),
+            summary: commit.get_summary()?.to_string(),
+            is_public: dag.is_public_commit(commit_oid)?,
+        });
+    }
+
+    Ok(DagDump {
+        main_branch_oid: main_branch_oid.to_string(),
+        upstream_oid: upstream_oid.map(|oid| oid.to_string()),
+        root_oids: root_commit_oids.iter().map(NonZeroOid::to_string).collect(),
+        commits,
+    })
+}
+
+/// A line of `--json-lines` output, describing the result of syncing a
+/// single stack.
+#[derive(Serialize)]
+struct SyncJsonLine {
+    root_oid: String,
+    status: SyncStatus,
+    summary: String,
+}
+
+/// Print the result of syncing the stack rooted at the provided commit as a
+/// single line of JSON.
+fn write_json_line(
+    effects: &Effects,
+    repo: &Repo,
+    root_commit_oid: NonZeroOid,
+    status: SyncStatus,
+) -> eyre::Result<()> {
+    let root_commit = repo.find_commit_or_fail(root_commit_oid)?;
+    let json_line = SyncJsonLine {
+        root_oid: root_commit_oid.to_string(),
+        status,
+        summary: String::from_utf8_lossy(&root_commit.get_summary()?).into_owned(),
+    };
+    // Write the entire line at once, since the output stream is flushed after
+    // each write.
+    let line = format!("{}\n", serde_json::to_string(&json_line)?);
+    effects.get_output_stream().write_str(&line)?;
+    Ok(())
 }
 
+/// Execute the provided rebase plans.
+///
+/// If `rewrite_options.rebase_merges` is set, then plans which recreate merge
+/// commits are executed on-disk, since merge commits can't be rebased
+/// in-memory.
+#[instrument(skip_all)]
 fn execute_plans(
     effects: &Effects,
     git_run_info: &GitRunInfo,
     repo: &Repo,
     event_log_db: &EventLogDb,
     execute_options: &ExecuteRebasePlanOptions,
+    rewrite_options: &SyncRewriteOptions,
+    output_options: &SyncOutputOptions,
+    interrupt_guard: Option<&InterruptGuard>,
+    autostash: &Autostash,
+    mut on_stack_result: Option<&mut dyn FnMut(NonZeroOid, SyncStatus)>,
+    mut sync_state: Option<&mut SyncState>,
+    stack_branches: Option<&HashMap<NonZeroOid, Vec<(ReferenceName, NonZeroOid)>>>,
+    progress: &mut dyn SyncProgress,
     root_commit_and_plans: Vec<(NonZeroOid, Option<RebasePlan>)>,
-) -> EyreExitOr<()> {
-    let (success_commits, failed_merge_commits, skipped_commits) = {
-        let mut success_commits: Vec<Commit> = Vec::new();
+) -> EyreExitOr<ExecutePlansResult> {
+    // Report the result of each stack as it finishes. A panic in the callback
+    // is reported rather than aborting the sync partway through.
+    let mut report_stack_result = |effects: &Effects,
+                                   root_commit_oid: NonZeroOid,
+                                   status: SyncStatus|
+     -> eyre::Result<()> {
+        if output_options.json_lines {
+            write_json_line(effects, repo, root_commit_oid, status)?;
+        }
+        // Interrupted stacks are left in the saved state to be resumed later.
+        if status != SyncStatus::Interrupted {
+            if let Some(sync_state) = sync_state.as_deref_mut() {
+                sync_state.finish_stack(root_commit_oid);
+                write_sync_state(repo, Some(sync_state))?;
+            }
+        }
+
+        let on_stack_result_fn = match on_stack_result.as_mut() {
+            Some(on_stack_result_fn) => on_stack_result_fn,
+            None => return Ok(()),
+        };
+        let result = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            on_stack_result_fn(root_commit_oid, status)
+        }));
+        if let Err(panic) = result {
+            let message = panic
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            writeln!(
+                    effects.get_warning_stream(),
+                    "Warning: the stack result callback panicked, so it won't be called again: {message}"
+                )?;
+            on_stack_result = None;
+        }
+        Ok(())
+    };
+
+    // The stacks whose recorded merge conflicts are replaced by the result of
+    // this sync. Stacks which end up not being attempted are removed below, so
+    // that their recorded conflicts are kept.
+    let mut attempted_root_commit_oids: HashSet<NonZeroOid> = root_commit_and_plans
+        .iter()
+        .map(|(root_commit_oid, _rebase_plan)| *root_commit_oid)
+        .collect();
+
+    let mut sync_statistics = SyncStatistics::default();
+    let (
+        success_commits,
+        failed_merge_commits,
+        failed_verify_commits,
+        skipped_commits,
+        not_attempted_commits,
+        deleted_branch_names,
+        interrupted_root_commit_oids,
+        num_dropped_signatures,
+        has_synced_submodule_changes,
+        failed_hook_commits,
+    ) = {
+        let keep_going_on_merge_conflict = should_keep_going(rewrite_options, true);
+        let mut success_commits: Vec<(Commit, NonZeroOid, Duration)> = Vec::new();
         let mut failed_merge_commits: Vec<(Commit, FailedMergeInfo)> = Vec::new();
+        let mut failed_verify_commits: Vec<(Commit, NonZeroOid, ExitCode)> = Vec::new();
         let mut skipped_commits: Vec<Commit> = Vec::new();
+        let mut not_attempted_commits: Vec<Commit> = Vec::new();
+        let mut deleted_branch_names: Vec<ReferenceName> = Vec::new();
+        let mut interrupted_root_commit_oids: Vec<NonZeroOid> = Vec::new();
+        let mut num_dropped_signatures = 0;
+        let mut has_synced_submodule_changes = false;
+        let mut failed_hook_commits: Vec<(NonZeroOid, ExitCode)> = Vec::new();
 
-        let (effects, progress) = effects.start_operation(OperationType::SyncCommits);
-        for (root_commit_oid, rebase_plan) in
-            root_commit_and_plans.into_iter().with_progress(progress)
-        {
+        // With `--batch-size`, each batch of stacks is recorded under its own
+        // event transaction, so that `git undo` can undo one batch at a time.
+        let mut batch_event_tx_id = execute_options.event_tx_id;
+        let mut num_stacks_in_batch = 0;
+
+        let mut operation = progress.start(effects, root_commit_and_plans.len())?;
+        let effects = operation.effects().clone();
+        for (root_commit_oid, rebase_plan) in root_commit_and_plans {
             let root_commit = repo.find_commit_or_fail(root_commit_oid)?;
+            if interrupt_guard.map_or(false, InterruptGuard::is_interrupted) {
+                interrupted_root_commit_oids.push(root_commit_oid);
+                report_stack_result(&effects, root_commit_oid, SyncStatus::Interrupted)?;
+                operation.on_stack_finished(&root_commit, SyncStatus::Interrupted)?;
+                continue;
+            }
+            if !keep_going_on_merge_conflict && !failed_merge_commits.is_empty() {
+                operation.on_stack_finished(&root_commit, SyncStatus::Skipped)?;
+                not_attempted_commits.push(root_commit);
+                report_stack_result(&effects, root_commit_oid, SyncStatus::Skipped)?;
+                continue;
+            }
+
             let rebase_plan = match rebase_plan {
                 Some(rebase_plan) => rebase_plan,
                 None => {
+                    operation.on_stack_finished(&root_commit, SyncStatus::Skipped)?;
                     skipped_commits.push(root_commit);
+                    report_stack_result(&effects, root_commit_oid, SyncStatus::Skipped)?;
                     continue;
                 }
             };
 
+            // Another process may have deleted one of the stack's branches
+            // since the plan was built, in which case executing the plan would
+            // recreate it.
+            let deleted_branch_name = match stack_branches
+                .and_then(|stack_branches| stack_branches.get(&root_commit_oid))
+            {
+                Some(branches) => find_deleted_branch(repo, branches)?,
+                None => None,
+            };
+            if let Some(deleted_branch_name) = deleted_branch_name {
+                operation.on_stack_finished(&root_commit, SyncStatus::Skipped)?;
+                deleted_branch_names.push(deleted_branch_name);
+                report_stack_result(&effects, root_commit_oid, SyncStatus::Skipped)?;
+                continue;
+            }
+
+            let has_merge_commits = rebase_plan
+                .commands
+                .iter()
+                .any(|command| matches!(command, RebaseCommand::Merge { .. }));
+            let touches_submodules = plan_touches_submodules(repo, &rebase_plan)?;
+            if let Some(batch_size) = rewrite_options.batch_size {
+                if num_stacks_in_batch == batch_size.get() {
+                    batch_event_tx_id = event_log_db.make_sync_batch_transaction_id(
+                        SystemTime::now(),
+                        execute_options.event_tx_id,
+                    )?;
+                    num_stacks_in_batch = 0;
+                }
+                num_stacks_in_batch += 1;
+            }
+            let execute_options = ExecuteRebasePlanOptions {
+                event_tx_id: batch_event_tx_id,
+                force_on_disk: execute_options.force_on_disk
+                    || (rewrite_options.rebase_merges && has_merge_commits)
+                    || touches_submodules,
+                ..execute_options.clone()
+            };
+            if may_modify_working_copy(repo, &execute_options, &rebase_plan)? {
+                try_exit_code!(autostash.stash(&effects, git_run_info, repo)?);
+            }
+            let start_instant = Instant::now();
             let result = execute_rebase_plan(
                 &effects,
                 git_run_info,
                 repo,
                 event_log_db,
                 &rebase_plan,
-                execute_options,
+                &execute_options,
             )?;
+            let elapsed = start_instant.elapsed();
+            sync_statistics.record(&rebase_plan, &result);
             match result {
-                ExecuteRebasePlanResult::Succeeded { rewritten_oids: _ } => {
-                    success_commits.push(root_commit);
+                ExecuteRebasePlanResult::Succeeded {
+                    rewritten_oids: _,
+                    post_rewrite_hook_exit_code,
+                } => {
+                    if let Some(exit_code) = post_rewrite_hook_exit_code {
+                        if !exit_code.is_success() {
+                            failed_hook_commits.push((root_commit_oid, exit_code));
+                        }
+                    }
+                    if execute_options.sign_option == SignOption::Disable {
+                        num_dropped_signatures += count_signed_commits(repo, &rebase_plan)?;
+                    }
+                    has_synced_submodule_changes |= touches_submodules;
+                    let verify_failure = match &rewrite_options.verify_after {
+                        Some(command) => verify_synced_stack(
+                            &effects,
+                            git_run_info,
+                            repo,
+                            event_log_db,
+                            execute_options.event_tx_id,
+                            &rebase_plan,
+                            command,
+                        )?,
+                        None => None,
+                    };
+                    match verify_failure {
+                        None => {
+                            operation.on_stack_finished(&root_commit, SyncStatus::Synced)?;
+                            success_commits.push((
+                                root_commit,
+                                rebase_plan.first_dest_oid,
+                                elapsed,
+                            ));
+                            report_stack_result(&effects, root_commit_oid, SyncStatus::Synced)?;
+                        }
+                        Some((failed_commit_oid, exit_code)) => {
+                            revert_synced_stack(
+                                &effects,
+                                git_run_info,
+                                repo,
+                                event_log_db,
+                                execute_options.event_tx_id,
+                                &rebase_plan,
+                            )?;
+                            operation.on_stack_finished(&root_commit, SyncStatus::VerifyFailed)?;
+                            failed_verify_commits.push((root_commit, failed_commit_oid, exit_code));
+                            report_stack_result(
+                                &effects,
+                                root_commit_oid,
+                                SyncStatus::VerifyFailed,
+                            )?;
+                        }
+                    }
                 }
                 ExecuteRebasePlanResult::DeclinedToMerge { failed_merge_info } => {
+                    operation.on_stack_finished(&root_commit, SyncStatus::Conflicted)?;
                     failed_merge_commits.push((root_commit, failed_merge_info));
+                    report_stack_result(&effects, root_commit_oid, SyncStatus::Conflicted)?;
                 }
                 ExecuteRebasePlanResult::Failed { exit_code } => {
                     return Ok(Err(exit_code));
@@ -436,26 +5677,117 @@ fn execute_plans(
             }
         }
 
-        (success_commits, failed_merge_commits, skipped_commits)
+        (
+            success_commits,
+            failed_merge_commits,
+            failed_verify_commits,
+            skipped_commits,
+            not_attempted_commits,
+            deleted_branch_names,
+            interrupted_root_commit_oids,
+            num_dropped_signatures,
+            has_synced_submodule_changes,
+            failed_hook_commits,
+        )
     };
+    if rewrite_options.recurse_submodules && has_synced_submodule_changes {
+        try_exit_code!(git_run_info.run(
+            effects,
+            Some(execute_options.event_tx_id),
+            &["submodule", "update", "--recursive"],
+        )?);
+    }
 
-    for success_commit in success_commits {
+    let synced_root_commit_oids = success_commits
+        .iter()
+        .map(|(success_commit, _dest_oid, _elapsed)| success_commit.get_oid())
+        .collect();
+    let render_synced = |success_commit: &Commit, elapsed: Duration| -> eyre::Result<String> {
+        let mut message = StyledStringBuilder::new()
+            .append_plain("Synced ")
+            .append(success_commit.friendly_describe(effects.get_glyphs())?);
+        if output_options.timing {
+            message = message.append_plain(format!(" ({:.1}s)", elapsed.as_secs_f64()));
+        }
+        effects.get_glyphs().render(message.build())
+    };
+    if output_options.group_by_base {
+        // Group the stacks by the commit they were moved onto, in the order in
+        // which each base was first moved onto.
+        let mut groups: Vec<(NonZeroOid, Vec<(Commit, Duration)>)> = Vec::new();
+        for (success_commit, dest_oid, elapsed) in success_commits {
+            match groups
+                .iter_mut()
+                .find(|(base_oid, _)| *base_oid == dest_oid)
+            {
+                Some((_, group)) => group.push((success_commit, elapsed)),
+                None => groups.push((dest_oid, vec![(success_commit, elapsed)])),
+            }
+        }
+        for (base_oid, group) in groups {
+            let base_commit = repo.find_commit_or_fail(base_oid)?;
+            writeln!(
+                effects.get_output_stream(),
+                "Onto {}:",
+                effects
+                    .get_glyphs()
+                    .render(base_commit.friendly_describe(effects.get_glyphs())?)?
+            )?;
+            for (success_commit, elapsed) in group {
+                writeln!(
+                    effects.get_output_stream(),
+                    "  {}",
+                    render_synced(&success_commit, elapsed)?
+                )?;
+            }
+        }
+    } else {
+        for (success_commit, _dest_oid, elapsed) in success_commits {
+            writeln!(
+                effects.get_output_stream(),
+                "{}",
+                render_synced(&success_commit, elapsed)?
+            )?;
+        }
+    }
+    if num_dropped_signatures > 0 {
         writeln!(
-            effects.get_output_stream(),
-            "{}",
-            effects.get_glyphs().render(
-                StyledStringBuilder::new()
-                    .append_plain("Synced ")
-                    .append(success_commit.friendly_describe(effects.get_glyphs())?)
-                    .build()
-            )?
+            effects.get_warning_stream(),
+            "Warning: signatures were dropped for {}. Pass --gpg-sign or set commit.gpgSign to re-sign rewritten commits.",
+            Pluralize {
+                determiner: None,
+                amount: num_dropped_signatures,
+                unit: ("commit", "commits"),
+            }
         )?;
     }
 
+    let is_fully_synced = failed_merge_commits.is_empty()
+        && failed_verify_commits.is_empty()
+        && deleted_branch_names.is_empty();
+    let conflicted_root_commit_oids: Vec<NonZeroOid> = failed_merge_commits
+        .iter()
+        .map(|(failed_merge_commit, _failed_merge_info)| failed_merge_commit.get_oid())
+        .collect();
+
+    // Remember which stacks had merge conflicts, so that they can be synced
+    // again with `--only-conflicting`.
+    for root_commit_oid in interrupted_root_commit_oids.iter().copied().chain(
+        not_attempted_commits
+            .iter()
+            .map(|not_attempted_commit| not_attempted_commit.get_oid()),
+    ) {
+        attempted_root_commit_oids.remove(&root_commit_oid);
+    }
+    let mut sync_conflicts = read_sync_conflicts(repo)?;
+    sync_conflicts.retain(|root_commit_oid| !attempted_root_commit_oids.contains(root_commit_oid));
+    sync_conflicts.extend(conflicted_root_commit_oids.iter().copied());
+    write_sync_conflicts(repo, &sync_conflicts)?;
     for (failed_merge_commit, failed_merge_info) in failed_merge_commits {
         match failed_merge_info {
             FailedMergeInfo::Conflict {
-                commit_oid: _,
+                commit_oid,
+                onto_oid,
                 conflicting_paths,
             } => {
                 writeln!(
@@ -472,6 +5804,16 @@ fn execute_plans(
                             .build()
                     )?
                 )?;
+                if output_options.show_conflicted_diff {
+                    print_conflicted_diff(
+                        effects,
+                        git_run_info,
+                        repo,
+                        commit_oid,
+                        onto_oid,
+                        &conflicting_paths,
+                    )?;
+                }
             }
             FailedMergeInfo::CannotRebaseMergeInMemory { commit_oid: _ } => {
                 writeln!(
@@ -487,15 +5829,181 @@ fn execute_plans(
         }
     }
 
-    for skipped_commit in skipped_commits {
+    for (failed_verify_commit, failed_commit_oid, exit_code) in failed_verify_commits {
+        let failed_commit = repo.find_commit_or_fail(failed_commit_oid)?;
         writeln!(
             effects.get_output_stream(),
+            "Verification failed (exit code {}) for {} at {}, so it was left unsynced",
+            exit_code.0,
+            effects
+                .get_glyphs()
+                .render(failed_verify_commit.friendly_describe(effects.get_glyphs())?)?,
+            effects
+                .get_glyphs()
+                .render(failed_commit.friendly_describe(effects.get_glyphs())?)?,
+        )?;
+    }
+
+    for skipped_commit in skipped_commits {
+        writeln!(
+            effects.get_warning_stream(),
             "Not moving up-to-date stack at {}",
             effects
                 .get_glyphs()
                 .render(skipped_commit.friendly_describe(effects.get_glyphs())?)?
         )?;
     }
+    for deleted_branch_name in deleted_branch_names {
+        writeln!(
+            effects.get_warning_stream(),
+            "Branch {} was deleted during sync; skipping.",
+            CategorizedReferenceName::new(&deleted_branch_name).render_suffix()
+        )?;
+    }
+    for not_attempted_commit in not_attempted_commits {
+        writeln!(
+            effects.get_warning_stream(),
+            "Not attempting stack after merge conflict (--fail-fast): {}",
+            effects
+                .get_glyphs()
+                .render(not_attempted_commit.friendly_describe(effects.get_glyphs())?)?
+        )?;
+    }
 
-    Ok(Ok(()))
+    // Report the hook failures together, since the hook's own output is
+    // interleaved with the output of the rest of the sync.
+    if !failed_hook_commits.is_empty() {
+        let mut stream = if rewrite_options.strict_hooks {
+            effects.get_error_stream()
+        } else {
+            effects.get_warning_stream()
+        };
+        writeln!(
+            stream,
+            "Hooks failed for {}:",
+            Pluralize {
+                determiner: None,
+                amount: failed_hook_commits.len(),
+                unit: ("stack", "stacks"),
+            }
+        )?;
+        for (root_commit_oid, exit_code) in failed_hook_commits.iter() {
+            let root_commit = repo.find_commit_or_fail(*root_commit_oid)?;
+            writeln!(
+                stream,
+                "  post-rewrite hook exited with code {} for {}",
+                exit_code.0,
+                effects
+                    .get_glyphs()
+                    .render(root_commit.friendly_describe(effects.get_glyphs())?)?
+            )?;
+        }
+    }
+
+    if output_options.statistics {
+        sync_statistics.write(effects)?;
+    }
+
+    Ok(Ok(ExecutePlansResult {
+        is_fully_synced,
+        synced_root_commit_oids,
+        conflicted_root_commit_oids,
+        interrupted_root_commit_oids,
+        num_commits_rewritten: sync_statistics.num_commits_rewritten,
+        hook_failed_root_commit_oids: failed_hook_commits
+            .into_iter()
+            .map(|(root_commit_oid, _exit_code)| root_commit_oid)
+            .collect(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use lib::core::formatting::Glyphs;
+
+    use super::*;
+
+    #[test]
+    fn test_make_thread_pool_falls_back_to_one_thread() -> eyre::Result<()> {
+        let stdout = Arc::new(Mutex::new(Vec::new()));
+        let stderr = Arc::new(Mutex::new(Vec::new()));
+        let effects = Effects::new_from_buffer_for_test(Glyphs::text(), &stdout, &stderr);
+
+        let thread_pool = make_thread_pool_with(&effects, |num_threads| match num_threads {
+            None => Err(std::io::Error::new(
+                std::io::ErrorKind::WouldBlock,
+                "Resource temporarily unavailable",
+            )),
+            Some(num_threads) => Ok(ThreadPoolBuilder::new()
+                .num_threads(num_threads)
+                .build()
+                .unwrap()),
+        })?;
+        assert_eq!(thread_pool.current_num_threads(), 1);
+        insta::assert_snapshot!(String::from_utf8(stderr.lock().unwrap().clone())?, @"Warning: could not start the default number of threads (Resource temporarily unavailable), so only one thread will be used. This is usually caused by a limit on the number of threads (see `ulimit -u`). Set RAYON_NUM_THREADS to a lower number to silence this warning.");
+
+        let result = make_thread_pool_with(&effects, |_num_threads| {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::WouldBlock,
+                "Resource temporarily unavailable",
+            ))
+        });
+        insta::assert_snapshot!(format!("{:#}", result.unwrap_err()), @"Starting a thread to build the rebase plans (the number of threads may be limited, see `ulimit -u`): Resource temporarily unavailable");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_confirm_resume_sync() -> eyre::Result<()> {
+        let stdout = Arc::new(Mutex::new(Vec::new()));
+        let stderr = Arc::new(Mutex::new(Vec::new()));
+        let effects = Effects::new_from_buffer_for_test(Glyphs::text(), &stdout, &stderr);
+
+        let answer = confirm_resume_sync(&effects, 2, "maybe\nn\n".as_bytes())?;
+        assert_eq!(answer, Some(false));
+        insta::assert_snapshot!(String::from_utf8(stdout.lock().unwrap().clone())?, @r###"
+        A previous sync was interrupted with 2 stacks remaining. Resume it (y) or discard it (n)? [y/n] Please answer y or n.
+        A previous sync was interrupted with 2 stacks remaining. Resume it (y) or discard it (n)? [y/n]
+        "###);
+
+        assert_eq!(
+            confirm_resume_sync(&effects, 2, "Y\n".as_bytes())?,
+            Some(true)
+        );
+        assert_eq!(confirm_resume_sync(&effects, 2, "".as_bytes())?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_confirm_public_commit_rewrite() -> eyre::Result<()> {
+        let stdout = Arc::new(Mutex::new(Vec::new()));
+        let stderr = Arc::new(Mutex::new(Vec::new()));
+        let effects = Effects::new_from_buffer_for_test(Glyphs::text(), &stdout, &stderr);
+
+        assert!(confirm_public_commit_rewrite(
+            &effects,
+            "62fc20d create test1.txt",
+            2,
+            "y\n".as_bytes()
+        )?);
+        insta::assert_snapshot!(String::from_utf8(stdout.lock().unwrap().clone())?, @"The stack at 62fc20d create test1.txt will rewrite 2 public commits; proceed? [y/N] ");
+
+        assert!(!confirm_public_commit_rewrite(
+            &effects,
+            "62fc20d create test1.txt",
+            1,
+            "n\n".as_bytes()
+        )?);
+        assert!(!confirm_public_commit_rewrite(
+            &effects,
+            "62fc20d create test1.txt",
+            1,
+            "".as_bytes()
+        )?);
+
+        Ok(())
+    }
 }
@@ -385,3 +385,46 @@ fn test_git_am_recorded() -> eyre::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_hook_command_name() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test2", 2)?;
+
+    {
+        let hook_path = git
+            .repo_path
+            .join(".git")
+            .join("hooks")
+            .join("post-rewrite");
+        let mut hook_contents = std::fs::read_to_string(&hook_path)?;
+        hook_contents.push_str(
+            "echo \"${BRANCHLESS_COMMAND:-<none>}\" >>\"$(git rev-parse --git-dir)/commands.txt\"\n",
+        );
+        std::fs::write(&hook_path, hook_contents)?;
+    }
+
+    git.run(&["sync"])?;
+    git.run(&["move", "--on-disk", "-x", "draft()", "-d", "master^"])?;
+    git.run(&["commit", "--amend", "-m", "amend test2"])?;
+
+    {
+        let commands = std::fs::read_to_string(git.repo_path.join(".git").join("commands.txt"))?;
+        insta::assert_snapshot!(commands, @r###"
+        sync
+        move
+        <none>
+        "###);
+    }
+
+    Ok(())
+}
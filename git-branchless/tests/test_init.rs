@@ -556,6 +556,32 @@ fn test_init_core_hooks_path_warning() -> eyre::Result<()> {
     Ok(())
 }
 
+#[cfg(unix)]
+#[test]
+fn test_init_relative_core_hooks_path() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.run(&["config", "core.hooksPath", "my-hooks"])?;
+    git.branchless("init", &[])?;
+
+    // A relative `core.hooksPath` is resolved against the root of the working
+    // copy, which is where Git runs hooks from.
+    assert!(git.repo_path.join("my-hooks").join("post-commit").exists());
+    assert!(!git
+        .get_repo()?
+        .get_path()
+        .join("my-hooks")
+        .join("post-commit")
+        .exists());
+
+    Ok(())
+}
+
 #[cfg(unix)]
 #[test]
 
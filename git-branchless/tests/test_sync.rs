@@ -1,7 +1,19 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use git_branchless::commands::sync::{DagDump, SyncBuilder};
+use itertools::Itertools;
+use lib::core::dag::Dag;
+use lib::core::effects::Effects;
+use lib::core::eventlog::{Event, EventLogDb, EventReplayer};
+use lib::core::formatting::Glyphs;
+use lib::core::repo_ext::RepoExt;
+use lib::git::GitVersion;
 use lib::testing::{
     make_git, make_git_with_remote_repo, remove_nondeterministic_lines, GitInitOptions,
     GitRunOptions, GitWrapperWithRemoteRepo,
 };
+use regex::Regex;
 
 #[test]
 fn test_sync_basic() -> eyre::Result<()> {
@@ -103,9 +115,65 @@ fn test_sync_up_to_date() -> eyre::Result<()> {
 
     {
         let (stdout, stderr) = git.run(&["sync"])?;
-        insta::assert_snapshot!(stderr, @"");
-        insta::assert_snapshot!(stdout, @"Not moving up-to-date stack at 70deb1e create test3.txt
+        insta::assert_snapshot!(stderr, @"Not moving up-to-date stack at 70deb1e create test3.txt");
+        insta::assert_snapshot!(stdout, @"");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_repeated_is_no_op() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test2", 2)?;
+
+    {
+        let (stdout, _stderr) = git.run(&["sync"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/1] Committed as: 0770943 create test1.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Synced 62fc20d create test1.txt
+        "###);
+    }
+
+    {
+        let (stdout, stderr) = git.run(&["sync"])?;
+        insta::assert_snapshot!(stderr, @"Nothing to sync (no changes since the last sync).
 ");
+        insta::assert_snapshot!(stdout, @"");
+    }
+
+    // Passing different arguments shouldn't reuse the previous sync.
+    {
+        let (stdout, stderr) = git.run(&["sync", "draft()"])?;
+        insta::assert_snapshot!(stderr, @"Not moving up-to-date stack at 0770943 create test1.txt");
+        insta::assert_snapshot!(stdout, @"");
+    }
+
+    git.commit_file("test3", 3)?;
+    {
+        let (stdout, _stderr) = git.run(&["sync"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/1] Committed as: 27645ae create test1.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Synced 0770943 create test1.txt
+        "###);
     }
 
     Ok(())
@@ -158,7 +226,7 @@ fn test_sync_pull() -> eyre::Result<()> {
         let (stdout, _stderr) = cloned_repo.run(&["sync", "-p"])?;
         let stdout: String = remove_nondeterministic_lines(stdout);
         insta::assert_snapshot!(stdout, @r###"
-        branchless: running command: <git-executable> fetch --all
+        branchless: running command: <git-executable> fetch origin
         Fast-forwarding branch master to f81d55c create test5.txt
         Attempting rebase in-memory...
         [1/1] Committed as: 2831fb5 create test6.txt
@@ -183,9 +251,131 @@ fn test_sync_pull() -> eyre::Result<()> {
         let (stdout, _stderr) = cloned_repo.run(&["sync", "-p"])?;
         let stdout: String = remove_nondeterministic_lines(stdout);
         insta::assert_snapshot!(stdout, @r###"
-        branchless: running command: <git-executable> fetch --all
+        branchless: running command: <git-executable> fetch origin
         Not updating branch master at f81d55c create test5.txt
-        Not moving up-to-date stack at 2831fb5 create test6.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_pull_redirected_output_order() -> eyre::Result<()> {
+    let GitWrapperWithRemoteRepo {
+        temp_dir,
+        original_repo,
+        cloned_repo,
+    } = make_git_with_remote_repo()?;
+    if !original_repo.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    original_repo.init_repo()?;
+    original_repo.commit_file("test1", 1)?;
+    original_repo.commit_file("test2", 2)?;
+
+    original_repo.clone_repo_into(&cloned_repo, &["--branch", "master"])?;
+    cloned_repo.init_repo_with_options(&GitInitOptions {
+        make_initial_commit: false,
+        ..Default::default()
+    })?;
+    cloned_repo.detach_head()?;
+
+    original_repo.commit_file("test3", 3)?;
+    cloned_repo.commit_file("test4", 4)?;
+
+    // Send stdout and stderr to the same file, as when piping the output of
+    // `git sync` into a pager, so that the lines written by `git sync` itself
+    // can be checked against the output of the Git subprocesses (and their
+    // hooks), which write to the same file directly.
+    let output_path = temp_dir.path().join("sync-output.txt");
+    let output_file = std::fs::File::create(&output_path)?;
+    let status = std::process::Command::new(&cloned_repo.path_to_git)
+        .current_dir(&cloned_repo.repo_path)
+        .args(["sync", "--pull"])
+        .env_clear()
+        .envs(cloned_repo.get_base_env(0))
+        .stdin(std::process::Stdio::null())
+        .stdout(output_file.try_clone()?)
+        .stderr(output_file)
+        .status()?;
+    assert!(status.success());
+
+    let output = std::fs::read_to_string(&output_path)?;
+    let find_line = |pattern: &str| -> usize {
+        output
+            .lines()
+            .position(|line| line.contains(pattern))
+            .unwrap_or_else(|| panic!("Line containing {pattern:?} not found in:\n{output}"))
+    };
+    let lines_in_order = [
+        "branchless: running command:",
+        "Fast-forwarding branch master",
+        "[1/1] Committed as:",
+        // Written by the `post-rewrite` hook of the `git-branchless` subprocess.
+        "branchless: processing 1 rewritten commit",
+        "In-memory rebase succeeded.",
+        "Synced",
+    ];
+    let positions = lines_in_order.map(find_line);
+    assert!(
+        positions.windows(2).all(|window| window[0] < window[1]),
+        "Lines {lines_in_order:?} out of order in:\n{output}"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_pull_remote_tracking_main_branch() -> eyre::Result<()> {
+    let GitWrapperWithRemoteRepo {
+        temp_dir: _guard,
+        original_repo,
+        cloned_repo,
+    } = make_git_with_remote_repo()?;
+    if !original_repo.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    original_repo.init_repo()?;
+    original_repo.commit_file("test1", 1)?;
+    original_repo.commit_file("test2", 2)?;
+
+    original_repo.clone_repo_into(&cloned_repo, &["--branch", "master"])?;
+    cloned_repo.init_repo_with_options(&GitInitOptions {
+        make_initial_commit: false,
+        ..Default::default()
+    })?;
+    cloned_repo.detach_head()?;
+    cloned_repo.run(&["config", "branchless.core.mainBranch", "origin/master"])?;
+    cloned_repo.run(&["branch", "-D", "master"])?;
+
+    original_repo.commit_file("test3", 3)?;
+    original_repo.commit_file("test4", 4)?;
+    original_repo.commit_file("test5", 5)?;
+    cloned_repo.commit_file("test3", 3)?;
+    cloned_repo.commit_file("test4", 4)?;
+    cloned_repo.commit_file("test6", 6)?;
+
+    {
+        let (stdout, _stderr) = cloned_repo.run(&["sync", "-p"])?;
+        let stdout: String = remove_nondeterministic_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> fetch origin
+        Attempting rebase in-memory...
+        [1/1] Committed as: 2831fb5 create test6.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout 2831fb5864ee099dc3e448a38dcb3c8527149510
+        In-memory rebase succeeded.
+        Synced 6ac5566 create test6.txt
+        "###);
+    }
+
+    {
+        // No local main branch is created.
+        let (stdout, _stderr) = cloned_repo.run(&["branch", "--list"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        * (HEAD detached at 2831fb5)
         "###);
     }
 
@@ -294,10 +484,19 @@ fn test_sync_divergent_main_branch() -> eyre::Result<()> {
     }
 
     {
-        let (stdout, _stderr) = cloned_repo.run(&["sync", "-p"])?;
+        let (stdout, stderr) = cloned_repo.run(&["sync", "-p"])?;
+        let stderr = stderr
+            .lines()
+            .skip_while(|line| !line.starts_with("Warning:"))
+            .take(2)
+            .join("\n");
+        insta::assert_snapshot!(stderr, @r###"
+        Warning: branch master has diverged from its upstream branch; rebasing 1 local commit:
+          d2e18e3 create test5.txt
+        "###);
         let stdout = remove_nondeterministic_lines(stdout);
         insta::assert_snapshot!(stdout, @r###"
-        branchless: running command: <git-executable> fetch --all
+        branchless: running command: <git-executable> fetch origin
         Syncing branch master
         Attempting rebase in-memory...
         [1/1] Committed as: f81d55c create test5.txt
@@ -322,6 +521,65 @@ fn test_sync_divergent_main_branch() -> eyre::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_sync_divergent_main_branch_rewrite_options() -> eyre::Result<()> {
+    let GitWrapperWithRemoteRepo {
+        temp_dir: _guard,
+        original_repo,
+        cloned_repo,
+    } = make_git_with_remote_repo()?;
+    if !original_repo.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    original_repo.init_repo()?;
+    original_repo.commit_file("test1", 1)?;
+
+    original_repo.clone_repo_into(&cloned_repo, &["--branch", "master"])?;
+    cloned_repo.init_repo_with_options(&GitInitOptions {
+        make_initial_commit: false,
+        ..Default::default()
+    })?;
+
+    original_repo.commit_file("test2", 2)?;
+    cloned_repo.commit_file("test3", 3)?;
+    cloned_repo.run(&[
+        "commit",
+        "--amend",
+        "--no-edit",
+        "--author",
+        "Original Author <original.author@example.com>",
+    ])?;
+
+    cloned_repo.branchless(
+        "sync",
+        &[
+            "-p",
+            "--yes",
+            "--add-trailer",
+            "Rebased-onto={onto}",
+            "--reset-author",
+        ],
+    )?;
+
+    // The local main branch's own commits aren't part of any stack, so they
+    // don't get the trailers, and their author isn't reset.
+    {
+        let (stdout, _stderr) = cloned_repo.run(&["log", "-1", "--format=%B", "master"])?;
+        insta::assert_snapshot!(stdout, @"create test3.txt");
+    }
+    {
+        let (stdout, _stderr) = cloned_repo.run(&["log", "-1", "--format=%an <%ae>", "master"])?;
+        insta::assert_snapshot!(stdout, @"Original Author <original.author@example.com>");
+    }
+    {
+        let (stdout, _stderr) = cloned_repo.run(&["log", "-1", "--format=%s", "master^"])?;
+        insta::assert_snapshot!(stdout, @"create test2.txt");
+    }
+
+    Ok(())
+}
+
 #[test]
 fn test_sync_no_delete_main_branch() -> eyre::Result<()> {
     let GitWrapperWithRemoteRepo {
@@ -358,6 +616,8 @@ fn test_sync_no_delete_main_branch() -> eyre::Result<()> {
         let stdout = remove_nondeterministic_lines(stdout);
         let stderr = remove_nondeterministic_lines(stderr);
         insta::assert_snapshot!(stderr, @r###"
+        Warning: branch master has diverged from its upstream branch; rebasing 1 local commit:
+          6ffd720 updated commit message
         branchless: processing 1 update: ref HEAD
         Executing: git branchless hook-skip-upstream-applied-commit 6ffd720862b7ae71cbe30d66ed27ea8579e24b0f
         Executing: git branchless hook-register-extra-post-rewrite-hook
@@ -371,7 +631,7 @@ fn test_sync_no_delete_main_branch() -> eyre::Result<()> {
         Successfully rebased and updated detached HEAD.
         "###);
         insta::assert_snapshot!(stdout, @r###"
-        branchless: running command: <git-executable> fetch --all
+        branchless: running command: <git-executable> fetch origin
         Syncing branch master
         branchless: running command: <git-executable> diff --quiet
         Calling Git for on-disk rebase...
@@ -444,6 +704,57 @@ fn test_sync_merge_commit() -> eyre::Result<()> {
 }
 
 /// Regression test for https://github.com/arxanas/git-branchless/issues/838
+#[test]
+fn test_sync_rebase_merges() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.run(&["checkout", "-b", "foo"])?;
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.run(&["checkout", "--detach", "master"])?;
+    git.commit_file("test3", 3)?;
+    git.run(&["merge", "foo"])?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test4", 4)?;
+
+    {
+        let (stdout, _stderr) = git.branchless("sync", &["--rebase-merges"])?;
+        let stdout = remove_nondeterministic_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> diff --quiet
+        Calling Git for on-disk rebase...
+        branchless: running command: <git-executable> rebase --continue
+        Synced 62fc20d create test1.txt
+        "###);
+    }
+
+    {
+        let stdout = git.smartlog()?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        @ 8f7aef5 (> master) create test4.txt
+        |\
+        | o 6c398da create test1.txt
+        | |
+        | o d166405 (foo) create test2.txt
+        | & (merge) 4ccacbb Merge branch 'foo' into HEAD
+        |
+        o 9799dd9 create test3.txt
+        |
+        | & (merge) d166405 (foo) create test2.txt
+        |/
+        o 4ccacbb Merge branch 'foo' into HEAD
+        "###);
+    }
+
+    Ok(())
+}
+
 #[test]
 fn test_sync_checked_out_main_branch() -> eyre::Result<()> {
     let GitWrapperWithRemoteRepo {
@@ -469,9 +780,14 @@ fn test_sync_checked_out_main_branch() -> eyre::Result<()> {
         let (stdout, _stderr) = cloned_repo.branchless("sync", &["--pull"])?;
         let stdout: String = remove_nondeterministic_lines(stdout);
         insta::assert_snapshot!(stdout, @r###"
-            branchless: running command: <git-executable> fetch --all
+            branchless: running command: <git-executable> fetch origin
             Fast-forwarding branch master to 96d1c37 create test2.txt
-            branchless: running command: <git-executable> rebase 96d1c37a3d4363611c49f7e52186e189a04c531f
+            branchless: running command: <git-executable> merge --ff-only 96d1c37a3d4363611c49f7e52186e189a04c531f
+            Updating 62fc20d..96d1c37
+            Fast-forward
+             test2.txt | 1 +
+             1 file changed, 1 insertion(+)
+             create mode 100644 test2.txt
             "###);
     }
 
@@ -488,8 +804,9 @@ fn test_sync_checked_out_main_branch() -> eyre::Result<()> {
     Ok(())
 }
 
+#[cfg(unix)]
 #[test]
-fn test_sync_checked_out_main_with_dirty_working_copy() -> eyre::Result<()> {
+fn test_sync_main_branch_changed_concurrently() -> eyre::Result<()> {
     let GitWrapperWithRemoteRepo {
         temp_dir: _guard,
         original_repo,
@@ -507,8 +824,21 @@ fn test_sync_checked_out_main_with_dirty_working_copy() -> eyre::Result<()> {
         make_initial_commit: false,
         ..Default::default()
     })?;
+    cloned_repo.detach_head()?;
 
-    cloned_repo.write_file_txt("test1", "new contents, do not overwrite\n")?;
+    original_repo.commit_file("test2", 2)?;
+
+    // Simulate another process which is in the middle of updating the main
+    // branch when the sync tries to fast-forward it.
+    let hook_path = cloned_repo
+        .repo_path
+        .join(".git")
+        .join("hooks")
+        .join("reference-transaction");
+    std::fs::write(
+        &hook_path,
+        "#!/bin/sh\nif [ \"$1\" = committed ]; then touch .git/refs/heads/master.lock; fi\n",
+    )?;
     {
         let (stdout, stderr) = cloned_repo.branchless_with_options(
             "sync",
@@ -519,16 +849,4586 @@ fn test_sync_checked_out_main_with_dirty_working_copy() -> eyre::Result<()> {
             },
         )?;
         let stdout: String = remove_nondeterministic_lines(stdout);
-        insta::assert_snapshot!(stderr, @r###"
-        error: cannot rebase: You have unstaged changes.
-        error: Please commit or stash them.
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> fetch origin
+        Fast-forwarding branch master to 96d1c37 create test2.txt
         "###);
+        let stderr = stderr
+            .lines()
+            .filter(|line| line.starts_with("Error:"))
+            .join("\n");
+        insta::assert_snapshot!(stderr, @"Error: the main branch changed concurrently, so branch master wasn't fast-forwarded. Run git sync again to retry.");
+    }
+
+    std::fs::remove_file(&hook_path)?;
+    std::fs::remove_file(
+        cloned_repo
+            .repo_path
+            .join(".git")
+            .join("refs")
+            .join("heads")
+            .join("master.lock"),
+    )?;
+    {
+        let (stdout, _stderr) = cloned_repo.branchless("sync", &["--pull"])?;
+        let stdout: String = remove_nondeterministic_lines(stdout);
         insta::assert_snapshot!(stdout, @r###"
-        branchless: running command: <git-executable> fetch --all
+        branchless: running command: <git-executable> fetch origin
+        Fast-forwarding branch master to 96d1c37 create test2.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_checked_out_main_with_dirty_working_copy() -> eyre::Result<()> {
+    let GitWrapperWithRemoteRepo {
+        temp_dir: _guard,
+        original_repo,
+        cloned_repo,
+    } = make_git_with_remote_repo()?;
+    if !original_repo.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    original_repo.init_repo()?;
+    original_repo.commit_file("test1", 1)?;
+
+    original_repo.clone_repo_into(&cloned_repo, &["--branch", "master"])?;
+    cloned_repo.init_repo_with_options(&GitInitOptions {
+        make_initial_commit: false,
+        ..Default::default()
+    })?;
+
+    cloned_repo.write_file_txt("test1", "new contents, do not overwrite\n")?;
+    {
+        let (stdout, _stderr) = cloned_repo.branchless("sync", &["--pull"])?;
+        let stdout: String = remove_nondeterministic_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> fetch origin
         Not updating branch master at 62fc20d create test1.txt
-        branchless: running command: <git-executable> rebase 62fc20d2a290daea0d52bdc2ed2ad4be6491010e
         "###);
     }
 
+    // Uncommitted changes to files which aren't updated are carried over.
+    original_repo.commit_file("test2", 2)?;
+    {
+        let (stdout, _stderr) = cloned_repo.branchless("sync", &["--pull"])?;
+        let stdout: String = remove_nondeterministic_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> fetch origin
+        Fast-forwarding branch master to 96d1c37 create test2.txt
+        branchless: running command: <git-executable> merge --ff-only 96d1c37a3d4363611c49f7e52186e189a04c531f
+        Updating 62fc20d..96d1c37
+        Fast-forward
+         test2.txt | 1 +
+         1 file changed, 1 insertion(+)
+         create mode 100644 test2.txt
+        "###);
+    }
+    {
+        let (stdout, _stderr) = cloned_repo.run(&["status", "--short", "--branch"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        ## master...origin/master
+         M test1.txt
+        "###);
+    }
+
+    // Uncommitted changes to files which are updated aren't overwritten.
+    original_repo.commit_file_with_contents("test1", 3, "upstream contents\n")?;
+    {
+        let (stdout, stderr) = cloned_repo.branchless_with_options(
+            "sync",
+            &["--pull"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        let stdout: String = remove_nondeterministic_lines(stdout);
+        // The fetch output includes the path of the temporary directory, so
+        // only check Git's error.
+        let stderr = stderr
+            .lines()
+            .skip_while(|line| !line.starts_with("error:"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        insta::assert_snapshot!(stderr, @r###"
+        error: Your local changes to the following files would be overwritten by merge:
+        	test1.txt
+        Please commit your changes or stash them before you merge.
+        Aborting
+        "###);
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> fetch origin
+        Fast-forwarding branch master to ba6680b create test1.txt
+        branchless: running command: <git-executable> merge --ff-only ba6680b236f7456b683531523efaf4d4c0fb2525
+        Updating 96d1c37..ba6680b
+        "###);
+    }
+    {
+        let (stdout, _stderr) = cloned_repo.run(&["status", "--short", "--branch"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        ## master...origin/master [behind 1]
+         M test1.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_touched_paths() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.detach_head()?;
+    git.commit_file("services/payments/test1", 1)?;
+    git.commit_file("test2", 2)?;
+
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test3", 3)?;
+
+    git.detach_head()?;
+    git.commit_file("services/other/test4", 4)?;
+
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test5", 5)?;
+
+    {
+        let (stdout, stderr) = git.run(&["sync", "--touched-paths", "services/payments/**"])?;
+        insta::assert_snapshot!(stderr, @r###"
+        Skipping stack (no matching paths): e792dbf create services/other/test4.txt
+        branchless: creating working copy snapshot
+        Switched to branch 'master'
+        branchless: processing checkout
+        "###);
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/2] Committed as: 85612d4 create services/payments/test1.txt
+        [2/2] Committed as: 83f0a7b create test2.txt
+        branchless: processing 2 rewritten commits
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Synced 7c34abe create services/payments/test1.txt
+        "###);
+    }
+
+    {
+        let (stdout, stderr) = git.run(&[
+            "sync",
+            "--touched-paths",
+            "services/**",
+            "--touched-paths",
+            "!services/payments/**",
+        ])?;
+        insta::assert_snapshot!(stderr, @r###"
+        Skipping stack (no matching paths): 85612d4 create services/payments/test1.txt
+        branchless: creating working copy snapshot
+        Switched to branch 'master'
+        branchless: processing checkout
+        "###);
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/1] Committed as: 7da05d1 create services/other/test4.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Synced e792dbf create services/other/test4.txt
+        "###);
+    }
+
+    {
+        let stdout = git.smartlog()?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        @ 117e086 (> master) create test5.txt
+        |\
+        | o 85612d4 create services/payments/test1.txt
+        | |
+        | o 83f0a7b create test2.txt
+        |
+        o 7da05d1 create services/other/test4.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_report_merged_stacks() -> eyre::Result<()> {
+    let git = make_git()?;
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    git.run(&["checkout", "master"])?;
+    git.detach_head()?;
+    let test2_oid = git.commit_file("test2", 2)?;
+    git.run(&["checkout", "master"])?;
+    git.run(&["cherry-pick", &test1_oid.to_string()])?;
+    git.commit_file("test3", 3)?;
+
+    {
+        let (stdout, _stderr) = git.run(&["sync", "--report-merged", &test2_oid.to_string()])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/1] Committed as: 7c69c40 create test2.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Synced fe65c1f create test2.txt
+        Found 1 stack whose commits all appear to have been merged into the main branch (consider hiding with `git hide -r`):
+          62fc20d create test1.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["sync", "--report-merged"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/1] Skipped commit (was already applied upstream): 62fc20d create test1.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Synced 62fc20d create test1.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_checkout_args() -> eyre::Result<()> {
+    let git = make_git()?;
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test2", 2)?;
+    git.run(&["checkout", &test1_oid.to_string()])?;
+
+    {
+        let (stdout, stderr) = git.run_with_options(
+            &["sync", "--checkout-arg", "-b"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stderr, @"Unsupported --checkout-arg: -b (supported arguments are: --recurse-submodules, --no-recurse-submodules, -q, --quiet, --progress, --no-progress, -m, --merge, --conflict=<style>, --ignore-other-worktrees, --overwrite-ignore, --no-overwrite-ignore)
+");
+        insta::assert_snapshot!(stdout, @"");
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&[
+            "sync",
+            "--checkout-arg",
+            "--recurse-submodules",
+            "--checkout-arg=--quiet",
+        ])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/1] Committed as: 0770943 create test1.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout 07709435a8f6d1566e0091896d130c78acd429dd --recurse-submodules --quiet
+        In-memory rebase succeeded.
+        Synced 62fc20d create test1.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_sync_branch_deleted_during_sync() -> eyre::Result<()> {
+    let git = make_git()?;
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.run(&["checkout", "-b", "foo"])?;
+    git.commit_file("test1", 1)?;
+    git.run(&["checkout", "master"])?;
+    git.run(&["checkout", "-b", "bar"])?;
+    git.commit_file("test2", 2)?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test3", 3)?;
+
+    // Delete the second stack's branch once the first stack has been
+    // rewritten.
+    let hook_path = git
+        .repo_path
+        .join(".git")
+        .join("hooks")
+        .join("post-rewrite");
+    let original_hook_contents = std::fs::read_to_string(&hook_path)?;
+    std::fs::write(
+        &hook_path,
+        format!("{original_hook_contents}git branch -D bar >/dev/null\n"),
+    )?;
+
+    {
+        let (stdout, stderr) = git.run(&["sync"])?;
+        assert!(stdout.contains("Synced 62fc20d create test1.txt"));
+        assert!(stderr.contains("Branch bar was deleted during sync; skipping."));
+        assert!(!stdout.contains("Synced fe65c1f create test2.txt"));
+    }
+    std::fs::write(&hook_path, original_hook_contents)?;
+
+    {
+        // The branch wasn't recreated by moving its stack.
+        let (stdout, _stderr) = git.run(&["branch", "--list", "bar"])?;
+        insta::assert_snapshot!(stdout, @"");
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_sync_interrupted() -> eyre::Result<()> {
+    let git = make_git()?;
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.run(&["checkout", "-b", "foo"])?;
+    git.commit_file("test1", 1)?;
+    git.run(&["checkout", "master"])?;
+    git.run(&["checkout", "-b", "bar"])?;
+    git.commit_file("test2", 2)?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test3", 3)?;
+
+    // Interrupt `git sync` (the parent of the `git rebase` process running the
+    // hook) once the first stack has been rewritten.
+    let hook_path = git
+        .repo_path
+        .join(".git")
+        .join("hooks")
+        .join("post-rewrite");
+    let original_hook_contents = std::fs::read_to_string(&hook_path)?;
+    std::fs::write(
+        &hook_path,
+        format!("{original_hook_contents}kill -INT $(ps -o ppid= -p $PPID)\n"),
+    )?;
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["sync", "--on-disk"],
+            &GitRunOptions {
+                expected_exit_code: 130,
+                ..Default::default()
+            },
+        )?;
+        let stdout = remove_nondeterministic_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> diff --quiet
+        Calling Git for on-disk rebase...
+        branchless: running command: <git-executable> rebase --continue
+        Synced 62fc20d create test1.txt
+        Sync interrupted; 1 stack synced, 1 remaining.
+        Run git sync again to resume syncing the remaining stacks.
+        "###);
+    }
+
+    {
+        let stdout = git.smartlog()?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc create initial.txt
+        |\
+        | o fe65c1f (bar) create test2.txt
+        |
+        @ 98b9119 (> master) create test3.txt
+        |
+        o 4b9ce31 (foo) create test1.txt
+        "###);
+    }
+
+    std::fs::write(&hook_path, original_hook_contents)?;
+    {
+        let (stdout, stderr) = git.run_with_options(
+            &["sync"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stderr, @"Not syncing, since a previous sync was interrupted with 1 stack remaining. Run git sync from a terminal to resume or discard it.");
+        insta::assert_snapshot!(stdout, @"");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_verify_after() -> eyre::Result<()> {
+    let git = make_git()?;
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.run(&["checkout", "-b", "foo"])?;
+    git.commit_file("test1", 1)?;
+    git.run(&["checkout", "master"])?;
+    git.run(&["checkout", "-b", "bar"])?;
+    git.commit_file("test2", 2)?;
+    git.commit_file("test3", 3)?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test4", 4)?;
+
+    {
+        let (stdout, _stderr) =
+            git.branchless("sync", &["--verify-after", "test ! -e test3.txt"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> diff --quiet
+        Attempting rebase in-memory...
+        [1/1] Committed as: 6c398da create test1.txt
+        branchless: processing 1 update: branch foo
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        branchless: running command: <git-executable> checkout 6c398daee81c22d3d6e126a36d60a54a85c33f26
+        branchless: running command: <git-executable> -c alias.branchless-sync-verify-after=!test ! -e test3.txt branchless-sync-verify-after
+        branchless: running command: <git-executable> checkout master
+        Attempting rebase in-memory...
+        [1/2] Committed as: fa82175 create test2.txt
+        [2/2] Committed as: 127dd23 create test3.txt
+        branchless: processing 1 update: branch bar
+        branchless: processing 2 rewritten commits
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        branchless: running command: <git-executable> checkout fa8217586fd39a37f444096f95c5f00027dd1fbb
+        branchless: running command: <git-executable> -c alias.branchless-sync-verify-after=!test ! -e test3.txt branchless-sync-verify-after
+        branchless: running command: <git-executable> checkout 127dd237f00dd00a00c761dce29190947fc859c7
+        branchless: running command: <git-executable> -c alias.branchless-sync-verify-after=!test ! -e test3.txt branchless-sync-verify-after
+        branchless: running command: <git-executable> checkout master
+        branchless: processing 1 update: branch bar
+        Synced 62fc20d create test1.txt
+        Verification failed (exit code 1) for fe65c1f create test2.txt at 127dd23 create test3.txt, so it was left unsynced
+        "###);
+    }
+
+    {
+        let stdout = git.smartlog()?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc create initial.txt
+        |\
+        | o fe65c1f create test2.txt
+        | |
+        | o 0206717 (bar) create test3.txt
+        |
+        @ 8f7aef5 (> master) create test4.txt
+        |
+        o 6c398da (foo) create test1.txt
+        "###);
+    }
+
+    git.write_file_txt("test4", "uncommitted")?;
+    {
+        let (stdout, stderr) = git.branchless_with_options(
+            "sync",
+            &["--verify-after", "true"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stderr, @"Can't run --verify-after with uncommitted changes. Commit or stash them first.");
+        insta::assert_snapshot!(stdout, @"branchless: running command: <git-executable> diff --quiet");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_verify_after_failure_reverts_stack() -> eyre::Result<()> {
+    let git = make_git()?;
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.commit_file("test1", 1)?;
+    git.run(&["checkout", "HEAD^", "-b", "foo"])?;
+    git.commit_file("test2", 2)?;
+    git.commit_file("test3", 3)?;
+
+    {
+        let (stdout, _stderr) = git.branchless("sync", &["--verify-after", "false"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> diff --quiet
+        Attempting rebase in-memory...
+        [1/2] Committed as: 96d1c37 create test2.txt
+        [2/2] Committed as: 70deb1e create test3.txt
+        branchless: processing 1 update: branch foo
+        branchless: processing 2 rewritten commits
+        branchless: running command: <git-executable> checkout foo
+        In-memory rebase succeeded.
+        branchless: running command: <git-executable> checkout 96d1c37a3d4363611c49f7e52186e189a04c531f
+        branchless: running command: <git-executable> -c alias.branchless-sync-verify-after=!false branchless-sync-verify-after
+        branchless: running command: <git-executable> checkout foo
+        branchless: processing 1 update: branch foo
+        branchless: running command: <git-executable> checkout foo
+        Verification failed (exit code 1) for fe65c1f create test2.txt at 96d1c37 create test2.txt, so it was left unsynced
+        "###);
+    }
+
+    {
+        let stdout = git.smartlog()?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc create initial.txt
+        |\
+        | o fe65c1f create test2.txt
+        | |
+        | @ 0206717 (> foo) create test3.txt
+        |
+        O 62fc20d (master) create test1.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["status", "--short"])?;
+        insta::assert_snapshot!(stdout, @"");
+    }
+
+    // Stacks moved with `--onto` are verified and reverted the same way.
+    {
+        let (stdout, _stderr) = git.branchless(
+            "sync",
+            &["foo", "--onto", "master", "--verify-after", "false"],
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> diff --quiet
+        Attempting rebase in-memory...
+        [1/1] Committed as: 4838e49 create test3.txt
+        branchless: processing 1 update: branch foo
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout foo
+        In-memory rebase succeeded.
+        branchless: running command: <git-executable> checkout 4838e49b08954becdd17c0900c1179c2c654c627
+        branchless: running command: <git-executable> -c alias.branchless-sync-verify-after=!false branchless-sync-verify-after
+        branchless: running command: <git-executable> checkout foo
+        branchless: processing 1 update: branch foo
+        branchless: running command: <git-executable> checkout foo
+        Verification failed (exit code 1) for 0206717 create test3.txt at 4838e49 create test3.txt, so it was left unsynced
+        "###);
+    }
+
+    {
+        let stdout = git.smartlog()?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc create initial.txt
+        |\
+        | o fe65c1f create test2.txt
+        | |
+        | @ 0206717 (> foo) create test3.txt
+        |
+        O 62fc20d (master) create test1.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_exclude() -> eyre::Result<()> {
+    let git = make_git()?;
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.run(&["checkout", "master"])?;
+    git.detach_head()?;
+    let test2_oid = git.commit_file("test2", 2)?;
+    git.commit_file("test3", 3)?;
+    git.run(&["checkout", "master"])?;
+    git.detach_head()?;
+    git.commit_file("test4", 4)?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test5", 5)?;
+
+    {
+        let (stdout, stderr) = git.run(&["sync", "--exclude", &test2_oid.to_string()])?;
+        assert!(stderr.contains("Skipping stack (excluded): fe65c1f create test2.txt"));
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/1] Committed as: 9d4646a create test1.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Attempting rebase in-memory...
+        [1/1] Committed as: a83fa4b create test4.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Synced 62fc20d create test1.txt
+        Synced 8f7aef5 create test4.txt
+        "###);
+    }
+
+    {
+        let stdout = git.smartlog()?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc create initial.txt
+        |\
+        | o fe65c1f create test2.txt
+        | |
+        | o 0206717 create test3.txt
+        |
+        @ aff9c67 (> master) create test5.txt
+        |\
+        | o 9d4646a create test1.txt
+        |
+        o a83fa4b create test4.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_reconcile_event_log() -> eyre::Result<()> {
+    let git = make_git()?;
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test2", 2)?;
+
+    // Create a branch without running the hooks, so that it's not recorded in
+    // the event log.
+    git.run(&[
+        "-c",
+        "core.hooksPath=/dev/null",
+        "branch",
+        "foo",
+        &test1_oid.to_string(),
+    ])?;
+
+    // Only printing the plan doesn't record anything in the event log.
+    {
+        let (_stdout, stderr) = git.run(&["sync", "--print-plan-only"])?;
+        assert!(!stderr.contains("changed outside of git-branchless"));
+    }
+
+    {
+        let (stdout, stderr) = git.run(&["sync"])?;
+        insta::assert_snapshot!(stderr, @r###"
+        Warning: 1 branch was changed outside of git-branchless; recorded the change in the event log.
+        branchless: creating working copy snapshot
+        Switched to branch 'master'
+        branchless: processing checkout
+        "###);
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/1] Committed as: 0770943 create test1.txt
+        branchless: processing 1 update: branch foo
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Synced 62fc20d create test1.txt
+        "###);
+    }
+
+    {
+        let (_stdout, stderr) = git.run(&["sync"])?;
+        assert!(!stderr.contains("changed outside of git-branchless"));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_onto() -> eyre::Result<()> {
+    let git = make_git()?;
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    let test2_oid = git.commit_file("test2", 2)?;
+    let test3_oid = git.commit_file("test3", 3)?;
+    let test4_oid = git.commit_file("test4", 4)?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test5", 5)?;
+
+    {
+        let (stdout, stderr) = git.run_with_options(
+            &[
+                "sync",
+                &format!("{test1_oid} + {test3_oid}"),
+                "--onto",
+                "master",
+            ],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stderr, @"The commits to move with --onto must form a contiguous range within a single stack.
+");
+        insta::assert_snapshot!(stdout, @"");
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&[
+            "sync",
+            &format!("{test2_oid}..{test4_oid}"),
+            "--onto",
+            "master",
+        ])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/2] Committed as: 3a7de25 create test3.txt
+        [2/2] Committed as: 9c9710a create test4.txt
+        branchless: processing 2 rewritten commits
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Synced 70deb1e create test3.txt
+        "###);
+    }
+
+    {
+        let stdout = git.smartlog()?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc create initial.txt
+        |\
+        | o 62fc20d create test1.txt
+        | |
+        | o 96d1c37 create test2.txt
+        |
+        @ aff9c67 (> master) create test5.txt
+        |
+        o 3a7de25 create test3.txt
+        |
+        o 9c9710a create test4.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_onto_after_sync() -> eyre::Result<()> {
+    let git = make_git()?;
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    let test2_oid = git.commit_file("test2", 2)?;
+    git.commit_file("test3", 3)?;
+    let test4_oid = git.commit_file("test4", 4)?;
+    let revset = format!("{test2_oid}..{test4_oid}");
+
+    {
+        let (stdout, _stderr) = git.run(&["sync", &revset])?;
+        insta::assert_snapshot!(stdout, @"");
+    }
+
+    // The previous sync doesn't make moving commits with `--onto` a no-op.
+    {
+        let (stdout, _stderr) = git.run(&["sync", &revset, "--onto", &test1_oid.to_string()])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/2] Committed as: 4838e49 create test3.txt
+        [2/2] Committed as: a248207 create test4.txt
+        branchless: processing 2 rewritten commits
+        branchless: running command: <git-executable> checkout a248207402822b7396cabe0f1011d8a7ce7daf1b
+        In-memory rebase succeeded.
+        Synced 70deb1e create test3.txt
+        "###);
+    }
+
+    {
+        let stdout = git.smartlog()?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc (master) create initial.txt
+        |
+        o 62fc20d create test1.txt
+        |\
+        | o 96d1c37 create test2.txt
+        |
+        o 4838e49 create test3.txt
+        |
+        @ a248207 create test4.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_onto_tag() -> eyre::Result<()> {
+    let git = make_git()?;
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    let test2_oid = git.commit_file("test2", 2)?;
+    git.commit_file("test3", 3)?;
+    let test4_oid = git.commit_file("test4", 4)?;
+    git.run(&["tag", "v1", &test1_oid.to_string()])?;
+    let revset = format!("{test2_oid}..{test4_oid}");
+
+    {
+        let (stdout, stderr) = git.run_with_options(
+            &["sync", &revset, "--onto-tag", "nonexistent"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stderr, @"Error: tag nonexistent does not exist.
+");
+        insta::assert_snapshot!(stdout, @"");
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["sync", &revset, "--onto-tag", "v1"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/2] Committed as: 4838e49 create test3.txt
+        [2/2] Committed as: a248207 create test4.txt
+        branchless: processing 2 rewritten commits
+        branchless: running command: <git-executable> checkout a248207402822b7396cabe0f1011d8a7ce7daf1b
+        In-memory rebase succeeded.
+        Synced 70deb1e create test3.txt
+        "###);
+    }
+
+    {
+        let stdout = git.smartlog()?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc (master) create initial.txt
+        |
+        o 62fc20d create test1.txt
+        |\
+        | o 96d1c37 create test2.txt
+        |
+        o 4838e49 create test3.txt
+        |
+        @ a248207 create test4.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_onto_annotated_tag() -> eyre::Result<()> {
+    let git = make_git()?;
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    let test2_oid = git.commit_file("test2", 2)?;
+    git.commit_file("test3", 3)?;
+    let test4_oid = git.commit_file("test4", 4)?;
+    git.run(&[
+        "tag",
+        "-a",
+        "-m",
+        "Release v1",
+        "v1",
+        &test1_oid.to_string(),
+    ])?;
+
+    {
+        let (stdout, _stderr) = git.run(&[
+            "sync",
+            &format!("{test2_oid}..{test4_oid}"),
+            "--onto-tag",
+            "v1",
+        ])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/2] Committed as: 4838e49 create test3.txt
+        [2/2] Committed as: a248207 create test4.txt
+        branchless: processing 2 rewritten commits
+        branchless: running command: <git-executable> checkout a248207402822b7396cabe0f1011d8a7ce7daf1b
+        In-memory rebase succeeded.
+        Synced 70deb1e create test3.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_onto_rebase_merges() -> eyre::Result<()> {
+    let git = make_git()?;
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.run(&["checkout", "-b", "foo"])?;
+    git.commit_file("test1", 1)?;
+    git.run(&["checkout", "--detach", "master"])?;
+    let test2_oid = git.commit_file("test2", 2)?;
+    git.run(&["merge", "foo"])?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test3", 3)?;
+
+    {
+        let (stdout, _stderr) = git.branchless(
+            "sync",
+            &[
+                &format!("{test2_oid}::"),
+                "--onto",
+                "master",
+                "--rebase-merges",
+            ],
+        )?;
+        let stdout = remove_nondeterministic_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> diff --quiet
+        Calling Git for on-disk rebase...
+        branchless: running command: <git-executable> rebase --continue
+        Synced fe65c1f create test2.txt
+        "###);
+    }
+
+    {
+        let stdout = git.smartlog()?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc create initial.txt
+        |\
+        | o 62fc20d (foo) create test1.txt
+        | & (merge) 58a30f8 Merge branch 'foo' into HEAD
+        |
+        @ 98b9119 (> master) create test3.txt
+        |
+        o 200e547 create test2.txt
+        |
+        | & (merge) 62fc20d (foo) create test1.txt
+        |/
+        o 58a30f8 Merge branch 'foo' into HEAD
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_onto_descendant() -> eyre::Result<()> {
+    let git = make_git()?;
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    let test2_oid = git.commit_file("test2", 2)?;
+    let test3_oid = git.commit_file("test3", 3)?;
+    git.run(&["checkout", "master"])?;
+
+    {
+        let (stdout, stderr) = git.run_with_options(
+            &[
+                "sync",
+                &test2_oid.to_string(),
+                "--onto",
+                &test3_oid.to_string(),
+            ],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stderr, @"Error: cannot rebase a stack onto its own descendant.
+");
+        insta::assert_snapshot!(stdout, @"");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_onto_include_public() -> eyre::Result<()> {
+    let git = make_git()?;
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    let test2_oid = git.commit_file("test2", 2)?;
+    git.run(&["checkout", "HEAD~2"])?;
+    let test3_oid = git.commit_file("test3", 3)?;
+    git.run(&["checkout", "master"])?;
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &[
+                "sync",
+                &test2_oid.to_string(),
+                "--onto",
+                &test3_oid.to_string(),
+            ],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        You are trying to rewrite 1 public commit, such as: 96d1c37 create test2.txt
+        It is generally not advised to rewrite public commits, because your
+        collaborators will have difficulty merging your changes.
+        Retry with -f/--force-rewrite to proceed anyways.
+        "###);
+    }
+
+    // Only the provided public commits are permitted to be rewritten.
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &[
+                "sync",
+                &format!("{test1_oid}::{test2_oid}"),
+                "--onto",
+                &test3_oid.to_string(),
+                "--include-public",
+                &test2_oid.to_string(),
+            ],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        You are trying to rewrite 1 public commit, such as: 62fc20d create test1.txt
+        It is generally not advised to rewrite public commits, because your
+        collaborators will have difficulty merging your changes.
+        Retry with -f/--force-rewrite to proceed anyways.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&[
+            "sync",
+            &test2_oid.to_string(),
+            "--onto",
+            &test3_oid.to_string(),
+            "--include-public",
+            &test2_oid.to_string(),
+        ])?;
+        assert!(stdout.contains("Synced 96d1c37 create test2.txt"));
+
+        let (stdout, _stderr) = git.run(&["rev-parse", "master^"])?;
+        assert_eq!(stdout.trim(), test3_oid.to_string());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_onto_conflicting_options() -> eyre::Result<()> {
+    let git = make_git()?;
+    git.init_repo()?;
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    let test2_oid = git.commit_file("test2", 2)?;
+
+    {
+        let (stdout, stderr) = git.run_with_options(
+            &[
+                "sync",
+                &test2_oid.to_string(),
+                "--onto",
+                "master",
+                "--exclude",
+                &test1_oid.to_string(),
+            ],
+            &GitRunOptions {
+                expected_exit_code: 2,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stderr, @r###"
+        error: the argument '--onto <REVSET>' cannot be used with '--exclude <EXCLUDE>'
+
+        Usage: git-branchless sync --onto <REVSET> <REVSETS>...
+
+        For more information, try '--help'.
+        "###);
+        insta::assert_snapshot!(stdout, @"");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_at_event() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test2", 2)?;
+
+    let event_id = {
+        let repo = git.get_repo()?;
+        let conn = repo.get_db_conn()?;
+        let event_log_db = EventLogDb::new(&conn)?;
+        event_log_db.get_latest_event_id()?.unwrap()
+    };
+
+    {
+        let (stdout, _stderr) = git.run(&["sync"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/1] Committed as: 0770943 create test1.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Synced 62fc20d create test1.txt
+        "###);
+    }
+
+    {
+        let (stdout, stderr) = git.run(&["sync", "--at-event", &event_id.to_string()])?;
+        insta::assert_snapshot!(stderr, @"Not syncing, since the plans were built as of event 8. Pass --print-plan-only to print them.");
+        insta::assert_snapshot!(stdout, @"Would sync 62fc20d create test1.txt");
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        @ fe65c1f (> master) create test2.txt
+        |
+        o 0770943 create test1.txt
+        "###);
+    }
+
+    {
+        let (stdout, stderr) = git.run_with_options(
+            &["sync", "--at-event", "9999"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stderr, @"There is no event with ID 9999 in the event log.
+");
+        insta::assert_snapshot!(stdout, @"");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_since_event() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test2", 2)?;
+
+    let event_id = {
+        let repo = git.get_repo()?;
+        let conn = repo.get_db_conn()?;
+        let event_log_db = EventLogDb::new(&conn)?;
+        event_log_db.get_latest_event_id()?.unwrap()
+    };
+
+    git.run(&["checkout", "HEAD^"])?;
+    git.commit_file("test3", 3)?;
+    git.run(&["checkout", "master"])?;
+
+    {
+        let (stdout, stderr) = git.run(&["sync", "--since-event", &event_id.to_string()])?;
+        assert!(stderr.contains("Skipping 1 stack with no changes since event 8."));
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/1] Committed as: 0206717 create test3.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Synced 98b9119 create test3.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc create initial.txt
+        |\
+        | o 62fc20d create test1.txt
+        |
+        @ fe65c1f (> master) create test2.txt
+        |
+        o 0206717 create test3.txt
+        "###);
+    }
+
+    {
+        let (stdout, stderr) = git.run_with_options(
+            &["sync", "--since-event", "9999"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stderr, @"There is no event with ID 9999 in the event log.
+");
+        insta::assert_snapshot!(stdout, @"");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_gc() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test2", 2)?;
+
+    {
+        let (stdout, stderr) = git.run(&["sync", "--gc"])?;
+        assert!(stderr.contains(
+            "Skipping garbage collection: 23 loose objects (gc.auto threshold is 6700)."
+        ));
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/1] Committed as: 0770943 create test1.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Synced 62fc20d create test1.txt
+        "###);
+    }
+
+    {
+        // The garbage collection is still attempted when there's nothing to sync.
+        let (stdout, stderr) = git.run(&["sync", "--gc"])?;
+        assert!(stderr.contains("Skipping garbage collection:"));
+        assert!(!stderr.contains("Nothing to sync"));
+        insta::assert_snapshot!(stdout, @"");
+    }
+
+    git.detach_head()?;
+    git.commit_file("test3", 3)?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test4", 4)?;
+
+    git.run(&["config", "gc.auto", "1"])?;
+    git.run(&["config", "branchless.sync.autoGc", "true"])?;
+    {
+        let (stdout, _stderr) = git.run(&["sync"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/1] Committed as: 38b42a8 create test1.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Attempting rebase in-memory...
+        [1/1] Committed as: deb4156 create test3.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Synced 0770943 create test1.txt
+        Synced 0206717 create test3.txt
+        branchless: running command: <git-executable> repack -d -q
+        Packed 21 loose objects (23 remaining).
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_print_plan_only() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test3", 3)?;
+    git.detach_head()?;
+    git.commit_file("test4", 4)?;
+    git.run(&["checkout", "master"])?;
+
+    {
+        let (stdout, _stderr) = git.run(&["sync", "--print-plan-only"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        plan 62fc20d2a290daea0d52bdc2ed2ad4be6491010e
+          onto 98b9119d16974f372e76cb64a3b77c528fc0b18b
+          reset 98b9119d16974f372e76cb64a3b77c528fc0b18b
+          pick 62fc20d2a290daea0d52bdc2ed2ad4be6491010e
+          detect-empty 62fc20d2a290daea0d52bdc2ed2ad4be6491010e
+          pick 96d1c37a3d4363611c49f7e52186e189a04c531f
+          detect-empty 96d1c37a3d4363611c49f7e52186e189a04c531f
+        plan 2b633ed788585e863511ffa56fc58b117d0ddab0
+          up-to-date
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["sync", "--print-plan-only", "--format", "graphviz"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        digraph sync {
+          rankdir=BT;
+          "2b633ed788585e863511ffa56fc58b117d0ddab0" [label="2b633ed create test4.txt"];
+          "62fc20d2a290daea0d52bdc2ed2ad4be6491010e" [label="62fc20d create test1.txt"];
+          "96d1c37a3d4363611c49f7e52186e189a04c531f" [label="96d1c37 create test2.txt"];
+          "98b9119d16974f372e76cb64a3b77c528fc0b18b" [label="98b9119 create test3.txt"];
+          "f777ecc9b0db5ed372b2615695191a8a17f79f24" [label="f777ecc create initial.txt"];
+          "62fc20d2a290daea0d52bdc2ed2ad4be6491010e" -> "f777ecc9b0db5ed372b2615695191a8a17f79f24";
+          "96d1c37a3d4363611c49f7e52186e189a04c531f" -> "62fc20d2a290daea0d52bdc2ed2ad4be6491010e";
+          "62fc20d2a290daea0d52bdc2ed2ad4be6491010e" -> "98b9119d16974f372e76cb64a3b77c528fc0b18b" [style=dashed];
+          "2b633ed788585e863511ffa56fc58b117d0ddab0" -> "98b9119d16974f372e76cb64a3b77c528fc0b18b";
+        }
+        "###);
+    }
+
+    {
+        let stdout = git.smartlog()?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc create initial.txt
+        |\
+        | o 62fc20d create test1.txt
+        | |
+        | o 96d1c37 create test2.txt
+        |
+        @ 98b9119 (> master) create test3.txt
+        |
+        o 2b633ed create test4.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_print_plan_graphviz_multiple_stacks() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+
+    git.run(&["checkout", "master"])?;
+    git.detach_head()?;
+    git.commit_file("test4", 4)?;
+
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test3", 3)?;
+
+    {
+        let (stdout, _stderr) = git.run(&["sync", "--print-plan-only", "--format", "graphviz"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        digraph sync {
+          rankdir=BT;
+          "62fc20d2a290daea0d52bdc2ed2ad4be6491010e" [label="62fc20d create test1.txt"];
+          "8f7aef57d66466a6e0737ae10f67cd98ddecdc66" [label="8f7aef5 create test4.txt"];
+          "96d1c37a3d4363611c49f7e52186e189a04c531f" [label="96d1c37 create test2.txt"];
+          "98b9119d16974f372e76cb64a3b77c528fc0b18b" [label="98b9119 create test3.txt"];
+          "f777ecc9b0db5ed372b2615695191a8a17f79f24" [label="f777ecc create initial.txt"];
+          "62fc20d2a290daea0d52bdc2ed2ad4be6491010e" -> "f777ecc9b0db5ed372b2615695191a8a17f79f24";
+          "96d1c37a3d4363611c49f7e52186e189a04c531f" -> "62fc20d2a290daea0d52bdc2ed2ad4be6491010e";
+          "62fc20d2a290daea0d52bdc2ed2ad4be6491010e" -> "98b9119d16974f372e76cb64a3b77c528fc0b18b" [style=dashed];
+          "8f7aef57d66466a6e0737ae10f67cd98ddecdc66" -> "f777ecc9b0db5ed372b2615695191a8a17f79f24";
+          "8f7aef57d66466a6e0737ae10f67cd98ddecdc66" -> "98b9119d16974f372e76cb64a3b77c528fc0b18b" [style=dashed];
+        }
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_publish() -> eyre::Result<()> {
+    let GitWrapperWithRemoteRepo {
+        temp_dir: _guard,
+        original_repo,
+        cloned_repo,
+    } = make_git_with_remote_repo()?;
+    if !original_repo.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    original_repo.init_repo()?;
+    original_repo.commit_file("test1", 1)?;
+    original_repo.clone_repo_into(&cloned_repo, &["--branch", "master"])?;
+    cloned_repo.init_repo_with_options(&GitInitOptions {
+        make_initial_commit: false,
+        ..Default::default()
+    })?;
+
+    cloned_repo.run(&["checkout", "-b", "foo"])?;
+    cloned_repo.commit_file("test2", 2)?;
+    cloned_repo.run(&["checkout", "-b", "bar"])?;
+    cloned_repo.commit_file("test3", 3)?;
+    cloned_repo.run(&["checkout", "master"])?;
+    cloned_repo.commit_file("test4", 4)?;
+
+    {
+        let (stdout, _stderr) = cloned_repo.run(&["sync", "--publish"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/2] Committed as: 44352d0 create test2.txt
+        [2/2] Committed as: cf5eb24 create test3.txt
+        branchless: processing 2 updates: branch bar, branch foo
+        branchless: processing 2 rewritten commits
+        branchless: running command: <git-executable> checkout master
+        Your branch is ahead of 'origin/master' by 1 commit.
+          (use "git push" to publish your local commits)
+        In-memory rebase succeeded.
+        Synced 96d1c37 create test2.txt
+        branchless: running command: <git-executable> push --force-with-lease=refs/heads/users/test/bar: --force-with-lease=refs/heads/users/test/foo: origin refs/heads/bar:refs/heads/users/test/bar refs/heads/foo:refs/heads/users/test/foo
+        Published 2 branches to origin.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) =
+            original_repo.run(&["for-each-ref", "--format=%(refname)", "refs/heads/users/"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        refs/heads/users/test/bar
+        refs/heads/users/test/foo
+        "###);
+    }
+
+    cloned_repo.run(&[
+        "config",
+        "branchless.sync.publishRefTemplate",
+        "refs/heads/review/{branch}",
+    ])?;
+    cloned_repo.run(&["checkout", "foo"])?;
+    cloned_repo.commit_file("test5", 5)?;
+    cloned_repo.run(&["checkout", "master"])?;
+    cloned_repo.commit_file("test6", 6)?;
+    {
+        let (stdout, _stderr) = cloned_repo.run(&["sync", "--publish"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/3] Committed as: c23b4a7 create test2.txt
+        [2/3] Committed as: 7a9c7f6 create test5.txt
+        [3/3] Committed as: 098f162 create test3.txt
+        branchless: processing 2 updates: branch bar, branch foo
+        branchless: processing 3 rewritten commits
+        branchless: running command: <git-executable> checkout master
+        Your branch is ahead of 'origin/master' by 2 commits.
+          (use "git push" to publish your local commits)
+        In-memory rebase succeeded.
+        Synced 44352d0 create test2.txt
+        branchless: running command: <git-executable> push --force-with-lease=refs/heads/review/bar: --force-with-lease=refs/heads/review/foo: origin refs/heads/bar:refs/heads/review/bar refs/heads/foo:refs/heads/review/foo
+        Published 2 branches to origin.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) =
+            original_repo.run(&["for-each-ref", "--format=%(refname)", "refs/heads/review/"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        refs/heads/review/bar
+        refs/heads/review/foo
+        "###);
+    }
+
+    // The published branches can be overwritten while they're unchanged on the
+    // remote, but not once someone else has updated them.
+    cloned_repo.run(&["checkout", "master"])?;
+    cloned_repo.commit_file("test7", 7)?;
+    {
+        let (stdout, _stderr) = cloned_repo.run(&["sync", "--publish"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/3] Committed as: d32df8d create test2.txt
+        [2/3] Committed as: cdae9de create test3.txt
+        [3/3] Committed as: 74eb1ae create test5.txt
+        branchless: processing 2 updates: branch bar, branch foo
+        branchless: processing 3 rewritten commits
+        branchless: running command: <git-executable> checkout master
+        Your branch is ahead of 'origin/master' by 3 commits.
+          (use "git push" to publish your local commits)
+        In-memory rebase succeeded.
+        Synced c23b4a7 create test2.txt
+        branchless: running command: <git-executable> push --force-with-lease=refs/heads/review/bar:098f1623d422dc39ad6e7d3324cdbd13001a2e50 --force-with-lease=refs/heads/review/foo:7a9c7f6cd1f1ab3d7b31b7c3bc7829e8ec750253 origin refs/heads/bar:refs/heads/review/bar refs/heads/foo:refs/heads/review/foo
+        Published 2 branches to origin.
+        "###);
+    }
+
+    original_repo.run(&["update-ref", "refs/heads/review/foo", "master"])?;
+    cloned_repo.commit_file("test8", 8)?;
+    {
+        let (stdout, _stderr) = cloned_repo.run_with_options(
+            &["sync", "--publish"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/3] Committed as: 5db1484 create test2.txt
+        [2/3] Committed as: 59628e0 create test5.txt
+        [3/3] Committed as: 16debc2 create test3.txt
+        branchless: processing 2 updates: branch bar, branch foo
+        branchless: processing 3 rewritten commits
+        branchless: running command: <git-executable> checkout master
+        Your branch is ahead of 'origin/master' by 4 commits.
+          (use "git push" to publish your local commits)
+        In-memory rebase succeeded.
+        Synced d32df8d create test2.txt
+        branchless: running command: <git-executable> push --force-with-lease=refs/heads/review/bar:cdae9deb2340c6c8a48a01be18481a73734f9b96 --force-with-lease=refs/heads/review/foo:74eb1ae8fa63dbcde2decfc8d1b3c22b38baebb5 origin refs/heads/bar:refs/heads/review/bar refs/heads/foo:refs/heads/review/foo
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = original_repo.run(&["log", "-1", "--format=%s", "review/foo"])?;
+        insta::assert_snapshot!(stdout, @"create test1.txt");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_on_rewrite() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test3", 3)?;
+
+    git.run(&[
+        "config",
+        "branchless.sync.onRewrite",
+        "echo {old} {new} >> .git/rewrites.txt",
+    ])?;
+    {
+        let (stdout, stderr) = git.run(&["sync"])?;
+        insta::assert_snapshot!(stderr, @r###"
+        branchless: creating working copy snapshot
+        Switched to branch 'master'
+        branchless: processing checkout
+        "###);
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/2] Committed as: 4b9ce31 create test1.txt
+        [2/2] Committed as: 9f77bc5 create test2.txt
+        branchless: processing 2 rewritten commits
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Synced 62fc20d create test1.txt
+        "###);
+    }
+
+    {
+        let rewrites = std::fs::read_to_string(git.repo_path.join(".git").join("rewrites.txt"))?;
+        let rewrites = rewrites.lines().sorted().join("\n");
+        insta::assert_snapshot!(rewrites, @r###"
+        62fc20d2a290daea0d52bdc2ed2ad4be6491010e 4b9ce31bb58aed39e2329c3993ef97b0c4680e49
+        96d1c37a3d4363611c49f7e52186e189a04c531f 9f77bc5fa2a01bc869e783c338a757c3b31e32ef
+        "###);
+    }
+
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test4", 4)?;
+    git.run(&[
+        "config",
+        "branchless.sync.onRewrite",
+        "echo could not notify about {new} >&2; exit 2",
+    ])?;
+    {
+        let (stdout, stderr) = git.run(&["sync"])?;
+        insta::assert_snapshot!(stderr, @r###"
+        branchless: creating working copy snapshot
+        Switched to branch 'master'
+        branchless: processing checkout
+        Failed to run branchless.sync.onRewrite for 2 rewritten commits:
+          Command for rewrite of 4b9ce31bb58aed39e2329c3993ef97b0c4680e49 to 3a4858a6f4e2034bee81339bfa961a4301c2eb1c failed with exit code 2: could not notify about 3a4858a6f4e2034bee81339bfa961a4301c2eb1c
+          Command for rewrite of 9f77bc5fa2a01bc869e783c338a757c3b31e32ef to 76092d5a1819c234db6b1e6199579097871ef2f1 failed with exit code 2: could not notify about 76092d5a1819c234db6b1e6199579097871ef2f1
+        "###);
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/2] Committed as: 3a4858a create test1.txt
+        [2/2] Committed as: 76092d5 create test2.txt
+        branchless: processing 2 rewritten commits
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Synced 4b9ce31 create test1.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_not_in_repo() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    {
+        let (stdout, stderr) = git.branchless_with_options(
+            "sync",
+            &[],
+            &GitRunOptions {
+                expected_exit_code: 3,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stderr, @"Error: not in a Git repository. Run `git sync` from inside a Git repository.");
+        insta::assert_snapshot!(stdout, @"");
+    }
+
+    git.init_repo_with_options(&GitInitOptions {
+        make_initial_commit: false,
+        run_branchless_init: false,
+    })?;
+    {
+        let (stdout, stderr) = git.branchless_with_options(
+            "sync",
+            &[],
+            &GitRunOptions {
+                expected_exit_code: 4,
+
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stderr, @"Error: git-branchless is not initialized in this repository. Run `git branchless init` first.");
+        insta::assert_snapshot!(stdout, @"");
+    }
+
+    // Once the main branch exists under its default name, `git sync` works
+    // without `git branchless init`, as it did before.
+    git.commit_file("test1", 1)?;
+    {
+        let (stdout, _stderr) = git.branchless("sync", &[])?;
+        insta::assert_snapshot!(stdout, @"");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_operation_in_progress() -> eyre::Result<()> {
+    let git = make_git()?;
+    git.init_repo()?;
+
+    git.detach_head()?;
+    let feature_oid = git.commit_file_with_contents("test1", 1, "feature\n")?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file_with_contents("test1", 2, "master\n")?;
+
+    let run_sync = || -> eyre::Result<String> {
+        let (stdout, stderr) = git.branchless_with_options(
+            "sync",
+            &[],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        assert_eq!(stdout, "");
+        Ok(stderr)
+    };
+    let conflict_options = GitRunOptions {
+        expected_exit_code: 1,
+        ..Default::default()
+    };
+
+    git.run_with_options(&["merge", &feature_oid.to_string()], &conflict_options)?;
+    insta::assert_snapshot!(run_sync()?, @"Finish or abort the in-progress merge before syncing.");
+    git.run(&["merge", "--abort"])?;
+
+    git.run_with_options(
+        &["cherry-pick", &feature_oid.to_string()],
+        &conflict_options,
+    )?;
+    insta::assert_snapshot!(run_sync()?, @"Finish or abort the in-progress cherry-pick before syncing.");
+    git.run(&["cherry-pick", "--abort"])?;
+
+    git.commit_file_with_contents("test1", 3, "master again\n")?;
+    git.run_with_options(&["revert", "HEAD~"], &conflict_options)?;
+    insta::assert_snapshot!(run_sync()?, @"Finish or abort the in-progress revert before syncing.");
+    git.run(&["revert", "--abort"])?;
+
+    git.run(&["checkout", &feature_oid.to_string()])?;
+    git.run_with_options(&["rebase", "master"], &conflict_options)?;
+    insta::assert_snapshot!(run_sync()?, @"Finish or abort the in-progress rebase before syncing.");
+    git.run(&["rebase", "--abort"])?;
+
+    // Once the operation is finished, syncing works again.
+    git.branchless("sync", &[])?;
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_include_public_roots() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.run(&["checkout", "-b", "develop"])?;
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.detach_head()?;
+    git.commit_file("test3", 3)?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test4", 4)?;
+    git.run(&["config", "branchless.sync.trunks", "develop"])?;
+
+    {
+        let (_stdout, stderr) = git.branchless_with_options(
+            "sync",
+            &["--base-auto", "--include-public-roots"],
+            &GitRunOptions {
+                expected_exit_code: 2,
+                ..Default::default()
+            },
+        )?;
+        let stderr = stderr.lines().take(3).join("\n");
+        insta::assert_snapshot!(stderr, @r###"
+        error: the following required arguments were not provided:
+          --force-rewrite
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) =
+            git.branchless("sync", &["--base-auto", "--include-public-roots", "-f"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Moving stack onto master: 62fc20d create test1.txt
+        Attempting rebase in-memory...
+        [1/3] Committed as: 6c398da create test1.txt
+        [2/3] Committed as: d166405 create test2.txt
+        [3/3] Committed as: e2e80c7 create test3.txt
+        branchless: processing 1 update: branch develop
+        branchless: processing 3 rewritten commits
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Synced 62fc20d create test1.txt
+        "###);
+    }
+
+    {
+        let stdout = git.smartlog()?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        @ 8f7aef5 (> master) create test4.txt
+        |
+        o 6c398da create test1.txt
+        |
+        o d166405 (develop) create test2.txt
+        |
+        o e2e80c7 create test3.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_base_auto() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.run(&["checkout", "-b", "develop"])?;
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.run(&["checkout", "develop^"])?;
+    git.commit_file("test3", 3)?;
+    git.run(&["checkout", "master"])?;
+    git.detach_head()?;
+    git.commit_file("test4", 4)?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test5", 5)?;
+    git.run(&["config", "branchless.sync.trunks", "develop nonexistent"])?;
+
+    {
+        let stdout = git.smartlog()?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc create initial.txt
+        |\
+        | o 62fc20d create test1.txt
+        | |\
+        | | o 96d1c37 (develop) create test2.txt
+        | |
+        | o 4838e49 create test3.txt
+        |\
+        | o 8f7aef5 create test4.txt
+        |
+        @ aff9c67 (> master) create test5.txt
+        "###);
+    }
+
+    {
+        let (stdout, stderr) = git.run(&["sync", "--base-auto"])?;
+        insta::assert_snapshot!(stderr, @r###"
+        Warning: trunk branch nonexistent does not exist, so stacks won't be moved onto it.
+        branchless: creating working copy snapshot
+        Switched to branch 'master'
+        branchless: processing checkout
+        branchless: creating working copy snapshot
+        Switched to branch 'master'
+        branchless: processing checkout
+        "###);
+        insta::assert_snapshot!(stdout, @r###"
+        Moving stack onto develop: 4838e49 create test3.txt
+        Moving stack onto master: 8f7aef5 create test4.txt
+        Attempting rebase in-memory...
+        [1/1] Committed as: 70deb1e create test3.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Attempting rebase in-memory...
+        [1/1] Committed as: a83fa4b create test4.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Synced 4838e49 create test3.txt
+        Synced 8f7aef5 create test4.txt
+        "###);
+    }
+
+    {
+        let stdout = git.smartlog()?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc create initial.txt
+        |\
+        | o 62fc20d create test1.txt
+        | |
+        | o 96d1c37 (develop) create test2.txt
+        | |
+        | o 70deb1e create test3.txt
+        |
+        @ aff9c67 (> master) create test5.txt
+        |
+        o a83fa4b create test4.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_group_by_base() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.run(&["checkout", "-b", "develop"])?;
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.run(&["checkout", "develop^"])?;
+    git.commit_file("test3", 3)?;
+    git.run(&["checkout", "master"])?;
+    git.detach_head()?;
+    git.commit_file("test4", 4)?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test5", 5)?;
+    git.run(&["config", "branchless.sync.trunks", "develop"])?;
+
+    {
+        let (stdout, _stderr) = git.run(&["sync", "--base-auto", "--group-by-base"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Moving stack onto develop: 4838e49 create test3.txt
+        Moving stack onto master: 8f7aef5 create test4.txt
+        Attempting rebase in-memory...
+        [1/1] Committed as: 70deb1e create test3.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Attempting rebase in-memory...
+        [1/1] Committed as: a83fa4b create test4.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Onto 96d1c37 create test2.txt:
+          Synced 4838e49 create test3.txt
+        Onto aff9c67 create test5.txt:
+          Synced 8f7aef5 create test4.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_directory_main_branch() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.run(&["checkout", "-b", "foo-trunk"])?;
+    git.commit_file("test1", 1)?;
+    git.run(&["checkout", "master"])?;
+    git.detach_head()?;
+    git.commit_file("test2", 2)?;
+    git.run(&["checkout", "master"])?;
+
+    let project_dir = git.repo_path.join("projects").join("foo");
+    std::fs::create_dir_all(project_dir.join("src"))?;
+    std::fs::write(
+        project_dir.join(".branchless"),
+        "[branchless \"sync\"]\n\tmainBranch = foo-trunk\n",
+    )?;
+
+    {
+        let (stdout, _stderr) = git.run(&["-C", "projects/foo/src", "sync"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/1] Committed as: 96d1c37 create test2.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Synced fe65c1f create test2.txt
+        "###);
+    }
+
+    {
+        let stdout = git.smartlog()?;
+        insta::assert_snapshot!(stdout, @r###"
+        @ f777ecc (> master) create initial.txt
+        |
+        o 62fc20d (foo-trunk) create test1.txt
+        |
+        o 96d1c37 create test2.txt
+        "###);
+    }
+
+    std::fs::write(
+        project_dir.join(".branchless"),
+        "[branchless \"sync\"]\n\tmainBranch = nonexistent\n",
+    )?;
+    {
+        let (stdout, stderr) = git.run_with_options(
+            &["-C", "projects/foo", "sync"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stderr, @"Error: the main branch nonexistent set by branchless.sync.mainBranch in .branchless doesn't exist.
+");
+        insta::assert_snapshot!(stdout, @"");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_preserve_committer() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.detach_head()?;
+    git.write_file_txt("test1", "test1 contents\n")?;
+    git.run(&["add", "."])?;
+    git.run(&[
+        "-c",
+        "user.name=Original Committer",
+        "-c",
+        "user.email=original.committer@example.com",
+        "commit",
+        "-m",
+        "create test1.txt",
+    ])?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test2", 2)?;
+
+    {
+        let (stdout, _stderr) = git.run(&["sync", "--preserve-committer"])?;
+        let stdout = remove_nondeterministic_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/1] Committed as: 072b522 create test1.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Synced a86db44 create test1.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["query", "--raw", "draft()"])?;
+        let (stdout, _stderr) = git.run(&[
+            "log",
+            "-1",
+            "--format=%h %s%n%cn <%ce> %cd",
+            "--date=raw",
+            stdout.trim(),
+        ])?;
+        insta::assert_snapshot!(stdout, @r###"
+        072b522 create test1.txt
+        Original Committer <original.committer@example.com> 1603974896 +0000
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_preserve_committer_merge_conflict() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.run(&["checkout", "-b", "foo"])?;
+    git.commit_file_with_contents("test1", 1, "foo contents")?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file_with_contents("test1", 2, "master contents")?;
+
+    {
+        let (stdout, _stderr) = git.run(&["sync", "--merge", "--preserve-committer"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        Aborting since preserving the committer requires an in-memory rebase.
+        Merge conflict (1 file) for c344d17 create test1.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_reset_author() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    let commit_as_original_author = |name: &str, time: isize| -> eyre::Result<()> {
+        git.write_file_txt(name, &format!("{name} contents\n"))?;
+        git.run(&["add", "."])?;
+        git.run_with_options(
+            &[
+                "commit",
+                "--author",
+                "Original Author <original.author@example.com>",
+                "-m",
+                &format!("create {name}.txt"),
+            ],
+            &GitRunOptions {
+                time,
+                ..Default::default()
+            },
+        )?;
+        Ok(())
+    };
+    git.detach_head()?;
+    commit_as_original_author("test1", 1)?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test2", 2)?;
+    git.detach_head()?;
+    commit_as_original_author("test3", 3)?;
+    git.run(&["checkout", "master"])?;
+
+    {
+        let (stdout, _stderr) = git.run(&["sync", "--reset-author"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/1] Committed as: 96d1c37 create test1.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Synced 2cb0378 create test1.txt
+        "###);
+    }
+
+    {
+        // The commit which was already on the main branch wasn't rewritten,
+        // so it keeps its author.
+        let (stdout, _stderr) =
+            git.run(&["show", "-s", "--format=%h %an <%ae>", "96d1c37", "618486a"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        96d1c37 Testy McTestface <test@example.com>
+        618486a Original Author <original.author@example.com>
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_failing_post_rewrite_hook() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test3", 3)?;
+    git.detach_head()?;
+    git.commit_file("test4", 4)?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test5", 5)?;
+
+    let hook_path = git
+        .repo_path
+        .join(".git")
+        .join("hooks")
+        .join("post-rewrite");
+    let original_hook_contents = std::fs::read_to_string(&hook_path)?;
+    std::fs::write(&hook_path, format!("{original_hook_contents}exit 1\n"))?;
+
+    {
+        let (stdout, stderr) = git.run(&["sync"])?;
+        let stderr = remove_nondeterministic_lines(stderr);
+        insta::assert_snapshot!(stderr, @r###"
+        branchless: creating working copy snapshot
+        branchless: processing checkout
+        branchless: creating working copy snapshot
+        branchless: processing checkout
+        Hooks failed for 2 stacks:
+          post-rewrite hook exited with code 1 for 62fc20d create test1.txt
+          post-rewrite hook exited with code 1 for 2b633ed create test4.txt
+        "###);
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/2] Committed as: 87c7a36 create test1.txt
+        [2/2] Committed as: 8ee4f26 create test2.txt
+        branchless: processing 2 rewritten commits
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Attempting rebase in-memory...
+        [1/1] Committed as: d7e7e6c create test4.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Synced 62fc20d create test1.txt
+        Synced 2b633ed create test4.txt
+        "###);
+    }
+
+    git.commit_file("test6", 6)?;
+    {
+        let (_stdout, stderr) = git.run_with_options(
+            &["sync", "--strict-hooks"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        let stderr = remove_nondeterministic_lines(stderr);
+        insta::assert_snapshot!(stderr, @r###"
+        branchless: creating working copy snapshot
+        branchless: processing checkout
+        branchless: creating working copy snapshot
+        branchless: processing checkout
+        Hooks failed for 2 stacks:
+          post-rewrite hook exited with code 1 for 87c7a36 create test1.txt
+          post-rewrite hook exited with code 1 for d7e7e6c create test4.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_show_conflicted_diff() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    if git.get_version()? < GitVersion(2, 40, 0) {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.run(&["checkout", "-b", "foo"])?;
+    git.commit_file_with_contents("test1", 1, "foo contents")?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file_with_contents("test1", 2, "master contents")?;
+
+    {
+        let (stdout, _stderr) = git.run(&["sync", "--show-conflicted-diff"])?;
+        let stdout = Regex::new(r"(<<<<<<<|>>>>>>>) .*")?.replace_all(&stdout, "$1 <commit>");
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        Merge conflict (1 file) for c344d17 create test1.txt
+          Conflict in test1.txt:
+            <<<<<<< <commit>
+            master contents
+            =======
+            foo contents
+            >>>>>>> <commit>
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_max_depth() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    let test2_oid = git.commit_file("test2", 2)?;
+    let test3_oid = git.commit_file("test3", 3)?;
+    git.commit_file("test4", 4)?;
+    git.commit_file("test5", 5)?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test6", 6)?;
+
+    {
+        let (stdout, stderr) = git.run(&["sync", "--max-depth", "2"])?;
+        assert!(stdout.contains("[1/2] Committed as:"));
+        assert!(stdout.contains("create test2.txt"));
+        assert!(!stdout.contains("create test3.txt"));
+        assert!(
+            stderr.contains("Moved bottom 2 of 5 commits for this stack: 62fc20d create test1.txt")
+        );
+    }
+
+    {
+        // The commits above the cutoff are left on the original commits.
+        let (stdout, _stderr) = git.run(&["rev-parse", &format!("{test3_oid}^")])?;
+        assert_eq!(stdout.trim(), test2_oid.to_string());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_limit() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    for i in 1..=5 {
+        git.run(&["checkout", "master"])?;
+        git.detach_head()?;
+        git.commit_file(&format!("test{i}"), i)?;
+    }
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test6", 6)?;
+
+    {
+        let (stdout, stderr) = git.run(&["sync", "--limit", "2"])?;
+        assert!(stderr.contains("Run again to sync the remaining 3 stacks."));
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/1] Committed as: 3368c67 create test1.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Attempting rebase in-memory...
+        [1/1] Committed as: 1d5c73b create test2.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Synced 62fc20d create test1.txt
+        Synced fe65c1f create test2.txt
+        "###);
+    }
+
+    {
+        let (stdout, stderr) = git.run(&["sync", "--limit", "2"])?;
+        assert!(stderr.contains("Run again to sync the remaining 1 stack."));
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/1] Committed as: a77cf93 create test3.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Attempting rebase in-memory...
+        [1/1] Committed as: 0d381eb create test4.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Synced 98b9119 create test3.txt
+        Synced 8f7aef5 create test4.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["sync", "--limit", "2"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/1] Committed as: b8e1555 create test5.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Synced aff9c67 create test5.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_reapply_empty() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.run(&["commit", "--allow-empty", "-m", "marker"])?;
+    git.commit_file("test2", 2)?;
+
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test3", 3)?;
+
+    {
+        let (stdout, _stderr) = git.run(&["sync", "--reapply-empty"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/3] Committed as: 4b9ce31 create test1.txt
+        [2/3] Committed as: d16648b marker
+        [3/3] Committed as: 6ef2d6c create test2.txt
+        branchless: processing 3 rewritten commits
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Synced 62fc20d create test1.txt
+        "###);
+    }
+
+    git.commit_file("test4", 4)?;
+    {
+        let (stdout, _stderr) = git.run(&["sync", "--reapply-empty", "--on-disk"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> diff --quiet
+        Calling Git for on-disk rebase...
+        branchless: running command: <git-executable> rebase --continue
+        Synced 4b9ce31 create test1.txt
+        "###);
+    }
+
+    {
+        let stdout = git.smartlog()?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        @ 2b633ed (> master) create test4.txt
+        |
+        o 3a4858a create test1.txt
+        |
+        o ffebac0 marker
+        |
+        o 9861d0a create test2.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_autostash() -> eyre::Result<()> {
+    let git = make_git()?;
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.run(&["checkout", "-b", "foo"])?;
+    git.commit_file("test1", 1)?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test3", 3)?;
+    git.write_file_txt("initial", "uncommitted contents\n")?;
+
+    {
+        let (stdout, _stderr) = git.run_with_options(
+            &["sync", "--on-disk"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> diff --quiet
+        This operation would modify the working copy, but you have uncommitted changes
+        in your working copy which might be overwritten as a result.
+        Commit your changes and then try again.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["sync", "--on-disk", "--autostash"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> diff --quiet
+        branchless: running command: <git-executable> stash push --quiet --message branchless: automatic stash before sync
+        branchless: running command: <git-executable> diff --quiet
+        Calling Git for on-disk rebase...
+        branchless: running command: <git-executable> rebase --continue
+        Synced 62fc20d create test1.txt
+        branchless: running command: <git-executable> stash pop --quiet stash@{0}
+        "###);
+    }
+
+    {
+        let stdout = git.smartlog()?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        @ 98b9119 (> master) create test3.txt
+        |
+        o 4b9ce31 (foo) create test1.txt
+        "###);
+    }
+
+    let contents = std::fs::read_to_string(git.repo_path.join("initial.txt"))?;
+    assert_eq!(contents, "uncommitted contents\n");
+    let (stdout, _stderr) = git.run(&["stash", "list"])?;
+    insta::assert_snapshot!(stdout, @"");
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_autostash_in_memory() -> eyre::Result<()> {
+    let git = make_git()?;
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.run(&["checkout", "-b", "foo"])?;
+    git.commit_file("test1", 1)?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test3", 3)?;
+    git.write_file_txt("initial", "uncommitted contents\n")?;
+
+    // Nothing is stashed, since the in-memory rebase doesn't move `HEAD`.
+    {
+        let (stdout, _stderr) = git.run(&["sync", "--autostash"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/1] Committed as: 4b9ce31 create test1.txt
+        branchless: processing 1 update: branch foo
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        M	initial.txt
+        In-memory rebase succeeded.
+        Synced 62fc20d create test1.txt
+        "###);
+    }
+
+    // Nor when there's nothing to sync.
+    {
+        let (stdout, stderr) = git.run(&["sync", "--autostash"])?;
+        insta::assert_snapshot!(stderr, @"Nothing to sync (no changes since the last sync).");
+        insta::assert_snapshot!(stdout, @"");
+    }
+
+    let contents = std::fs::read_to_string(git.repo_path.join("initial.txt"))?;
+    assert_eq!(contents, "uncommitted contents\n");
+    let (stdout, _stderr) = git.run(&["stash", "list"])?;
+    insta::assert_snapshot!(stdout, @"");
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_keep_branches_on_conflict() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.run(&["checkout", "-b", "foo"])?;
+    git.commit_file_with_contents("test1", 1, "foo contents")?;
+    git.run(&["checkout", "-b", "bar", "master"])?;
+    git.commit_file("test2", 2)?;
+
+    git.run(&["checkout", "master"])?;
+    git.commit_file_with_contents("test1", 3, "master contents")?;
+
+    {
+        let (stdout, _stderr) = git.run(&["sync", "--keep-branches-on-conflict"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        Attempting rebase in-memory...
+        [1/1] Committed as: 8e26a7c create test2.txt
+        branchless: processing 1 update: branch bar
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Synced fe65c1f create test2.txt
+        Merge conflict (1 file) for c344d17 create test1.txt
+        "###);
+    }
+
+    {
+        let stdout = git.smartlog()?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc create initial.txt
+        |\
+        | o c344d17 (foo) create test1.txt
+        |
+        @ a9f9461 (> master) create test1.txt
+        |
+        o 8e26a7c (bar) create test2.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_upstream() -> eyre::Result<()> {
+    let GitWrapperWithRemoteRepo {
+        temp_dir: _guard,
+        original_repo,
+        cloned_repo,
+    } = make_git_with_remote_repo()?;
+    if !original_repo.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    original_repo.init_repo()?;
+    original_repo.commit_file("test1", 1)?;
+    original_repo.commit_file("test2", 2)?;
+    original_repo.clone_repo_into(&cloned_repo, &["--branch", "master"])?;
+    cloned_repo.init_repo_with_options(&GitInitOptions {
+        make_initial_commit: false,
+        ..Default::default()
+    })?;
+    cloned_repo.run(&["branch", "--unset-upstream", "master"])?;
+    original_repo.commit_file("test3", 3)?;
+
+    {
+        let (stdout, _stderr) = cloned_repo.run(&["sync", "-p"])?;
+        let stdout = remove_nondeterministic_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> fetch --all
+        branch master does not track an upstream branch, so not pulling.
+        "###);
+    }
+
+    {
+        let (stdout, stderr) = cloned_repo.run_with_options(
+            &["sync", "-p", "--upstream", "::origin/master"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stderr, @"The --upstream expression ::origin/master must resolve to exactly one commit, but it resolved to 3 commits.
+");
+        insta::assert_snapshot!(stdout, @"branchless: running command: <git-executable> fetch --all
+");
+    }
+
+    {
+        let (stdout, _stderr) = cloned_repo.run(&["sync", "-p", "--upstream", "origin/master"])?;
+        let stdout = remove_nondeterministic_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> fetch --all
+        Fast-forwarding branch master to 70deb1e create test3.txt
+        branchless: running command: <git-executable> merge --ff-only 70deb1e28791d8e7dd5a1f0c871a51b91282562f
+        Updating 96d1c37..70deb1e
+        Fast-forward
+         test3.txt | 1 +
+         1 file changed, 1 insertion(+)
+         create mode 100644 test3.txt
+        "###);
+    }
+
+    {
+        let stdout = cloned_repo.smartlog()?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        @ 70deb1e (> master) create test3.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_fetch_main_branch_remote() -> eyre::Result<()> {
+    let GitWrapperWithRemoteRepo {
+        temp_dir: _guard,
+        original_repo,
+        cloned_repo,
+    } = make_git_with_remote_repo()?;
+    if !original_repo.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    original_repo.init_repo()?;
+    original_repo.commit_file("test1", 1)?;
+    original_repo.commit_file("test2", 2)?;
+    original_repo.clone_repo_into(&cloned_repo, &["--branch", "master"])?;
+    cloned_repo.init_repo_with_options(&GitInitOptions {
+        make_initial_commit: false,
+        ..Default::default()
+    })?;
+    cloned_repo.run(&["remote", "rename", "origin", "upstream"])?;
+    // This remote can't be fetched, so fetching all remotes would fail.
+    cloned_repo.run(&["remote", "add", "broken", "/nonexistent"])?;
+    cloned_repo.detach_head()?;
+
+    original_repo.commit_file("test3", 3)?;
+    cloned_repo.commit_file("test4", 4)?;
+
+    {
+        let (stdout, _stderr) = cloned_repo.run(&["sync", "-p"])?;
+        let stdout = remove_nondeterministic_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> fetch upstream
+        Fast-forwarding branch master to 70deb1e create test3.txt
+        Attempting rebase in-memory...
+        [1/1] Committed as: 355e173 create test4.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout 355e173bf9c5d2efac2e451da0cdad3fb82b869a
+        In-memory rebase succeeded.
+        Synced f57e36f create test4.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_pull_fetch_failure() -> eyre::Result<()> {
+    let GitWrapperWithRemoteRepo {
+        temp_dir: _guard,
+        original_repo,
+        cloned_repo,
+    } = make_git_with_remote_repo()?;
+    if !original_repo.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    original_repo.init_repo()?;
+    original_repo.commit_file("test1", 1)?;
+    original_repo.clone_repo_into(&cloned_repo, &["--branch", "master"])?;
+    cloned_repo.init_repo_with_options(&GitInitOptions {
+        make_initial_commit: false,
+        ..Default::default()
+    })?;
+    cloned_repo.run(&["remote", "add", "broken", "/nonexistent"])?;
+
+    {
+        let (_stdout, stderr) = cloned_repo.run_with_options(
+            &["sync", "-p", "--fetch-remote", "broken"],
+            &GitRunOptions {
+                expected_exit_code: 128,
+                ..Default::default()
+            },
+        )?;
+        assert!(
+            stderr.contains("Fetching from broken failed with exit code 128: fatal: "),
+            "{stderr}"
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_no_fetch_tags() -> eyre::Result<()> {
+    let GitWrapperWithRemoteRepo {
+        temp_dir: _guard,
+        original_repo,
+        cloned_repo,
+    } = make_git_with_remote_repo()?;
+    if !original_repo.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    original_repo.init_repo()?;
+    original_repo.commit_file("test1", 1)?;
+    original_repo.commit_file("test2", 2)?;
+    original_repo.clone_repo_into(&cloned_repo, &["--branch", "master"])?;
+    cloned_repo.init_repo_with_options(&GitInitOptions {
+        make_initial_commit: false,
+        ..Default::default()
+    })?;
+    cloned_repo.detach_head()?;
+
+    original_repo.commit_file("test3", 3)?;
+    original_repo.run(&["tag", "v1"])?;
+
+    {
+        let (stdout, _stderr) = cloned_repo.run(&["sync", "-p", "--no-fetch-tags"])?;
+        let stdout = remove_nondeterministic_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> fetch origin --no-tags
+        Fast-forwarding branch master to 70deb1e create test3.txt
+        "###);
+    }
+    {
+        let (stdout, _stderr) = cloned_repo.run(&["tag", "--list"])?;
+        insta::assert_snapshot!(stdout, @"");
+    }
+
+    original_repo.commit_file("test4", 4)?;
+    original_repo.run(&["tag", "v2"])?;
+    cloned_repo.run(&["config", "branchless.sync.fetchTags", "false"])?;
+    cloned_repo.run(&["config", "branchless.sync.nativeFetch", "true"])?;
+
+    {
+        cloned_repo.run(&["sync", "-p"])?;
+        let (stdout, _stderr) = cloned_repo.run(&["tag", "--list"])?;
+        insta::assert_snapshot!(stdout, @"");
+        let (stdout, _stderr) = cloned_repo.run(&["log", "-1", "--oneline", "master"])?;
+        insta::assert_snapshot!(stdout, @"355e173 create test4.txt");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_native_fetch() -> eyre::Result<()> {
+    let GitWrapperWithRemoteRepo {
+        temp_dir: _guard,
+        original_repo,
+        cloned_repo,
+    } = make_git_with_remote_repo()?;
+    if !original_repo.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    original_repo.init_repo()?;
+    original_repo.commit_file("test1", 1)?;
+    original_repo.commit_file("test2", 2)?;
+    original_repo.clone_repo_into(&cloned_repo, &["--branch", "master"])?;
+    cloned_repo.init_repo_with_options(&GitInitOptions {
+        make_initial_commit: false,
+        ..Default::default()
+    })?;
+    cloned_repo.run(&["config", "branchless.sync.nativeFetch", "true"])?;
+    cloned_repo.detach_head()?;
+
+    original_repo.commit_file("test3", 3)?;
+    cloned_repo.commit_file("test4", 4)?;
+
+    {
+        let (stdout, _stderr) = cloned_repo.run(&["sync", "-p"])?;
+        let stdout = remove_nondeterministic_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        Fast-forwarding branch master to 70deb1e create test3.txt
+        Attempting rebase in-memory...
+        [1/1] Committed as: 355e173 create test4.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout 355e173bf9c5d2efac2e451da0cdad3fb82b869a
+        In-memory rebase succeeded.
+        Synced f57e36f create test4.txt
+        "###);
+    }
+
+    {
+        let repo = cloned_repo.get_repo()?;
+        let conn = repo.get_db_conn()?;
+        let event_log_db = EventLogDb::new(&conn)?;
+        let event_tx_id = event_log_db
+            .get_latest_transaction_id_with_message("sync fetch")?
+            .unwrap();
+        let ref_updates = event_log_db
+            .get_events()?
+            .into_iter()
+            .filter_map(|event| match event {
+                Event::RefUpdateEvent {
+                    event_tx_id: tx_id,
+                    ref_name,
+                    old_oid,
+                    new_oid,
+                    ..
+                } if tx_id == event_tx_id => {
+                    Some(format!("{} {old_oid} -> {new_oid}", ref_name.as_str()))
+                }
+                _ => None,
+            })
+            .join("\n");
+        insta::assert_snapshot!(ref_updates, @"refs/remotes/origin/master 96d1c37a3d4363611c49f7e52186e189a04c531f -> 70deb1e28791d8e7dd5a1f0c871a51b91282562f
+");
+    }
+
+    // With more than one remote, only the main branch's remote is fetched.
+    cloned_repo.run(&[
+        "remote",
+        "add",
+        "other",
+        original_repo.repo_path.to_str().unwrap(),
+    ])?;
+    {
+        let (stdout, _stderr) = cloned_repo.run(&["sync", "-p"])?;
+        let stdout = remove_nondeterministic_lines(stdout);
+        insta::assert_snapshot!(stdout, @"Not updating branch master at 70deb1e create test3.txt");
+    }
+
+    // If the main branch's remote isn't known, fall back to `git fetch --all`.
+    cloned_repo.run(&["branch", "--unset-upstream", "master"])?;
+    {
+        let (stdout, _stderr) = cloned_repo.run(&["sync", "-p"])?;
+        let stdout = remove_nondeterministic_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> fetch --all
+        branch master does not track an upstream branch, so not pulling.
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_builder_on_stack_result() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.run(&["checkout", "-b", "foo"])?;
+    git.commit_file_with_contents("test1", 1, "foo contents")?;
+    git.run(&["checkout", "-b", "bar", "master"])?;
+    git.commit_file("test2", 2)?;
+
+    git.run(&["checkout", "master"])?;
+    git.commit_file_with_contents("test1", 3, "master contents")?;
+
+    // The builder operates on the repository in the current directory.
+    std::env::set_current_dir(&git.repo_path)?;
+    let git_run_info = git.get_git_run_info();
+    let stdout: Arc<Mutex<Vec<u8>>> = Default::default();
+    let stderr: Arc<Mutex<Vec<u8>>> = Default::default();
+    let effects = Effects::new_from_buffer_for_test(Glyphs::text(), &stdout, &stderr);
+
+    {
+        let mut stack_results = Vec::new();
+        let outcome = SyncBuilder::new(&effects, &git_run_info)
+            .on_stack_result(|root_commit_oid, status| {
+                stack_results.push(format!("{root_commit_oid} {status:?}"))
+            })
+            .run()?
+            .unwrap();
+        insta::assert_snapshot!(stack_results.join("\n"), @r###"
+        c344d17b1ef8a578ab3a171067cca63c4ede2822 Conflicted
+        fe65c1fe15584744e649b2c79d4cf9b0d878f92e Synced
+        "###);
+        assert!(!outcome.is_fully_synced);
+    }
+
+    // A panicking callback doesn't abort the sync.
+    git.run(&["checkout", "bar"])?;
+    git.commit_file("test4", 4)?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test5", 5)?;
+    stderr.lock().unwrap().clear();
+    {
+        let mut num_calls = 0;
+        let outcome = SyncBuilder::new(&effects, &git_run_info)
+            .on_stack_result(|_root_commit_oid, _status| {
+                num_calls += 1;
+                panic!("callback failed");
+            })
+            .run()?
+            .unwrap();
+        assert_eq!(num_calls, 1);
+        assert_eq!(outcome.synced_root_commit_oids.len(), 1);
+        let stderr = String::from_utf8(stderr.lock().unwrap().clone())?;
+        insta::assert_snapshot!(stderr, @r###"
+        Warning: the stack result callback panicked, so it won't be called again: callback failed
+        branchless: creating working copy snapshot
+        Switched to branch 'master'
+        branchless: processing checkout
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_builder_dag() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test2", 2)?;
+
+    // The builder operates on the repository in the current directory.
+    std::env::set_current_dir(&git.repo_path)?;
+    let git_run_info = git.get_git_run_info();
+    let effects = Effects::new_suppress_for_test(Glyphs::text());
+    let repo = git.get_repo()?;
+    let conn = repo.get_db_conn()?;
+    let event_log_db = EventLogDb::new(&conn)?;
+    let event_replayer = EventReplayer::from_event_log_db(&effects, &repo, &event_log_db)?;
+    let event_cursor = event_replayer.make_default_cursor();
+    let references_snapshot = repo.get_references_snapshot()?;
+    let mut dag = Dag::open_and_sync(
+        &effects,
+        &repo,
+        &event_replayer,
+        event_cursor,
+        &references_snapshot,
+    )?;
+
+    let outcome = SyncBuilder::new(&effects, &git_run_info)
+        .dag(&mut dag)
+        .run()?
+        .unwrap();
+    assert!(outcome.is_fully_synced);
+    assert_eq!(outcome.synced_root_commit_oids.len(), 1);
+
+    {
+        let stdout = git.smartlog()?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        @ fe65c1f (> master) create test2.txt
+        |
+        o 0770943 create test1.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_progress_plain() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.run(&["checkout", "master"])?;
+    git.detach_head()?;
+    git.commit_file("test2", 2)?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test3", 3)?;
+
+    {
+        let (_stdout, stderr) = git.run(&["sync", "--progress", "plain"])?;
+        insta::assert_snapshot!(stderr, @r###"
+        branchless: creating working copy snapshot
+        Switched to branch 'master'
+        branchless: processing checkout
+        [1/2] synced: 62fc20d create test1.txt
+        branchless: creating working copy snapshot
+        Switched to branch 'master'
+        branchless: processing checkout
+        [2/2] synced: fe65c1f create test2.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_gpg_sign() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    // Sign with an SSH key, since it doesn't need a keyring or an agent. Skip
+    // the test if a key can't be generated.
+    let key_path = git.repo_path.join(".git").join("signing-key");
+    match std::process::Command::new("ssh-keygen")
+        .args(["-q", "-t", "ed25519", "-N", "", "-C", "", "-f"])
+        .arg(&key_path)
+        .status()
+    {
+        Ok(status) if status.success() => {}
+        _ => return Ok(()),
+    }
+    let public_key = std::fs::read_to_string(key_path.with_extension("pub"))?;
+    let allowed_signers_path = git.repo_path.join(".git").join("allowed-signers");
+    std::fs::write(&allowed_signers_path, format!("* {public_key}"))?;
+    git.run(&["config", "gpg.format", "ssh"])?;
+    git.run(&["config", "user.signingKey", key_path.to_str().unwrap()])?;
+    git.run(&[
+        "config",
+        "gpg.ssh.allowedSignersFile",
+        allowed_signers_path.to_str().unwrap(),
+    ])?;
+
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.run(&["commit", "--amend", "--no-edit", "-S"])?;
+    let (test1_oid, _stderr) = git.run(&["rev-parse", "HEAD"])?;
+    git.run(&["checkout", "master"])?;
+    git.detach_head()?;
+    git.commit_file("test2", 2)?;
+    git.run(&["commit", "--amend", "--no-edit", "-S"])?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test3", 3)?;
+
+    {
+        let (_stdout, stderr) = git.run(&["sync", "--gpg-sign", test1_oid.trim()])?;
+        insta::assert_snapshot!(stderr, @r###"
+        branchless: creating working copy snapshot
+        Switched to branch 'master'
+        branchless: processing checkout
+        "###);
+    }
+    {
+        let (_stdout, stderr) = git.run(&["sync"])?;
+        // The hash of the re-signed commit isn't deterministic, since the
+        // signature is different each time.
+        let stderr = stderr
+            .lines()
+            .filter(|line| !line.starts_with("Not moving up-to-date stack"))
+            .flat_map(|line| [line, "\n"])
+            .collect::<String>();
+        insta::assert_snapshot!(stderr, @r###"
+        branchless: creating working copy snapshot
+        Switched to branch 'master'
+        branchless: processing checkout
+        Warning: signatures were dropped for 1 commit. Pass --gpg-sign or set commit.gpgSign to re-sign rewritten commits.
+        "###);
+    }
+    {
+        let (draft_oids, _stderr) = git.run(&["query", "--raw", "draft()"])?;
+        let mut args = vec!["show", "--no-patch", "--format=%G? %s"];
+        args.extend(draft_oids.lines());
+        let (stdout, _stderr) = git.run(&args)?;
+        insta::assert_snapshot!(stdout, @r###"
+        G create test1.txt
+        N create test2.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_no_verify() -> eyre::Result<()> {
+    let GitWrapperWithRemoteRepo {
+        temp_dir: _guard,
+        original_repo,
+        cloned_repo,
+    } = make_git_with_remote_repo()?;
+    if !original_repo.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    original_repo.init_repo()?;
+    original_repo.commit_file("test1", 1)?;
+    original_repo.clone_repo_into(&cloned_repo, &["--branch", "master"])?;
+    cloned_repo.init_repo_with_options(&GitInitOptions {
+        make_initial_commit: false,
+        ..Default::default()
+    })?;
+
+    cloned_repo.run(&["checkout", "-b", "foo"])?;
+    cloned_repo.commit_file("test2", 2)?;
+    cloned_repo.run(&["checkout", "master"])?;
+    cloned_repo.commit_file("test3", 3)?;
+
+    let pre_push_path = cloned_repo
+        .repo_path
+        .join(".git")
+        .join("hooks")
+        .join("pre-push");
+    std::fs::write(
+        &pre_push_path,
+        "#!/bin/sh\necho 'pre-push hook rejected the push' >&2\nexit 1\n",
+    )?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&pre_push_path, std::fs::Permissions::from_mode(0o755))?;
+    }
+
+    {
+        let (stdout, _stderr) = cloned_repo.run(&["sync", "--publish", "--no-verify"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/1] Committed as: d742fb9 create test2.txt
+        branchless: processing 1 update: branch foo
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        Your branch is ahead of 'origin/master' by 1 commit.
+          (use "git push" to publish your local commits)
+        In-memory rebase succeeded.
+        Synced 96d1c37 create test2.txt
+        branchless: running command: <git-executable> push --force-with-lease=refs/heads/users/test/foo: --no-verify origin refs/heads/foo:refs/heads/users/test/foo
+        Published 1 branch to origin.
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) =
+            original_repo.run(&["for-each-ref", "--format=%(refname)", "refs/heads/users/"])?;
+        insta::assert_snapshot!(stdout, @"refs/heads/users/test/foo");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_no_verify_merge_conflict() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.run(&["checkout", "-b", "foo"])?;
+    git.commit_file_with_contents("test1", 1, "foo contents")?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file_with_contents("test1", 2, "master contents")?;
+
+    {
+        let (stdout, _stderr) = git.run(&["sync", "--merge", "--no-verify"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        Aborting since skipping hooks requires an in-memory rebase.
+        Merge conflict (1 file) for c344d17 create test1.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_update_tags() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    git.run(&["tag", "v1"])?;
+    git.commit_file("test2", 2)?;
+    git.run(&["tag", "-a", "-m", "annotated", "v2"])?;
+
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test3", 3)?;
+
+    {
+        let (stdout, stderr) = git.run(&["sync", "--update-tags"])?;
+        insta::assert_snapshot!(stderr, @r###"
+        branchless: creating working copy snapshot
+        Switched to branch 'master'
+        branchless: processing checkout
+        Warning: not moving annotated tag v2, since moving it would change the tag object. Recreate it with `git tag -f -a v2 9f77bc5fa2a01bc869e783c338a757c3b31e32ef`.
+        "###);
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/2] Committed as: 4b9ce31 create test1.txt
+        [2/2] Committed as: 9f77bc5 create test2.txt
+        branchless: processing 2 rewritten commits
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Synced 62fc20d create test1.txt
+        Moved tag v1 to 4b9ce31 create test1.txt
+        branchless: processing 1 update: ref refs/tags/v1
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["tag", "--points-at", "4b9ce31"])?;
+        insta::assert_snapshot!(stdout, @"v1");
+    }
+
+    // The tag move is recorded under the sync's transaction, so undoing the
+    // sync moves the tag back.
+    git.branchless("undo", &["-y"])?;
+    {
+        let (stdout, _stderr) = git.run(&["tag", "--points-at", &test1_oid.to_string()])?;
+        insta::assert_snapshot!(stdout, @"v1");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_merge_base_strategy_git() -> eyre::Result<()> {
+    let git = make_git()?;
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.run(&["checkout", "master"])?;
+    git.run(&["cherry-pick", &test1_oid.to_string()])?;
+    git.commit_file("test4", 4)?;
+
+    {
+        // The upstream copy of `test1` is still found when the merge-bases
+        // are computed by `git merge-base`.
+        let (stdout, _stderr) = git.run(&["sync", "--merge-base-strategy", "git"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/2] Skipped commit (was already applied upstream): 62fc20d create test1.txt
+        [2/2] Committed as: 037216a create test2.txt
+        branchless: processing 2 rewritten commits
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Synced 62fc20d create test1.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_statistics() -> eyre::Result<()> {
+    let git = make_git()?;
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.run(&["checkout", "master"])?;
+    git.detach_head()?;
+    git.commit_file("test3", 3)?;
+    git.run(&["checkout", "master"])?;
+    git.run(&["cherry-pick", &test1_oid.to_string()])?;
+    git.commit_file("test4", 4)?;
+
+    {
+        let (stdout, _stderr) = git.run(&["sync", "--statistics"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/2] Skipped commit (was already applied upstream): 62fc20d create test1.txt
+        [2/2] Committed as: 037216a create test2.txt
+        branchless: processing 2 rewritten commits
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Attempting rebase in-memory...
+        [1/1] Committed as: c6c8819 create test3.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Synced 62fc20d create test1.txt
+        Synced 98b9119 create test3.txt
+        Sync statistics:
+          Stacks rebased in-memory     2
+          Stacks rebased on-disk       0
+          Stacks with conflicts        0
+          Commits picked               2
+          Commits skipped (empty)      0
+          Commits skipped (duplicate)  1
+          Commits rewritten            2
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_update_main_no_fetch() -> eyre::Result<()> {
+    let GitWrapperWithRemoteRepo {
+        temp_dir: _guard,
+        original_repo,
+        cloned_repo,
+    } = make_git_with_remote_repo()?;
+    if !original_repo.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    original_repo.init_repo()?;
+    original_repo.commit_file("test1", 1)?;
+    original_repo.clone_repo_into(&cloned_repo, &["--branch", "master"])?;
+    cloned_repo.init_repo_with_options(&GitInitOptions {
+        make_initial_commit: false,
+        ..Default::default()
+    })?;
+    cloned_repo.detach_head()?;
+    cloned_repo.commit_file("test2", 2)?;
+
+    original_repo.commit_file("test3", 3)?;
+    cloned_repo.run(&["fetch", "origin"])?;
+    original_repo.commit_file("test4", 4)?;
+
+    {
+        let (stdout, _stderr) = cloned_repo.run(&["sync", "--update-main-no-fetch"])?;
+        let stdout = remove_nondeterministic_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        Fast-forwarding branch master to 4838e49 create test3.txt
+        Attempting rebase in-memory...
+        [1/1] Committed as: d742fb9 create test2.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout d742fb97c050479c1d3057d8de0f05190dd3a89f
+        In-memory rebase succeeded.
+        Synced 96d1c37 create test2.txt
+        "###);
+    }
+    {
+        let (stdout, _stderr) = cloned_repo.run(&["log", "-1", "--oneline", "master"])?;
+        insta::assert_snapshot!(stdout, @"4838e49 create test3.txt");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_upstream_only() -> eyre::Result<()> {
+    let GitWrapperWithRemoteRepo {
+        temp_dir: _guard,
+        original_repo,
+        cloned_repo,
+    } = make_git_with_remote_repo()?;
+    if !original_repo.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    original_repo.init_repo()?;
+    original_repo.commit_file("test1", 1)?;
+    original_repo.clone_repo_into(&cloned_repo, &["--branch", "master"])?;
+    cloned_repo.init_repo_with_options(&GitInitOptions {
+        make_initial_commit: false,
+        ..Default::default()
+    })?;
+    cloned_repo.detach_head()?;
+    cloned_repo.commit_file("test2", 2)?;
+    let (stack_oid_before, _stderr) = cloned_repo.run(&["rev-parse", "HEAD"])?;
+
+    original_repo.commit_file("test3", 3)?;
+
+    {
+        let (stdout, _stderr) = cloned_repo.run(&["sync", "--pull", "--upstream-only"])?;
+        let stdout = remove_nondeterministic_lines(stdout);
+        assert!(stdout.contains("Fast-forwarding branch master to"));
+        assert!(!stdout.contains("Synced"));
+    }
+    {
+        let (master_oid, _stderr) = cloned_repo.run(&["rev-parse", "master"])?;
+        let (upstream_oid, _stderr) = cloned_repo.run(&["rev-parse", "origin/master"])?;
+        assert_eq!(master_oid, upstream_oid);
+    }
+    {
+        // The stack is left where it was.
+        let (stack_oid_after, _stderr) = cloned_repo.run(&["rev-parse", "HEAD"])?;
+        assert_eq!(stack_oid_after, stack_oid_before);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_fetch_remote() -> eyre::Result<()> {
+    let GitWrapperWithRemoteRepo {
+        temp_dir: _guard,
+        original_repo,
+        cloned_repo,
+    } = make_git_with_remote_repo()?;
+    if !original_repo.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    original_repo.init_repo()?;
+    original_repo.commit_file("test1", 1)?;
+    original_repo.clone_repo_into(&cloned_repo, &["--branch", "master"])?;
+    cloned_repo.init_repo_with_options(&GitInitOptions {
+        make_initial_commit: false,
+        ..Default::default()
+    })?;
+    cloned_repo.run(&[
+        "remote",
+        "add",
+        "upstream",
+        original_repo.repo_path.to_str().unwrap(),
+    ])?;
+    original_repo.commit_file("test2", 2)?;
+
+    {
+        let (stdout, stderr) = cloned_repo.run_with_options(
+            &["sync", "--pull", "--fetch-remote", "nonexistent"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stderr, @"Remote to fetch does not exist: nonexistent");
+        insta::assert_snapshot!(stdout, @"");
+    }
+
+    cloned_repo.run(&["config", "branchless.sync.remote", "upstream"])?;
+    {
+        let (stdout, _stderr) = cloned_repo.run(&["sync", "--pull"])?;
+        let stdout = remove_nondeterministic_lines(stdout);
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> fetch upstream
+        Fast-forwarding branch master to 96d1c37 create test2.txt
+        "###);
+    }
+    {
+        // Only the configured remote was fetched.
+        let (stdout, _stderr) = cloned_repo.run(&["rev-parse", "origin/master"])?;
+        let (master_stdout, _stderr) = cloned_repo.run(&["rev-parse", "master"])?;
+        assert_ne!(stdout, master_stdout);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_list_backups() -> eyre::Result<()> {
+    let git = make_git()?;
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.run(&["checkout", "-b", "foo"])?;
+    git.commit_file("test1", 1)?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test2", 2)?;
+
+    {
+        let (stdout, _stderr) = git.run(&["sync", "--list-backups"])?;
+        insta::assert_snapshot!(stdout, @"No backups recorded. Pass --record-backup-refs to git sync to record them.
+");
+    }
+
+    git.run(&["sync", "--record-backup-refs"])?;
+    // A backup from long ago, which has expired.
+    git.run(&["update-ref", "refs/branchless/backup/1/bar", "master"])?;
+
+    {
+        let (stdout, _stderr) = git.run(&["sync", "--list-backups"])?;
+        let stdout = Regex::new(r"[0-9]+/foo \([0-9]+s ago\)")?
+            .replace_all(&stdout, "<timestamp>/foo (<age> ago)");
+        insta::assert_snapshot!(stdout, @r###"
+        <timestamp>/foo (<age> ago): branch foo was at 62fc20d create test1.txt
+        Deleted 1 expired backup older than 30 days.
+        "###);
+    }
+
+    {
+        let (stdout, stderr) = git.run_with_options(
+            &["sync", "--restore-backup", "1/bar"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stderr, @"No backup named 1/bar. Run git sync --list-backups to see the recorded backups.
+");
+        insta::assert_snapshot!(stdout, @"");
+    }
+
+    let (backup_ref_name, _stderr) = git.run(&[
+        "for-each-ref",
+        "--format=%(refname)",
+        "refs/branchless/backup/",
+    ])?;
+    let backup_name = backup_ref_name
+        .trim()
+        .strip_prefix("refs/branchless/backup/")
+        .unwrap();
+    {
+        let (stdout, _stderr) = git.run(&["sync", "--restore-backup", backup_name])?;
+        let stdout = Regex::new(r"[0-9a-f]{40}")?.replace_all(&stdout, "<oid>");
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> update-ref -m branchless: restoring sync backup refs/heads/foo <oid>
+        Restored branch foo to 62fc20d create test1.txt
+        "###);
+    }
+    {
+        let (stdout, _stderr) = git.run(&["rev-parse", "--short", "foo"])?;
+        insta::assert_snapshot!(stdout, @"62fc20d
+");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_timing() -> eyre::Result<()> {
+    let git = make_git()?;
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test2", 2)?;
+
+    {
+        let (stdout, _stderr) = git.run(&["sync", "--timing"])?;
+        let elapsed_re = Regex::new(r"\([0-9]+\.[0-9]s\)")?;
+        let stdout = elapsed_re.replace_all(&stdout, "(<elapsed>)");
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/1] Committed as: 0770943 create test1.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Synced 62fc20d create test1.txt (<elapsed>)
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_commit_graph_refresh() -> eyre::Result<()> {
+    let git = make_git()?;
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test2", 2)?;
+
+    {
+        let (stdout, stderr) = git.run(&["sync", "--commit-graph-refresh"])?;
+        assert!(stderr
+            .contains("Skipping commit-graph refresh: 1 commit rewritten (threshold is 100)."));
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/1] Committed as: 0770943 create test1.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Synced 62fc20d create test1.txt
+        "###);
+    }
+
+    let git = make_git()?;
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test2", 2)?;
+    git.run(&["config", "branchless.sync.writeCommitGraphMinCommits", "1"])?;
+    git.run(&["config", "branchless.sync.writeCommitGraph", "true"])?;
+
+    {
+        let (stdout, _stderr) = git.run(&["sync"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/1] Committed as: 0770943 create test1.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Synced 62fc20d create test1.txt
+        Refreshed the commit-graph after rewriting 1 commit.
+        "###);
+    }
+    assert!(git
+        .repo_path
+        .join(".git")
+        .join("objects")
+        .join("info")
+        .join("commit-graph")
+        .exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_submodules() -> eyre::Result<()> {
+    let git = make_git()?;
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    git.write_file(
+        ".gitmodules",
+        "[submodule \"sub\"]\n\tpath = sub\n\turl = ./sub\n",
+    )?;
+    git.run(&["add", ".gitmodules"])?;
+    git.run(&[
+        "update-index",
+        "--add",
+        "--cacheinfo",
+        &format!("160000,{test1_oid},sub"),
+    ])?;
+    git.run(&["commit", "-m", "add submodule"])?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test2", 2)?;
+
+    {
+        let (stdout, _stderr) = git.run(&["sync", "--recurse-submodules"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        branchless: running command: <git-executable> diff --quiet
+        Calling Git for on-disk rebase...
+        branchless: running command: <git-executable> rebase --continue
+        branchless: running command: <git-executable> submodule update --recursive
+        Synced 62fc20d create test1.txt
+        "###);
+    }
+
+    {
+        let stdout = git.smartlog()?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        @ fe65c1f (> master) create test2.txt
+        |
+        o 0770943 create test1.txt
+        |
+        o 0e00b85 add submodule
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_json_lines() -> eyre::Result<()> {
+    let git = make_git()?;
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test2", 2)?;
+    git.detach_head()?;
+    git.commit_file("test3", 3)?;
+    git.run(&["checkout", "master"])?;
+
+    {
+        let (stdout, _stderr) = git.run(&["sync", "--json-lines"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/1] Committed as: 0770943 create test1.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        {"root_oid":"62fc20d2a290daea0d52bdc2ed2ad4be6491010e","status":"synced","summary":"create test1.txt"}
+        {"root_oid":"02067177964ab16eedc74600341b2d9e4e19487e","status":"skipped","summary":"create test3.txt"}
+        Synced 62fc20d create test1.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_batch_size() -> eyre::Result<()> {
+    let git = make_git()?;
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    git.run(&["checkout", "master"])?;
+    git.detach_head()?;
+    let test2_oid = git.commit_file("test2", 2)?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test3", 3)?;
+
+    git.run(&["sync", "--batch-size", "1"])?;
+
+    {
+        let repo = git.get_repo()?;
+        let conn = repo.get_db_conn()?;
+        let event_log_db = EventLogDb::new(&conn)?;
+        let rewrite_event_tx_ids = event_log_db
+            .get_events()?
+            .into_iter()
+            .filter_map(|event| match event {
+                Event::RewriteEvent {
+                    event_tx_id,
+                    old_commit_oid,
+                    ..
+                } if old_commit_oid == test1_oid.into() || old_commit_oid == test2_oid.into() => {
+                    Some(event_tx_id)
+                }
+                _ => None,
+            })
+            .collect_vec();
+        assert_eq!(rewrite_event_tx_ids.len(), 2);
+        assert_ne!(rewrite_event_tx_ids[0], rewrite_event_tx_ids[1]);
+    }
+
+    // The commits synced in every batch are part of the sync.
+    {
+        let (stdout, _stderr) = git.run(&["query", "--raw", "synced()"])?;
+        assert_eq!(stdout.lines().count(), 2, "{stdout}");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_output_file() -> eyre::Result<()> {
+    let git = make_git()?;
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test2", 2)?;
+    git.detach_head()?;
+    git.commit_file("test3", 3)?;
+    git.run(&["checkout", "master"])?;
+
+    let output_file = git.repo_path.join("sync-output");
+    let output_file_arg = format!("--output-file={}", output_file.to_str().unwrap());
+
+    {
+        let (stdout, _stderr) = git.run(&["sync", "--print-plan-only", &output_file_arg])?;
+        insta::assert_snapshot!(stdout, @"");
+        insta::assert_snapshot!(std::fs::read_to_string(&output_file)?, @r###"
+        plan 62fc20d2a290daea0d52bdc2ed2ad4be6491010e
+          onto fe65c1fe15584744e649b2c79d4cf9b0d878f92e
+          reset fe65c1fe15584744e649b2c79d4cf9b0d878f92e
+          pick 62fc20d2a290daea0d52bdc2ed2ad4be6491010e
+          detect-empty 62fc20d2a290daea0d52bdc2ed2ad4be6491010e
+        plan 02067177964ab16eedc74600341b2d9e4e19487e
+          up-to-date
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["sync", &output_file_arg])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/1] Committed as: 0770943 create test1.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Synced 62fc20d create test1.txt
+        "###);
+        insta::assert_snapshot!(std::fs::read_to_string(&output_file)?, @r###"
+        {
+          "is_fully_synced": true,
+          "synced_root_oids": [
+            "62fc20d2a290daea0d52bdc2ed2ad4be6491010e"
+          ],
+          "conflicted_root_oids": [],
+          "num_commits_rewritten": 1
+        }
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_onto_git_revision() -> eyre::Result<()> {
+    let git = make_git()?;
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.commit_file("test1", 1)?;
+    git.detach_head()?;
+    let test2_oid = git.commit_file("test2", 2)?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test3", 3)?;
+    git.commit_file("test4", 4)?;
+    git.commit_file("test5", 5)?;
+
+    {
+        let (stdout, _stderr) = git.run(&["sync", &test2_oid.to_string(), "--onto", "HEAD~2"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/1] Committed as: d742fb9 create test2.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Synced 96d1c37 create test2.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_onto_upstream() -> eyre::Result<()> {
+    let GitWrapperWithRemoteRepo {
+        temp_dir: _guard,
+        original_repo,
+        cloned_repo,
+    } = make_git_with_remote_repo()?;
+    if !original_repo.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    original_repo.init_repo()?;
+    original_repo.commit_file("test1", 1)?;
+    original_repo.clone_repo_into(&cloned_repo, &["--branch", "master"])?;
+    cloned_repo.init_repo_with_options(&GitInitOptions {
+        make_initial_commit: false,
+        ..Default::default()
+    })?;
+    cloned_repo.detach_head()?;
+    let test2_oid = cloned_repo.commit_file("test2", 2)?;
+    cloned_repo.run(&["checkout", "master"])?;
+
+    original_repo.commit_file("test3", 3)?;
+    cloned_repo.run(&["fetch", "origin"])?;
+
+    {
+        let (stdout, _stderr) =
+            cloned_repo.run(&["sync", &test2_oid.to_string(), "--onto", "@{u}"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/1] Committed as: d742fb9 create test2.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        Your branch is behind 'origin/master' by 1 commit, and can be fast-forwarded.
+          (use "git pull" to update your local branch)
+        In-memory rebase succeeded.
+        Synced 96d1c37 create test2.txt
+        "###);
+    }
+
+    original_repo.commit_file("test4", 4)?;
+    cloned_repo.run(&["fetch", "origin"])?;
+
+    {
+        // `@{push}` isn't supported by `libgit2`, so it's resolved by Git.
+        let (stdout, _stderr) =
+            cloned_repo.run(&["sync", &test2_oid.to_string(), "--onto", "@{push}"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/1] Committed as: 5a436ed create test2.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        Your branch is behind 'origin/master' by 2 commits, and can be fast-forwarded.
+          (use "git pull" to update your local branch)
+        In-memory rebase succeeded.
+        Synced 96d1c37 create test2.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_keep_going() -> eyre::Result<()> {
+    let git = make_git()?;
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test2", 2)?;
+
+    {
+        let (stdout, _stderr) = git.run(&["sync", "--keep-going", "--print-plan-only"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        plan 62fc20d2a290daea0d52bdc2ed2ad4be6491010e
+          onto fe65c1fe15584744e649b2c79d4cf9b0d878f92e
+          reset fe65c1fe15584744e649b2c79d4cf9b0d878f92e
+          pick 62fc20d2a290daea0d52bdc2ed2ad4be6491010e
+          detect-empty 62fc20d2a290daea0d52bdc2ed2ad4be6491010e
+        "###);
+    }
+
+    {
+        // The last of `--keep-going` and `--fail-fast` wins.
+        let (stdout, _stderr) = git.run(&["sync", "--no-fail-fast", "--fail-fast"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/1] Committed as: 0770943 create test1.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Synced 62fc20d create test1.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_fail_fast() -> eyre::Result<()> {
+    let git = make_git()?;
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.run(&["checkout", "-b", "foo"])?;
+    git.commit_file_with_contents("test1", 1, "foo contents")?;
+    git.run(&["checkout", "-b", "bar", "master"])?;
+    git.commit_file_with_contents("test1", 2, "bar contents")?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file_with_contents("test1", 3, "master contents")?;
+
+    {
+        let (stdout, stderr) = git.run(&["sync", "--fail-fast"])?;
+        assert!(stderr.contains(
+            "Not attempting stack after merge conflict (--fail-fast): d6e7aea create test1.txt"
+        ));
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        Merge conflict (1 file) for c344d17 create test1.txt
+        "###);
+    }
+
+    {
+        // Without `--fail-fast`, the remaining stacks are still attempted.
+        let (stdout, _stderr) = git.run(&["sync"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        Attempting rebase in-memory...
+        Merge conflict (1 file) for c344d17 create test1.txt
+        Merge conflict (1 file) for d6e7aea create test1.txt
+        "###);
+    }
+
+    {
+        // `--keep-going` also applies to the stacks with merge conflicts.
+        let (stdout, _stderr) = git.run(&["sync", "--fail-fast", "--keep-going"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        Attempting rebase in-memory...
+        Merge conflict (1 file) for c344d17 create test1.txt
+        Merge conflict (1 file) for d6e7aea create test1.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_only_conflicting() -> eyre::Result<()> {
+    let git = make_git()?;
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.run(&["checkout", "master"])?;
+    git.detach_head()?;
+    git.commit_file_with_contents("test2", 2, "conflicting contents\n")?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test2", 3)?;
+
+    {
+        let (stdout, stderr) = git.run(&["sync", "--only-conflicting"])?;
+        insta::assert_snapshot!(stderr, @r###"
+        There are no stacks which had merge conflicts when they were last synced.
+        "###);
+        insta::assert_snapshot!(stdout, @"");
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["sync"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/1] Committed as: 8af9d44 create test1.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Attempting rebase in-memory...
+        Synced 62fc20d create test1.txt
+        Merge conflict (1 file) for 19c46a9 create test2.txt
+        "###);
+    }
+
+    // Resolve the conflict on the main branch. The stack which was synced
+    // above is now out of date too, but isn't moved with `--only-conflicting`.
+    git.run(&["rm", "test2.txt"])?;
+    git.run(&["commit", "-m", "remove test2.txt"])?;
+    {
+        let (stdout, _stderr) = git.run(&["sync", "--only-conflicting"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/1] Committed as: da0dd71 create test2.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Synced 19c46a9 create test2.txt
+        "###);
+    }
+
+    {
+        let (stdout, stderr) = git.run(&["sync", "--only-conflicting"])?;
+        insta::assert_snapshot!(stderr, @r###"
+        There are no stacks which had merge conflicts when they were last synced.
+        "###);
+        insta::assert_snapshot!(stdout, @"");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_describe_plan() -> eyre::Result<()> {
+    let git = make_git()?;
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.run(&["checkout", "master"])?;
+    git.detach_head()?;
+    git.commit_file_with_contents("test2", 2, "conflicting contents\n")?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test2", 3)?;
+    git.detach_head()?;
+    git.commit_file("test4", 4)?;
+    git.run(&["checkout", "master"])?;
+
+    {
+        let (stdout, _stderr) = git.run(&["sync", "--describe-plan", "draft() | master"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Not moving commit (not part of a draft stack): e32e9f7 create test2.txt
+        Would move stack onto master (1 commit): 62fc20d create test1.txt
+          No merge conflicts expected.
+        Would move stack onto master (1 commit): 19c46a9 create test2.txt
+          Would conflict (1 file) when moving: 19c46a9 create test2.txt
+            test2.txt
+        Not moving stack (its root is already on master): 07f6e70 create test4.txt
+        "###);
+    }
+
+    {
+        // Nothing was moved.
+        let stdout = git.smartlog()?;
+        insta::assert_snapshot!(stdout, @r###"
+        O f777ecc create initial.txt
+        |\
+        | o 62fc20d create test1.txt
+        |\
+        | o 19c46a9 create test2.txt
+        |
+        @ e32e9f7 (> master) create test2.txt
+        |
+        o 07f6e70 create test4.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_default_revsets() -> eyre::Result<()> {
+    let git = make_git()?;
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    git.run(&["checkout", "master"])?;
+    git.detach_head()?;
+    git.commit_file("test2", 2)?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test3", 3)?;
+
+    git.run(&[
+        "config",
+        "--add",
+        "branchless.sync.defaultRevsets",
+        &test1_oid.to_string(),
+    ])?;
+    {
+        let (stdout, _stderr) = git.run(&["sync"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/1] Committed as: 4b9ce31 create test1.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Synced 62fc20d create test1.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["sync", "--all"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/1] Committed as: 200e547 create test2.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Synced fe65c1f create test2.txt
+        "###);
+    }
+
+    git.run(&["config", "--add", "branchless.sync.defaultRevsets", "foo("])?;
+    {
+        let (stdout, stderr) = git.run_with_options(
+            &["sync"],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        insta::assert_snapshot!(stderr, @r###"
+        Invalid revset in branchless.sync.defaultRevsets: parse error: Unrecognized EOF found at 4
+        Expected one of "(", ")", "..", ":", "::", a commit/branch/tag or a string literal
+        "###);
+        insta::assert_snapshot!(stdout, @"");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_unrelated_histories() -> eyre::Result<()> {
+    let git = make_git()?;
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.commit_file("test1", 1)?;
+    git.run(&["checkout", "--orphan", "other"])?;
+    git.run(&["rm", "-rf", "."])?;
+    git.commit_file("test2", 2)?;
+    git.commit_file("test3", 3)?;
+    git.run(&["checkout", "master"])?;
+
+    {
+        let (stdout, stderr) = git.run(&["sync"])?;
+        insta::assert_snapshot!(stderr, @r###"
+        Skipping stack (root commit has no parents): 1525640 create test2.txt
+        Pass --allow-unrelated-histories to move stacks with no common history anyway.
+        "###);
+        insta::assert_snapshot!(stdout, @"");
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["sync", "--allow-unrelated-histories"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/2] Committed as: 96d1c37 create test2.txt
+        [2/2] Committed as: 70deb1e create test3.txt
+        branchless: processing 1 update: branch other
+        branchless: processing 2 rewritten commits
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Synced 1525640 create test2.txt
+        "###);
+    }
+
+    {
+        let stdout = git.smartlog()?;
+        insta::assert_snapshot!(stdout, @r###"
+        :
+        @ 62fc20d (> master) create test1.txt
+        |
+        o 96d1c37 create test2.txt
+        |
+        o 70deb1e (other) create test3.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_parentless_root() -> eyre::Result<()> {
+    let git = make_git()?;
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.commit_file("test1", 1)?;
+    git.detach_head()?;
+    git.commit_file("test2", 2)?;
+    git.run(&["checkout", "--orphan", "other"])?;
+    git.run(&["rm", "-rf", "."])?;
+    let test3_oid = git.commit_file("test3", 3)?;
+    git.run(&["checkout", "master"])?;
+
+    {
+        let (stdout, stderr) = git.run(&["sync"])?;
+        insta::assert_snapshot!(stderr, @r###"
+        Skipping stack (root commit has no parents): 1d512a3 create test3.txt
+        Pass --allow-unrelated-histories to move stacks with no common history anyway.
+        "###);
+        insta::assert_snapshot!(stdout, @"");
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["rev-parse", "other"])?;
+        assert_eq!(stdout.trim(), test3_oid.to_string());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_color() -> eyre::Result<()> {
+    let git = make_git()?;
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test2", 2)?;
+
+    let force_color_options = GitRunOptions {
+        env: [("CLICOLOR_FORCE".to_string(), "1".to_string())]
+            .into_iter()
+            .collect(),
+        ..Default::default()
+    };
+
+    {
+        let (stdout, stderr) =
+            git.branchless_with_options("sync", &["--color", "never"], &force_color_options)?;
+        assert!(!stdout.contains('\x1b'), "stdout: {stdout:?}");
+        assert!(!stderr.contains('\x1b'), "stderr: {stderr:?}");
+    }
+
+    git.commit_file("test3", 3)?;
+    {
+        let (stdout, _stderr) = git.branchless_with_options("sync", &[], &force_color_options)?;
+        assert!(stdout.contains('\x1b'), "stdout: {stdout:?}");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_add_trailer() -> eyre::Result<()> {
+    let git = make_git()?;
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test2", 2)?;
+    git.detach_head()?;
+    git.commit_file("test3", 3)?;
+    git.run(&["checkout", "master"])?;
+
+    {
+        let (stdout, _stderr) = git.branchless(
+            "sync",
+            &[
+                "--add-trailer",
+                "Rebased-onto={onto}",
+                "--add-trailer",
+                "Reviewed-by=Someone",
+            ],
+        )?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/1] Committed as: cea7593 create test1.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Synced 62fc20d create test1.txt
+        "###);
+    }
+
+    {
+        let (stdout, _stderr) = git.run(&["log", "--format=%h %B", "cea7593", "0206717"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        0206717 create test3.txt
+
+        fe65c1f create test2.txt
+
+        cea7593 create test1.txt
+
+        Rebased-onto: fe65c1fe15584744e649b2c79d4cf9b0d878f92e
+        Reviewed-by: Someone
+
+        f777ecc create initial.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+#[test]
+fn test_sync_notify() -> eyre::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let git = make_git()?;
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test2", 2)?;
+
+    // The sync succeeds even if there's no notifier to send the notification.
+    {
+        let (stdout, _stderr) = git.branchless("sync", &["--notify"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/1] Committed as: 96d1c37 create test1.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Synced 62fc20d create test1.txt
+        "###);
+    }
+
+    let notifier_dir = git.repo_path.join(".git").join("notifier-bin");
+    std::fs::create_dir(&notifier_dir)?;
+    let notification_path = git.repo_path.join(".git").join("notification.txt");
+    let notify_send_path = notifier_dir.join("notify-send");
+    std::fs::write(
+        &notify_send_path,
+        format!("#!/bin/sh\nprintf '%s\\n' \"$@\" > {notification_path:?}\n"),
+    )?;
+    std::fs::set_permissions(&notify_send_path, std::fs::Permissions::from_mode(0o755))?;
+    let mut path = notifier_dir.into_os_string();
+    path.push(":");
+    path.push(git.get_path_for_env());
+    let notifier_options = GitRunOptions {
+        env: [("PATH".to_string(), path.into_string().unwrap())]
+            .into_iter()
+            .collect(),
+        ..Default::default()
+    };
+
+    git.commit_file("test3", 3)?;
+    git.run(&["config", "branchless.sync.notify", "true"])?;
+    git.branchless_with_options("sync", &[], &notifier_options)?;
+    let notification = std::fs::read_to_string(&notification_path)?;
+    insta::assert_snapshot!(notification, @r###"
+    git sync
+    Synced 1 stack, 0 with merge conflicts.
+    "###);
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_profile() -> eyre::Result<()> {
+    let git = make_git()?;
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+
+    git.init_repo()?;
+    git.detach_head()?;
+    git.commit_file("test1", 1)?;
+    git.run(&["checkout", "master"])?;
+    git.commit_file("test2", 2)?;
+
+    let profile_path = git.repo_path.join(".git").join("sync-profile.json");
+    let profile_arg = format!("--profile={}", profile_path.to_str().unwrap());
+    {
+        let (stdout, _stderr) = git.branchless("sync", &[&profile_arg])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Attempting rebase in-memory...
+        [1/1] Committed as: 96d1c37 create test1.txt
+        branchless: processing 1 rewritten commit
+        branchless: running command: <git-executable> checkout master
+        In-memory rebase succeeded.
+        Synced 62fc20d create test1.txt
+        "###);
+    }
+
+    let events: Vec<serde_json::Value> =
+        serde_json::from_str(&std::fs::read_to_string(&profile_path)?)?;
+    let span_names: HashSet<&str> = events
+        .iter()
+        .filter_map(|event| event.get("name").and_then(|name| name.as_str()))
+        .collect();
+    assert!(span_names.contains("run_sync"), "{span_names:?}");
+    assert!(span_names.contains("execute_plans"), "{span_names:?}");
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_dump_dag() -> eyre::Result<()> {
+    let git = make_git()?;
+
+    if !git.supports_reference_transactions()? {
+        return Ok(());
+    }
+    git.init_repo()?;
+
+    git.detach_head()?;
+    let test1_oid = git.commit_file("test1", 1)?;
+    git.commit_file("test2", 2)?;
+    git.run(&["checkout", "master"])?;
+    let test3_oid = git.commit_file("test3", 3)?;
+
+    let dump_path = git.repo_path.join(".git").join("sync-dag.json");
+    let dump_arg = format!("--dump-dag={}", dump_path.to_str().unwrap());
+    git.branchless("sync", &[&dump_arg, "--print-plan-only"])?;
+
+    let dump: DagDump = serde_json::from_str(&std::fs::read_to_string(&dump_path)?)?;
+    assert_eq!(dump.main_branch_oid, test3_oid.to_string());
+    assert_eq!(dump.upstream_oid, None);
+    assert_eq!(dump.root_oids, vec![test1_oid.to_string()]);
+    assert_eq!(dump.commits[0].summary, "create initial.txt");
+
+    let summaries: HashMap<&str, &str> = dump
+        .commits
+        .iter()
+        .map(|commit| (commit.oid.as_str(), commit.summary.as_str()))
+        .collect();
+    let edges = dump
+        .commits
+        .iter()
+        .map(|commit| {
+            let parent_summaries = commit
+                .parent_oids
+                .iter()
+                .map(|parent_oid| summaries[parent_oid.as_str()])
+                .join(", ");
+            format!(
+                "{} <- [{}] (public: {})",
+                commit.summary, parent_summaries, commit.is_public
+            )
+        })
+        .sorted()
+        .join("\n");
+    insta::assert_snapshot!(edges, @r###"
+    create initial.txt <- [] (public: true)
+    create test1.txt <- [create initial.txt] (public: false)
+    create test2.txt <- [create test1.txt] (public: false)
+    create test3.txt <- [create initial.txt] (public: true)
+    "###);
+
     Ok(())
 }